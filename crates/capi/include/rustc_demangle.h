@@ -5,12 +5,42 @@
 extern "C" {
 #endif
 
+#include <stddef.h>
+#include <stdint.h>
+
+// All functions in this header are reentrant and thread-safe: they keep no
+// global or thread-local state, and don't allocate memory, so they can be
+// called concurrently from any number of threads (e.g. from a debugger's
+// or profiler's symbolication threads), as long as each call gets its own
+// `out` buffer, and the input strings aren't modified during the call.
+
 // Demangles symbol given in `mangled` argument into `out` buffer
 //
 // Returns 0 if `mangled` is not Rust symbol or if `out` buffer is too small
 // Returns 1 otherwise
 int rustc_demangle(const char *mangled, char *out, size_t out_size);
 
+// Returns the size of the `out` buffer `rustc_demangle` needs for the symbol
+// given in `mangled` argument, including the terminating NUL
+//
+// Returns 0 if `mangled` is not Rust symbol
+size_t rustc_demangle_size(const char *mangled);
+
+// Flag for `rust_demangle_to_json`: include the hash of the symbol in the
+// `"demangled"` field.
+#define RUST_DEMANGLE_JSON_INCLUDE_HASH 1
+
+// Demangles symbol given in `sym` argument into `out` buffer, as a
+// JSON object of the form
+// `{"mangled":"...","scheme":"legacy"|"v0","demangled":"..."}`
+//
+// `flags` is a combination of `RUST_DEMANGLE_JSON_*` flags (or 0).
+//
+// Returns 0 if `sym` is not Rust symbol, if `out` buffer is too small or if
+// `flags` contains unknown bits
+// Returns 1 otherwise
+int rust_demangle_to_json(const char *sym, char *out, size_t len, uint32_t flags);
+
 #ifdef __cplusplus
 }
 #endif
@@ -1,5 +1,13 @@
+//! C API for the `rustc-demangle` crate, see `include/rustc_demangle.h`.
+//!
+//! All the functions are reentrant and thread-safe: they keep no global or
+//! thread-local state, and only access the buffers passed to them, so they
+//! can be called concurrently from any thread. This is relied upon by e.g.
+//! debuggers, and is checked by the `concurrent` test.
+
 extern crate rustc_demangle;
 
+use std::fmt;
 use std::io::Write;
 use std::os::raw::{c_char, c_int};
 
@@ -32,6 +40,127 @@ pub unsafe extern "C" fn rustc_demangle(
     }
 }
 
+/// Returns the size of the `out` buffer `rustc_demangle` needs for the symbol
+/// given in `mangled` argument, including the terminating NUL
+///
+/// Returns 0 if `mangled` is not Rust symbol
+///
+/// # Safety
+///
+/// `mangled` must point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustc_demangle_size(mangled: *const c_char) -> usize {
+    let mangled_str = match std::ffi::CStr::from_ptr(mangled).to_str() {
+        Ok(s) => s,
+        Err(_) => return 0,
+    };
+    match rustc_demangle::try_demangle(mangled_str) {
+        Ok(demangle) => demangle.display_len(true) + 1,
+        Err(_) => 0,
+    }
+}
+
+/// Flag for `rust_demangle_to_json`: include the hash of the symbol in the
+/// `"demangled"` field (like the `{}` formatting, instead of `{:#}`).
+pub const RUST_DEMANGLE_JSON_INCLUDE_HASH: u32 = 1;
+
+/// `fmt::Write` adapter escaping everything written to it for the inside of
+/// a JSON string literal, before passing it on to `out`.
+struct JsonEscaper<'a, W: 'a> {
+    out: &'a mut W,
+}
+
+impl<'a, W: Write> fmt::Write for JsonEscaper<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let result = match c {
+                '"' => self.out.write_all(b"\\\""),
+                '\\' => self.out.write_all(b"\\\\"),
+                '\n' => self.out.write_all(b"\\n"),
+                '\r' => self.out.write_all(b"\\r"),
+                '\t' => self.out.write_all(b"\\t"),
+                c if (c as u32) < 0x20 => write!(self.out, "\\u{:04x}", c as u32),
+                c => self.out.write_all(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            };
+            result.map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `x` (formatted with `{:#}` if `alternate` is set, or `{}`) as a
+/// JSON string literal, including the quotes, without allocating.
+fn write_json_str<W: Write, T: fmt::Display>(
+    out: &mut W,
+    x: T,
+    alternate: bool,
+) -> std::io::Result<()> {
+    out.write_all(b"\"")?;
+    let result = {
+        let mut escaper = JsonEscaper { out: &mut *out };
+        if alternate {
+            fmt::Write::write_fmt(&mut escaper, format_args!("{:#}", x))
+        } else {
+            fmt::Write::write_fmt(&mut escaper, format_args!("{}", x))
+        }
+    };
+    // Converting an `ErrorKind` doesn't allocate, unlike `io::Error::new`.
+    result.map_err(|_| std::io::Error::from(std::io::ErrorKind::WriteZero))?;
+    out.write_all(b"\"")
+}
+
+/// C-style interface for demangling to a JSON object.
+/// Demangles symbol given in `sym` argument into `out` buffer, as
+/// `{"mangled":"...","scheme":"legacy"|"v0","demangled":"..."}`
+///
+/// `flags` is a combination of `RUST_DEMANGLE_JSON_*` flags (or 0).
+///
+/// Returns 0 if `sym` is not Rust symbol, if `out` buffer is too small or if
+/// `flags` contains unknown bits
+/// Returns 1 otherwise
+///
+/// # Safety
+///
+/// `sym` must point to a NUL-terminated string, and `out` to a buffer of at
+/// least `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rust_demangle_to_json(
+    sym: *const c_char,
+    out: *mut c_char,
+    len: usize,
+    flags: u32,
+) -> c_int {
+    if flags & !RUST_DEMANGLE_JSON_INCLUDE_HASH != 0 {
+        return 0;
+    }
+    let mangled_str = match std::ffi::CStr::from_ptr(sym).to_str() {
+        Ok(s) => s,
+        Err(_) => return 0,
+    };
+    let demangle = match rustc_demangle::try_demangle(mangled_str) {
+        Ok(demangle) => demangle,
+        Err(_) => return 0,
+    };
+    let include_hash = flags & RUST_DEMANGLE_JSON_INCLUDE_HASH != 0;
+    let scheme = match demangle.mangling_scheme() {
+        rustc_demangle::ManglingScheme::Legacy => "legacy",
+        _ => "v0",
+    };
+
+    let mut out_slice = std::slice::from_raw_parts_mut(out as *mut u8, len);
+    let result = (|| {
+        out_slice.write_all(b"{\"mangled\":")?;
+        write_json_str(&mut out_slice, mangled_str, false)?;
+        write!(out_slice, ",\"scheme\":\"{}\",\"demangled\":", scheme)?;
+        write_json_str(&mut out_slice, &demangle, !include_hash)?;
+        out_slice.write_all(b"}\0")
+    })();
+    match result {
+        Ok(_) => 1,
+        Err(_) => 0,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std;
@@ -157,4 +286,127 @@ mod tests {
         };
         assert_eq!(res, 0);
     }
+
+    #[test]
+    fn demangle_c_str_size() {
+        let size = |mangled: &str| unsafe {
+            super::rustc_demangle_size(mangled.as_ptr() as *const c_char)
+        };
+        assert_eq!(size("_ZN4testE\0"), 5);
+        assert_eq!(size("_ZN3foo3bar17h05af221e174051e9E\0"), 9);
+        assert_eq!(size("la la la\0"), 0);
+        assert_eq!(size("\0"), 0);
+
+        let mangled = "_RNvCs1234_7mycrate4main\0";
+        let mut out_buf: Vec<u8> = vec![42; size(mangled)];
+        let res = unsafe {
+            super::rustc_demangle(
+                mangled.as_ptr() as *const c_char,
+                out_buf.as_mut_ptr() as *mut c_char,
+                out_buf.len(),
+            )
+        };
+        assert_eq!(res, 1);
+        assert_eq!(out_buf, b"mycrate::main\0");
+    }
+
+    fn to_json(mangled: &str, flags: u32, len: usize) -> Option<String> {
+        let mangled = std::ffi::CString::new(mangled).unwrap();
+        let mut out_buf: Vec<u8> = vec![42; len];
+        let res = unsafe {
+            super::rust_demangle_to_json(
+                mangled.as_ptr(),
+                out_buf.as_mut_ptr() as *mut c_char,
+                len,
+                flags,
+            )
+        };
+        if res == 0 {
+            return None;
+        }
+        let nul = out_buf.iter().position(|&b| b == 0).unwrap();
+        Some(String::from_utf8(out_buf[..nul].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn demangle_to_json() {
+        assert_eq!(
+            to_json("_ZN3foo17h05af221e174051e9E", 0, 256).unwrap(),
+            r#"{"mangled":"_ZN3foo17h05af221e174051e9E","scheme":"legacy","demangled":"foo"}"#
+        );
+        assert_eq!(
+            to_json(
+                "_ZN3foo17h05af221e174051e9E",
+                super::RUST_DEMANGLE_JSON_INCLUDE_HASH,
+                256
+            )
+            .unwrap(),
+            r#"{"mangled":"_ZN3foo17h05af221e174051e9E","scheme":"legacy","demangled":"foo::h05af221e174051e9"}"#
+        );
+        assert_eq!(
+            to_json("_RINvC3foo3barKRe616263_E", 0, 256).unwrap(),
+            r#"{"mangled":"_RINvC3foo3barKRe616263_E","scheme":"v0","demangled":"foo::bar::<\"abc\">"}"#
+        );
+    }
+
+    #[test]
+    fn concurrent() {
+        let symbols = [
+            "_ZN4testE",
+            "_ZN3foo17h05af221e174051e9E",
+            "_RINvC3foo3barKRe616263_E",
+            "_RNvMNtNtNtNtCs8a2262Dv4r_3mio3sys4unix8selector5epollNtB2_8Selector6select",
+            "la la la",
+        ];
+        let threads: Vec<_> = (0..16)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let symbol = symbols[(t + i) % symbols.len()];
+                        let demangled = rustc_demangle::try_demangle(symbol)
+                            .ok()
+                            .map(|d| format!("{:#}", d));
+
+                        let mangled = std::ffi::CString::new(symbol).unwrap();
+                        let mut out_buf = vec![42u8; 256];
+                        let res = unsafe {
+                            super::rustc_demangle(
+                                mangled.as_ptr(),
+                                out_buf.as_mut_ptr() as *mut c_char,
+                                out_buf.len(),
+                            )
+                        };
+                        let out = if res == 1 {
+                            let nul = out_buf.iter().position(|&b| b == 0).unwrap();
+                            Some(String::from_utf8(out_buf[..nul].to_vec()).unwrap())
+                        } else {
+                            None
+                        };
+                        assert_eq!(out, demangled);
+
+                        let json = to_json(symbol, 0, 512);
+                        assert_eq!(json.is_some(), demangled.is_some());
+                        if let (Some(json), Some(demangled)) = (json, demangled) {
+                            let mut expected = b"\"demangled\":".to_vec();
+                            super::write_json_str(&mut expected, &demangled, false).unwrap();
+                            expected.push(b'}');
+                            assert!(json.as_bytes().ends_with(&expected));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn demangle_to_json_errors() {
+        let json = to_json("_ZN4testE", 0, 256).unwrap();
+        assert_eq!(to_json("_ZN4testE", 0, json.len() + 1), Some(json.clone()));
+        assert_eq!(to_json("_ZN4testE", 0, json.len()), None);
+        assert_eq!(to_json("la la la", 0, 256), None);
+        assert_eq!(to_json("_ZN4testE", 2, 256), None);
+    }
 }
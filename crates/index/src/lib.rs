@@ -0,0 +1,294 @@
+//! Persistent lookup tables from mangled to demangled symbols, so that
+//! repeated symbolication of the same binary can skip demangling entirely.
+//!
+//! Indexes are built with `IndexBuilder`, and can then be either read into
+//! memory, or memory-mapped, with `Index::open` and `Index::map`.
+//!
+//! The on-disk format (all integers are little-endian) is:
+//! * the magic bytes `RDMIDX01`
+//! * the number of entries, as an `u64`
+//! * for each entry, sorted by mangled symbol (compared bytewise):
+//!   the offset (`u64`) and length (`u32`) of the mangled symbol,
+//!   followed by the same for the demangled symbol
+//! * the strings referred to by the entries, with offsets relative to the
+//!   start of the file
+
+extern crate memmap2;
+extern crate rustc_demangle;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::str;
+
+use memmap2::Mmap;
+use rustc_demangle::try_demangle;
+
+const MAGIC: &[u8; 8] = b"RDMIDX01";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 24;
+
+/// Builds an `Index` from a list of symbols.
+///
+/// ```
+/// use rustc_demangle_index::IndexBuilder;
+///
+/// let mut builder = IndexBuilder::new(false);
+/// assert!(builder.insert("_ZN3foo17h05af221e174051e9E"));
+/// assert!(!builder.insert("main"));
+///
+/// let mut index = Vec::new();
+/// builder.write(&mut index).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct IndexBuilder {
+    include_hash: bool,
+    entries: BTreeMap<String, String>,
+}
+
+impl IndexBuilder {
+    /// Creates an empty builder, which will store demangled symbols with
+    /// their hashes (`{}` formatting) if `include_hash` is set, or without
+    /// them (`{:#}` formatting) otherwise.
+    pub fn new(include_hash: bool) -> IndexBuilder {
+        IndexBuilder {
+            include_hash,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Demangles `mangled` and adds it to the index, returning `false`
+    /// (and leaving the index unchanged) if it's not a Rust symbol.
+    pub fn insert(&mut self, mangled: &str) -> bool {
+        if self.entries.contains_key(mangled) {
+            return true;
+        }
+        let demangled = match try_demangle(mangled) {
+            Ok(demangled) => demangled,
+            Err(_) => return false,
+        };
+        let demangled = if self.include_hash {
+            format!("{}", demangled)
+        } else {
+            format!("{:#}", demangled)
+        };
+        self.entries.insert(mangled.into(), demangled);
+        true
+    }
+
+    /// Returns the number of symbols added to the index so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no symbols have been added to the index yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the index out, in the format expected by `Index::open`.
+    pub fn write<W: io::Write>(&self, mut output: W) -> io::Result<()> {
+        output.write_all(MAGIC)?;
+        output.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+
+        let mut offset = (HEADER_SIZE + self.entries.len() * ENTRY_SIZE) as u64;
+        for (mangled, demangled) in &self.entries {
+            for s in &[mangled, demangled] {
+                if s.len() > u32::MAX as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "symbol too long to be indexed",
+                    ));
+                }
+                output.write_all(&offset.to_le_bytes())?;
+                output.write_all(&(s.len() as u32).to_le_bytes())?;
+                offset += s.len() as u64;
+            }
+        }
+        for (mangled, demangled) in &self.entries {
+            output.write_all(mangled.as_bytes())?;
+            output.write_all(demangled.as_bytes())?;
+        }
+        output.flush()
+    }
+}
+
+/// An index, as written by `IndexBuilder::write`.
+pub struct Index {
+    map: Data,
+    len: usize,
+}
+
+/// The contents of an index file, either read into memory or mapped.
+enum Data {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Data::Read(ref bytes) => bytes,
+            Data::Mapped(ref map) => map,
+        }
+    }
+}
+
+impl Index {
+    /// Reads the whole index at `path` into memory.
+    pub fn open(path: &Path) -> io::Result<Index> {
+        Index::new(Data::Read(fs::read(path)?))
+    }
+
+    /// Memory-maps the index at `path`, which avoids reading all of it, for
+    /// looking up only a few symbols in a large index.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified (e.g. by another process) while the
+    /// `Index` is alive: the strings returned by `get` point directly into
+    /// the mapped file, so rewriting it would change them (even to invalid
+    /// UTF-8) after the fact, and truncating it would make reading them
+    /// crash the process (with `SIGBUS`). Use `Index::open` if that can't
+    /// be guaranteed.
+    pub unsafe fn map(path: &Path) -> io::Result<Index> {
+        Index::new(Data::Mapped(Mmap::map(&File::open(path)?)?))
+    }
+
+    fn new(map: Data) -> io::Result<Index> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid symbol index");
+        if map.len() < HEADER_SIZE || &map[..MAGIC.len()] != MAGIC {
+            return Err(invalid());
+        }
+        let len = read_u64(&map, MAGIC.len());
+        let table_size = len
+            .checked_mul(ENTRY_SIZE as u64)
+            .filter(|&size| size <= (map.len() - HEADER_SIZE) as u64)
+            .ok_or_else(invalid)?;
+        Ok(Index {
+            len: (table_size / ENTRY_SIZE as u64) as usize,
+            map,
+        })
+    }
+
+    /// Returns the number of symbols in the index.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the index contains no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Looks up the demangled form of `mangled`, returning `None` if it's
+    /// not in the index (or the index is corrupted).
+    pub fn get(&self, mangled: &str) -> Option<&str> {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = HEADER_SIZE + mid * ENTRY_SIZE;
+            let key = self.string(entry)?;
+            match key.cmp(mangled.as_bytes()) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => {
+                    return str::from_utf8(self.string(entry + ENTRY_SIZE / 2)?).ok();
+                }
+            }
+        }
+        None
+    }
+
+    fn string(&self, at: usize) -> Option<&[u8]> {
+        let start = read_u64(&self.map, at);
+        let len = read_u32(&self.map, at + 8);
+        let end = start.checked_add(len as u64)?;
+        if end > self.map.len() as u64 {
+            return None;
+        }
+        Some(&self.map[start as usize..end as usize])
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rustc_demangle;
+
+    use super::{Index, IndexBuilder};
+
+    #[test]
+    fn roundtrip() {
+        let path = std::env::temp_dir().join(format!("rustc-demangle-{}.idx", std::process::id()));
+        let symbols = [
+            "_ZN3foo17h05af221e174051e9E",
+            "_RNvC3foo3bar",
+            "_ZN3foo3barE",
+            "_RNvMNtNtNtNtCs8a2262Dv4r_3mio3sys4unix8selector5epollNtB2_8Selector6select",
+        ];
+
+        for &include_hash in &[true, false] {
+            let mut builder = IndexBuilder::new(include_hash);
+            for symbol in &symbols {
+                assert!(builder.insert(symbol));
+            }
+            assert!(builder.insert(symbols[0]));
+            assert!(!builder.insert("main"));
+            assert_eq!(builder.len(), symbols.len());
+            builder
+                .write(std::fs::File::create(&path).unwrap())
+                .unwrap();
+
+            let index = Index::open(&path).unwrap();
+            let mapped = unsafe { Index::map(&path) }.unwrap();
+            assert_eq!(index.len(), symbols.len());
+            assert_eq!(mapped.len(), symbols.len());
+            for symbol in &symbols {
+                let demangled = rustc_demangle::demangle(symbol);
+                let expected = if include_hash {
+                    format!("{}", demangled)
+                } else {
+                    format!("{:#}", demangled)
+                };
+                assert_eq!(index.get(symbol), Some(&expected[..]));
+                assert_eq!(mapped.get(symbol), Some(&expected[..]));
+            }
+            assert_eq!(index.get("main"), None);
+            assert_eq!(index.get("_ZN3foo3bazE"), None);
+            assert_eq!(index.get(""), None);
+        }
+
+        std::fs::write(&path, b"RDMIDX01\xff\xff\xff\xff\xff\xff\xff\xff").unwrap();
+        assert!(Index::open(&path).is_err());
+        std::fs::write(&path, "not an index").unwrap();
+        assert!(Index::open(&path).is_err());
+        assert!(unsafe { Index::map(&path) }.is_err());
+
+        let empty = IndexBuilder::new(true);
+        empty.write(std::fs::File::create(&path).unwrap()).unwrap();
+        let index = Index::open(&path).unwrap();
+        assert!(index.is_empty());
+        let index = unsafe { Index::map(&path) }.unwrap();
+        assert!(index.is_empty());
+        assert_eq!(index.get("_ZN3foo3barE"), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -43,8 +43,9 @@ struct demangle {
     size_t suffix_len;
 };
 
-// if the length of the output buffer is less than `output_len-OVERFLOW_MARGIN`,
-// the demangler will return `OverflowOverflow` even if there is no overflow.
+// if the length of the output buffer is less than `output_len+OVERFLOW_MARGIN`,
+// `rust_demangle_display_demangle` will return `OverflowOverflow` even if there
+// is no overflow. `rust_demangle_display_demangle_exact` has no such margin.
 #define OVERFLOW_MARGIN 4
 
 /// Demangle a C string that refers to a Rust symbol and put the demangle intermediate result in `res`.
@@ -68,6 +69,15 @@ void rust_demangle_demangle(const char *s, struct demangle *res);
 /// [$$] Technically, bounded by `O(n^MAX_DEPTH)`, but this is practically exponential.
 DEMANGLE_NODISCARD overflow_status rust_demangle_display_demangle(struct demangle const *res, char *out, size_t len, bool alternate);
 
+/// The same as `rust_demangle_display_demangle`, except that it only returns `OverflowOverflow`
+/// if the output (including its NUL terminator) does not fit in `len` bytes, i.e. without
+/// requiring an `OVERFLOW_MARGIN`.
+///
+/// This matches the truncation behavior of the Rust implementation writing into a buffer of
+/// `len - 1` bytes (e.g. `rustc_demangle` in `rustc-demangle-capi`), which allows comparing
+/// truncated outputs exactly.
+DEMANGLE_NODISCARD overflow_status rust_demangle_display_demangle_exact(struct demangle const *res, char *out, size_t len, bool alternate);
+
 /// Returns true if `res` refers to a known valid Rust demangling style, false if it's an unknown style.
 bool rust_demangle_is_known(struct demangle *res);
 
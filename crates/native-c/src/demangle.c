@@ -182,8 +182,9 @@ NODISCARD static demangle_status rust_demangle_v0_demangle(const char *s, size_t
     return DemangleOk;
 }
 
-// This might require `len` to be up to 3 characters bigger than the real output len in case of utf-8
-NODISCARD static overflow_status rust_demangle_v0_display_demangle(struct demangle_v0 res, char *out, size_t len, bool alternate) {
+// Requires `len` to be at least `margin` bytes bigger than the real output len,
+// `margin` being at least 1 (for the NUL terminator).
+NODISCARD static overflow_status rust_demangle_v0_display_demangle(struct demangle_v0 res, char *out, size_t len, bool alternate, size_t margin) {
     struct printer printer = {
         DemangleOk,
         {
@@ -200,7 +201,7 @@ NODISCARD static overflow_status rust_demangle_v0_display_demangle(struct demang
     if (printer_print_path(&printer, true) == OverflowOverflow) {
         return OverflowOverflow;
     }
-    if (printer.out_len < OVERFLOW_MARGIN) {
+    if (printer.out_len < margin) {
         return OverflowOverflow;
     }
     *printer.out = '\0';
@@ -406,11 +407,13 @@ static NODISCARD overflow_status display_ident(const char *ascii_start, size_t a
     } else if (punycode_decode(ascii_start, ascii_len, punycode_start, punycode_len, &outbuf, &wide_len) == PunycodeOk) {
         size_t narrow_len = 0;
         for (size_t i = 0; i < wide_len; i++) {
-            if (out_buflen - narrow_len < 4) {
+            unsigned char wchr[4];
+            size_t wchr_len = code_to_utf8(wchr, outbuf[i]);
+            if (out_buflen - narrow_len < wchr_len) {
                 return OverflowOverflow;
             }
-            unsigned char *pos = &out[narrow_len];
-            narrow_len += code_to_utf8(pos, outbuf[i]);
+            memcpy(&out[narrow_len], wchr, wchr_len);
+            narrow_len += wchr_len;
         }
         *out_len = narrow_len;
     } else {
@@ -1762,7 +1765,9 @@ static bool is_rust_hash(const char *s, size_t len) {
     return true;
 }
 
-NODISCARD static overflow_status rust_demangle_legacy_display_demangle(struct demangle_legacy res, char *out, size_t len, bool alternate)
+// Requires `len` to be at least `margin` bytes bigger than the real output len,
+// `margin` being at least 1 (for the NUL terminator).
+NODISCARD static overflow_status rust_demangle_legacy_display_demangle(struct demangle_legacy res, char *out, size_t len, bool alternate, size_t margin)
 {
     struct printer printer = {
         // not actually using the parser part of the printer, just keeping it to share the format functions
@@ -1880,7 +1885,7 @@ NODISCARD static overflow_status rust_demangle_legacy_display_demangle(struct de
         PRINT(printer_print_buf(&printer, rest, len));
     }
 
-    if (printer.out_len < OVERFLOW_MARGIN) {
+    if (printer.out_len < margin) {
         return OverflowOverflow;
     }
     *printer.out = '\0';
@@ -1982,7 +1987,7 @@ bool rust_demangle_is_known(struct demangle *res) {
     return res->style != DemangleStyleUnknown;
 }
 
-overflow_status rust_demangle_display_demangle(struct demangle const *res, char *out, size_t len, bool alternate) {    
+static NODISCARD overflow_status display_demangle(struct demangle const *res, char *out, size_t len, bool alternate, size_t margin) {
     size_t original_len = res->original_len;
     size_t out_len;
     switch (res->style) {
@@ -2002,7 +2007,7 @@ overflow_status rust_demangle_display_demangle(struct demangle const *res, char
             res->mangled_len,
             res->elements
         };
-        if (rust_demangle_legacy_display_demangle(legacy, out, len, alternate) == OverflowOverflow) {
+        if (rust_demangle_legacy_display_demangle(legacy, out, len, alternate, margin) == OverflowOverflow) {
             return OverflowOverflow;
         }
         out_len = strlen(out);
@@ -2015,7 +2020,7 @@ overflow_status rust_demangle_display_demangle(struct demangle const *res, char
             res->mangled,
             res->mangled_len
         };
-        if (rust_demangle_v0_display_demangle(v0, out, len, alternate) == OverflowOverflow) {
+        if (rust_demangle_v0_display_demangle(v0, out, len, alternate, margin) == OverflowOverflow) {
             return OverflowOverflow;
         }
         out_len = strlen(out);
@@ -2025,10 +2030,18 @@ overflow_status rust_demangle_display_demangle(struct demangle const *res, char
     }
     }
     size_t suffix_len = res->suffix_len;
-    if (len < suffix_len || len - suffix_len < OVERFLOW_MARGIN) {
+    if (len < suffix_len || len - suffix_len < margin) {
         return OverflowOverflow;
     }
     memcpy(out, res->suffix, suffix_len);
     out[suffix_len] = 0;
     return OverflowOk;
 }
+
+overflow_status rust_demangle_display_demangle(struct demangle const *res, char *out, size_t len, bool alternate) {
+    return display_demangle(res, out, len, alternate, OVERFLOW_MARGIN);
+}
+
+overflow_status rust_demangle_display_demangle_exact(struct demangle const *res, char *out, size_t len, bool alternate) {
+    return display_demangle(res, out, len, alternate, 1);
+}
@@ -41,11 +41,45 @@ extern "C" {
         len: usize,
         alternate: bool,
     ) -> c_int;
+    /// call rust_demangle_display_demangle_exact
+    pub fn rust_demangle_display_demangle_exact(
+        res: *const CDemangle,
+        out: *mut c_char,
+        len: usize,
+        alternate: bool,
+    ) -> c_int;
 }
 
 #[test]
 fn smoke_test() {
+    type DisplayFn = unsafe extern "C" fn(*const CDemangle, *mut c_char, usize, bool) -> c_int;
+
     fn test_single(input: &str, expected: &str, alternate: bool) {
+        // test overflow margin
+        test_display(
+            input,
+            expected,
+            alternate,
+            rust_demangle_display_demangle,
+            4,
+        );
+        // only the NUL terminator is needed on top of the output
+        test_display(
+            input,
+            expected,
+            alternate,
+            rust_demangle_display_demangle_exact,
+            1,
+        );
+    }
+
+    fn test_display(
+        input: &str,
+        expected: &str,
+        alternate: bool,
+        display: DisplayFn,
+        margin: usize,
+    ) {
         use std::ffi::{CStr, CString};
 
         let mut buf = [0u8; 4096];
@@ -54,13 +88,7 @@ fn smoke_test() {
             let cs = CString::new(input).unwrap();
             for output_len in 0..4096 {
                 rust_demangle_demangle(cs.as_ptr(), &mut demangle);
-                if rust_demangle_display_demangle(
-                    &demangle,
-                    buf.as_mut_ptr().cast(),
-                    output_len,
-                    alternate,
-                ) != 0
-                {
+                if display(&demangle, buf.as_mut_ptr().cast(), output_len, alternate) != 0 {
                     continue; // buffer is not big enough
                 }
                 let output = CStr::from_bytes_until_nul(&buf[..])
@@ -68,8 +96,7 @@ fn smoke_test() {
                     .to_str()
                     .expect("utf-8");
                 assert_eq!(output, expected);
-                // test overflow margin
-                assert_eq!(output_len, output.len() + 4);
+                assert_eq!(output_len, output.len() + margin);
                 return;
             }
             panic!("overflow");
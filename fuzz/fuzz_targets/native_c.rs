@@ -140,6 +140,23 @@ fn fuzz(data: &[u8], alternate: bool) {
                         (alternate, asciify(&str_buf)),
                         (alternate, asciify(demangled))
                     );
+
+                    // Without the overflow margin, the output (plus its NUL
+                    // terminator) has to fit exactly.
+                    let demangled_len = demangled.len();
+                    let mut exact_buf = [0u8; 4096];
+                    for (len, expected) in [(demangled_len + 1, 0), (demangled_len, 1)] {
+                        let status = unsafe {
+                            rustc_demangle_native_c::rust_demangle_display_demangle_exact(
+                                &demangle,
+                                exact_buf.as_mut_ptr().cast(),
+                                len,
+                                alternate,
+                            )
+                        };
+                        assert_eq!((alternate, len, status), (alternate, len, expected));
+                    }
+                    assert_eq!(&exact_buf[..demangled_len + 1], &buf[..demangled_len + 1]);
                 }
             }
         }
@@ -0,0 +1,97 @@
+//! Colored output for terminals, using ANSI escape codes.
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use super::tokens::Tokens;
+use super::{Demangle, TokenKind};
+
+/// Wrapper for printing a symbol colored with ANSI escape codes, e.g. for
+/// terminals, see `Demangle::ansi_colored`.
+#[derive(Copy, Clone, Debug)]
+pub struct AnsiColored<'a, 'b> {
+    sym: &'b Demangle<'a>,
+}
+
+impl<'a, 'b> AnsiColored<'a, 'b> {
+    pub(crate) fn new(sym: &'b Demangle<'a>) -> AnsiColored<'a, 'b> {
+        AnsiColored { sym }
+    }
+}
+
+impl<'a, 'b> fmt::Display for AnsiColored<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = Cell::new(TokenKind::Other);
+        let alternate = f.alternate();
+        let mut writer = AnsiWriter {
+            inner: f,
+            kind: &kind,
+            current: TokenKind::Other,
+        };
+        let tokens = Tokens::new(self.sym, &kind);
+        if alternate {
+            write!(writer, "{:#}", tokens)?;
+        } else {
+            write!(writer, "{}", tokens)?;
+        }
+        writer.set_color(TokenKind::Other)
+    }
+}
+
+/// The SGR parameters used for each kind of token (if any).
+fn color(kind: TokenKind) -> Option<&'static str> {
+    match kind {
+        // Cyan.
+        TokenKind::Crate => Some("36"),
+        // Yellow.
+        TokenKind::PathSegment => Some("33"),
+        // Dim.
+        TokenKind::GenericOpen | TokenKind::GenericClose => Some("2"),
+        // Magenta.
+        TokenKind::Lifetime => Some("35"),
+        // Green.
+        TokenKind::Const => Some("32"),
+        // Blue.
+        TokenKind::Keyword | TokenKind::PrimitiveType => Some("34"),
+        // Bold red.
+        TokenKind::Error => Some("1;31"),
+        TokenKind::Other => None,
+    }
+}
+
+/// `fmt::Write` adapter switching colors whenever the kind of the token being
+/// printed (kept up to date in `kind` by the printer) changes.
+struct AnsiWriter<'a, W> {
+    inner: W,
+    kind: &'a Cell<TokenKind>,
+    /// The kind of token the current color is for.
+    current: TokenKind,
+}
+
+impl<'a, W: Write> AnsiWriter<'a, W> {
+    fn set_color(&mut self, kind: TokenKind) -> fmt::Result {
+        if color(kind) == color(self.current) {
+            return Ok(());
+        }
+        if color(self.current).is_some() {
+            self.inner.write_str("\x1b[0m")?;
+        }
+        if let Some(color) = color(kind) {
+            self.inner.write_str("\x1b[")?;
+            self.inner.write_str(color)?;
+            self.inner.write_str("m")?;
+        }
+        self.current = kind;
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for AnsiWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        self.set_color(self.kind.get())?;
+        self.inner.write_str(s)
+    }
+}
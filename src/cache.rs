@@ -0,0 +1,305 @@
+//! In-memory, bounded memoization of demangled symbols, for long-running
+//! symbolication services, which see the same (hot) frames over and over.
+//!
+//! The cache is a classic LRU: a hash map from mangled symbols to entries,
+//! which are also kept in a doubly-linked list (through indices into a
+//! `Vec`), from the most recently used to the least recently used one, which
+//! is the one evicted once the cache is full.
+
+use std::collections::HashMap;
+use std::string::String;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use super::try_demangle;
+
+// Index of a missing entry, at either end of the list.
+const NIL: usize = usize::MAX;
+
+/// LRU cache of demangled symbols, keyed by the mangled symbols.
+///
+/// ```
+/// use rustc_demangle::DemangleCache;
+///
+/// let mut cache = DemangleCache::new(1024).include_hash(false);
+/// assert_eq!(cache.get("_ZN3foo17h05af221e174051e9E").as_deref(), Some("foo"));
+/// assert_eq!(cache.get("main"), None);
+/// assert_eq!(cache.len(), 2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DemangleCache {
+    capacity: usize,
+    include_hash: bool,
+    interning: bool,
+    map: HashMap<Arc<str>, usize>,
+    entries: Vec<Entry>,
+    /// The most recently used entry, or `NIL` if the cache is empty.
+    head: usize,
+    /// The least recently used entry, or `NIL` if the cache is empty.
+    tail: usize,
+    /// Demangled symbols shared between entries, along with the number of
+    /// entries sharing each of them, see `DemangleCache::interning`.
+    interned: HashMap<Arc<str>, usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    mangled: Arc<str>,
+    /// `None` if `mangled` isn't a Rust symbol.
+    demangled: Option<Arc<str>>,
+    prev: usize,
+    next: usize,
+}
+
+impl DemangleCache {
+    /// Creates an empty cache, holding up to `capacity` symbols (nothing is
+    /// cached with a `capacity` of `0`), storing demangled symbols with their
+    /// hashes (`{}` formatting), and without interning them.
+    pub fn new(capacity: usize) -> DemangleCache {
+        DemangleCache {
+            capacity,
+            include_hash: true,
+            interning: false,
+            map: HashMap::new(),
+            entries: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            interned: HashMap::new(),
+        }
+    }
+
+    /// Whether to store demangled symbols with their hashes, i.e. whether
+    /// they're formatted with `{}` (the default) or `{:#}`.
+    ///
+    /// This should be set before using the cache, as it doesn't affect the
+    /// symbols already in it.
+    pub fn include_hash(mut self, include_hash: bool) -> DemangleCache {
+        self.include_hash = include_hash;
+        self
+    }
+
+    /// Whether to share a single allocation between all the cached symbols
+    /// which demangle the same (`false` by default), e.g. the copies of a
+    /// function from different builds, if hashes aren't included, at the
+    /// cost of an extra lookup for every newly cached symbol.
+    ///
+    /// ```
+    /// use rustc_demangle::DemangleCache;
+    /// use std::sync::Arc;
+    ///
+    /// let mut cache = DemangleCache::new(1024).include_hash(false).interning(true);
+    /// let a = cache.get("_ZN3foo17h05af221e174051e9E").unwrap();
+    /// let b = cache.get("_ZN3foo17h1bc3a6b0a0a2e5f5E").unwrap();
+    /// assert!(Arc::ptr_eq(&a, &b));
+    /// ```
+    pub fn interning(mut self, interning: bool) -> DemangleCache {
+        self.interning = interning;
+        self
+    }
+
+    /// Returns the demangling of `mangled`, or `None` if it's not a Rust
+    /// symbol (see `try_demangle`), demangling it only if it's not already
+    /// in the cache, in which case the least recently used symbol is evicted
+    /// if the cache is full.
+    pub fn get(&mut self, mangled: &str) -> Option<Arc<str>> {
+        if let Some(&i) = self.map.get(mangled) {
+            self.unlink(i);
+            self.push_front(i);
+            return self.entries[i].demangled.clone();
+        }
+
+        let demangled = try_demangle(mangled).ok().map(|sym| {
+            if self.include_hash {
+                format!("{}", sym)
+            } else {
+                format!("{:#}", sym)
+            }
+        });
+        // Nothing is cached (and would ever release the interned string).
+        if self.capacity == 0 {
+            return demangled.map(Arc::from);
+        }
+        let demangled = demangled.map(|demangled| self.intern(demangled));
+
+        let entry = Entry {
+            mangled: mangled.into(),
+            demangled: demangled.clone(),
+            prev: NIL,
+            next: NIL,
+        };
+        let i = if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+            self.entries.len() - 1
+        } else {
+            let i = self.tail;
+            self.unlink(i);
+            let evicted = std::mem::replace(&mut self.entries[i], entry);
+            self.map.remove(&evicted.mangled);
+            if let Some(demangled) = evicted.demangled {
+                self.release(&demangled);
+            }
+            i
+        };
+        self.map.insert(self.entries[i].mangled.clone(), i);
+        self.push_front(i);
+        demangled
+    }
+
+    /// Returns the number of symbols in the cache (including the ones which
+    /// aren't Rust symbols).
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the maximum number of symbols in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Removes all the symbols from the cache.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.interned.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    fn intern(&mut self, demangled: String) -> Arc<str> {
+        if !self.interning {
+            return demangled.into();
+        }
+        if let Some((shared, count)) = self.interned.get_key_value(&*demangled) {
+            let shared = shared.clone();
+            let count = *count;
+            self.interned.insert(shared.clone(), count + 1);
+            return shared;
+        }
+        let shared: Arc<str> = demangled.into();
+        self.interned.insert(shared.clone(), 1);
+        shared
+    }
+
+    /// Undoes `intern`, once an entry sharing `demangled` is evicted.
+    fn release(&mut self, demangled: &str) {
+        if !self.interning {
+            return;
+        }
+        let remaining = match self.interned.get_mut(demangled) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.interned.remove(demangled);
+        }
+    }
+
+    /// Removes entry `i` from the list (but not from `entries` or `map`).
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.entries[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.entries[next].prev = prev,
+        }
+    }
+
+    /// Adds entry `i` to the list, as the most recently used one.
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].prev = NIL;
+        self.entries[i].next = self.head;
+        match self.head {
+            NIL => self.tail = i,
+            head => self.entries[head].prev = i,
+        }
+        self.head = i;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+    use std::sync::Arc;
+
+    use super::DemangleCache;
+
+    #[test]
+    fn lru() {
+        let mut cache = DemangleCache::new(2);
+        assert_eq!(cache.get("_ZN3fooE").as_deref(), Some("foo"));
+        assert_eq!(cache.get("_ZN3barE").as_deref(), Some("bar"));
+        // `foo` is now the most recently used, so `bar` gets evicted.
+        let foo = cache.get("_ZN3fooE").unwrap();
+        assert_eq!(cache.get("main"), None);
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&foo, &cache.get("_ZN3fooE").unwrap()));
+        let bar = cache.get("_ZN3barE").unwrap();
+        assert_eq!(&*bar, "bar");
+        // And now `main` was the least recently used.
+        assert!(Arc::ptr_eq(&foo, &cache.get("_ZN3fooE").unwrap()));
+        assert!(Arc::ptr_eq(&bar, &cache.get("_ZN3barE").unwrap()));
+        assert_eq!(cache.len(), 2);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get("_ZN3fooE").as_deref(), Some("foo"));
+        assert_eq!(cache.capacity(), 2);
+    }
+
+    #[test]
+    fn include_hash() {
+        let sym = "_ZN3foo17h05af221e174051e9E";
+        let mut cache = DemangleCache::new(8);
+        assert_eq!(cache.get(sym).as_deref(), Some("foo::h05af221e174051e9"));
+        let mut cache = DemangleCache::new(8).include_hash(false);
+        assert_eq!(cache.get(sym).as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = DemangleCache::new(0);
+        assert_eq!(cache.get("_ZN3fooE").as_deref(), Some("foo"));
+        assert_eq!(cache.get("main"), None);
+        assert!(cache.is_empty());
+
+        // Nothing is interned either.
+        let mut cache = DemangleCache::new(0).interning(true);
+        assert_eq!(cache.get("_ZN3fooE").as_deref(), Some("foo"));
+        assert_eq!(cache.get("_ZN3barE").as_deref(), Some("bar"));
+        assert!(cache.interned.is_empty());
+    }
+
+    #[test]
+    fn interning() {
+        let mut cache = DemangleCache::new(2).include_hash(false).interning(true);
+        let a = cache.get("_ZN3foo17h05af221e174051e9E").unwrap();
+        let b = cache.get("_RCs1234_3foo").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(cache.interned.len(), 1);
+
+        // Evicting one of the two entries keeps the shared string around.
+        cache.get("_ZN3barE");
+        assert_eq!(cache.interned.len(), 2);
+        cache.get("_RCs1234_3foo");
+        let c = cache.get("_ZN3foo17h1bc3a6b0a0a2e5f5E").unwrap();
+        assert!(Arc::ptr_eq(&a, &c));
+        assert_eq!(cache.interned.len(), 1);
+
+        // Evicting both entries drops it.
+        cache.get("_ZN3bazE");
+        cache.get("_ZN3quxE");
+        assert_eq!(cache.interned.len(), 2);
+        assert!(!cache.interned.contains_key("foo"));
+    }
+}
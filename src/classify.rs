@@ -0,0 +1,167 @@
+//! Cheap, shape-based classification of symbols from various languages,
+//! for routing them to the right demangler.
+
+/// The mangling scheme a symbol appears to use, see `classify_symbol`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolClass {
+    /// Rust's legacy mangling, e.g. `_ZN3foo3bar17h05af221e174051e9E`.
+    RustLegacy,
+    /// Rust's v0 mangling, e.g. `_RNvCs1234_3foo3bar`.
+    RustV0,
+    /// The Itanium C++ ABI mangling, e.g. `_ZN3foo3barEv`.
+    ItaniumCpp,
+    /// Microsoft Visual C++ mangling, e.g. `?bar@foo@@YAXXZ`.
+    Msvc,
+    /// Swift mangling, e.g. `$s3foo3barSiyF`.
+    Swift,
+    /// Go symbol names, e.g. `main.main` or `github.com/foo/bar.(*T).Baz`.
+    Golang,
+    /// Anything else, e.g. C symbols.
+    Unknown,
+}
+
+/// Guesses the mangling scheme of `sym` from its shape alone, without
+/// demangling it, so it's only a heuristic: e.g. a Rust symbol classified
+/// as such may still fail to demangle, and a C symbol could happen to look
+/// like a Go one.
+///
+/// ```
+/// use rustc_demangle::{classify_symbol, SymbolClass};
+///
+/// assert_eq!(classify_symbol("_ZN3foo17h05af221e174051e9E"), SymbolClass::RustLegacy);
+/// assert_eq!(classify_symbol("_RNvC3foo3bar"), SymbolClass::RustV0);
+/// assert_eq!(classify_symbol("_ZN3foo3barEv"), SymbolClass::ItaniumCpp);
+/// assert_eq!(classify_symbol("?bar@foo@@YAXXZ"), SymbolClass::Msvc);
+/// assert_eq!(classify_symbol("$s3foo3barSiyF"), SymbolClass::Swift);
+/// assert_eq!(classify_symbol("runtime.gopanic"), SymbolClass::Golang);
+/// assert_eq!(classify_symbol("malloc"), SymbolClass::Unknown);
+/// ```
+pub fn classify_symbol(sym: &str) -> SymbolClass {
+    // Mach-O adds an extra leading `_` to all symbols.
+    let unprefixed = if sym.starts_with("__") {
+        &sym[1..]
+    } else {
+        sym
+    };
+
+    if is_rust_v0(sym) {
+        return SymbolClass::RustV0;
+    }
+    if unprefixed.starts_with("_Z") || unprefixed.starts_with("ZN") {
+        return if is_rust_legacy(unprefixed) {
+            SymbolClass::RustLegacy
+        } else {
+            SymbolClass::ItaniumCpp
+        };
+    }
+    if sym.starts_with('?') {
+        return SymbolClass::Msvc;
+    }
+    // Swift 5+, Swift 4.2 and Swift 4.0 manglings, with and without the
+    // extra Mach-O `_` (and `$e` for Embedded Swift).
+    for prefix in &["$s", "$S", "$e", "_T0", "_$s", "_$S", "_$e", "__T0"] {
+        if sym.starts_with(prefix) {
+            return SymbolClass::Swift;
+        }
+    }
+    if is_golang(sym) {
+        return SymbolClass::Golang;
+    }
+    SymbolClass::Unknown
+}
+
+// Same prefixes as accepted by `v0::demangle`, followed by the (uppercase)
+// tag of the path.
+fn is_rust_v0(sym: &str) -> bool {
+    match sym.as_bytes() {
+        [b'_', b'R', tag, ..] | [b'R', tag, ..] | [b'_', b'_', b'R', tag, ..] => {
+            tag.is_ascii_uppercase()
+        }
+        _ => false,
+    }
+}
+
+// Legacy Rust symbols are Itanium-like paths ending in a `h` + 16 hex digits
+// hash element (`17h...E`), optionally followed by a `.`-separated suffix.
+fn is_rust_legacy(sym: &str) -> bool {
+    let sym = match sym.find('.') {
+        Some(i) => &sym[..i],
+        None => sym,
+    };
+    if !sym.ends_with('E') || sym.len() < 20 {
+        return false;
+    }
+    let hash = &sym[sym.len() - 20..sym.len() - 1];
+    hash.starts_with("17h") && hash[3..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// Go symbols are `import/path.Name`, with methods as `path.(*Type).Name`,
+// and a few compiler-generated ones like `go:buildid` or `type:*T`.
+fn is_golang(sym: &str) -> bool {
+    for prefix in &["go:", "go.", "type:", "type.."] {
+        if sym.starts_with(prefix) {
+            return true;
+        }
+    }
+
+    let last_slash = sym.rfind('/').map_or(0, |i| i + 1);
+    let dot = match sym[last_slash..].find('.') {
+        Some(i) => last_slash + i,
+        None => return false,
+    };
+    let (package, name) = (&sym[..dot], &sym[dot + 1..]);
+    let valid_package = !package.is_empty()
+        && !package.starts_with(&['_', '.', '/'][..])
+        && package
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '.' | '/' | '-'));
+    if !valid_package {
+        return false;
+    }
+    // Avoid mistaking e.g. C symbols with a GCC suffix (`foo.part.0`) for Go
+    // ones, which usually either have an import path, or an exported name.
+    package.contains('/')
+        || package == "main"
+        || package == "runtime"
+        || name.starts_with(|c: char| c.is_ascii_uppercase() || c == '(')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_symbol, SymbolClass};
+
+    #[test]
+    fn classify() {
+        for &(sym, class) in &[
+            ("_ZN3foo17h05af221e174051e9E", SymbolClass::RustLegacy),
+            ("__ZN3foo17h05af221e174051e9E", SymbolClass::RustLegacy),
+            ("ZN3foo17h05af221e174051e9E", SymbolClass::RustLegacy),
+            (
+                "_ZN3foo17h05af221e174051e9E.llvm.1234",
+                SymbolClass::RustLegacy,
+            ),
+            ("_RNvC3foo3bar", SymbolClass::RustV0),
+            ("RNvC3foo3bar", SymbolClass::RustV0),
+            ("__RNvC3foo3bar", SymbolClass::RustV0),
+            ("_ZN3foo3barE", SymbolClass::ItaniumCpp),
+            ("_ZN3foo17h05af221e174051e9Ev", SymbolClass::ItaniumCpp),
+            ("__Z3foov", SymbolClass::ItaniumCpp),
+            ("?bar@foo@@YAXXZ", SymbolClass::Msvc),
+            ("$s3foo3barSiyF", SymbolClass::Swift),
+            ("_$s3foo3barSiyF", SymbolClass::Swift),
+            ("_T0s3fooSiyF", SymbolClass::Swift),
+            ("main.main", SymbolClass::Golang),
+            ("github.com/foo/bar.(*T).Baz", SymbolClass::Golang),
+            ("net/http.ListenAndServe", SymbolClass::Golang),
+            ("go:buildid", SymbolClass::Golang),
+            ("type:*main.T", SymbolClass::Golang),
+            ("malloc", SymbolClass::Unknown),
+            ("foo.part.0", SymbolClass::Unknown),
+            ("Rust", SymbolClass::Unknown),
+            ("_R", SymbolClass::Unknown),
+            ("", SymbolClass::Unknown),
+        ] {
+            assert_eq!((sym, classify_symbol(sym)), (sym, class));
+        }
+    }
+}
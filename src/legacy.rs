@@ -1,5 +1,8 @@
 use core::char;
 use core::fmt;
+use core::str;
+
+use {DemangleOptions, PathElision};
 
 /// Representation of a demangled symbol name.
 pub struct Demangle<'a> {
@@ -46,7 +49,10 @@ pub struct Demangle<'a> {
 // Note that this demangler isn't quite as fancy as it could be. We have lots
 // of other information in our symbols like hashes, version, type information,
 // etc. Additionally, this doesn't handle glue symbols at all.
-pub fn demangle(s: &str) -> Result<(Demangle, &str), ()> {
+//
+// On error, this returns the offset (in `s`) at which the error was found,
+// which is `0` if `s` doesn't start with `_ZN` (or one of its variants).
+pub fn demangle(s: &str) -> Result<(Demangle, &str), usize> {
     // First validate the symbol. If it doesn't look like anything we're
     // expecting, we just print it literally. Note that we must handle non-Rust
     // symbols because we could have any function in the backtrace.
@@ -60,41 +66,49 @@ pub fn demangle(s: &str) -> Result<(Demangle, &str), ()> {
         // On OSX, symbols are prefixed with an extra _
         &s[4..]
     } else {
-        return Err(());
+        return Err(0);
     };
 
-    // only work with ascii text
-    if inner.bytes().any(|c| c & 0x80 != 0) {
-        return Err(());
-    }
+    // only work with ascii text (anything after it can only be trailing data)
+    let prefix_len = s.len() - inner.len();
+    let inner = match inner.bytes().position(|c| c & 0x80 != 0) {
+        Some(i) => &inner[..i],
+        None => inner,
+    };
+
+    // Error offsets, for running out of characters, and for the last one taken
+    // from `chars` (i.e. `c`, which is ASCII, and so only one byte long).
+    let end = prefix_len + inner.len();
+    let offset_of_c = |chars: &str::Chars| end - chars.as_str().len() - 1;
 
     let mut elements = 0;
     let mut chars = inner.chars();
-    let mut c = chars.next().ok_or(())?;
+    let mut c = chars.next().ok_or(end)?;
     while c != 'E' {
         // Decode an identifier element's length.
         if !c.is_digit(10) {
-            return Err(());
+            return Err(offset_of_c(&chars));
         }
         let mut len = 0usize;
         while let Some(d) = c.to_digit(10) {
             len = len
                 .checked_mul(10)
                 .and_then(|len| len.checked_add(d as usize))
-                .ok_or(())?;
-            c = chars.next().ok_or(())?;
+                .ok_or_else(|| offset_of_c(&chars))?;
+            c = chars.next().ok_or(end)?;
         }
 
         // `c` already contains the first character of this identifier, skip it and
         // all the other characters of this identifier, to reach the next element.
         for _ in 0..len {
-            c = chars.next().ok_or(())?;
+            c = chars.next().ok_or(end)?;
         }
 
         elements += 1;
     }
 
-    Ok((Demangle { inner, elements }, chars.as_str()))
+    let consumed = inner.len() - chars.as_str().len();
+    Ok((Demangle { inner, elements }, &s[prefix_len + consumed..]))
 }
 
 // Rust hashes are hex digits with an `h` prepended.
@@ -102,90 +116,254 @@ fn is_rust_hash(s: &str) -> bool {
     s.starts_with('h') && s[1..].chars().all(|c| c.is_digit(16))
 }
 
-impl<'a> fmt::Display for Demangle<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Alright, let's do this.
+// Some older or patched toolchains emitted 64-bit hashes without the `h`.
+fn is_unprefixed_rust_hash(s: &str) -> bool {
+    s.len() == 16 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl<'a> Demangle<'a> {
+    /// Returns the first path element, or, for `impl`s (which legacy symbols
+    /// print as e.g. `<foo::Bar as baz::Trait>`), the first element of their
+    /// self type, if it's a path (e.g. `foo`).
+    pub fn crate_name(&self) -> Option<&'a str> {
+        let first = self.elements().next()?;
+        // `impl`s start with `<` (escaped, and possibly prefixed with `_`).
+        let path = match first.find("$LT$") {
+            Some(0) => &first[4..],
+            Some(1) if first.starts_with('_') => &first[5..],
+            _ => return Some(first),
+        };
+        let name = &path[..path.find("..")?];
+        if !name.is_empty() && name.bytes().all(|b| b == b'_' || b.is_ascii_alphanumeric()) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn fmt(&self, f: &mut fmt::Formatter, options: &DemangleOptions) -> fmt::Result {
+        let alternate = f.alternate();
+        if options.generics && !options.collapsed_generics {
+            self.fmt_to(f, options, alternate)
+        } else {
+            self.fmt_to(
+                &mut StripGenerics {
+                    inner: f,
+                    depth: 0,
+                    prev: '\0',
+                    collapse: options.generics,
+                },
+                options,
+                alternate,
+            )
+        }
+    }
+
+    /// Like `fmt`, but only prints the last path element (or the one before
+    /// it, if the last one is a hidden hash), see `Demangle::function_name`
+    /// in the crate root.
+    pub fn fmt_last_segment(
+        &self,
+        f: &mut fmt::Formatter,
+        options: &DemangleOptions,
+    ) -> fmt::Result {
+        let hide_hash = !options.shows_hash(f.alternate());
+        let mut elements = self.elements();
+        let mut last = elements.next();
+        let mut prev = None;
+        for element in elements {
+            prev = last;
+            last = Some(element);
+        }
+        let last = match (prev, last) {
+            (Some(prev), Some(last)) if hide_hash && is_hidden_hash(last, options) => prev,
+            (_, Some(last)) => last,
+            (_, None) => return Ok(()),
+        };
+        if options.generics {
+            fmt_element(f, last)
+        } else {
+            fmt_element(
+                &mut StripGenerics {
+                    inner: f,
+                    depth: 0,
+                    prev: '\0',
+                    collapse: false,
+                },
+                last,
+            )
+        }
+    }
+
+    /// Returns the (still escaped) path elements.
+    fn elements(&self) -> impl Iterator<Item = &'a str> {
         let mut inner = self.inner;
-        for element in 0..self.elements {
+        (0..self.elements).map(move |_| {
             let mut rest = inner;
             while rest.chars().next().unwrap().is_digit(10) {
                 rest = &rest[1..];
             }
             let i: usize = inner[..(inner.len() - rest.len())].parse().unwrap();
             inner = &rest[i..];
-            rest = &rest[..i];
-            // Skip printing the hash if alternate formatting
-            // was requested.
-            if f.alternate() && element + 1 == self.elements && is_rust_hash(&rest) {
+            &rest[..i]
+        })
+    }
+
+    fn fmt_to<W: fmt::Write>(
+        &self,
+        f: &mut W,
+        options: &DemangleOptions,
+        alternate: bool,
+    ) -> fmt::Result {
+        let hide_hash = !options.shows_hash(alternate);
+        let elided = match options.path_elision {
+            PathElision::Full => 0..0,
+            elision => {
+                let ends_in_hash = match self.elements().last() {
+                    Some(last) => is_hidden_hash(last, options),
+                    None => false,
+                };
+                elision.elided(self.elements - ends_in_hash as usize)
+            }
+        };
+        // Alright, let's do this.
+        for (element, rest) in self.elements().enumerate() {
+            // Skip printing the hash if requested (which is the default
+            // with alternate formatting).
+            if hide_hash && element + 1 == self.elements && is_hidden_hash(rest, options) {
                 break;
             }
+            if elided.contains(&element) {
+                if element == elided.start {
+                    if element != 0 {
+                        f.write_str("::")?;
+                    }
+                    f.write_str("…")?;
+                }
+                continue;
+            }
             if element != 0 {
                 f.write_str("::")?;
             }
-            if rest.starts_with("_$") {
+            fmt_element(f, rest)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Whether `element` is a hash, which isn't printed unless requested.
+fn is_hidden_hash(element: &str, options: &DemangleOptions) -> bool {
+    is_rust_hash(element) || options.unprefixed_hashes && is_unprefixed_rust_hash(element)
+}
+
+// Prints a single path element, unescaping it.
+fn fmt_element<W: fmt::Write>(f: &mut W, mut rest: &str) -> fmt::Result {
+    if rest.starts_with("_$") {
+        rest = &rest[1..];
+    }
+    loop {
+        if rest.starts_with('.') {
+            if let Some('.') = rest[1..].chars().next() {
+                f.write_str("::")?;
+                rest = &rest[2..];
+            } else {
+                f.write_str(".")?;
                 rest = &rest[1..];
             }
-            loop {
-                if rest.starts_with('.') {
-                    if let Some('.') = rest[1..].chars().next() {
-                        f.write_str("::")?;
-                        rest = &rest[2..];
-                    } else {
-                        f.write_str(".")?;
-                        rest = &rest[1..];
-                    }
-                } else if rest.starts_with('$') {
-                    let (escape, after_escape) = if let Some(end) = rest[1..].find('$') {
-                        (&rest[1..=end], &rest[end + 2..])
-                    } else {
-                        break;
-                    };
-
-                    // see src/librustc_codegen_utils/symbol_names/legacy.rs for these mappings
-                    let unescaped = match escape {
-                        "SP" => "@",
-                        "BP" => "*",
-                        "RF" => "&",
-                        "LT" => "<",
-                        "GT" => ">",
-                        "LP" => "(",
-                        "RP" => ")",
-                        "C" => ",",
-
-                        _ => {
-                            if escape.starts_with('u') {
-                                let digits = &escape[1..];
-                                let all_lower_hex = digits.chars().all(|c| match c {
-                                    '0'..='9' | 'a'..='f' => true,
-                                    _ => false,
-                                });
-                                let c = u32::from_str_radix(digits, 16)
-                                    .ok()
-                                    .and_then(char::from_u32);
-                                if let (true, Some(c)) = (all_lower_hex, c) {
-                                    // FIXME(eddyb) do we need to filter out control codepoints?
-                                    if !c.is_control() {
-                                        c.fmt(f)?;
-                                        rest = after_escape;
-                                        continue;
-                                    }
-                                }
+        } else if rest.starts_with('$') {
+            let (escape, after_escape) = if let Some(end) = rest[1..].find('$') {
+                (&rest[1..=end], &rest[end + 2..])
+            } else {
+                break;
+            };
+
+            // see src/librustc_codegen_utils/symbol_names/legacy.rs for these mappings
+            let unescaped = match escape {
+                "SP" => "@",
+                "BP" => "*",
+                "RF" => "&",
+                "LT" => "<",
+                "GT" => ">",
+                "LP" => "(",
+                "RP" => ")",
+                "C" => ",",
+
+                _ => {
+                    if escape.starts_with('u') {
+                        let digits = &escape[1..];
+                        let all_lower_hex = digits.chars().all(|c| match c {
+                            '0'..='9' | 'a'..='f' => true,
+                            _ => false,
+                        });
+                        let c = u32::from_str_radix(digits, 16)
+                            .ok()
+                            .and_then(char::from_u32);
+                        if let (true, Some(c)) = (all_lower_hex, c) {
+                            // FIXME(eddyb) do we need to filter out control codepoints?
+                            if !c.is_control() {
+                                f.write_char(c)?;
+                                rest = after_escape;
+                                continue;
                             }
-                            break;
                         }
-                    };
-                    f.write_str(unescaped)?;
-                    rest = after_escape;
-                } else if let Some(i) = rest.find(|c| c == '$' || c == '.') {
-                    f.write_str(&rest[..i])?;
-                    rest = &rest[i..];
-                } else {
+                    }
                     break;
                 }
-            }
-            f.write_str(rest)?;
+            };
+            f.write_str(unescaped)?;
+            rest = after_escape;
+        } else if let Some(i) = rest.find(|c| c == '$' || c == '.') {
+            f.write_str(&rest[..i])?;
+            rest = &rest[i..];
+        } else {
+            break;
         }
+    }
+    f.write_str(rest)
+}
+
+/// `fmt::Write` adapter dropping generic arguments (i.e. `<...>` directly
+/// following a name, as opposed to e.g. the `<` starting a qualified path).
+struct StripGenerics<W> {
+    inner: W,
+    /// How many `<` are currently open in the dropped generic arguments.
+    depth: usize,
+    /// The last character written (or dropped).
+    prev: char,
+    /// Whether to replace the generic arguments with `<…>`, instead of
+    /// dropping them entirely.
+    collapse: bool,
+}
 
+impl<W: fmt::Write> fmt::Write for StripGenerics<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            if self.depth > 0 {
+                match c {
+                    '<' => self.depth += 1,
+                    // Don't get confused by the `>` in `->`.
+                    '>' if self.prev != '-' => {
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            start = i + 1;
+                        }
+                    }
+                    _ => {}
+                }
+            } else if c == '<' && (self.prev.is_alphanumeric() || self.prev == '_') {
+                self.inner.write_str(&s[start..i])?;
+                if self.collapse {
+                    self.inner.write_str("<…>")?;
+                }
+                self.depth = 1;
+            }
+            self.prev = c;
+        }
+        if self.depth == 0 {
+            self.inner.write_str(&s[start..])?;
+        }
         Ok(())
     }
 }
@@ -319,6 +497,30 @@ mod tests {
         t_nohash!("_ZN3foo17hg5af221e174051e9E", "foo::hg5af221e174051e9");
     }
 
+    #[test]
+    fn demangle_unprefixed_hashes() {
+        let t = |s| {
+            let options = ::DemangleOptions::new().unprefixed_hashes(true);
+            format!("{:#}", ::demangle(s).with_options(options))
+        };
+        assert_eq!(t("_ZN3foo16ffaf221e174051e9E"), "foo");
+        assert_eq!(t("_ZN3foo17h05af221e174051e9E"), "foo");
+        // Only exactly 16 hex digits are treated as a hash.
+        assert_eq!(t("_ZN3foo15faf221e174051e9E"), "foo::faf221e174051e9");
+        assert_eq!(t("_ZN3foo16gfaf221e174051e9E"), "foo::gfaf221e174051e9");
+        // Not at the end.
+        assert_eq!(t("_ZN16ffaf221e174051e93fooE"), "ffaf221e174051e9::foo");
+        // Still shown without alternate formatting.
+        let options = ::DemangleOptions::new().unprefixed_hashes(true);
+        assert_eq!(
+            format!(
+                "{}",
+                ::demangle("_ZN3foo16ffaf221e174051e9E").with_options(options)
+            ),
+            "foo::ffaf221e174051e9"
+        );
+    }
+
     #[test]
     fn demangle_thinlto() {
         // One element, no hash.
@@ -22,6 +22,17 @@
 //! // Without hash
 //! assert_eq!(format!("{:#}", demangle("_ZN3foo17h05af221e174051e9E")), "foo");
 //! ```
+//!
+//! # Compatibility with rustc
+//!
+//! rustc's own `symbol-names` UI tests print their `demangling(...)` and
+//! `demangling-alt(...)` lines with this crate, using `{}` and `{:#}`
+//! respectively. Those two formats (which are also what the default
+//! `DemangleOptions` produce) are therefore kept bit-exact, including e.g.
+//! the formatting of v0 crate disambiguators (`foo[317d481089b8c8fe]`) and
+//! of compiler-generated items (`{closure#0}`, `{shim:vtable#0}`), and
+//! changes to them are treated as breaking, so e.g. more descriptive output
+//! (like `DemangleOptions::cyclic_backrefs`) has to be opted into.
 
 #![no_std]
 #![deny(missing_docs)]
@@ -31,6 +42,14 @@
 #[macro_use]
 extern crate std;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
 // HACK(eddyb) helper macros for tests.
 #[cfg(test)]
 macro_rules! assert_contains {
@@ -57,16 +76,955 @@ macro_rules! assert_ends_with {
     }};
 }
 
+#[cfg(feature = "ansi")]
+mod ansi;
+#[cfg(feature = "std")]
+mod cache;
+mod classify;
 mod legacy;
+#[cfg(feature = "alloc")]
+mod pretty;
+mod punycode;
+mod runtime;
+#[cfg(feature = "std")]
+mod sort_key;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "stress")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stress")))]
+pub mod stress;
+mod tokens;
 mod v0;
 
+#[cfg(feature = "ansi")]
+pub use ansi::AnsiColored;
+#[cfg(feature = "std")]
+pub use cache::DemangleCache;
+pub use classify::{classify_symbol, SymbolClass};
+#[cfg(feature = "alloc")]
+pub use pretty::Pretty;
+pub use punycode::{decode_punycode, PunycodeError};
+pub use runtime::runtime_symbol_description;
+#[cfg(feature = "std")]
+pub use sort_key::SortKey;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use stream::Compression;
+#[cfg(feature = "std")]
+pub use stream::{
+    demangle_file, demangle_stream, demangle_stream_with_options, StreamOptions, StreamStats,
+};
+pub use tokens::TokenKind;
+
+use core::cell::Cell;
 use core::fmt::{self, Write as _};
+use core::hash::{Hash, Hasher};
+use core::str;
 
 /// Representation of a demangled symbol name.
 pub struct Demangle<'a> {
     style: Option<DemangleStyle<'a>>,
     original: &'a str,
+    /// A linker/assembler decoration (e.g. `__imp_`) found before the symbol,
+    /// see `DemangleOptions::linker_prefixes`.
+    prefix: &'a str,
     suffix: &'a str,
+    /// The whole input, i.e. `original` before stripping `.llvm.*` from it.
+    input: &'a str,
+    options: DemangleOptions,
+}
+
+/// Options controlling how a `Demangle` is printed, see `Demangle::with_options`.
+///
+/// Unless set explicitly, the parts of the output which are hidden by
+/// alternate formatting (`{:#}`) are shown or hidden according to it.
+///
+/// ```
+/// use rustc_demangle::{demangle, DemangleOptions};
+///
+/// let sym = demangle("_RINvCs1234_3foo3barKj7b_E");
+/// assert_eq!(format!("{:#}", sym), "foo::bar::<123>");
+///
+/// let options = DemangleOptions::new().const_type_suffixes(true);
+/// let sym = sym.with_options(options);
+/// assert_eq!(format!("{:#}", sym), "foo::bar::<123usize>");
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct DemangleOptions {
+    hash: Option<bool>,
+    generics: bool,
+    const_type_suffixes: Option<bool>,
+    const_values: bool,
+    const_hex: bool,
+    const_digit_separators: bool,
+    generic_placeholders: bool,
+    lifetime_names: LifetimeNames,
+    lifetimes: bool,
+    binders: Binders,
+    explicit_unit_returns: bool,
+    legacy_closures: bool,
+    shim_kinds: bool,
+    suffixes: SuffixPolicy,
+    unprefixed_hashes: bool,
+    undecodable_punycode: PunycodeFallback,
+    const_escapes: ConstEscapes,
+    raw_str_consts: bool,
+    crate_disambiguators: Option<bool>,
+    recursion_limit: u32,
+    path_disambiguators: bool,
+    instantiating_crate: bool,
+    impl_paths: bool,
+    linker_prefixes: bool,
+    max_output_bytes: Option<usize>,
+    runtime_symbols: bool,
+    path_elision: PathElision,
+    error_offsets: bool,
+    unknown_tags: bool,
+    cyclic_backrefs: bool,
+    unknown_namespaces: bool,
+    /// Whether to fail with `fmt::Error` instead of printing errors inline,
+    /// see `Demangle::write_checked`.
+    checked: bool,
+    /// Whether to print generic arguments as `<…>` (if `generics` is set),
+    /// see `DisplayBuilder::max_width`.
+    collapsed_generics: bool,
+}
+
+/// How to name the lifetimes introduced by `for<...>` binders in v0 symbols,
+/// see `DemangleOptions::lifetime_names`.
+#[derive(Copy, Clone, Debug)]
+pub enum LifetimeNames {
+    /// `'a`, `'b`, ..., `'z`, followed by `'_26`, `'_27`, etc. (the default).
+    Alphabetic,
+
+    /// `'1`, `'2`, etc., like rustc's diagnostics name the lifetimes which
+    /// can't be named otherwise (e.g. in borrow checking errors).
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions, LifetimeNames};
+    ///
+    /// let options = DemangleOptions::new().lifetime_names(LifetimeNames::Numeric);
+    /// let sym = demangle("_RINvC3foo3barFG0_RL1_hRL0_tEuE").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<for<'1, '2> fn(&'1 u8, &'2 u16)>");
+    /// ```
+    Numeric,
+
+    /// Print each name (after the leading `'`) with the given function, which
+    /// receives the index of the lifetime, counting from the outermost binder.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions, LifetimeNames};
+    ///
+    /// let options = DemangleOptions::new()
+    ///     .lifetime_names(LifetimeNames::Custom(|i, f| write!(f, "l{}", i)));
+    /// let sym = demangle("_RINvC3foo3barFG0_RL1_hRL0_tEuE").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<for<'l0, 'l1> fn(&'l0 u8, &'l1 u16)>");
+    /// ```
+    Custom(fn(u64, &mut fmt::Formatter) -> fmt::Result),
+}
+
+/// How to print the `for<...>` binders introducing lifetimes in v0 symbols
+/// (e.g. in `for<'a, 'b> fn(&'a u8, &'b u16)`), see `DemangleOptions::binders`.
+///
+/// ```
+/// use rustc_demangle::{demangle, Binders, DemangleOptions};
+///
+/// let sym = demangle("_RINvC3foo3barFG0_RL1_hEuE");
+/// assert_eq!(format!("{:#}", sym), "foo::bar::<for<'a, 'b> fn(&'a u8)>");
+///
+/// let collapsed = sym.with_options(DemangleOptions::new().binders(Binders::Collapsed));
+/// assert_eq!(format!("{:#}", collapsed), "foo::bar::<for<…> fn(&'a u8)>");
+///
+/// let sym = demangle("_RINvC3foo3barFG0_hEuE");
+/// let hidden = sym.with_options(DemangleOptions::new().binders(Binders::HideUnused));
+/// assert_eq!(format!("{:#}", hidden), "foo::bar::<fn(u8)>");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binders {
+    /// List every lifetime, e.g. `for<'a, 'b> ` (the default).
+    Full,
+
+    /// Replace the lifetimes with `…`, e.g. `for<…> `.
+    Collapsed,
+
+    /// Leave out binders whose lifetimes aren't used, only listing the
+    /// lifetimes of the others in full.
+    HideUnused,
+}
+
+/// Which parts of the suffix following a symbol (e.g. `.llvm.1234` or
+/// `.exit.i.i`) to print, see `DemangleOptions::suffixes`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SuffixPolicy {
+    /// Print the whole suffix (the default).
+    KeepAll,
+
+    /// Don't print the parts of the suffix which are known to only be
+    /// artifacts of compilation, i.e. `.llvm.*` (from ThinLTO), numeric ones
+    /// (e.g. the `.0.0` of promoted statics), and the ones added by function
+    /// splitting and cloning optimizations (`.cold`, and GCC's `.constprop`,
+    /// `.isra`, `.part` and `.lto_priv`, usually followed by a number), so
+    /// that the output doesn't change between otherwise identical builds.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions, SuffixPolicy};
+    ///
+    /// let options = DemangleOptions::new().suffixes(SuffixPolicy::StripKnown);
+    /// let sym = demangle("_ZN3foo3BAR17h05af221e174051e9E.0.0").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::BAR");
+    /// let sym = demangle("_ZN3foo3barE.isra.0.cold").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar");
+    /// ```
+    StripKnown,
+
+    /// Don't print the suffix at all.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions, SuffixPolicy};
+    ///
+    /// let options = DemangleOptions::new().suffixes(SuffixPolicy::StripAll);
+    /// let sym = demangle("_ZN3foo3barE.exit.i.i").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar");
+    /// ```
+    StripAll,
+}
+
+/// Which segments of paths to print, e.g. to fit symbols into the narrow
+/// frames of flamegraphs, see `DemangleOptions::path_elision`.
+///
+/// The segments which aren't printed (including any generic arguments they
+/// have) are replaced with a single `…`. This applies to every path in the
+/// symbol (e.g. also to the self type and trait of an `impl`), and the
+/// segments of a path don't include the hash of a legacy symbol.
+///
+/// ```
+/// use rustc_demangle::{demangle, DemangleOptions, PathElision};
+///
+/// let sym = "_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E";
+///
+/// let options = DemangleOptions::new().path_elision(PathElision::CrateAndLeaf);
+/// let short = demangle(sym).with_options(options);
+/// assert_eq!(format!("{:#}", short), "alloc::…::push");
+///
+/// let options = DemangleOptions::new().path_elision(PathElision::LastSegments(2));
+/// let short = demangle(sym).with_options(options);
+/// assert_eq!(format!("{:#}", short), "…::Vec<T>::push");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathElision {
+    /// Print every segment (the default).
+    Full,
+
+    /// Only print the first segment (usually the crate name) and the last
+    /// one, e.g. `alloc::…::push`.
+    CrateAndLeaf,
+
+    /// Only print the last `n` segments (or at least the last one), e.g.
+    /// `…::Vec<T>::push` for `n = 2`.
+    LastSegments(usize),
+}
+
+impl PathElision {
+    /// Returns the positions of the segments not to print (counted from the
+    /// first segment) in a path of `segments` segments.
+    fn elided(self, segments: usize) -> core::ops::Range<usize> {
+        match self {
+            PathElision::CrateAndLeaf if segments > 2 => 1..segments - 1,
+            PathElision::LastSegments(n) => 0..segments.saturating_sub(n.max(1)),
+            PathElision::Full | PathElision::CrateAndLeaf => 0..0,
+        }
+    }
+}
+
+/// How to print identifiers of v0 symbols whose punycode can't be decoded,
+/// either because it's malformed, or because it decodes to more characters
+/// than can be handled (128 without allocating, or 4096 with the `alloc`
+/// feature), see `DemangleOptions::undecodable_punycode`.
+///
+/// ```
+/// use rustc_demangle::{demangle, DemangleOptions, PunycodeFallback};
+///
+/// let sym = "_RNvC3foou5foo_9";
+/// assert_eq!(demangle(sym).to_string(), "foo::punycode{foo-9}");
+///
+/// let options = DemangleOptions::new().undecodable_punycode(PunycodeFallback::Raw);
+/// assert_eq!(demangle(sym).with_options(options).to_string(), "foo::foo_9");
+///
+/// let options = DemangleOptions::new().undecodable_punycode(PunycodeFallback::Escaped);
+/// assert_eq!(
+///     demangle(sym).with_options(options).to_string(),
+///     r"foo::\u{66}\u{6f}\u{6f}\u{2d}\u{39}"
+/// );
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PunycodeFallback {
+    /// `punycode{...}`, containing the standard Punycode encoding of the
+    /// identifier, i.e. with `-` after the ASCII characters (the default).
+    Placeholder,
+
+    /// The identifier as it appears in the symbol, i.e. with `_` after the
+    /// ASCII characters.
+    Raw,
+
+    /// The standard Punycode encoding of the identifier (like `Placeholder`),
+    /// but with every character written as a `\u{...}` escape, so that it
+    /// can't be mistaken for an actual identifier.
+    Escaped,
+
+    /// Treat such identifiers as malformed, so that `Demangle::write_checked`
+    /// fails on them. Otherwise, they're printed like with `Placeholder`.
+    Error,
+}
+
+/// Which characters of `char` and string constants in v0 symbols to escape,
+/// see `DemangleOptions::const_escapes`.
+///
+/// ```
+/// use rustc_demangle::{demangle, ConstEscapes, DemangleOptions};
+///
+/// let sym = "_RINvC3foo3barKc2202_Kca_E";
+/// assert_eq!(format!("{:#}", demangle(sym)), r"foo::bar::<'∂', '\n'>");
+///
+/// let options = DemangleOptions::new().const_escapes(ConstEscapes::Always);
+/// let escaped = demangle(sym).with_options(options);
+/// assert_eq!(format!("{:#}", escaped), r"foo::bar::<'\u{2202}', '\n'>");
+///
+/// let options = DemangleOptions::new().const_escapes(ConstEscapes::Never);
+/// let unescaped = demangle(sym).with_options(options);
+/// assert_eq!(format!("{:#}", unescaped), "foo::bar::<'∂', '\n'>");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConstEscapes {
+    /// Escape every character other than printable ASCII ones (using
+    /// `char::escape_default`), so that the output is pure ASCII.
+    Always,
+
+    /// Only escape the characters which aren't printable, as well as quotes
+    /// and backslashes, like `{:?}` does (using `char::escape_debug`, the
+    /// default).
+    NonPrintable,
+
+    /// Don't escape anything, printing every character as it is, even if
+    /// that's ambiguous (e.g. `'''`) or includes control characters.
+    Never,
+}
+
+impl DemangleOptions {
+    /// Creates the default options, which print symbols exactly like
+    /// `Demangle`'s `Display` implementation always has.
+    pub fn new() -> DemangleOptions {
+        DemangleOptions {
+            hash: None,
+            generics: true,
+            const_type_suffixes: None,
+            const_values: true,
+            const_hex: false,
+            const_digit_separators: false,
+            generic_placeholders: false,
+            lifetime_names: LifetimeNames::Alphabetic,
+            lifetimes: true,
+            binders: Binders::Full,
+            explicit_unit_returns: false,
+            legacy_closures: false,
+            shim_kinds: false,
+            suffixes: SuffixPolicy::KeepAll,
+            unprefixed_hashes: false,
+            undecodable_punycode: PunycodeFallback::Placeholder,
+            const_escapes: ConstEscapes::NonPrintable,
+            raw_str_consts: false,
+            crate_disambiguators: None,
+            recursion_limit: v0::MAX_DEPTH,
+            path_disambiguators: false,
+            instantiating_crate: false,
+            impl_paths: false,
+            linker_prefixes: true,
+            max_output_bytes: None,
+            runtime_symbols: false,
+            path_elision: PathElision::Full,
+            error_offsets: false,
+            unknown_tags: false,
+            cyclic_backrefs: false,
+            unknown_namespaces: false,
+            checked: false,
+            collapsed_generics: false,
+        }
+    }
+
+    /// Whether to print the hashes of legacy symbols, and the crate
+    /// disambiguators of v0 symbols (e.g. the `[1234]` in `foo[1234]::bar`),
+    /// unless overridden by `crate_disambiguators`.
+    ///
+    /// By default, they're only printed without alternate formatting.
+    pub fn hash(mut self, show: bool) -> DemangleOptions {
+        self.hash = Some(show);
+        self
+    }
+
+    /// Whether to print the crate disambiguators of v0 symbols (e.g. the
+    /// `[1234]` in `foo[1234]::bar`), independently of the hashes of legacy
+    /// symbols. By default, this follows `hash`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().hash(true).crate_disambiguators(false);
+    /// let sym = demangle("_RNvCs1234_3foo3bar").with_options(options);
+    /// assert_eq!(sym.to_string(), "foo::bar");
+    /// let sym = demangle("_ZN3foo17h05af221e174051e9E").with_options(options);
+    /// assert_eq!(sym.to_string(), "foo::h05af221e174051e9");
+    /// ```
+    pub fn crate_disambiguators(mut self, show: bool) -> DemangleOptions {
+        self.crate_disambiguators = Some(show);
+        self
+    }
+
+    /// The maximum depth v0 symbols can nest (e.g. paths within generic
+    /// arguments) before they're considered invalid, or, if only reached when
+    /// printing, before `{recursion limit reached}` is printed instead.
+    ///
+    /// The default is 500, which real-world symbols rarely come close to.
+    /// Services handling untrusted input can lower it, and trusted offline
+    /// tools can raise it, as long as they run on threads with enough stack
+    /// (demangling recurses at least once per level), as going too deep would
+    /// overflow the stack instead.
+    pub fn recursion_limit(mut self, limit: u32) -> DemangleOptions {
+        self.recursion_limit = limit;
+        self
+    }
+
+    /// Whether to include the offset (in bytes, from the start of the input,
+    /// including any linker prefix, like `TryDemangleError::offset`) at which
+    /// an error was found in v0 symbols in the error printed in its place
+    /// (`false` by default), e.g. `{invalid syntax at byte 7}` instead of
+    /// `{invalid syntax}`.
+    ///
+    /// Such errors can only be found while printing, e.g. in the targets of
+    /// backrefs (which aren't followed when checking the symbol's syntax).
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// // The backref points into the middle of an identifier.
+    /// let sym = demangle("_RINvC3foo3barB3_E");
+    /// assert_eq!(sym.to_string(), "foo::bar::<{invalid syntax}>");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().error_offsets(true));
+    /// assert_eq!(sym.to_string(), "foo::bar::<{invalid syntax at byte 7}>");
+    /// ```
+    pub fn error_offsets(mut self, show: bool) -> DemangleOptions {
+        self.error_offsets = show;
+        self
+    }
+
+    /// Whether to skip the parts of v0 symbols starting with unknown tags,
+    /// e.g. from newer versions of rustc, printing `{unknown 'Z'}` in their
+    /// place (`false` by default), instead of considering such symbols invalid.
+    /// What follows an unknown tag is assumed to be a list of generic
+    /// arguments (i.e. types, consts and lifetimes) ending with `E`, like in
+    /// most of the v0 grammar, and skipped as such.
+    ///
+    /// Like `recursion_limit`, this only applies to validating symbols when
+    /// passed to `demangle_with_options`, not `Demangle::with_options`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, demangle_with_options, DemangleOptions};
+    ///
+    /// let s = "_RINvC3foo3barZhtEmE";
+    /// assert_eq!(demangle(s).to_string(), s);
+    ///
+    /// let options = DemangleOptions::new().unknown_tags(true);
+    /// let sym = demangle_with_options(s, options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<{unknown 'Z'}, u32>");
+    /// ```
+    pub fn unknown_tags(mut self, skip: bool) -> DemangleOptions {
+        self.unknown_tags = skip;
+        self
+    }
+
+    /// Whether to stop at backrefs in v0 symbols which are part of their own
+    /// target (possibly through other backrefs), printing `{cyclic backref}`
+    /// in their place (`false` by default), instead of following them until
+    /// the recursion limit is reached, and printing `{recursion limit reached}`.
+    ///
+    /// Such backrefs are always reported as cyclic by `try_demangle` (and not
+    /// by `Demangle::recursion_limit_reached`), regardless of this option.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// // The backref refers to the path it's part of.
+    /// let sym = demangle("_RNvNvB1_1x1y");
+    /// let demangled = format!("{:#}", sym);
+    /// assert!(demangled.starts_with("{recursion limit reached}::?::x::x::x::"));
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().cyclic_backrefs(true));
+    /// assert_eq!(format!("{:#}", sym), "{cyclic backref}::?::x::y");
+    /// ```
+    pub fn cyclic_backrefs(mut self, report: bool) -> DemangleOptions {
+        self.cyclic_backrefs = report;
+        self
+    }
+
+    /// Whether to mark the special namespaces of v0 symbols which are unknown
+    /// to this crate (i.e. other than `C` for closures and `S` for shims) as
+    /// such (`false` by default), e.g. `{unknown-namespace-A#0}` instead of
+    /// just their letter, e.g. `{A#0}`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RNANvC3foo3bar0");
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{A#0}");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().unknown_namespaces(true));
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{unknown-namespace-A#0}");
+    /// ```
+    pub fn unknown_namespaces(mut self, mark: bool) -> DemangleOptions {
+        self.unknown_namespaces = mark;
+        self
+    }
+
+    /// Limits the output to `max` bytes (unlimited, by default), printing
+    /// `{output truncated}` once that's exhausted, instead of the rest of the
+    /// symbol, e.g. to bound the work done on hostile v0 symbols, whose
+    /// backrefs can make the output exponentially larger than the symbol.
+    ///
+    /// The limit applies to the symbol itself, not to any prefix or suffix
+    /// (e.g. `.llvm.1234`), and may be undershot by up to one identifier (or
+    /// other unit of output), as those are never split. Regardless of this
+    /// option, symbols with over a million bytes of output are always cut
+    /// off, with `{size limit reached}`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().max_output_bytes(10);
+    /// let sym = demangle("_RNvNvC3foo3bar3baz").with_options(options);
+    /// assert_eq!(sym.to_string(), "foo::bar::{output truncated}");
+    /// ```
+    pub fn max_output_bytes(mut self, max: usize) -> DemangleOptions {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Whether to demangle symbols decorated with a linker or assembler
+    /// prefix (`true` by default), i.e. `__imp_` (import table entries on
+    /// Windows) and `.L` (assembler local labels), which is kept in the
+    /// output, in front of the demangled symbol.
+    ///
+    /// Like `recursion_limit`, this only has an effect when demangling, i.e.
+    /// when passed to `demangle_with_options`, not to `with_options`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, demangle_with_options, DemangleOptions};
+    ///
+    /// assert_eq!(demangle("__imp__ZN3foo3barE").to_string(), "__imp_foo::bar");
+    /// assert_eq!(demangle(".L_RNvC3foo3bar").to_string(), ".Lfoo::bar");
+    ///
+    /// let options = DemangleOptions::new().linker_prefixes(false);
+    /// let sym = demangle_with_options("__imp__ZN3foo3barE", options);
+    /// assert_eq!(sym.to_string(), "__imp__ZN3foo3barE");
+    /// ```
+    pub fn linker_prefixes(mut self, demangle: bool) -> DemangleOptions {
+        self.linker_prefixes = demangle;
+        self
+    }
+
+    /// Which segments of paths to print (all of them, by default).
+    pub fn path_elision(mut self, elision: PathElision) -> DemangleOptions {
+        self.path_elision = elision;
+        self
+    }
+
+    /// Whether to describe the unmangled symbols defined by the Rust runtime
+    /// (`false` by default), e.g. `__rust_alloc` or `rust_begin_unwind`, in
+    /// front of the symbol itself, so that they stand out as Rust symbols
+    /// too, see `runtime_symbol_description`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().runtime_symbols(true);
+    /// let sym = demangle("__rust_alloc").with_options(options);
+    /// assert_eq!(sym.to_string(), "alloc shim (__rust_alloc)");
+    /// let sym = demangle("rust_begin_unwind").with_options(options);
+    /// assert_eq!(sym.to_string(), "panic handler (rust_begin_unwind)");
+    /// ```
+    pub fn runtime_symbols(mut self, describe: bool) -> DemangleOptions {
+        self.runtime_symbols = describe;
+        self
+    }
+
+    /// Whether to also treat a trailing element of exactly 16 hex digits,
+    /// without the usual leading `h`, as the hash of a legacy symbol (`false`
+    /// by default), as emitted by some older or patched toolchains.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_ZN3foo16ffaf221e174051e9E");
+    /// assert_eq!(format!("{:#}", sym), "foo::ffaf221e174051e9");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().unprefixed_hashes(true));
+    /// assert_eq!(format!("{:#}", sym), "foo");
+    /// ```
+    pub fn unprefixed_hashes(mut self, heuristic: bool) -> DemangleOptions {
+        self.unprefixed_hashes = heuristic;
+        self
+    }
+
+    /// Whether to print generic arguments, e.g. the `<u8>` in
+    /// `alloc::vec::Vec<u8>::push` (`true` by default).
+    pub fn generics(mut self, show: bool) -> DemangleOptions {
+        self.generics = show;
+        self
+    }
+
+    /// Whether to print the types of integer constants in generic arguments
+    /// of v0 symbols (e.g. the `usize` in `ArrayVec<u8, 123usize>`).
+    ///
+    /// By default, they're only printed without alternate formatting, but
+    /// setting this makes them independent of it, so e.g. hashes (see `hash`)
+    /// can be kept while hiding these suffixes, or the other way around.
+    pub fn const_type_suffixes(mut self, show: bool) -> DemangleOptions {
+        self.const_type_suffixes = Some(show);
+        self
+    }
+
+    /// Whether to print the values of constants in v0 symbols, i.e. const
+    /// generic arguments and array lengths (`true` by default).
+    ///
+    /// When disabled, every constant is printed as `_` instead, which helps
+    /// grouping symbols that only differ in e.g. array lengths:
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().const_values(false);
+    /// let sym = demangle("_RMC0INtC8arrayvec8ArrayVechKj7b_E").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "<arrayvec::ArrayVec<u8, _>>");
+    /// ```
+    pub fn const_values(mut self, show: bool) -> DemangleOptions {
+        self.const_values = show;
+        self
+    }
+
+    /// Whether to print integer constants in v0 symbols (i.e. const generic
+    /// arguments and array lengths) in hexadecimal, e.g. `0xffff_ffff`
+    /// instead of `4294967295` (`false` by default).
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().const_hex(true);
+    /// let sym = demangle("_RINvC3foo3barKjffffffff_Kine_E").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<0xffffffff, -0xe>");
+    /// ```
+    pub fn const_hex(mut self, hex: bool) -> DemangleOptions {
+        self.const_hex = hex;
+        self
+    }
+
+    /// Whether to separate the digits of integer constants in v0 symbols
+    /// with `_` (`false` by default), into groups of three for decimal
+    /// values, and of four for hexadecimal ones (see `const_hex`).
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().const_digit_separators(true);
+    /// let sym = "_RINvC3foo3barKjffffffffffffffff_E";
+    /// assert_eq!(
+    ///     format!("{:#}", demangle(sym).with_options(options)),
+    ///     "foo::bar::<18_446_744_073_709_551_615>"
+    /// );
+    /// assert_eq!(
+    ///     format!("{:#}", demangle(sym).with_options(options.const_hex(true))),
+    ///     "foo::bar::<0xffff_ffff_ffff_ffff>"
+    /// );
+    /// ```
+    pub fn const_digit_separators(mut self, separators: bool) -> DemangleOptions {
+        self.const_digit_separators = separators;
+        self
+    }
+
+    /// Whether to print the type and const generic arguments of v0 symbols as
+    /// positional placeholders (`false` by default), i.e. `T0`, `T1`, etc.
+    /// for types (including the associated types of trait objects), and
+    /// `C0`, `C1`, etc. for constants, numbered separately in the order
+    /// they're printed, while keeping the paths they're arguments of.
+    ///
+    /// This gives all the instances of a generic function, or of a method of
+    /// a generic type, the same readable name, e.g. for aggregating them:
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().generic_placeholders(true);
+    /// let sym = demangle("_RINvC3foo3barNtNtC5alloc6string6StringKj4_E");
+    /// assert_eq!(format!("{:#}", sym.with_options(options)), "foo::bar::<T0, C0>");
+    /// let sym = demangle("_RNvXC3fooINtB2_3BarhENtB2_5Trait3baz");
+    /// assert_eq!(format!("{:#}", sym.with_options(options)), "<foo::Bar<T0> as foo::Trait>::baz");
+    /// ```
+    pub fn generic_placeholders(mut self, placeholders: bool) -> DemangleOptions {
+        self.generic_placeholders = placeholders;
+        self
+    }
+
+    /// How to name the lifetimes bound by `for<...>` binders in v0 symbols.
+    pub fn lifetime_names(mut self, names: LifetimeNames) -> DemangleOptions {
+        self.lifetime_names = names;
+        self
+    }
+
+    /// Whether to print the lifetimes in v0 symbols (`true` by default), both
+    /// in types (e.g. `&'a T` and `dyn Trait + 'a`) and as generic arguments,
+    /// along with the `for<...>` binders introducing them, e.g. for grouping
+    /// types by their shape.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RINvC3foo3barFG_RL0_hEuE");
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<for<'a> fn(&'a u8)>");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().lifetimes(false));
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<fn(&u8)>");
+    /// ```
+    pub fn lifetimes(mut self, show: bool) -> DemangleOptions {
+        self.lifetimes = show;
+        self
+    }
+
+    /// How to print the `for<...>` binders introducing lifetimes in v0
+    /// symbols, which, unless `lifetimes` hides them, are listed in full by
+    /// default (see `Binders`).
+    pub fn binders(mut self, binders: Binders) -> DemangleOptions {
+        self.binders = binders;
+        self
+    }
+
+    /// Whether to print the `-> ()` of `fn` types returning `()` in v0
+    /// symbols (`false` by default, like Rust syntax), e.g. to match other
+    /// demanglers' output.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RINvC3foo3barFhEuE");
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<fn(u8)>");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().explicit_unit_returns(true));
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::<fn(u8) -> ()>");
+    /// ```
+    pub fn explicit_unit_returns(mut self, explicit: bool) -> DemangleOptions {
+        self.explicit_unit_returns = explicit;
+        self
+    }
+
+    /// Whether to print closures and shims in v0 symbols like legacy symbols
+    /// had them (`false` by default), i.e. `{{closure}}` and e.g.
+    /// `{{vtable.shim}}`, without their names (for closures) and indices,
+    /// e.g. to compare with output from before switching manglings.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RNCNvC3foo3bar0");
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{closure#0}");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().legacy_closures(true));
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{{closure}}");
+    /// ```
+    pub fn legacy_closures(mut self, legacy: bool) -> DemangleOptions {
+        self.legacy_closures = legacy;
+        self
+    }
+
+    /// Whether to describe the shims in v0 symbols whose kinds are known
+    /// (`false` by default), e.g. `{vtable-shim}` or `{fn-ptr-shim}`, instead
+    /// of printing rustc's names for them, e.g. `{shim:vtable#0}`, whose
+    /// index (the `#0`) is then only printed if it's not `0`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RNSNvC3foo3bar6vtable");
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{shim:vtable#0}");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().shim_kinds(true));
+    /// assert_eq!(format!("{:#}", sym), "foo::bar::{vtable-shim}");
+    /// ```
+    pub fn shim_kinds(mut self, describe: bool) -> DemangleOptions {
+        self.shim_kinds = describe;
+        self
+    }
+
+    /// Which parts of the suffix following a symbol to print.
+    pub fn suffixes(mut self, policy: SuffixPolicy) -> DemangleOptions {
+        self.suffixes = policy;
+        self
+    }
+
+    /// How to print identifiers of v0 symbols with undecodable punycode.
+    pub fn undecodable_punycode(mut self, fallback: PunycodeFallback) -> DemangleOptions {
+        self.undecodable_punycode = fallback;
+        self
+    }
+
+    /// Which characters of `char` and string constants in v0 symbols to
+    /// escape.
+    pub fn const_escapes(mut self, escapes: ConstEscapes) -> DemangleOptions {
+        self.const_escapes = escapes;
+        self
+    }
+
+    /// Whether to print string constants in v0 symbols as raw string
+    /// literals (`false` by default), e.g. `r"C:\dir"` or `r#"say "hi""#`,
+    /// so that quotes and backslashes don't need escaping.
+    ///
+    /// This only applies to strings without any characters which would have
+    /// to be escaped otherwise (see `const_escapes`), as raw strings can't
+    /// contain escapes, so e.g. strings with newlines are still printed as
+    /// regular string literals.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = "_RINvC3foo3barKRe433a5c646972_E";
+    /// assert_eq!(format!("{:#}", demangle(sym)), r#"foo::bar::<"C:\\dir">"#);
+    ///
+    /// let options = DemangleOptions::new().raw_str_consts(true);
+    /// let raw = demangle(sym).with_options(options);
+    /// assert_eq!(format!("{:#}", raw), r#"foo::bar::<r"C:\dir">"#);
+    /// ```
+    pub fn raw_str_consts(mut self, raw: bool) -> DemangleOptions {
+        self.raw_str_consts = raw;
+        self
+    }
+
+    /// Whether to print the crate which instantiated a v0 symbol, if the
+    /// symbol says (`false` by default), after the symbol, e.g. to find out
+    /// which crates are responsible for the copies of a generic function.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RINvNtC4core3ptr13drop_in_placeNtC7mycrate3FooEBv_");
+    /// assert_eq!(format!("{:#}", sym), "core::ptr::drop_in_place::<mycrate::Foo>");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().instantiating_crate(true));
+    /// assert_eq!(
+    ///     format!("{:#}", sym),
+    ///     "core::ptr::drop_in_place::<mycrate::Foo> (in mycrate)"
+    /// );
+    /// ```
+    pub fn instantiating_crate(mut self, show: bool) -> DemangleOptions {
+        self.instantiating_crate = show;
+        self
+    }
+
+    /// Whether to print the paths of the `impl`s of v0 symbols (`false` by
+    /// default), along with their disambiguators, which are otherwise left
+    /// out, as `impl`s are printed as their self types (and traits), e.g. so
+    /// that methods from different `impl`s of a type print differently.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_RNvMNtC3foo3bazNtC3foo3Bar3new");
+    /// assert_eq!(format!("{:#}", sym), "<foo::Bar>::new");
+    ///
+    /// let sym = sym.with_options(DemangleOptions::new().impl_paths(true));
+    /// assert_eq!(format!("{:#}", sym), "<foo::Bar>[impl in foo::baz]::new");
+    /// ```
+    pub fn impl_paths(mut self, show: bool) -> DemangleOptions {
+        self.impl_paths = show;
+        self
+    }
+
+    /// Whether to print the disambiguators of the segments and `impl`s of v0
+    /// paths (`false` by default), e.g. the `[1]` in `foo::bar[1]`, which
+    /// tell apart items which would otherwise print the same, such as
+    /// closures in different `const` blocks of the same function.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let options = DemangleOptions::new().path_disambiguators(true);
+    /// let sym = demangle("_RNvNtC3foos_3bar3baz").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "foo::bar[1]::baz");
+    /// let sym = demangle("_RNvMs0_C3fooNtB5_3Bar3new").with_options(options);
+    /// assert_eq!(format!("{:#}", sym), "<foo::Bar>[2]::new");
+    /// ```
+    pub fn path_disambiguators(mut self, show: bool) -> DemangleOptions {
+        self.path_disambiguators = show;
+        self
+    }
+
+    /// Whether to print symbols such that distinct symbols never print the
+    /// same (`false` by default), for e.g. renaming symbols to their
+    /// demangled forms.
+    ///
+    /// This prints everything the other options can hide, i.e. hashes, crate
+    /// disambiguators, generics, lifetimes, the types and values of constants, the
+    /// crates instantiating v0 symbols, and suffixes, as well as the
+    /// disambiguators of v0 paths, which `demangle` never prints (see
+    /// `path_disambiguators`). Later calls to other setters can still
+    /// override any of these.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let a = demangle("_RNvNtC3foo3bar3baz");
+    /// let b = demangle("_RNvNtC3foos_3bar3baz");
+    /// assert_eq!(a.to_string(), b.to_string());
+    ///
+    /// let options = DemangleOptions::new().unambiguous(true);
+    /// assert_eq!(format!("{:#}", a.with_options(options)), "foo::bar::baz");
+    /// assert_eq!(format!("{:#}", b.with_options(options)), "foo::bar[1]::baz");
+    /// ```
+    pub fn unambiguous(mut self, unambiguous: bool) -> DemangleOptions {
+        self.path_disambiguators = unambiguous;
+        if unambiguous {
+            self.hash = Some(true);
+            self.crate_disambiguators = Some(true);
+            self.unprefixed_hashes = false;
+            self.generics = true;
+            self.const_type_suffixes = Some(true);
+            self.const_values = true;
+            self.generic_placeholders = false;
+            self.lifetimes = true;
+            self.binders = Binders::Full;
+            self.legacy_closures = false;
+            self.instantiating_crate = true;
+            self.suffixes = SuffixPolicy::KeepAll;
+            // Unlike `Raw`, this can't be mistaken for a regular identifier.
+            self.undecodable_punycode = PunycodeFallback::Placeholder;
+            // Unlike `Never`, this can't print e.g. a quote inside quotes.
+            self.const_escapes = ConstEscapes::NonPrintable;
+        }
+        self
+    }
+
+    fn shows_hash(&self, alternate: bool) -> bool {
+        self.hash.unwrap_or(!alternate)
+    }
+
+    fn shows_crate_disambiguators(&self, alternate: bool) -> bool {
+        self.crate_disambiguators
+            .or(self.hash)
+            .unwrap_or(!alternate)
+    }
+
+    fn shows_const_type_suffixes(&self, alternate: bool) -> bool {
+        self.const_type_suffixes.unwrap_or(!alternate)
+    }
+}
+
+impl Default for DemangleOptions {
+    fn default() -> DemangleOptions {
+        DemangleOptions::new()
+    }
 }
 
 enum DemangleStyle<'a> {
@@ -89,7 +1047,28 @@ enum DemangleStyle<'a> {
 /// assert_eq!(demangle("_ZN3foo3barE").to_string(), "foo::bar");
 /// assert_eq!(demangle("foo").to_string(), "foo");
 /// ```
-pub fn demangle(mut s: &str) -> Demangle {
+pub fn demangle(s: &str) -> Demangle {
+    demangle_with_options(s, DemangleOptions::new())
+}
+
+/// The same as `demangle`, except the returned `Demangle` is printed using
+/// `options`, whose recursion limit also applies to validating `s` (unlike
+/// with `Demangle::with_options`, which can only affect printing).
+///
+/// ```
+/// use rustc_demangle::{demangle_with_options, DemangleOptions};
+///
+/// let options = DemangleOptions::new().hash(false);
+/// let sym = demangle_with_options("_ZN3foo17h05af221e174051e9E", options);
+/// assert_eq!(sym.to_string(), "foo");
+///
+/// let options = DemangleOptions::new().recursion_limit(2);
+/// let sym = demangle_with_options("_RNvNvC3foo3bar3baz", options);
+/// assert_eq!(sym.to_string(), "_RNvNvC3foo3bar3baz");
+/// ```
+pub fn demangle_with_options<'a>(mut s: &'a str, options: DemangleOptions) -> Demangle<'a> {
+    let input = s;
+
     // During ThinLTO LLVM may import and rename internal symbols, so strip out
     // those endings first as they're one of the last manglings applied to symbol
     // names.
@@ -106,30 +1085,40 @@ pub fn demangle(mut s: &str) -> Demangle {
         }
     }
 
+    let mut prefix = "";
+    if options.linker_prefixes {
+        prefix = linker_prefix(s);
+    }
+
     let mut suffix = "";
-    let mut style = match legacy::demangle(s) {
+    let mut style = match legacy::demangle(&s[prefix.len()..]) {
         Ok((d, s)) => {
             suffix = s;
             Some(DemangleStyle::Legacy(d))
         }
-        Err(()) => match v0::demangle(s) {
-            Ok((d, s)) => {
-                suffix = s;
-                Some(DemangleStyle::V0(d))
-            }
-            // FIXME(eddyb) would it make sense to treat an unknown-validity
-            // symbol (e.g. one that errored with `RecursedTooDeep`) as
-            // v0-mangled, and have the error show up in the demangling?
-            // (that error already gets past this initial check, and therefore
-            // will show up in the demangling, if hidden behind a backref)
-            Err(v0::ParseError::Invalid) | Err(v0::ParseError::RecursedTooDeep) => None,
-        },
+        Err(_) => {
+            match v0::demangle_with_error_offset(&s[prefix.len()..], &options) {
+                Ok((d, s)) => {
+                    suffix = s;
+                    Some(DemangleStyle::V0(d.with_outer_prefix(prefix.len())))
+                }
+                // FIXME(eddyb) would it make sense to treat an unknown-validity
+                // symbol (e.g. one that errored with `RecursedTooDeep`) as
+                // v0-mangled, and have the error show up in the demangling?
+                // (that error already gets past this initial check, and therefore
+                // will show up in the demangling, if hidden behind a backref)
+                Err((v0::ParseError::Invalid, _, _))
+                | Err((v0::ParseError::RecursedTooDeep, _, _))
+                | Err((v0::ParseError::CyclicBackref, _, _)) => None,
+            }
+        }
     };
 
-    // Output like LLVM IR adds extra period-delimited words. See if
-    // we are in that case and save the trailing words if so.
+    // Output like LLVM IR adds extra period-delimited words, and dynamic
+    // symbol tables add ELF symbol versions. See if we are in that case
+    // and save the trailing words if so.
     if !suffix.is_empty() {
-        if suffix.starts_with('.') && is_symbol_like(suffix) {
+        if is_valid_suffix(suffix) {
             // Keep the suffix.
         } else {
             // Reset the suffix and invalidate the demangling.
@@ -138,451 +1127,4946 @@ pub fn demangle(mut s: &str) -> Demangle {
         }
     }
 
+    // The prefix is only kept for actual Rust symbols.
+    if style.is_none() {
+        prefix = "";
+    }
+
     Demangle {
         style,
         original: s,
+        prefix,
         suffix,
+        input,
+        options,
     }
 }
 
-#[cfg(feature = "std")]
-fn demangle_line(
-    line: &str,
-    output: &mut impl std::io::Write,
-    include_hash: bool,
-) -> std::io::Result<()> {
-    let mut head = 0;
-    while head < line.len() {
-        // Move to the next potential match
-        let next_head = match (line[head..].find("_ZN"), line[head..].find("_R")) {
-            (Some(idx), None) | (None, Some(idx)) => head + idx,
-            (Some(idx1), Some(idx2)) => head + idx1.min(idx2),
-            (None, None) => {
-                // No more matches...
-                line.len()
-            }
-        };
-        output.write_all(line[head..next_head].as_bytes())?;
-        head = next_head;
-        // Find the non-matching character.
-        //
-        // If we do not find a character, then until the end of the line is the
-        // thing to demangle.
-        let match_end = line[head..]
-            .find(|ch: char| !(ch == '$' || ch == '.' || ch == '_' || ch.is_ascii_alphanumeric()))
-            .map(|idx| head + idx)
-            .unwrap_or(line.len());
-
-        let mangled = &line[head..match_end];
-        head = head + mangled.len();
-        if let Ok(demangled) = try_demangle(mangled) {
-            if include_hash {
-                write!(output, "{}", demangled)?;
-            } else {
-                write!(output, "{:#}", demangled)?;
-            }
-        } else {
-            output.write_all(mangled.as_bytes())?;
+/// Returns the linker/assembler decoration `s` starts with, if any (or an
+/// empty string), see `DemangleOptions::linker_prefixes`.
+fn linker_prefix(s: &str) -> &str {
+    for prefix in &["__imp_", ".L"] {
+        if s.starts_with(prefix) {
+            return prefix;
         }
     }
-    Ok(())
+    ""
 }
 
-/// Process a stream of data from `input` into the provided `output`, demangling any symbols found
-/// within.
+/// Demangles `s` into a `Cow`, which borrows `s` when it isn't a Rust symbol,
+/// avoiding any allocation in that (common) case.
 ///
-/// Note that the underlying implementation will perform many relatively small writes to the
-/// output. If the output is expensive to write to (e.g., requires syscalls), consider using
-/// `std::io::BufWriter`.
-#[cfg(feature = "std")]
-#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
-pub fn demangle_stream<R: std::io::BufRead, W: std::io::Write>(
-    input: &mut R,
-    output: &mut W,
-    include_hash: bool,
-) -> std::io::Result<()> {
-    let mut buf = std::string::String::new();
-    // We read in lines to reduce the memory usage at any time.
-    //
-    // demangle_line is also more efficient with relatively small buffers as it will copy around
-    // trailing data during demangling. In the future we might directly stream to the output but at
-    // least right now that seems to be less efficient.
-    while input.read_line(&mut buf)? > 0 {
-        demangle_line(&buf, output, include_hash)?;
-        buf.clear();
-    }
-    Ok(())
-}
-
-/// Error returned from the `try_demangle` function below when demangling fails.
-#[derive(Debug, Clone)]
-pub struct TryDemangleError {
-    _priv: (),
-}
-
-/// The same as `demangle`, except return an `Err` if the string does not appear
-/// to be a Rust symbol, rather than "demangling" the given string as a no-op.
+/// The demangled symbol is printed with `{}`, i.e. including its hash.
 ///
 /// ```
-/// extern crate rustc_demangle;
+/// use std::borrow::Cow;
+/// use rustc_demangle::demangle_cow;
 ///
-/// let not_a_rust_symbol = "la la la";
-///
-/// // The `try_demangle` function will reject strings which are not Rust symbols.
-/// assert!(rustc_demangle::try_demangle(not_a_rust_symbol).is_err());
-///
-/// // While `demangle` will just pass the non-symbol through as a no-op.
-/// assert_eq!(rustc_demangle::demangle(not_a_rust_symbol).as_str(), not_a_rust_symbol);
+/// assert_eq!(demangle_cow("_ZN3foo3barE"), Cow::<str>::Owned("foo::bar".into()));
+/// assert_eq!(demangle_cow("main"), Cow::Borrowed("main"));
 /// ```
-pub fn try_demangle(s: &str) -> Result<Demangle, TryDemangleError> {
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn demangle_cow<'a>(s: &'a str) -> alloc::borrow::Cow<'a, str> {
+    use alloc::borrow::Cow;
+    use alloc::string::ToString;
+
     let sym = demangle(s);
-    if sym.style.is_some() {
-        Ok(sym)
-    } else {
-        Err(TryDemangleError { _priv: () })
+    match sym.style {
+        Some(_) => Cow::Owned(sym.to_string()),
+        None => Cow::Borrowed(s),
     }
 }
 
-impl<'a> Demangle<'a> {
-    /// Returns the underlying string that's being demangled.
-    pub fn as_str(&self) -> &'a str {
-        self.original
+/// Demangles every symbol in `syms`, lazily, e.g. for a whole symbol table.
+///
+/// Like with `demangle`, symbols which aren't Rust symbols are printed as-is.
+///
+/// ```
+/// use rustc_demangle::demangle_all;
+///
+/// let syms = ["_ZN3foo3barE", "main", "_RNvC3foo3baz"];
+/// let demangled: Vec<_> = demangle_all(syms.iter().cloned())
+///     .map(|sym| sym.to_string())
+///     .collect();
+/// assert_eq!(demangled, ["foo::bar", "main", "foo::baz"]);
+/// ```
+pub fn demangle_all<'a, I>(syms: I) -> DemangleAll<I::IntoIter>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    DemangleAll {
+        syms: syms.into_iter(),
     }
 }
 
-fn is_symbol_like(s: &str) -> bool {
-    s.chars().all(|c| {
-        // Once `char::is_ascii_punctuation` and `char::is_ascii_alphanumeric`
-        // have been stable for long enough, use those instead for clarity
-        is_ascii_alphanumeric(c) || is_ascii_punctuation(c)
-    })
+/// Iterator over demangled symbols, see `demangle_all`.
+#[derive(Clone, Debug)]
+pub struct DemangleAll<I> {
+    syms: I,
 }
 
-// Copied from the documentation of `char::is_ascii_alphanumeric`
-fn is_ascii_alphanumeric(c: char) -> bool {
-    match c {
-        '\u{0041}'..='\u{005A}' | '\u{0061}'..='\u{007A}' | '\u{0030}'..='\u{0039}' => true,
-        _ => false,
-    }
-}
+impl<'a, I: Iterator<Item = &'a str>> Iterator for DemangleAll<I> {
+    type Item = Demangle<'a>;
 
-// Copied from the documentation of `char::is_ascii_punctuation`
-fn is_ascii_punctuation(c: char) -> bool {
-    match c {
-        '\u{0021}'..='\u{002F}'
-        | '\u{003A}'..='\u{0040}'
-        | '\u{005B}'..='\u{0060}'
-        | '\u{007B}'..='\u{007E}' => true,
-        _ => false,
+    fn next(&mut self) -> Option<Demangle<'a>> {
+        self.syms.next().map(demangle)
     }
-}
 
-impl<'a> fmt::Display for DemangleStyle<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            DemangleStyle::Legacy(ref d) => fmt::Display::fmt(d, f),
-            DemangleStyle::V0(ref d) => fmt::Display::fmt(d, f),
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.syms.size_hint()
     }
 }
 
-// Maximum size of the symbol that we'll print.
-const MAX_SIZE: usize = 1_000_000;
+impl<'a, I: ExactSizeIterator<Item = &'a str>> ExactSizeIterator for DemangleAll<I> {}
 
-#[derive(Copy, Clone, Debug)]
-struct SizeLimitExhausted;
+/// Demangles every symbol in `syms` into a `String`, printed with `{}`, i.e.
+/// including its hash (see also `demangle_all`, which doesn't allocate).
+///
+/// ```
+/// use rustc_demangle::demangle_all_to_strings;
+///
+/// assert_eq!(
+///     demangle_all_to_strings(vec!["_ZN3foo3barE", "main"]),
+///     ["foo::bar", "main"]
+/// );
+/// ```
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn demangle_all_to_strings<'a, I>(syms: I) -> alloc::vec::Vec<alloc::string::String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    use alloc::string::ToString;
 
-struct SizeLimitedFmtAdapter<F> {
-    remaining: Result<usize, SizeLimitExhausted>,
-    inner: F,
+    demangle_all(syms).map(|sym| sym.to_string()).collect()
 }
 
-impl<F: fmt::Write> fmt::Write for SizeLimitedFmtAdapter<F> {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.remaining = self
-            .remaining
-            .and_then(|r| r.checked_sub(s.len()).ok_or(SizeLimitExhausted));
+/// Demangles every Rust symbol found in `line` (e.g. a log line or a panic
+/// message), like `demangle_stream` does for whole streams, and leaves the
+/// rest of it unchanged, only allocating if there was anything to demangle.
+///
+/// The demangled symbols are printed with `{}`, i.e. including their hashes.
+///
+/// ```
+/// use std::borrow::Cow;
+/// use rustc_demangle::demangle_line;
+///
+/// assert_eq!(
+///     demangle_line("called from _ZN3foo3barE+0x1c (_RNvC3foo3baz)"),
+///     "called from foo::bar+0x1c (foo::baz)"
+/// );
+/// assert_eq!(demangle_line("nothing to see _R here"), Cow::Borrowed("nothing to see _R here"));
+/// ```
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn demangle_line<'a>(line: &'a str) -> alloc::borrow::Cow<'a, str> {
+    use alloc::borrow::Cow;
+    use alloc::string::String;
 
-        match self.remaining {
-            Ok(_) => self.inner.write_str(s),
-            Err(SizeLimitExhausted) => Err(fmt::Error),
+    let mut out: Option<String> = None;
+    let mut copied = 0;
+    for (range, sym) in find_mangled(line) {
+        let out = out.get_or_insert_with(|| String::with_capacity(line.len()));
+        out.push_str(&line[copied..range.start]);
+        let _ = write!(out, "{}", sym);
+        copied = range.end;
+    }
+    match out {
+        Some(mut out) => {
+            out.push_str(&line[copied..]);
+            Cow::Owned(out)
         }
+        None => Cow::Borrowed(line),
     }
 }
 
-impl<'a> fmt::Display for Demangle<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.style {
-            None => f.write_str(self.original)?,
-            Some(ref d) => {
-                let alternate = f.alternate();
-                let mut size_limited_fmt = SizeLimitedFmtAdapter {
-                    remaining: Ok(MAX_SIZE),
-                    inner: &mut *f,
-                };
-                let fmt_result = if alternate {
-                    write!(size_limited_fmt, "{:#}", d)
-                } else {
-                    write!(size_limited_fmt, "{}", d)
-                };
-                let size_limit_result = size_limited_fmt.remaining.map(|_| ());
-
-                // Translate a `fmt::Error` generated by `SizeLimitedFmtAdapter`
-                // into an error message, instead of propagating it upwards
-                // (which could cause panicking from inside e.g. `std::io::print`).
-                match (fmt_result, size_limit_result) {
-                    (Err(_), Err(SizeLimitExhausted)) => f.write_str("{size limit reached}")?,
-
-                    _ => {
-                        fmt_result?;
-                        size_limit_result
-                            .expect("`fmt::Error` from `SizeLimitedFmtAdapter` was discarded");
-                    }
-                }
+/// Finds the Rust symbols in `text` (e.g. disassembly, LLVM IR or logs),
+/// yielding their byte ranges in `text` along with their demangling, in the
+/// same way `demangle_stream` and `demangle_line` find them.
+///
+/// Symbols are the longest runs of the characters mangled symbols can contain
+/// (`[A-Za-z0-9_.$]`) starting at `_ZN` or `_R`, which `try_demangle` accepts.
+///
+/// ```
+/// use rustc_demangle::find_mangled;
+///
+/// let text = "call _ZN3foo3barE ; _RNvC3foo3baz+0x10";
+/// let found: Vec<_> = find_mangled(text)
+///     .map(|(range, sym)| (&text[range], sym.to_string()))
+///     .collect();
+/// assert_eq!(
+///     found,
+///     [
+///         ("_ZN3foo3barE", "foo::bar".to_string()),
+///         ("_RNvC3foo3baz", "foo::baz".to_string())
+///     ]
+/// );
+/// ```
+pub fn find_mangled<'a>(text: &'a str) -> FindMangled<'a> {
+    FindMangled { text, head: 0 }
+}
+
+/// Iterator over the Rust symbols in a string, see `find_mangled`.
+#[derive(Clone, Debug)]
+pub struct FindMangled<'a> {
+    text: &'a str,
+    head: usize,
+}
+
+impl<'a> Iterator for FindMangled<'a> {
+    type Item = (core::ops::Range<usize>, Demangle<'a>);
+
+    fn next(&mut self) -> Option<(core::ops::Range<usize>, Demangle<'a>)> {
+        while let Some(candidate) = next_candidate_symbol(self.text, self.head) {
+            self.head = candidate.end;
+            if let Ok(sym) = try_demangle(&self.text[candidate.clone()]) {
+                return Some((candidate, sym));
             }
         }
-        f.write_str(self.suffix)
+        self.head = self.text.len();
+        None
     }
 }
 
-impl<'a> fmt::Debug for Demangle<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+/// Finds the next candidate symbol in `text` starting at or after `from`,
+/// i.e. the longest run of the characters mangled symbols can contain,
+/// starting with `_ZN` or `_R`, which may well not be an actual symbol.
+fn next_candidate_symbol(text: &str, from: usize) -> Option<core::ops::Range<usize>> {
+    let start = match (text[from..].find("_ZN"), text[from..].find("_R")) {
+        (Some(idx), None) | (None, Some(idx)) => from + idx,
+        (Some(idx1), Some(idx2)) => from + idx1.min(idx2),
+        (None, None) => return None,
+    };
+    let end = text[start..]
+        .find(|ch: char| !(ch == '$' || ch == '.' || ch == '_' || ch.is_ascii_alphanumeric()))
+        .map_or(text.len(), |idx| start + idx);
+    Some(start..end)
+}
+
+/// Demangles `s` into `buf`, returning the part of `buf` written to, without
+/// needing an allocator (e.g. in a panic handler).
+///
+/// Like with `demangle`, the output (printed with `{}`, i.e. including the
+/// hash) is `s` itself if `s` isn't a Rust symbol.
+///
+/// If the output doesn't fit, as much of it as possible (ending at a `char`
+/// boundary) is written to `buf`, see `BufferTooSmall::written`.
+///
+/// ```
+/// use rustc_demangle::demangle_into;
+///
+/// let mut buf = [0; 16];
+/// assert_eq!(demangle_into("_ZN3foo3barE", &mut buf), Ok("foo::bar"));
+/// assert_eq!(demangle_into("main", &mut buf), Ok("main"));
+///
+/// let err = demangle_into("_ZN3foo3barE", &mut buf[..4]).unwrap_err();
+/// assert_eq!(&buf[..err.written()], b"foo:");
+/// ```
+pub fn demangle_into<'b>(s: &str, buf: &'b mut [u8]) -> Result<&'b str, BufferTooSmall> {
+    let mut out = SliceWriter {
+        buf,
+        len: 0,
+        overflowed: false,
+    };
+    let _ = write!(out, "{}", demangle(s));
+    if out.overflowed {
+        return Err(BufferTooSmall { written: out.len });
     }
+    let SliceWriter { buf, len, .. } = out;
+    Ok(str::from_utf8(&buf[..len]).expect("only whole `char`s are written"))
 }
 
-#[cfg(test)]
-mod tests {
-    use std::prelude::v1::*;
+/// Error returned from `demangle_into` when the output doesn't fit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BufferTooSmall {
+    written: usize,
+}
 
-    macro_rules! t {
-        ($a:expr, $b:expr) => {
-            assert!(ok($a, $b))
-        };
+impl BufferTooSmall {
+    /// Returns the number of bytes written to the buffer, i.e. the length of
+    /// the truncated output, which is valid UTF-8.
+    pub fn written(&self) -> usize {
+        self.written
     }
+}
 
-    macro_rules! t_err {
-        ($a:expr) => {
-            assert!(ok_err($a))
+/// Demangles a symbol given as bytes (e.g. read from an object file's string
+/// table), without requiring the caller to validate them as UTF-8 first.
+///
+/// The symbol ends at the first NUL, if any (so whole NUL-terminated string
+/// table entries can be passed in). Mangled Rust symbols are always ASCII,
+/// so invalid UTF-8 is rejected with the `Utf8Error` from `str::from_utf8`,
+/// leaving it up to the caller whether to print such (non-Rust) symbols
+/// lossily (e.g. with `String::from_utf8_lossy`) or skip them.
+///
+/// ```
+/// use rustc_demangle::demangle_bytes;
+///
+/// let sym = demangle_bytes(b"_ZN3foo3barE\0_ZN3foo3bazE\0").unwrap();
+/// assert_eq!(sym.to_string(), "foo::bar");
+/// assert_eq!(demangle_bytes(b"main").unwrap().to_string(), "main");
+/// assert!(demangle_bytes(b"caf\xe9").is_err());
+/// ```
+pub fn demangle_bytes<'a>(s: &'a [u8]) -> Result<Demangle<'a>, str::Utf8Error> {
+    let len = s.iter().position(|&b| b == 0).unwrap_or(s.len());
+    str::from_utf8(&s[..len]).map(demangle)
+}
+
+/// Demangles a symbol given as UTF-16 code units (e.g. by DbgHelp or DIA),
+/// after converting it to UTF-8 into `buf` (whose previous contents are
+/// discarded), which the returned `Demangle` borrows from.
+///
+/// The symbol ends at the first NUL, if any (as C APIs often return whole,
+/// NUL-padded buffers), and invalid code units (i.e. unpaired surrogates)
+/// are replaced with `U+FFFD REPLACEMENT CHARACTER`, like with
+/// `String::from_utf16_lossy`.
+///
+/// ```
+/// use rustc_demangle::demangle_utf16;
+///
+/// let mut sym: Vec<u16> = "_ZN3foo3barE".encode_utf16().collect();
+/// sym.resize(64, 0);
+/// let mut buf = String::new();
+/// assert_eq!(demangle_utf16(&sym, &mut buf).to_string(), "foo::bar");
+/// ```
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn demangle_utf16<'a>(sym: &[u16], buf: &'a mut std::string::String) -> Demangle<'a> {
+    let len = sym.iter().position(|&unit| unit == 0).unwrap_or(sym.len());
+    buf.clear();
+    buf.extend(
+        core::char::decode_utf16(sym[..len].iter().cloned())
+            .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER)),
+    );
+    demangle(buf)
+}
+
+/// Demangles the symbol at the very start of `s`, returning it along with
+/// the number of bytes of `s` it spans, or `None` if `s` doesn't start with
+/// a Rust symbol.
+///
+/// Unlike `demangle`, any data may follow the symbol (without having to be
+/// separated from it in any way), which makes this suitable for parsing
+/// symbols embedded in other formats. As such, suffixes like `.llvm.1234`
+/// are not considered to be part of the symbol.
+///
+/// ```
+/// use rustc_demangle::demangle_prefix;
+///
+/// let data = "_ZN3foo3barE_RNvC3foo3baz|la la la";
+/// let (first, len) = demangle_prefix(data).unwrap();
+/// assert_eq!((first.to_string().as_str(), len), ("foo::bar", 12));
+/// let (second, len) = demangle_prefix(&data[12..]).unwrap();
+/// assert_eq!((second.to_string().as_str(), len), ("foo::baz", 13));
+/// assert!(demangle_prefix(&data[25..]).is_none());
+/// ```
+pub fn demangle_prefix<'a>(s: &'a str) -> Option<(Demangle<'a>, usize)> {
+    let (style, rest) = match legacy::demangle(s) {
+        Ok((d, rest)) => (DemangleStyle::Legacy(d), rest),
+        Err(_) => match v0::demangle(s) {
+            Ok((d, rest)) => (DemangleStyle::V0(d), rest),
+            Err(_) => return None,
+        },
+    };
+    let len = s.len() - rest.len();
+    let sym = Demangle {
+        style: Some(style),
+        original: &s[..len],
+        prefix: "",
+        suffix: "",
+        input: &s[..len],
+        options: DemangleOptions::new(),
+    };
+    Some((sym, len))
+}
+
+/// Error returned from the `try_demangle` function below when demangling fails.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TryDemangleError {
+    kind: TryDemangleErrorKind,
+    scheme: Option<ManglingScheme>,
+    offset: usize,
+    production: Option<V0Production>,
+}
+
+/// Why `try_demangle` failed, see `TryDemangleError::kind`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TryDemangleErrorKind {
+    /// The input doesn't start like a Rust symbol (e.g. with `_ZN` or `_R`).
+    BadPrefix,
+    /// The symbol is malformed, e.g. it has an invalid identifier, or ends
+    /// too early.
+    Invalid,
+    /// The symbol goes past the recursion limit while parsing, see
+    /// `TryDemangleError::recursion_limit_reached`.
+    RecursionLimit,
+    /// A backref in the v0 symbol is part of its own target (possibly through
+    /// other backrefs), so printing it would never end.
+    CyclicBackref,
+    /// The symbol itself is well-formed, but it's followed by trailing data
+    /// which isn't a suffix like `.llvm.1234` (e.g. C++ function parameters).
+    InvalidSuffix,
+}
+
+impl TryDemangleError {
+    /// Returns why the symbol couldn't be demangled.
+    ///
+    /// ```
+    /// use rustc_demangle::{try_demangle, TryDemangleErrorKind};
+    ///
+    /// let kind = |sym| try_demangle(sym).unwrap_err().kind();
+    /// assert_eq!(kind("la la la"), TryDemangleErrorKind::BadPrefix);
+    /// assert_eq!(kind("_ZN3foo"), TryDemangleErrorKind::Invalid);
+    /// assert_eq!(kind("_ZN3fooEv"), TryDemangleErrorKind::InvalidSuffix);
+    /// ```
+    pub fn kind(&self) -> TryDemangleErrorKind {
+        self.kind
+    }
+
+    /// Returns the mangling scheme the symbol was demangled as (i.e. whose
+    /// prefix it starts with), or `None` for `TryDemangleErrorKind::BadPrefix`.
+    pub fn scheme(&self) -> Option<ManglingScheme> {
+        self.scheme
+    }
+
+    /// Returns the offset (in bytes, from the start of the input) at which
+    /// demangling failed, i.e. where the error was found.
+    ///
+    /// For malformed symbols, this is only approximately where parsing stopped,
+    /// i.e. right at (or just past) the first byte which couldn't be parsed,
+    /// or the end of the input, if it ends too early. For invalid suffixes,
+    /// it's the start of the suffix.
+    ///
+    /// ```
+    /// use rustc_demangle::try_demangle;
+    ///
+    /// assert_eq!(try_demangle("_ZN3foo!3barE").unwrap_err().offset(), 7);
+    /// assert_eq!(try_demangle("_ZN3fooEv").unwrap_err().offset(), 8);
+    /// assert_eq!(try_demangle("_RNvC3foo").unwrap_err().offset(), 9);
+    /// ```
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns whether the symbol was rejected for going past the recursion
+    /// limit while parsing, instead of having invalid syntax.
+    ///
+    /// Such symbols may well be valid, just too deeply nested for this crate.
+    ///
+    /// ```
+    /// let deep = format!("_RNv{}C3foo3bar", "Nv".repeat(500));
+    /// let err = rustc_demangle::try_demangle(&deep).unwrap_err();
+    /// assert!(err.recursion_limit_reached());
+    ///
+    /// let err = rustc_demangle::try_demangle("_RNvC3foo").unwrap_err();
+    /// assert!(!err.recursion_limit_reached());
+    /// ```
+    pub fn recursion_limit_reached(&self) -> bool {
+        self.kind == TryDemangleErrorKind::RecursionLimit
+    }
+
+    /// Returns the production of the v0 grammar which was being parsed when
+    /// demangling failed, for v0 symbols which are malformed or go past the
+    /// recursion limit, or `None` for all other errors.
+    ///
+    /// ```
+    /// use rustc_demangle::{try_demangle, V0Production};
+    ///
+    /// let production = |sym| try_demangle(sym).unwrap_err().production();
+    /// assert_eq!(production("_RNvC3foo3ba"), Some(V0Production::Identifier));
+    /// assert_eq!(production("_RINvC3foo3barKjx_E"), Some(V0Production::Const));
+    /// assert_eq!(production("_ZN3foo!3barE"), None);
+    /// ```
+    pub fn production(&self) -> Option<V0Production> {
+        self.production
+    }
+}
+
+/// A production of the v0 mangling grammar (see RFC 2603), i.e. which part of
+/// a malformed symbol couldn't be parsed, see `TryDemangleError::production`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum V0Production {
+    /// A path (e.g. `NvC3foo3bar` for `foo::bar`), anywhere other than in
+    /// one of its identifiers, generic arguments, or backrefs.
+    Path,
+    /// A type (e.g. `RNtC3foo3Bar` for `&foo::Bar`).
+    Type,
+    /// A constant, e.g. a const generic argument or an array length.
+    Const,
+    /// An identifier (e.g. `3bar`), including its disambiguator.
+    Identifier,
+    /// A backref (e.g. `B4_`), whose target is before it.
+    Backref,
+}
+
+/// Statistics about the structure of a v0 symbol, see `validate_v0`.
+///
+/// These describe the symbol as it's written, i.e. the targets of backrefs
+/// aren't parsed again (or counted) for each backref pointing to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct V0Stats {
+    max_depth: u32,
+    backrefs: usize,
+    punycode_identifiers: usize,
+    const_args: usize,
+    productions: usize,
+}
+
+impl V0Stats {
+    /// Returns the deepest nesting reached while parsing the symbol, which is
+    /// what `DemangleOptions::recursion_limit` is compared against.
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Returns the number of backrefs in the symbol.
+    pub fn backrefs(&self) -> usize {
+        self.backrefs
+    }
+
+    /// Returns the number of punycode-encoded (i.e. non-ASCII) identifiers.
+    pub fn punycode_identifiers(&self) -> usize {
+        self.punycode_identifiers
+    }
+
+    /// Returns the number of const generic arguments.
+    pub fn const_args(&self) -> usize {
+        self.const_args
+    }
+
+    /// Returns the total number of paths, types, constants, identifiers and
+    /// backrefs (see `V0Production`) in the symbol, counting nested ones.
+    pub fn productions(&self) -> usize {
+        self.productions
+    }
+}
+
+/// Describes the error, including the offset it was found at, e.g.
+/// `invalid legacy symbol (at offset 7)`.
+impl fmt::Display for TryDemangleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = match self.scheme {
+            Some(ManglingScheme::Legacy) => "legacy ",
+            Some(ManglingScheme::V0) => "v0 ",
+            Some(ManglingScheme::NotMangled) | None => "",
         };
+        match self.kind {
+            TryDemangleErrorKind::BadPrefix => {
+                return f.write_str("not a Rust symbol (unrecognized prefix)")
+            }
+            TryDemangleErrorKind::Invalid => write!(f, "invalid {}symbol", scheme)?,
+            TryDemangleErrorKind::RecursionLimit => {
+                write!(f, "recursion limit reached while parsing {}symbol", scheme)?
+            }
+            TryDemangleErrorKind::CyclicBackref => write!(f, "cyclic backref in {}symbol", scheme)?,
+            TryDemangleErrorKind::InvalidSuffix => {
+                write!(f, "invalid suffix after {}symbol", scheme)?
+            }
+        }
+        write!(f, " (at offset {})", self.offset)
     }
+}
 
-    macro_rules! t_nohash {
-        ($a:expr, $b:expr) => {{
-            assert_eq!(format!("{:#}", super::demangle($a)), $b);
-        }};
+impl core::error::Error for TryDemangleError {}
+
+/// Splits the `+offset` decoration (as printed by e.g. `perf`, `gdb` or the
+/// Linux kernel) off the end of `s`, returning the symbol and the offset.
+///
+/// The offset can either be hexadecimal (with a `0x` prefix) or decimal. If
+/// `s` doesn't end with a valid offset, it's returned unchanged, with `None`.
+///
+/// ```
+/// use rustc_demangle::{demangle, split_symbol_plus_offset};
+///
+/// let (sym, offset) = split_symbol_plus_offset("_ZN3foo3barE+0x1c");
+/// assert_eq!((demangle(sym).to_string().as_str(), offset), ("foo::bar", Some(0x1c)));
+/// assert_eq!(split_symbol_plus_offset("_RNvC3foo3bar+28"), ("_RNvC3foo3bar", Some(28)));
+/// assert_eq!(split_symbol_plus_offset("_RNvC3foo3bar"), ("_RNvC3foo3bar", None));
+/// ```
+pub fn split_symbol_plus_offset(s: &str) -> (&str, Option<u64>) {
+    let i = match s.rfind('+') {
+        Some(i) => i,
+        None => return (s, None),
+    };
+    let offset = &s[i + 1..];
+    let (digits, radix) = if offset.starts_with("0x") || offset.starts_with("0X") {
+        (&offset[2..], 16)
+    } else {
+        (offset, 10)
+    };
+    // `from_str_radix` would also accept a leading sign.
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return (s, None);
+    }
+    match u64::from_str_radix(digits, radix) {
+        Ok(offset) => (&s[..i], Some(offset)),
+        Err(_) => (s, None),
     }
+}
 
-    fn ok(sym: &str, expected: &str) -> bool {
-        match super::try_demangle(sym) {
-            Ok(s) => {
-                if s.to_string() == expected {
-                    true
-                } else {
-                    println!("\n{}\n!=\n{}\n", s, expected);
-                    false
-                }
+/// The mangling scheme of a symbol, see `Demangle::mangling_scheme`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ManglingScheme {
+    /// The legacy mangling, based on the Itanium C++ ABI, e.g. `_ZN3foo3barE`.
+    Legacy,
+    /// The v0 mangling (see RFC 2603), e.g. `_RNvC3foo3bar`.
+    V0,
+    /// Not a Rust symbol, so it's printed unchanged.
+    NotMangled,
+}
+
+/// Error returned from `Demangle::write_checked` and `demangle_checked`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckedFmtError {
+    /// The symbol turned out to be malformed (or too large to print), and
+    /// nothing was written.
+    Invalid,
+    /// The symbol couldn't be demangled (see `try_demangle`), or it turned
+    /// out to be malformed while printing, and nothing was written. Only
+    /// returned by `demangle_checked`.
+    Parse(TryDemangleError),
+    /// Writing to the output failed.
+    Fmt(fmt::Error),
+}
+
+/// The same as `demangle`, except return an `Err` if the string does not appear
+/// to be a Rust symbol, rather than "demangling" the given string as a no-op.
+///
+/// ```
+/// extern crate rustc_demangle;
+///
+/// let not_a_rust_symbol = "la la la";
+///
+/// // The `try_demangle` function will reject strings which are not Rust symbols.
+/// assert!(rustc_demangle::try_demangle(not_a_rust_symbol).is_err());
+///
+/// // While `demangle` will just pass the non-symbol through as a no-op.
+/// assert_eq!(rustc_demangle::demangle(not_a_rust_symbol).as_str(), not_a_rust_symbol);
+/// ```
+///
+/// Note that v0 symbols are only validated up to the targets of backrefs, so
+/// some malformed symbols are only found to be malformed while printing them
+/// (see `try_demangle_strict` for a version which rules those out as well).
+pub fn try_demangle(s: &str) -> Result<Demangle, TryDemangleError> {
+    let sym = demangle(s);
+    if sym.style.is_some() {
+        return Ok(sym);
+    }
+
+    // Demangle again, keeping track of the details this time, of whatever
+    // follows the linker prefix (if any), like `demangle` does.
+    let mut s = sym.original;
+    let prefix_len = if sym.options.linker_prefixes {
+        linker_prefix(s).len()
+    } else {
+        0
+    };
+    s = &s[prefix_len..];
+    let error = |kind, scheme, offset| TryDemangleError {
+        kind,
+        scheme: Some(scheme),
+        offset: prefix_len + offset,
+        production: None,
+    };
+    Err(match legacy::demangle(s) {
+        Ok((_, suffix)) => error(
+            TryDemangleErrorKind::InvalidSuffix,
+            ManglingScheme::Legacy,
+            s.len() - suffix.len(),
+        ),
+        Err(legacy_offset) => {
+            match v0::demangle_with_error_offset(s, &sym.options) {
+                Ok((_, suffix)) => error(
+                    TryDemangleErrorKind::InvalidSuffix,
+                    ManglingScheme::V0,
+                    s.len() - suffix.len(),
+                ),
+                Err((v0::ParseError::RecursedTooDeep, offset, production)) => TryDemangleError {
+                    production: Some(production),
+                    ..error(
+                        TryDemangleErrorKind::RecursionLimit,
+                        ManglingScheme::V0,
+                        offset,
+                    )
+                },
+                Err((v0::ParseError::CyclicBackref, offset, production)) => TryDemangleError {
+                    production: Some(production),
+                    ..error(
+                        TryDemangleErrorKind::CyclicBackref,
+                        ManglingScheme::V0,
+                        offset,
+                    )
+                },
+                // Both fail at the very start only if the prefix is wrong.
+                Err((v0::ParseError::Invalid, 0, _)) if legacy_offset == 0 => TryDemangleError {
+                    kind: TryDemangleErrorKind::BadPrefix,
+                    scheme: None,
+                    offset: 0,
+                    production: None,
+                },
+                Err((v0::ParseError::Invalid, 0, _)) => error(
+                    TryDemangleErrorKind::Invalid,
+                    ManglingScheme::Legacy,
+                    legacy_offset,
+                ),
+                Err((v0::ParseError::Invalid, offset, production)) => TryDemangleError {
+                    production: Some(production),
+                    ..error(TryDemangleErrorKind::Invalid, ManglingScheme::V0, offset)
+                },
             }
-            Err(_) => {
-                println!("error demangling");
-                false
+        }
+    })
+}
+
+/// The same as `try_demangle`, except that v0 symbols are fully validated,
+/// including the targets of their backrefs (and their instantiating crate),
+/// so `Ok` guarantees that printing the symbol (with e.g. `{}` or `{:#}`)
+/// won't emit error markers such as `{invalid syntax}` or `?`, at the cost of
+/// an extra pass over the symbol.
+///
+/// ```
+/// use rustc_demangle::{try_demangle, try_demangle_strict, TryDemangleErrorKind};
+///
+/// // The backref points into the middle of an identifier.
+/// let sym = "_RINvC3foo3barB3_E";
+/// assert_eq!(try_demangle(sym).unwrap().to_string(), "foo::bar::<{invalid syntax}>");
+///
+/// let err = try_demangle_strict(sym).unwrap_err();
+/// assert_eq!((err.kind(), err.offset()), (TryDemangleErrorKind::Invalid, 7));
+/// ```
+pub fn try_demangle_strict<'a>(s: &'a str) -> Result<Demangle<'a>, TryDemangleError> {
+    let sym = try_demangle(s)?;
+    if let Some(DemangleStyle::V0(ref d)) = sym.style {
+        if let Some((err, offset, production)) = d.first_error(&sym.options) {
+            return Err(TryDemangleError {
+                kind: v0_error_kind(err),
+                scheme: Some(ManglingScheme::V0),
+                offset,
+                production: Some(production),
+            });
+        }
+    }
+    Ok(sym)
+}
+
+/// Parses `s` as a v0 symbol, without demangling it, and returns statistics
+/// about its structure, or the details of why it isn't a v0 symbol.
+///
+/// Like `try_demangle`, this only validates `s` up to the targets of its
+/// backrefs, and allows suffixes (e.g. `.llvm.1234`) after the symbol.
+///
+/// ```
+/// use rustc_demangle::{validate_v0, TryDemangleErrorKind};
+///
+/// // `foo::bar::<foo::bar, 3>`, with the generic argument as a backref.
+/// let stats = validate_v0("_RINvC3foo3barB2_Kj3_E").unwrap();
+/// assert_eq!(stats.backrefs(), 1);
+/// assert_eq!(stats.const_args(), 1);
+/// assert_eq!(stats.punycode_identifiers(), 0);
+///
+/// let err = validate_v0("_ZN3foo3barE").unwrap_err();
+/// assert_eq!(err.kind(), TryDemangleErrorKind::BadPrefix);
+/// ```
+pub fn validate_v0(s: &str) -> Result<V0Stats, TryDemangleError> {
+    match v0::demangle_with_stats(s, &DemangleOptions::new()) {
+        Ok((_, suffix, stats)) => {
+            if suffix.is_empty() || is_valid_suffix(suffix) {
+                Ok(stats)
+            } else {
+                Err(TryDemangleError {
+                    kind: TryDemangleErrorKind::InvalidSuffix,
+                    scheme: Some(ManglingScheme::V0),
+                    offset: s.len() - suffix.len(),
+                    production: None,
+                })
+            }
+        }
+        Err((v0::ParseError::Invalid, 0, _)) => Err(TryDemangleError {
+            kind: TryDemangleErrorKind::BadPrefix,
+            scheme: None,
+            offset: 0,
+            production: None,
+        }),
+        Err((err, offset, production)) => Err(TryDemangleError {
+            kind: v0_error_kind(err),
+            scheme: Some(ManglingScheme::V0),
+            offset,
+            production: Some(production),
+        }),
+    }
+}
+
+fn v0_error_kind(err: v0::ParseError) -> TryDemangleErrorKind {
+    match err {
+        v0::ParseError::Invalid => TryDemangleErrorKind::Invalid,
+        v0::ParseError::RecursedTooDeep => TryDemangleErrorKind::RecursionLimit,
+        v0::ParseError::CyclicBackref => TryDemangleErrorKind::CyclicBackref,
+    }
+}
+
+/// Demangles `s` and writes it to `out` (with alternate formatting if
+/// `alternate` is set), failing with the details of the first error found
+/// instead of writing anything if `s` isn't a well-formed Rust symbol.
+///
+/// This is `try_demangle_strict` followed by `Demangle::write_checked`, so the
+/// errors in v0 symbols which can only be found while printing (e.g. in the
+/// targets of backrefs) are also returned as a `TryDemangleError`, with their
+/// offset and production, instead of as `CheckedFmtError::Invalid`.
+///
+/// ```
+/// use rustc_demangle::{demangle_checked, CheckedFmtError, TryDemangleErrorKind};
+///
+/// let mut out = String::new();
+/// demangle_checked("_RNvC3foo3bar", &mut out, false).unwrap();
+/// assert_eq!(out, "foo::bar");
+///
+/// // The backref points into the middle of an identifier.
+/// let mut out = String::new();
+/// match demangle_checked("_RINvC3foo3barB3_E", &mut out, false) {
+///     Err(CheckedFmtError::Parse(err)) => {
+///         assert_eq!((err.kind(), err.offset()), (TryDemangleErrorKind::Invalid, 7));
+///     }
+///     r => panic!("unexpected {:?}", r),
+/// }
+/// assert_eq!(out, "");
+/// ```
+pub fn demangle_checked<W: fmt::Write>(
+    s: &str,
+    out: &mut W,
+    alternate: bool,
+) -> Result<(), CheckedFmtError> {
+    let sym = try_demangle_strict(s).map_err(CheckedFmtError::Parse)?;
+    sym.write_checked(out, alternate)
+}
+
+/// Returns whether `a` and `b` demangle to the same output (as printed by
+/// `{}`, i.e. including hashes and crate disambiguators).
+///
+/// This is the same as `demangle(a) == demangle(b)`, see the `PartialEq`
+/// impl of `Demangle` for the details (and costs) of the comparison, which
+/// doesn't allocate, even with the `alloc` feature.
+///
+/// ```
+/// use rustc_demangle::demangled_eq;
+///
+/// assert!(demangled_eq("_ZN3foo3barE", "_RNvC3foo3bar"));
+/// assert!(demangled_eq("_ZN3foo3barE.llvm.A5310EB9", "_ZN3foo3barE"));
+/// assert!(!demangled_eq("_RNvCs1234_3foo3bar", "_RNvC3foo3bar"));
+/// assert!(demangled_eq("la la la", "la la la"));
+/// ```
+pub fn demangled_eq(a: &str, b: &str) -> bool {
+    demangle(a) == demangle(b)
+}
+
+/// Returns whether `s` looks like a mangled Rust symbol, without demangling
+/// it, which makes it much cheaper than `try_demangle`, for pre-filtering
+/// large numbers of symbols.
+///
+/// Every symbol accepted by `try_demangle` is also accepted by this function,
+/// but the opposite isn't true, as v0 symbols are only checked to have the
+/// right prefix and characters, and to start with a plausible path (e.g. a
+/// crate root with a valid identifier length), not to actually parse.
+///
+/// ```
+/// use rustc_demangle::is_mangled_rust_symbol;
+///
+/// assert!(is_mangled_rust_symbol("_ZN3foo3barE"));
+/// assert!(is_mangled_rust_symbol("_RNvC3foo3bar.llvm.1234"));
+/// assert!(!is_mangled_rust_symbol("_ZN3foo3bar"));
+/// assert!(!is_mangled_rust_symbol("la la la"));
+/// assert!(!is_mangled_rust_symbol("README"));
+/// ```
+pub fn is_mangled_rust_symbol(s: &str) -> bool {
+    let s = &s[linker_prefix(s).len()..];
+
+    // Legacy symbols are cheap to validate, as they're only a list of
+    // length-prefixed elements.
+    let suffix = match legacy::demangle(s) {
+        Ok((_, suffix)) => suffix,
+        Err(_) => {
+            let inner = match s.as_bytes() {
+                [b'_', b'R', ..] => &s[2..],
+                [b'R', ..] => &s[1..],
+                [b'_', b'_', b'R', ..] => &s[3..],
+                _ => return false,
+            };
+            if !starts_with_v0_path(inner.as_bytes(), 0) {
+                return false;
             }
+            let len = inner
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(inner.len());
+            &inner[len..]
+        }
+    };
+    suffix.is_empty() || is_valid_suffix(suffix)
+}
+
+impl<'a> Demangle<'a> {
+    /// Returns the underlying string that's being demangled.
+    pub fn as_str(&self) -> &'a str {
+        self.original
+    }
+
+    /// Returns the whole input this was created from, unlike `as_str`, which
+    /// excludes `.llvm.*` suffixes (as they're ignored while demangling).
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN3fooE.llvm.A5310EB9");
+    /// assert_eq!(sym.as_mangled_str(), "_ZN3fooE.llvm.A5310EB9");
+    /// assert_eq!(sym.as_str(), "_ZN3fooE");
+    /// ```
+    pub fn as_mangled_str(&self) -> &'a str {
+        self.input
+    }
+
+    /// Returns which mangling scheme the symbol was demangled as.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, ManglingScheme};
+    ///
+    /// assert_eq!(demangle("_ZN3foo3barE").mangling_scheme(), ManglingScheme::Legacy);
+    /// assert_eq!(demangle("_RNvC3foo3bar").mangling_scheme(), ManglingScheme::V0);
+    /// assert_eq!(demangle("foo").mangling_scheme(), ManglingScheme::NotMangled);
+    /// ```
+    pub fn mangling_scheme(&self) -> ManglingScheme {
+        match self.style {
+            Some(DemangleStyle::Legacy(_)) => ManglingScheme::Legacy,
+            Some(DemangleStyle::V0(_)) => ManglingScheme::V0,
+            None => ManglingScheme::NotMangled,
+        }
+    }
+
+    /// Returns the name of the crate the symbol is defined in, without
+    /// formatting the rest of the symbol, or `None` if it isn't a Rust symbol.
+    ///
+    /// For legacy symbols, this is the first path element (or, for `impl`s,
+    /// the first element of the self type's path, as trait `impl`s aren't
+    /// otherwise identified). For v0 symbols, it's the crate at the root of
+    /// the path, or the crate the `impl` is in, and `None` if its name is
+    /// punycode-encoded (i.e. not ASCII).
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5tokio7runtime5spawn17h05af221e174051e9E");
+    /// assert_eq!(sym.crate_name(), Some("tokio"));
+    /// let sym = demangle("_RNvXs1_C3fooNtC3bar3BazNtC3std7Default7default");
+    /// assert_eq!(sym.crate_name(), Some("foo"));
+    /// assert_eq!(demangle("main").crate_name(), None);
+    /// ```
+    pub fn crate_name(&self) -> Option<&'a str> {
+        match self.style {
+            Some(DemangleStyle::Legacy(ref d)) => d.crate_name(),
+            Some(DemangleStyle::V0(ref d)) => d.crate_name(),
+            None => None,
         }
     }
 
-    fn ok_err(sym: &str) -> bool {
-        match super::try_demangle(sym) {
-            Ok(_) => {
-                println!("succeeded in demangling");
-                false
-            }
-            Err(_) => super::demangle(sym).to_string() == sym,
-        }
+    /// Returns the disambiguator of the crate the symbol is defined in (see
+    /// `crate_name`), which tells apart crates with the same name, e.g. from
+    /// different builds or versions, and is printed in hex by `{}` (e.g. the
+    /// `[3c1c0]` in `foo[3c1c0]::bar`).
+    ///
+    /// Only v0 symbols have crate disambiguators, and even then, they're
+    /// optional, so this returns `None` for all other symbols.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RNvCs1234_3foo3bar");
+    /// assert_eq!(sym.crate_disambiguator(), Some(0x3c1c0));
+    /// assert_eq!(format!("{:x}", sym.crate_disambiguator().unwrap()), "3c1c0");
+    /// assert_eq!(demangle("_RNvC3foo3bar").crate_disambiguator(), None);
+    /// assert_eq!(demangle("_ZN3foo3barE").crate_disambiguator(), None);
+    /// ```
+    pub fn crate_disambiguator(&self) -> Option<u64> {
+        match self.style {
+            Some(DemangleStyle::V0(ref d)) => d.crate_disambiguator(),
+            _ => None,
+        }
+    }
+
+    /// Prints the symbol like `{}` (or `{:#}`, if `alternate` is set) would,
+    /// but passing its output to `emit` in pieces, along with the kind of the
+    /// token each of them is part of, e.g. for syntax highlighting.
+    ///
+    /// Only v0 symbols are split into tokens, everything printed for others
+    /// (and e.g. the punctuation in v0 symbols) is `TokenKind::Other`. Tokens
+    /// can be split into several pieces, and contain other tokens (e.g. the
+    /// generic arguments of a path segment are part of it).
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, TokenKind};
+    ///
+    /// let mut tokens = vec![];
+    /// demangle("_RINvC3foo3barmE")
+    ///     .write_tokens(false, |kind, s| tokens.push((kind, s.to_string())))
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     tokens,
+    ///     [
+    ///         (TokenKind::Crate, "foo".to_string()),
+    ///         (TokenKind::Other, "::".to_string()),
+    ///         (TokenKind::PathSegment, "bar".to_string()),
+    ///         (TokenKind::GenericOpen, "::<".to_string()),
+    ///         (TokenKind::PrimitiveType, "u32".to_string()),
+    ///         (TokenKind::GenericClose, ">".to_string()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn write_tokens<F>(&self, alternate: bool, emit: F) -> fmt::Result
+    where
+        F: FnMut(TokenKind, &str),
+    {
+        tokens::write_tokens(self, alternate, emit)
+    }
+
+    /// Returns a wrapper printing the symbol like it would be printed itself,
+    /// but colored with ANSI escape codes (based on the kinds of its tokens,
+    /// see `write_tokens`), e.g. for terminals.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RNvC3foo3bar");
+    /// assert_eq!(
+    ///     sym.ansi_colored().to_string(),
+    ///     "\x1b[36mfoo\x1b[0m::\x1b[33mbar\x1b[0m"
+    /// );
+    /// ```
+    #[cfg(feature = "ansi")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "ansi")))]
+    pub fn ansi_colored<'b>(&'b self) -> AnsiColored<'a, 'b> {
+        AnsiColored::new(self)
+    }
+
+    /// Returns a wrapper printing the symbol like it would be printed itself,
+    /// but over multiple lines if it's longer than `width`, with any lists of
+    /// generic arguments which don't fit on their line broken up, and each
+    /// of their arguments on its own (indented) line, like `rustfmt` does.
+    ///
+    /// Only the generic arguments of v0 symbols are broken up (see
+    /// `write_tokens`), and lines can still be longer than `width`, if there
+    /// is nowhere left to break them up.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RINvNtNtC3std3ptr4drop13drop_in_placeINtNtNtC5alloc3vec4into8IntoIterhEEB6_");
+    /// assert_eq!(
+    ///     format!("{:#}", sym.pretty(40)),
+    ///     "std::ptr::drop::drop_in_place::<
+    ///     alloc::vec::into::IntoIter<u8>,
+    /// >"
+    /// );
+    /// assert_eq!(format!("{:#}", sym.pretty(80)), format!("{:#}", sym));
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    pub fn pretty<'b>(&'b self, width: usize) -> Pretty<'a, 'b> {
+        Pretty::new(self, width)
+    }
+
+    /// Returns the last segment of the symbol's path, without formatting the
+    /// rest of it, or `None` if it isn't a Rust symbol.
+    ///
+    /// This is printed like the whole symbol would be (i.e. using its
+    /// options, and without hashes for `{:#}`), except without generic
+    /// arguments, even if the whole symbol would have them, and without any
+    /// suffix. Qualified paths (e.g. `<Foo as Trait>`) are a single segment.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5tokio7runtime5spawn17h05af221e174051e9E");
+    /// assert_eq!(format!("{:#}", sym.function_name().unwrap()), "spawn");
+    /// let sym = demangle("_RNCINvC3foo3barNtC3std6StringE0B6_");
+    /// assert_eq!(sym.function_name().unwrap().to_string(), "{closure#0}");
+    /// assert!(demangle("main").function_name().is_none());
+    /// ```
+    pub fn function_name<'b>(&'b self) -> Option<FunctionName<'a, 'b>> {
+        if self.style.is_some() {
+            Some(FunctionName { sym: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over the segments of the symbol's path, as printed
+    /// by `{:#}` (i.e. without hashes or suffixes), e.g. for building
+    /// breadcrumbs, or nothing if it isn't a Rust symbol.
+    ///
+    /// Segments are separated by `::`, except within generic arguments and
+    /// qualified paths (e.g. `<Foo as Trait>`, which is a single segment),
+    /// and generic arguments (even after `::`) stay with their segment.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RNCINvNtC3std6thread5spawnNtC3foo3BarE0B6_");
+    /// let segments: Vec<_> = sym.path_segments().map(|s| s.to_string()).collect();
+    /// assert_eq!(segments, ["std", "thread", "spawn::<foo::Bar>", "{closure#0}"]);
+    /// ```
+    pub fn path_segments<'b>(&'b self) -> PathSegments<'a, 'b> {
+        let mut counter = SegmentSplitter::new(LenCounter { len: 0 }, None);
+        if let Some(ref style) = self.style {
+            let d = DisplayStyle {
+                style,
+                options: &self.options,
+                last_segment: false,
+                tokens: None,
+            };
+            let _ = write!(counter, "{:#}", SizeLimited(d));
+        }
+        let len = if counter.inner.len > 0 {
+            counter.segment + 1
+        } else {
+            0
+        };
+        PathSegments {
+            sym: self,
+            next: 0,
+            len,
+        }
+    }
+
+    /// Returns the trailing data following the mangled symbol in the input
+    /// (e.g. `.llvm.1234` or `.exit.i.i`), including the parts not printed
+    /// (see `DemangleOptions::suffixes`), or an empty string if the input
+    /// isn't a Rust symbol.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// assert_eq!(demangle("_ZN3fooE").suffix(), "");
+    /// assert_eq!(demangle("_ZN3fooE.exit.i.i").suffix(), ".exit.i.i");
+    /// assert_eq!(demangle("_RNvC3foo3bar.llvm.A5310EB9").suffix(), ".llvm.A5310EB9");
+    /// assert_eq!(demangle("foo.exit").suffix(), "");
+    /// ```
+    pub fn suffix(&self) -> &'a str {
+        match self.style {
+            Some(_) => &self.input[self.mangled_len()..],
+            None => "",
+        }
+    }
+
+    /// Returns the number of bytes at the start of the input which make up
+    /// the mangled symbol proper, i.e. excluding any trailing data such as
+    /// `.llvm.1234` or `.exit.i.i` suffixes, or `0` if the input isn't a
+    /// Rust symbol.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// assert_eq!(demangle("_ZN3fooE").mangled_len(), 8);
+    /// assert_eq!(demangle("_ZN3fooE.exit.i.i").mangled_len(), 8);
+    /// assert_eq!(demangle("_RNvC3foo3bar.llvm.A5310EB9").mangled_len(), 13);
+    /// assert_eq!(demangle("foo").mangled_len(), 0);
+    /// ```
+    pub fn mangled_len(&self) -> usize {
+        match self.style {
+            Some(_) => self.original.len() - self.suffix.len(),
+            None => 0,
+        }
+    }
+
+    /// Writes the demangled symbol to `out` (with alternate formatting if
+    /// `alternate` is set), like `Display` would, except that malformed
+    /// symbols result in an error, instead of e.g. `{invalid syntax}` being
+    /// included in the output.
+    ///
+    /// Some errors can only be found while printing, so the symbol is printed
+    /// twice: once to check it, and again to write it out. Either way, nothing
+    /// is written to `out` for malformed symbols.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, CheckedFmtError};
+    ///
+    /// let mut out = String::new();
+    /// demangle("_RNvC3foo3bar").write_checked(&mut out, false).unwrap();
+    /// assert_eq!(out, "foo::bar");
+    ///
+    /// // The backref points into the middle of an identifier.
+    /// let sym = demangle("_RINvC3foo3barB3_E");
+    /// assert_eq!(sym.to_string(), "foo::bar::<{invalid syntax}>");
+    /// let mut out = String::new();
+    /// assert_eq!(sym.write_checked(&mut out, false), Err(CheckedFmtError::Invalid));
+    /// assert_eq!(out, "");
+    /// ```
+    pub fn write_checked<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        alternate: bool,
+    ) -> Result<(), CheckedFmtError> {
+        let mut check = self.display();
+        check.options.checked = true;
+        let mut counter = LenCounter { len: 0 };
+        let checked = if alternate {
+            write!(counter, "{:#}", check)
+        } else {
+            write!(counter, "{}", check)
+        };
+        // `LenCounter` never fails, so any error comes from the symbol.
+        checked.map_err(|_| CheckedFmtError::Invalid)?;
+
+        if alternate {
+            write!(out, "{:#}", self)
+        } else {
+            write!(out, "{}", self)
+        }
+        .map_err(CheckedFmtError::Fmt)
+    }
+
+    /// Returns the exact length (in bytes) of the output of `{}` (or `{:#}`,
+    /// if `alternate` is `true`), without producing it, e.g. to pre-size a
+    /// `String` or a fixed-size buffer before writing the symbol into it.
+    ///
+    /// The symbol is formatted into a counting sink, so this costs about as
+    /// much as printing the symbol, but never allocates.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN3foo3bar17h05af221e174051e9E");
+    /// assert_eq!(sym.display_len(false), "foo::bar::h05af221e174051e9".len());
+    /// assert_eq!(sym.display_len(true), "foo::bar".len());
+    /// ```
+    pub fn display_len(&self, alternate: bool) -> usize {
+        let mut counter = LenCounter { len: 0 };
+        // `LenCounter` never fails, and neither does printing the symbol.
+        let _ = if alternate {
+            write!(counter, "{:#}", self)
+        } else {
+            write!(counter, "{}", self)
+        };
+        counter.len
+    }
+
+    /// Returns a 64-bit hash of this symbol as printed with `{}`, or, if
+    /// `alternate` is set, with `{:#}`, e.g. as a compact key for "the same
+    /// demangled function" in symbol databases.
+    ///
+    /// Unlike `hash_demangled`, the result doesn't depend on the platform or
+    /// on the version of this crate: it's the 64-bit FNV-1a hash of the
+    /// output (in UTF-8), including any suffix, which is kept stable between
+    /// versions like the output itself (see the crate documentation). What's
+    /// included in the output (e.g. hashes, crate disambiguators, or
+    /// suffixes) can be chosen with `with_options`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, DemangleOptions};
+    ///
+    /// let sym = demangle("_ZN3foo3bar17h05af221e174051e9E");
+    /// assert_eq!(sym.identity_hash(false), 0x600541d87c56f1be);
+    /// assert_eq!(sym.identity_hash(true), 0xe4fa64ea27083a28);
+    /// assert_eq!(demangle("_RNvCs1234_3foo3bar").identity_hash(true), 0xe4fa64ea27083a28);
+    ///
+    /// // Keep the crate disambiguators of v0 symbols, but not legacy hashes.
+    /// let options = DemangleOptions::new().hash(false).crate_disambiguators(true);
+    /// let sym = demangle("_RNvCs1234_3foo3bar").with_options(options);
+    /// assert_eq!(sym.identity_hash(false), 0x5cea637e7c02a3da);
+    /// ```
+    pub fn identity_hash(&self, alternate: bool) -> u64 {
+        let mut fnv = Fnv1a(FNV_OFFSET_BASIS);
+        // `Fnv1a` never fails, and neither does printing the symbol.
+        let _ = if alternate {
+            write!(fnv, "{:#}", self)
+        } else {
+            write!(fnv, "{}", self)
+        };
+        fnv.0
+    }
+
+    /// Feeds the output of `{}` to `state`, streaming it through a fixed-size
+    /// buffer instead of allocating a `String` for it first.
+    ///
+    /// This is what the `Hash` impl of `Demangle` does, so symbols that
+    /// compare equal (see also `demangled_eq`) hash the same.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::hash::Hasher;
+    ///
+    /// let hash = |sym: &str| {
+    ///     let mut state = DefaultHasher::new();
+    ///     demangle(sym).hash_demangled(&mut state);
+    ///     state.finish()
+    /// };
+    /// assert_eq!(hash("_ZN3foo3barE"), hash("_RNvC3foo3bar"));
+    /// ```
+    pub fn hash_demangled<H: Hasher>(&self, state: &mut H) {
+        let mut writer = HashWriter {
+            state,
+            buf: [0; BLOCK_LEN],
+            len: 0,
+        };
+        let _ = write!(writer, "{}", self);
+        writer.finish();
+    }
+
+    /// Returns whether printing this symbol goes past the recursion limit,
+    /// which `demangle` (and `try_demangle`) can't always detect, as they
+    /// don't follow the backrefs of v0 symbols, to avoid repeated work.
+    ///
+    /// When printed, such symbols contain `{recursion limit reached}`, as do
+    /// (by default) those with cyclic backrefs, which aren't reported by this
+    /// method, see `DemangleOptions::cyclic_backrefs`.
+    ///
+    /// ```
+    /// use rustc_demangle::{demangle, demangle_with_options, DemangleOptions};
+    ///
+    /// // The backref's target, `baz::a::b`, nests deeper than the limit
+    /// // allows from where the backref is.
+    /// let options = DemangleOptions::new().recursion_limit(6);
+    /// let sym = demangle_with_options("_RINvC3foo3barNvNvC3baz1a1bBb_E", options);
+    /// assert!(sym.recursion_limit_reached());
+    ///
+    /// assert!(!demangle("_RNvC3foo3bar").recursion_limit_reached());
+    /// // Backrefs which are part of their own targets are reported as such.
+    /// let sym = demangle("_RINvC3foo3barNvB_3bazE");
+    /// assert!(!sym.recursion_limit_reached());
+    /// let sym = sym.with_options(DemangleOptions::new().cyclic_backrefs(true));
+    /// assert_eq!(sym.to_string(), "foo::bar::<foo::bar<{cyclic backref}::?>::baz>");
+    /// ```
+    pub fn recursion_limit_reached(&self) -> bool {
+        match self.style {
+            Some(DemangleStyle::V0(ref d)) => {
+                let first_error = d.first_error(&self.options);
+                first_error.map(|(err, _, _)| err) == Some(v0::ParseError::RecursedTooDeep)
+            }
+            _ => false,
+        }
+    }
+
+    /// Changes the options used when printing this symbol.
+    pub fn with_options(mut self, options: DemangleOptions) -> Demangle<'a> {
+        self.options = options;
+        self
+    }
+
+    /// Returns a wrapper for printing this symbol with some options changed
+    /// inline, e.g. in `format!` arguments.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E");
+    /// assert_eq!(
+    ///     format!("{}", sym.display().no_hash().strip_generics()),
+    ///     "alloc::vec::Vec::push"
+    /// );
+    /// assert_eq!(format!("{}", sym.display().max_len(16)), "alloc::vec::Vec…");
+    /// ```
+    pub fn display<'b>(&'b self) -> DisplayBuilder<'a, 'b> {
+        DisplayBuilder {
+            sym: self,
+            options: self.options,
+            max_len: None,
+            max_width: None,
+            identifier_safe: false,
+            ascii_escaped: false,
+            html_escaped: false,
+            without_hash: false,
+        }
+    }
+
+    /// Returns a wrapper for printing this symbol without any generic
+    /// arguments, wherever they appear (e.g. also in the self types and
+    /// traits of `impl`s), e.g. for grouping all the instances of a generic
+    /// function together. This is a shorthand for
+    /// `display().strip_generics()`, see `DemangleOptions::generics`.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RINvC3foo3barmE");
+    /// assert_eq!(format!("{:#}", sym.without_generics()), "foo::bar");
+    /// let sym = demangle("_RNvXC3fooINtB2_3BarmEINtB2_5TraitNtC3std6StringE3baz");
+    /// assert_eq!(format!("{:#}", sym.without_generics()), "<foo::Bar as foo::Trait>::baz");
+    /// ```
+    pub fn without_generics<'b>(&'b self) -> DisplayBuilder<'a, 'b> {
+        self.display().strip_generics()
+    }
+
+    /// Returns a wrapper for printing this symbol without the hash of a
+    /// legacy symbol, but otherwise exactly as it would be printed, e.g. with
+    /// the crate disambiguators and const type suffixes of v0 symbols still
+    /// following the alternate flag (unlike `{:#}`, which hides all three).
+    /// This is a shorthand for `display().without_hash()`.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN3foo3bar17h05af221e174051e9E");
+    /// assert_eq!(format!("{}", sym.without_hash()), "foo::bar");
+    /// let sym = demangle("_RNvCs1234_3foo3bar");
+    /// assert_eq!(format!("{}", sym.without_hash()), "foo[3c1c0]::bar");
+    /// assert_eq!(format!("{:#}", sym.without_hash()), "foo::bar");
+    /// ```
+    pub fn without_hash<'b>(&'b self) -> DisplayBuilder<'a, 'b> {
+        self.display().without_hash()
+    }
+
+    /// Returns a key for sorting symbols in a stable, human-sensible order.
+    ///
+    /// Symbols are ordered by their path first (so that items from the same
+    /// crate and module are grouped together, with closures and other
+    /// compiler-generated items after the named ones), then by their generic
+    /// arguments, and finally by their hashes and disambiguators.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let mut syms = vec![
+    ///     "_RNCNvC3foo4main0B3_",
+    ///     "_RINvC3foo3barpE",
+    ///     "_RNvC3foo4main",
+    ///     "_RNvC3foo3bar",
+    ///     "_RNvNvC3foo4main5inner",
+    /// ];
+    /// syms.sort_by_key(|sym| demangle(sym).sort_key());
+    /// let syms: Vec<_> = syms.iter().map(|sym| format!("{:#}", demangle(sym))).collect();
+    /// assert_eq!(
+    ///     syms,
+    ///     [
+    ///         "foo::bar",
+    ///         "foo::bar::<_>",
+    ///         "foo::main",
+    ///         "foo::main::inner",
+    ///         "foo::main::{closure#0}",
+    ///     ]
+    /// );
+    /// ```
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn sort_key(&self) -> SortKey {
+        SortKey::new(self)
+    }
+
+    /// Returns whether the demangled symbol name (as printed by `{:#}`,
+    /// i.e. without hashes) starts with `prefix`.
+    ///
+    /// This doesn't allocate, and formatting stops as soon as the answer is
+    /// known, which makes it suitable for e.g. filtering a symbol table by
+    /// module path (`demangled_starts_with("tokio::runtime::")`).
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5tokio7runtime5spawn17h05af221e174051e9E");
+    /// assert!(sym.demangled_starts_with("tokio::runtime::"));
+    /// assert!(!sym.demangled_starts_with("tokio::sync::"));
+    /// ```
+    pub fn demangled_starts_with(&self, prefix: &str) -> bool {
+        let mut matcher = PrefixMatcher {
+            remaining: prefix,
+            mismatch: false,
+        };
+        let _ = write!(matcher, "{:#}", self);
+        !matcher.mismatch && matcher.remaining.is_empty()
+    }
+
+    /// Returns whether the demangled symbol name (as printed by `{:#}`,
+    /// i.e. without hashes) ends with `suffix`.
+    ///
+    /// Like `demangled_starts_with`, this doesn't allocate, but the symbol
+    /// has to be formatted twice: once to find out the length of the output,
+    /// and once more to compare its tail against `suffix`.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5tokio7runtime5spawn17h05af221e174051e9E");
+    /// assert!(sym.demangled_ends_with("::spawn"));
+    /// assert!(!sym.demangled_ends_with("::block_on"));
+    /// ```
+    pub fn demangled_ends_with(&self, suffix: &str) -> bool {
+        let mut counter = LenCounter { len: 0 };
+        if write!(counter, "{:#}", self).is_err() || counter.len < suffix.len() {
+            return false;
+        }
+
+        let mut matcher = SuffixMatcher {
+            skip: counter.len - suffix.len(),
+            remaining: suffix.as_bytes(),
+            mismatch: false,
+        };
+        let _ = write!(matcher, "{:#}", self);
+        !matcher.mismatch && matcher.remaining.is_empty()
+    }
+}
+
+/// Wrapper for printing a `Demangle` with some options changed, see
+/// `Demangle::display`.
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayBuilder<'a, 'b> {
+    sym: &'b Demangle<'a>,
+    options: DemangleOptions,
+    max_len: Option<usize>,
+    max_width: Option<usize>,
+    identifier_safe: bool,
+    ascii_escaped: bool,
+    html_escaped: bool,
+    without_hash: bool,
+}
+
+impl<'a, 'b> DisplayBuilder<'a, 'b> {
+    /// Don't print hashes (or v0 crate disambiguators), even without
+    /// alternate formatting, see `DemangleOptions::hash`.
+    pub fn no_hash(mut self) -> DisplayBuilder<'a, 'b> {
+        self.options = self.options.hash(false);
+        self
+    }
+
+    /// Don't print the hashes of legacy symbols, without affecting anything
+    /// else (unlike `no_hash`, v0 crate disambiguators are still printed
+    /// according to the options and the alternate flag).
+    pub fn without_hash(mut self) -> DisplayBuilder<'a, 'b> {
+        self.without_hash = true;
+        self
+    }
+
+    /// Don't print generic arguments, see `DemangleOptions::generics`.
+    pub fn strip_generics(mut self) -> DisplayBuilder<'a, 'b> {
+        self.options = self.options.generics(false);
+        self
+    }
+
+    /// Only print the first and last segments of paths, see
+    /// `PathElision::CrateAndLeaf`.
+    pub fn crate_and_leaf(mut self) -> DisplayBuilder<'a, 'b> {
+        self.options = self.options.path_elision(PathElision::CrateAndLeaf);
+        self
+    }
+
+    /// Only print the last `n` segments of paths, see
+    /// `PathElision::LastSegments`.
+    pub fn last_segments(mut self, n: usize) -> DisplayBuilder<'a, 'b> {
+        self.options = self.options.path_elision(PathElision::LastSegments(n));
+        self
+    }
+
+    /// Limit the output to `max_len` characters, replacing its end with `…`
+    /// if it would be any longer.
+    pub fn max_len(mut self, max_len: usize) -> DisplayBuilder<'a, 'b> {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Limit the output to `max_width` characters, like `max_len`, but by
+    /// eliding the middle of the symbol first, keeping its crate and leaf
+    /// names visible, e.g. `alloc::…::box_free::<…>`.
+    ///
+    /// If the output would be too long, paths are first shortened to their
+    /// first and last segments (see `PathElision::CrateAndLeaf`), and then
+    /// generic arguments are replaced with `…` too, or left out entirely.
+    /// Only if that's still too long is the end cut off, like with `max_len`
+    /// (but never between the two `:` of a `::`).
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_ZN5alloc5alloc8box_free17h0123456789abcdefE");
+    /// assert_eq!(format!("{:#}", sym.display().max_width(30)), "alloc::alloc::box_free");
+    /// assert_eq!(format!("{:#}", sym.display().max_width(20)), "alloc::…::box_free");
+    ///
+    /// let sym = demangle("_RINvNtC5alloc5alloc8box_freeNtNtC5alloc6string6StringE");
+    /// assert_eq!(format!("{:#}", sym.display().max_width(24)), "alloc::…::box_free::<…>");
+    /// assert_eq!(format!("{:#}", sym.display().max_width(8)), "alloc::…");
+    /// assert_eq!(format!("{:#}", sym.display().max_width(7)), "alloc…");
+    /// ```
+    pub fn max_width(mut self, max_width: usize) -> DisplayBuilder<'a, 'b> {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// Escape the output so it only contains `[A-Za-z0-9_$.]`, allowing it
+    /// to be used as e.g. a symbol or identifier itself.
+    ///
+    /// Every `::` is written as `$$`, and every other character outside of
+    /// `[A-Za-z0-9_.]` as a `$`-delimited escape, reusing the names legacy
+    /// symbols have for some of them (`$LT$` for `<`, `$GT$` for `>`, `$RF$`
+    /// for `&`, `$BP$` for `*`, `$SP$` for `@`, `$LP$` for `(`, `$RP$` for
+    /// `)` and `$C$` for `,`), and `$u...$` (with the code point in lowercase
+    /// hex) for the rest, including `$` itself. As escapes never contain `$`,
+    /// the original output can always be recovered.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RINvCs1234_3foo3barRShE");
+    /// assert_eq!(
+    ///     format!("{:#}", sym.display().identifier_safe()),
+    ///     "foo$$bar$$$LT$$RF$$u5b$u8$u5d$$GT$"
+    /// );
+    /// ```
+    pub fn identifier_safe(mut self) -> DisplayBuilder<'a, 'b> {
+        self.identifier_safe = true;
+        self
+    }
+
+    /// Escape every non-ASCII character in the output as `\u{...}` (with the
+    /// code point in lowercase hex, like `char::escape_unicode`), e.g. for
+    /// punycode-decoded identifiers, or the `…` ending truncated output.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RNvC7mycrateu10main__h9dh");
+    /// assert_eq!(
+    ///     format!("{}", sym.display().ascii_escaped()),
+    ///     "mycrate::main_\\u{3b1}\\u{3b2}"
+    /// );
+    /// ```
+    pub fn ascii_escaped(mut self) -> DisplayBuilder<'a, 'b> {
+        self.ascii_escaped = true;
+        self
+    }
+
+    /// Escape the output for embedding it in HTML (including in attribute
+    /// values), i.e. `&`, `<`, `>`, `"` and `'` as character references.
+    ///
+    /// Escaping is done as the output is printed, so it covers everything,
+    /// including punycode-decoded identifiers and string constants.
+    ///
+    /// ```
+    /// use rustc_demangle::demangle;
+    ///
+    /// let sym = demangle("_RINvC3foo3barRhE");
+    /// assert_eq!(
+    ///     format!("{:#}", sym.display().html_escaped()),
+    ///     "foo::bar::&lt;&amp;u8&gt;"
+    /// );
+    /// ```
+    pub fn html_escaped(mut self) -> DisplayBuilder<'a, 'b> {
+        self.html_escaped = true;
+        self
+    }
+}
+
+impl<'a, 'b> fmt::Display for DisplayBuilder<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Crate disambiguators follow `hash` unless set separately, so they
+        // have to be pinned down before the hash can be turned off.
+        if self.without_hash {
+            let options = self.options;
+            let options = options
+                .crate_disambiguators(options.shows_crate_disambiguators(f.alternate()))
+                .hash(false);
+            let resolved = DisplayBuilder {
+                options,
+                without_hash: false,
+                ..*self
+            };
+            return fmt::Display::fmt(&resolved, f);
+        }
+
+        // A precision (e.g. `{:.80}`) works just like `max_len`.
+        let max_len = match (self.max_len, f.precision()) {
+            (Some(max_len), Some(precision)) => Some(max_len.min(precision)),
+            (max_len, precision) => max_len.or(precision),
+        };
+
+        if let Some(width) = f.width() {
+            let unpadded = DisplayBuilder { max_len, ..*self };
+            // HTML character references only take up one character once rendered.
+            let counted = DisplayBuilder {
+                html_escaped: false,
+                ..unpadded
+            };
+            let alternate = f.alternate();
+            let mut counter = CharCounter { chars: 0 };
+            if alternate {
+                write!(counter, "{:#}", counted)?;
+            } else {
+                write!(counter, "{}", counted)?;
+            }
+            let padding = width.saturating_sub(counter.chars);
+            let (before, after) = match f.align() {
+                Some(fmt::Alignment::Right) => (padding, 0),
+                Some(fmt::Alignment::Center) => (padding / 2, padding - padding / 2),
+                // Like with strings, left alignment is the default.
+                Some(fmt::Alignment::Left) | None => (0, padding),
+            };
+            let fill = f.fill();
+            for _ in 0..before {
+                f.write_char(fill)?;
+            }
+            if alternate {
+                write!(f, "{:#}", unpadded)?;
+            } else {
+                write!(f, "{}", unpadded)?;
+            }
+            for _ in 0..after {
+                f.write_char(fill)?;
+            }
+            return Ok(());
+        }
+
+        if self.html_escaped {
+            let unescaped = DisplayBuilder {
+                max_len,
+                html_escaped: false,
+                ..*self
+            };
+            let alternate = f.alternate();
+            let mut escaper = HtmlEscaper { inner: &mut *f };
+            return if alternate {
+                write!(escaper, "{:#}", unescaped)
+            } else {
+                write!(escaper, "{}", unescaped)
+            };
+        }
+
+        if self.ascii_escaped {
+            let unescaped = DisplayBuilder {
+                max_len,
+                ascii_escaped: false,
+                ..*self
+            };
+            let alternate = f.alternate();
+            let mut escaper = AsciiEscaper { inner: &mut *f };
+            return if alternate {
+                write!(escaper, "{:#}", unescaped)
+            } else {
+                write!(escaper, "{}", unescaped)
+            };
+        }
+
+        if self.identifier_safe {
+            let unescaped = DisplayBuilder {
+                max_len,
+                identifier_safe: false,
+                ..*self
+            };
+            let alternate = f.alternate();
+            let mut escaper = IdentifierEscaper {
+                inner: &mut *f,
+                pending_colon: false,
+            };
+            if alternate {
+                write!(escaper, "{:#}", unescaped)?;
+            } else {
+                write!(escaper, "{}", unescaped)?;
+            }
+            return escaper.finish();
+        }
+
+        if let Some(max_width) = self.max_width {
+            let limit = max_len.map_or(max_width, |max_len| max_len.min(max_width));
+            let full = DisplayBuilder {
+                max_len: None,
+                max_width: None,
+                ..*self
+            };
+            let elided = DisplayBuilder {
+                options: full.options.path_elision(PathElision::CrateAndLeaf),
+                ..full
+            };
+            let mut collapsed = elided;
+            collapsed.options.collapsed_generics = true;
+            let stripped = DisplayBuilder {
+                options: elided.options.generics(false),
+                ..elided
+            };
+
+            let alternate = f.alternate();
+            for candidate in &[full, elided, collapsed, stripped] {
+                let mut counter = CharCounter { chars: 0 };
+                if alternate {
+                    write!(counter, "{:#}", candidate)?;
+                } else {
+                    write!(counter, "{}", candidate)?;
+                }
+                if counter.chars <= limit {
+                    return self.sym.fmt_with(f, &candidate.options);
+                }
+            }
+
+            // Cut off the end, but not between the two `:` of a `::`, which
+            // would be the last two `char`s before the `…`.
+            let mut chars_at = CharsAt {
+                index: limit.saturating_sub(2),
+                seen: 0,
+                chars: [None, None],
+            };
+            let _ = if alternate {
+                write!(chars_at, "{:#}", stripped)
+            } else {
+                write!(chars_at, "{}", stripped)
+            };
+            let limit = match chars_at.chars {
+                [Some(':'), Some(':')] if limit >= 2 => limit - 1,
+                _ => limit,
+            };
+            let truncated = DisplayBuilder {
+                max_len: Some(limit),
+                ..stripped
+            };
+            return fmt::Display::fmt(&truncated, f);
+        }
+
+        let max_len = match max_len {
+            Some(max_len) => max_len,
+            None => return self.sym.fmt_with(f, &self.options),
+        };
+
+        let untruncated = DisplayBuilder {
+            max_len: None,
+            ..*self
+        };
+        let alternate = f.alternate();
+        let mut counter = CharCounter { chars: 0 };
+        if alternate {
+            write!(counter, "{:#}", untruncated)?;
+        } else {
+            write!(counter, "{}", untruncated)?;
+        }
+        if counter.chars <= max_len {
+            return self.sym.fmt_with(f, &self.options);
+        }
+
+        // Leave room for the `…` at the end.
+        let mut truncated = Truncated {
+            remaining: max_len.saturating_sub(1),
+            inner: &mut *f,
+        };
+        let result = if alternate {
+            write!(truncated, "{:#}", untruncated)
+        } else {
+            write!(truncated, "{}", untruncated)
+        };
+        // Running out of room is the only expected error.
+        if truncated.remaining != 0 {
+            result?;
+        }
+        if max_len > 0 {
+            f.write_str("…")?;
+        }
+        Ok(())
+    }
+}
+
+/// The last segment of a symbol's path, see `Demangle::function_name`.
+#[derive(Copy, Clone, Debug)]
+pub struct FunctionName<'a, 'b> {
+    sym: &'b Demangle<'a>,
+}
+
+impl<'a, 'b> fmt::Display for FunctionName<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.sym.style {
+            Some(ref style) => fmt_size_limited(
+                f,
+                DisplayStyle {
+                    style,
+                    options: &self.sym.options,
+                    last_segment: true,
+                    tokens: None,
+                },
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Iterator over the path segments of a symbol, see `Demangle::path_segments`.
+#[derive(Clone, Debug)]
+pub struct PathSegments<'a, 'b> {
+    sym: &'b Demangle<'a>,
+    next: usize,
+    len: usize,
+}
+
+impl<'a, 'b> Iterator for PathSegments<'a, 'b> {
+    type Item = PathSegment<'a, 'b>;
+
+    fn next(&mut self) -> Option<PathSegment<'a, 'b>> {
+        if self.next == self.len {
+            return None;
+        }
+        self.next += 1;
+        Some(PathSegment {
+            sym: self.sym,
+            index: self.next - 1,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a, 'b> ExactSizeIterator for PathSegments<'a, 'b> {}
+
+/// A single path segment of a symbol, see `Demangle::path_segments`.
+///
+/// Printing it formats the whole symbol, only keeping this segment's output.
+#[derive(Copy, Clone, Debug)]
+pub struct PathSegment<'a, 'b> {
+    sym: &'b Demangle<'a>,
+    index: usize,
+}
+
+impl<'a, 'b> fmt::Display for PathSegment<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let style = match self.sym.style {
+            Some(ref style) => style,
+            None => return Ok(()),
+        };
+        let d = DisplayStyle {
+            style,
+            options: &self.sym.options,
+            last_segment: false,
+            tokens: None,
+        };
+        let mut splitter = SegmentSplitter::new(&mut *f, Some(self.index));
+        let result = write!(splitter, "{:#}", SizeLimited(d));
+        // Stopping after the segment is the only expected error.
+        if splitter.segment <= self.index {
+            result?;
+            splitter.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` adapter splitting the output into path segments (see
+/// `Demangle::path_segments`), and only passing through the one numbered
+/// `keep`, stopping the formatting (with `fmt::Error`) after it, or, without
+/// `keep`, passing through everything but the separators.
+struct SegmentSplitter<W> {
+    inner: W,
+    keep: Option<usize>,
+    /// The number of the current segment.
+    segment: usize,
+    /// How many brackets (of any kind) are currently open.
+    depth: usize,
+    /// The last character written.
+    prev: char,
+    /// How many `:` (outside of brackets) were written, but not yet passed
+    /// through, as they may be separating segments.
+    colons: usize,
+}
+
+impl<W: fmt::Write> SegmentSplitter<W> {
+    fn new(inner: W, keep: Option<usize>) -> SegmentSplitter<W> {
+        SegmentSplitter {
+            inner,
+            keep,
+            segment: 0,
+            depth: 0,
+            prev: '\0',
+            colons: 0,
+        }
+    }
+
+    fn pass_through(&mut self, s: &str) -> fmt::Result {
+        match self.keep {
+            Some(keep) if keep < self.segment => Err(fmt::Error),
+            Some(keep) if keep > self.segment => Ok(()),
+            _ => self.inner.write_str(s),
+        }
+    }
+
+    /// Passes through a trailing `:` or `::`, if any.
+    fn finish(&mut self) -> fmt::Result {
+        let colons = ["", ":", "::"][self.colons];
+        self.colons = 0;
+        self.pass_through(colons)
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for SegmentSplitter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.depth == 0 && c == ':' && self.colons < 2 {
+                self.colons += 1;
+                self.prev = c;
+                continue;
+            }
+            match (self.colons, c) {
+                (0, _) => {}
+                // Generic arguments stay with their segment.
+                (2, '<') => self.pass_through("::")?,
+                (2, _) => self.segment += 1,
+                (1, _) => self.pass_through(":")?,
+                _ => unreachable!(),
+            }
+            self.colons = 0;
+
+            match c {
+                '<' | '(' | '[' | '{' => self.depth += 1,
+                // Don't get confused by the `>` in `->`.
+                '>' if self.prev == '-' => {}
+                '>' | ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            self.prev = c;
+            self.pass_through(c.encode_utf8(&mut [0; 4]))?;
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink counting the number of `char`s written to it.
+struct CharCounter {
+    chars: usize,
+}
+
+impl fmt::Write for CharCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.chars += s.chars().count();
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink recording the two `char`s starting at position `index`,
+/// and stopping the formatting (with `fmt::Error`) after them.
+struct CharsAt {
+    index: usize,
+    seen: usize,
+    chars: [Option<char>; 2],
+}
+
+impl fmt::Write for CharsAt {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.seen >= self.index + 2 {
+                return Err(fmt::Error);
+            }
+            if self.seen >= self.index {
+                self.chars[self.seen - self.index] = Some(c);
+            }
+            self.seen += 1;
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` adapter passing through only the first `remaining` `char`s,
+/// and stopping the formatting (with `fmt::Error`) after that.
+struct Truncated<W> {
+    remaining: usize,
+    inner: W,
+}
+
+impl<W: fmt::Write> fmt::Write for Truncated<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match s.char_indices().nth(self.remaining) {
+            Some((i, _)) => {
+                self.inner.write_str(&s[..i])?;
+                self.remaining = 0;
+                Err(fmt::Error)
+            }
+            None => {
+                self.inner.write_str(s)?;
+                self.remaining -= s.chars().count();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `fmt::Write` adapter escaping everything outside of `[A-Za-z0-9_.]`, see
+/// `DisplayBuilder::identifier_safe`.
+struct IdentifierEscaper<W> {
+    inner: W,
+    /// Whether the last `char` written was a `:`, which is only escaped once
+    /// it's known whether it's part of a `::`.
+    pending_colon: bool,
+}
+
+impl<W: fmt::Write> fmt::Write for IdentifierEscaper<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == ':' && self.pending_colon {
+                self.pending_colon = false;
+                self.inner.write_str("$$")?;
+                continue;
+            }
+            self.finish()?;
+            let escape = match c {
+                ':' => {
+                    self.pending_colon = true;
+                    continue;
+                }
+                _ if is_ascii_alphanumeric(c) || c == '_' || c == '.' => {
+                    self.inner.write_char(c)?;
+                    continue;
+                }
+                '<' => "LT",
+                '>' => "GT",
+                '&' => "RF",
+                '*' => "BP",
+                '@' => "SP",
+                '(' => "LP",
+                ')' => "RP",
+                ',' => "C",
+                _ => {
+                    self.inner.write_fmt(format_args!("$u{:x}$", c as u32))?;
+                    continue;
+                }
+            };
+            self.inner.write_str("$")?;
+            self.inner.write_str(escape)?;
+            self.inner.write_str("$")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: fmt::Write> IdentifierEscaper<W> {
+    /// Writes out a trailing lone `:`, if any.
+    fn finish(&mut self) -> fmt::Result {
+        if self.pending_colon {
+            self.pending_colon = false;
+            self.inner.write_str("$u3a$")?;
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` adapter escaping non-ASCII characters as `\u{...}`, see
+/// `DisplayBuilder::ascii_escaped`.
+struct AsciiEscaper<W> {
+    inner: W,
+}
+
+impl<W: fmt::Write> fmt::Write for AsciiEscaper<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c.is_ascii() {
+                self.inner.write_char(c)?;
+            } else {
+                self.inner
+                    .write_fmt(format_args!("\\u{{{:x}}}", c as u32))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` adapter escaping the characters with special meaning in HTML
+/// as character references, see `DisplayBuilder::html_escaped`.
+struct HtmlEscaper<W> {
+    inner: W,
+}
+
+impl<W: fmt::Write> fmt::Write for HtmlEscaper<W> {
+    fn write_str(&mut self, mut s: &str) -> fmt::Result {
+        while let Some(i) = s.find(&['&', '<', '>', '"', '\''][..]) {
+            self.inner.write_str(&s[..i])?;
+            self.inner.write_str(match s.as_bytes()[i] {
+                b'&' => "&amp;",
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'"' => "&quot;",
+                _ => "&#39;",
+            })?;
+            s = &s[i + 1..];
+        }
+        self.inner.write_str(s)
+    }
+}
+
+/// `fmt::Write` sink writing to a byte buffer, and stopping the formatting
+/// (with `fmt::Error`) once it runs out of space, after writing as much as
+/// possible, up to a `char` boundary.
+struct SliceWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    overflowed: bool,
+}
+
+impl<'b> fmt::Write for SliceWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remaining = self.buf.len() - self.len;
+        let mut n = s.len();
+        if n > remaining {
+            n = remaining;
+            while !s.is_char_boundary(n) {
+                n -= 1;
+            }
+            self.overflowed = true;
+        }
+        self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if self.overflowed {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink counting the number of bytes written to it.
+struct LenCounter {
+    len: usize,
+}
+
+impl fmt::Write for LenCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// `fmt::Write` sink computing the 64-bit FNV-1a hash of the bytes written
+/// to it, see `Demangle::identity_hash`.
+struct Fnv1a(u64);
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(FNV_PRIME);
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink comparing the output against an expected prefix, which
+/// stops the formatting early (with `fmt::Error`) once the outcome is known.
+struct PrefixMatcher<'p> {
+    /// The part of the prefix not yet matched by the output.
+    remaining: &'p str,
+    mismatch: bool,
+}
+
+impl<'p> fmt::Write for PrefixMatcher<'p> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() >= self.remaining.len() {
+            self.mismatch = !s.starts_with(self.remaining);
+            self.remaining = "";
+            return Err(fmt::Error);
+        }
+        if !self.remaining.starts_with(s) {
+            self.mismatch = true;
+            return Err(fmt::Error);
+        }
+        self.remaining = &self.remaining[s.len()..];
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink ignoring the first `skip` bytes of the output, and then
+/// comparing the rest of it against the expected suffix.
+struct SuffixMatcher<'p> {
+    skip: usize,
+    /// The part of the suffix not yet matched by the output.
+    remaining: &'p [u8],
+    mismatch: bool,
+}
+
+impl<'p> fmt::Write for SuffixMatcher<'p> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut s = s.as_bytes();
+        if self.skip > 0 {
+            let skipped = self.skip.min(s.len());
+            self.skip -= skipped;
+            s = &s[skipped..];
+        }
+        if !self.remaining.starts_with(s) {
+            self.mismatch = true;
+            return Err(fmt::Error);
+        }
+        self.remaining = &self.remaining[s.len()..];
+        Ok(())
+    }
+}
+
+/// Size of the blocks the output is hashed in, by the `Hash` impl of `Demangle`.
+const BLOCK_LEN: usize = 256;
+
+/// Size of the blocks the output is compared in, by the `PartialEq` impl of
+/// `Demangle`, large enough for the output of almost all symbols to fit in a
+/// single block (see `OutputMatcher`).
+const CMP_BLOCK_LEN: usize = 4096;
+
+/// `fmt::Write` sink ignoring the first `skip` bytes of the output, and then
+/// keeping the next (up to) `CMP_BLOCK_LEN` bytes, stopping the formatting
+/// (with `fmt::Error`) once it has all of them.
+struct Block {
+    skip: usize,
+    buf: [u8; CMP_BLOCK_LEN],
+    len: usize,
+}
+
+impl Block {
+    fn new(sym: &Demangle, skip: usize) -> Block {
+        let mut block = Block {
+            skip,
+            buf: [0; CMP_BLOCK_LEN],
+            len: 0,
+        };
+        let _ = write!(block, "{}", sym);
+        block
+    }
+}
+
+impl fmt::Write for Block {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut s = s.as_bytes();
+        if self.skip > 0 {
+            let skipped = self.skip.min(s.len());
+            self.skip -= skipped;
+            s = &s[skipped..];
+        }
+        let n = s.len().min(CMP_BLOCK_LEN - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&s[..n]);
+        self.len += n;
+        if self.len == CMP_BLOCK_LEN {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink comparing the output against that of `expected`, which
+/// is kept one `Block` at a time, formatting `expected` again (but only up to
+/// the end of the block) whenever the next block is needed, and stopping the
+/// formatting (with `fmt::Error`) at the first mismatch.
+struct OutputMatcher<'a, 's: 'a> {
+    expected: &'a Demangle<'s>,
+    block: Block,
+    /// Offset of `block` in the output of `expected`.
+    block_start: usize,
+    /// How much of `block` has been matched so far.
+    matched: usize,
+    mismatch: bool,
+}
+
+impl<'a, 's> OutputMatcher<'a, 's> {
+    fn new(expected: &'a Demangle<'s>) -> OutputMatcher<'a, 's> {
+        OutputMatcher {
+            expected,
+            block: Block::new(expected, 0),
+            block_start: 0,
+            matched: 0,
+            mismatch: false,
+        }
+    }
+
+    /// Moves on to the next block of the output of `expected`, returning
+    /// `false` if there isn't one (i.e. the whole output has been matched).
+    fn next_block(&mut self) -> bool {
+        // Only the last block can be partial.
+        if self.block.len < CMP_BLOCK_LEN {
+            return false;
+        }
+        self.block_start += CMP_BLOCK_LEN;
+        self.block = Block::new(self.expected, self.block_start);
+        self.matched = 0;
+        self.block.len > 0
+    }
+
+    /// Returns whether the whole output was written, and nothing more.
+    fn finish(mut self) -> bool {
+        !self.mismatch && self.matched == self.block.len && !self.next_block()
+    }
+}
+
+impl<'a, 's> fmt::Write for OutputMatcher<'a, 's> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut s = s.as_bytes();
+        while !s.is_empty() {
+            if self.matched == self.block.len && !self.next_block() {
+                self.mismatch = true;
+                return Err(fmt::Error);
+            }
+            let n = s.len().min(self.block.len - self.matched);
+            if s[..n] != self.block.buf[self.matched..][..n] {
+                self.mismatch = true;
+                return Err(fmt::Error);
+            }
+            self.matched += n;
+            s = &s[n..];
+        }
+        Ok(())
+    }
+}
+
+/// `fmt::Write` sink feeding the output to a `Hasher`, in blocks of exactly
+/// `BLOCK_LEN` bytes (except for the last one, see `HashWriter::finish`), so
+/// that the hash doesn't depend on how the output was split between writes.
+struct HashWriter<'h, H> {
+    state: &'h mut H,
+    buf: [u8; BLOCK_LEN],
+    len: usize,
+}
+
+impl<'h, H: Hasher> fmt::Write for HashWriter<'h, H> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut s = s.as_bytes();
+        while !s.is_empty() {
+            let n = s.len().min(BLOCK_LEN - self.len);
+            self.buf[self.len..][..n].copy_from_slice(&s[..n]);
+            self.len += n;
+            s = &s[n..];
+            if self.len == BLOCK_LEN {
+                self.state.write(&self.buf);
+                self.len = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'h, H: Hasher> HashWriter<'h, H> {
+    fn finish(self) {
+        self.state.write(&self.buf[..self.len]);
+        // Like `str`'s `Hash` impl, to avoid collisions with following data.
+        self.state.write_u8(0xff);
+    }
+}
+
+fn is_symbol_like(s: &str) -> bool {
+    s.chars().all(|c| {
+        // Once `char::is_ascii_punctuation` and `char::is_ascii_alphanumeric`
+        // have been stable for long enough, use those instead for clarity
+        is_ascii_alphanumeric(c) || is_ascii_punctuation(c)
+    })
+}
+
+// Copied from the documentation of `char::is_ascii_alphanumeric`
+fn is_ascii_alphanumeric(c: char) -> bool {
+    match c {
+        '\u{0041}'..='\u{005A}' | '\u{0061}'..='\u{007A}' | '\u{0030}'..='\u{0039}' => true,
+        _ => false,
+    }
+}
+
+// Copied from the documentation of `char::is_ascii_punctuation`
+fn is_ascii_punctuation(c: char) -> bool {
+    match c {
+        '\u{0021}'..='\u{002F}'
+        | '\u{003A}'..='\u{0040}'
+        | '\u{005B}'..='\u{0060}'
+        | '\u{007B}'..='\u{007E}' => true,
+        _ => false,
+    }
+}
+
+/// Helper for printing a `DemangleStyle` with the given options.
+#[derive(Copy, Clone)]
+struct DisplayStyle<'a, 'b> {
+    style: &'b DemangleStyle<'a>,
+    options: &'b DemangleOptions,
+    /// Whether to only print the last path segment, see
+    /// `Demangle::function_name`.
+    last_segment: bool,
+    /// The kind of the token being printed, kept up to date by the printer,
+    /// see `Demangle::write_tokens`.
+    tokens: Option<&'b Cell<TokenKind>>,
+}
+
+impl<'a, 'b> fmt::Display for DisplayStyle<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.style, self.last_segment) {
+            (DemangleStyle::Legacy(d), false) => d.fmt(f, self.options),
+            (DemangleStyle::Legacy(d), true) => d.fmt_last_segment(f, self.options),
+            (DemangleStyle::V0(d), false) => d.fmt(f, self.options, self.tokens),
+            (DemangleStyle::V0(d), true) => d.fmt_last_segment(f, self.options),
+        }
+    }
+}
+
+// Maximum size of the symbol that we'll print.
+const MAX_SIZE: usize = 1_000_000;
+
+#[derive(Copy, Clone, Debug)]
+struct SizeLimitExhausted;
+
+struct SizeLimitedFmtAdapter<F> {
+    remaining: Result<usize, SizeLimitExhausted>,
+    inner: F,
+}
+
+impl<F: fmt::Write> fmt::Write for SizeLimitedFmtAdapter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.remaining = self
+            .remaining
+            .and_then(|r| r.checked_sub(s.len()).ok_or(SizeLimitExhausted));
+
+        match self.remaining {
+            Ok(_) => self.inner.write_str(s),
+            Err(SizeLimitExhausted) => Err(fmt::Error),
+        }
+    }
+}
+
+impl<'a> Demangle<'a> {
+    fn fmt_with(&self, f: &mut fmt::Formatter, options: &DemangleOptions) -> fmt::Result {
+        self.fmt_with_tokens(f, options, None)
+    }
+
+    /// Like `fmt_with`, but keeping track of the kind of the token being
+    /// printed in `tokens`, if any (see `Demangle::write_tokens`).
+    fn fmt_with_tokens(
+        &self,
+        f: &mut fmt::Formatter,
+        options: &DemangleOptions,
+        tokens: Option<&Cell<TokenKind>>,
+    ) -> fmt::Result {
+        match self.style {
+            None if options.runtime_symbols => match runtime_symbol_description(self.original) {
+                Some(description) => {
+                    f.write_str(description)?;
+                    f.write_str(" (")?;
+                    f.write_str(self.original)?;
+                    f.write_str(")")?;
+                }
+                None => f.write_str(self.original)?,
+            },
+            None => f.write_str(self.original)?,
+            Some(ref style) => {
+                f.write_str(self.prefix)?;
+                fmt_size_limited(
+                    f,
+                    DisplayStyle {
+                        style,
+                        options,
+                        last_segment: false,
+                        tokens,
+                    },
+                )?
+            }
+        }
+        match options.suffixes {
+            SuffixPolicy::KeepAll => f.write_str(self.suffix),
+            SuffixPolicy::StripKnown => {
+                // Symbol versions aren't compilation artifacts, so keep them.
+                let (words, version) = split_symbol_version(self.suffix);
+                f.write_str(strip_known_suffixes(words))?;
+                f.write_str(version)
+            }
+            SuffixPolicy::StripAll => Ok(()),
+        }
+    }
+}
+
+/// Helper for printing a `DisplayStyle` with `fmt_size_limited`.
+struct SizeLimited<'a, 'b>(DisplayStyle<'a, 'b>);
+
+impl<'a, 'b> fmt::Display for SizeLimited<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_size_limited(f, self.0)
+    }
+}
+
+/// Prints `d`, cut off with `{size limit reached}` if its output would be
+/// larger than `MAX_SIZE` (or `{output truncated}`, for the smaller limit set
+/// with `DemangleOptions::max_output_bytes`).
+fn fmt_size_limited(f: &mut fmt::Formatter, d: DisplayStyle) -> fmt::Result {
+    let alternate = f.alternate();
+    let budget = d.options.max_output_bytes.filter(|&max| max < MAX_SIZE);
+    let mut size_limited_fmt = SizeLimitedFmtAdapter {
+        remaining: Ok(budget.unwrap_or(MAX_SIZE)),
+        inner: &mut *f,
+    };
+    let fmt_result = if alternate {
+        write!(size_limited_fmt, "{:#}", d)
+    } else {
+        write!(size_limited_fmt, "{}", d)
+    };
+    let size_limit_result = size_limited_fmt.remaining.map(|_| ());
+
+    // Translate a `fmt::Error` generated by `SizeLimitedFmtAdapter`
+    // into an error message, instead of propagating it upwards
+    // (which could cause panicking from inside e.g. `std::io::print`).
+    match (fmt_result, size_limit_result) {
+        (Err(_), Err(SizeLimitExhausted)) => {
+            if d.options.checked {
+                return Err(fmt::Error);
+            }
+            f.write_str(match budget {
+                Some(_) => "{output truncated}",
+                None => "{size limit reached}",
+            })
+        }
+
+        _ => {
+            fmt_result?;
+            size_limit_result.expect("`fmt::Error` from `SizeLimitedFmtAdapter` was discarded");
+            Ok(())
+        }
+    }
+}
+
+/// Returns whether `suffix` (following a symbol) is made of period-delimited
+/// words (e.g. `.llvm.1234` or `.exit.i.i`), and/or an ELF symbol version
+/// (e.g. `@GLIBC_2.2.5` or `@@VERS_1`).
+fn is_valid_suffix(suffix: &str) -> bool {
+    if suffix.starts_with('.') && is_symbol_like(suffix) {
+        return true;
+    }
+    let version = suffix.trim_start_matches('@');
+    let ats = suffix.len() - version.len();
+    (ats == 1 || ats == 2)
+        && !version.is_empty()
+        && !version.contains('@')
+        && is_symbol_like(version)
+}
+
+/// Returns whether `s` starts like a v0 path, by following its leading path
+/// (and type) tags down to a crate root with a valid identifier length, or to
+/// a backref, so that e.g. `README` isn't taken for a v0 symbol.
+///
+/// Tags nested deeper than `depth` allows are assumed to be valid, to bound
+/// the recursion.
+fn starts_with_v0_path(s: &[u8], depth: u32) -> bool {
+    if depth > 64 {
+        return true;
+    }
+    match s.split_first() {
+        Some((b'C', rest)) => starts_with_v0_ident(skip_v0_disambiguator(rest)),
+        Some((b'N', rest)) => match rest.split_first() {
+            Some((ns, rest)) if ns.is_ascii_alphabetic() => starts_with_v0_path(rest, depth + 1),
+            _ => false,
+        },
+        Some((b'M', rest)) | Some((b'X', rest)) => {
+            starts_with_v0_path(skip_v0_disambiguator(rest), depth + 1)
+        }
+        Some((b'Y', rest)) => starts_with_v0_type(rest, depth + 1),
+        Some((b'I', rest)) => starts_with_v0_path(rest, depth + 1),
+        Some((b'B', rest)) => base62_end(rest).is_some(),
+        _ => false,
+    }
+}
+
+/// Returns whether `s` starts like a v0 type, see `starts_with_v0_path`.
+fn starts_with_v0_type(s: &[u8], depth: u32) -> bool {
+    if depth > 64 {
+        return true;
+    }
+    match s.split_first() {
+        Some((c, _)) if c.is_ascii_lowercase() => true,
+        Some((b'A', rest)) | Some((b'S', rest)) | Some((b'P', rest)) | Some((b'O', rest)) => {
+            starts_with_v0_type(rest, depth + 1)
+        }
+        Some((b'R', rest)) | Some((b'Q', rest)) => {
+            let rest = match rest.split_first() {
+                Some((b'L', lifetime)) => match base62_end(lifetime) {
+                    Some(end) => &lifetime[end..],
+                    None => return false,
+                },
+                _ => rest,
+            };
+            starts_with_v0_type(rest, depth + 1)
+        }
+        Some((b'T', _)) | Some((b'F', _)) | Some((b'D', _)) | Some((b'W', _)) => true,
+        _ => starts_with_v0_path(s, depth),
+    }
+}
+
+/// Returns whether `s` starts with an identifier, i.e. an optional `u`
+/// (for Punycode), and a decimal length that the rest of `s` can hold.
+fn starts_with_v0_ident(s: &[u8]) -> bool {
+    let s = match s.split_first() {
+        Some((b'u', rest)) => rest,
+        _ => s,
+    };
+    let digits = s.iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 || (digits > 1 && s[0] == b'0') {
+        return false;
+    }
+    let mut len = 0usize;
+    for &c in &s[..digits] {
+        len = match len
+            .checked_mul(10)
+            .and_then(|len| len.checked_add((c - b'0') as usize))
+        {
+            Some(len) => len,
+            None => return false,
+        };
+    }
+    let rest = &s[digits..];
+    let rest = match rest.split_first() {
+        Some((b'_', rest)) => rest,
+        _ => rest,
+    };
+    len <= rest.len()
+}
+
+/// Skips the `s<base62>_` disambiguator at the start of `s`, if any, or
+/// returns an empty slice if it's unterminated.
+fn skip_v0_disambiguator(s: &[u8]) -> &[u8] {
+    match s.split_first() {
+        Some((b's', rest)) => match base62_end(rest) {
+            Some(end) => &rest[end..],
+            None => &[],
+        },
+        _ => s,
+    }
+}
+
+/// Returns the offset past the `_` terminating the base-62 number at the
+/// start of `s`, if any.
+fn base62_end(s: &[u8]) -> Option<usize> {
+    let digits = s.iter().take_while(|c| c.is_ascii_alphanumeric()).count();
+    match s.get(digits) {
+        Some(b'_') => Some(digits + 1),
+        _ => None,
+    }
+}
+
+/// Splits `suffix` into its period-delimited words and its ELF symbol
+/// version (starting at the first `@`), if any.
+fn split_symbol_version(suffix: &str) -> (&str, &str) {
+    suffix.split_at(suffix.find('@').unwrap_or(suffix.len()))
+}
+
+/// Removes the parts of `suffix` which are known compilation artifacts,
+/// see `SuffixPolicy::StripKnown`.
+fn strip_known_suffixes(mut suffix: &str) -> &str {
+    if let Some(i) = suffix.find(".llvm.") {
+        suffix = &suffix[..i];
+    }
+    while let Some(i) = suffix.rfind('.') {
+        let known = match &suffix[i + 1..] {
+            "cold" | "constprop" | "isra" | "part" | "lto_priv" => true,
+            last => !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()),
+        };
+        if !known {
+            break;
+        }
+        suffix = &suffix[..i];
+    }
+    suffix
+}
+
+/// A precision (e.g. `{:.80}`) limits the output to that many characters,
+/// replacing its end with `…` if it would be any longer, see
+/// `DisplayBuilder::max_len`.
+///
+/// A width, fill and alignment (e.g. `{:>40}`) pad the output like strings
+/// (`DisplayBuilder` supports all of these too).
+///
+/// ```
+/// use rustc_demangle::demangle;
+///
+/// let sym = demangle("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E");
+/// assert_eq!(format!("{:#.16}", sym), "alloc::vec::Vec…");
+/// assert_eq!(format!("{:#.40}", sym), "alloc::vec::Vec<T>::push");
+/// assert_eq!(format!("[{:#30}]", sym), "[alloc::vec::Vec<T>::push      ]");
+/// assert_eq!(format!("[{:*^#30}]", sym), "[***alloc::vec::Vec<T>::push***]");
+/// ```
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.precision().is_some() || f.width().is_some() {
+            return self.display().fmt(f);
+        }
+        self.fmt_with(f, &self.options)
+    }
+}
+
+impl<'a> fmt::Debug for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Symbols are equal if they'd be printed the same by `{}` (e.g. `to_string`),
+/// which includes their hashes and crate disambiguators, unless they're hidden
+/// by the options of either symbol, see `Demangle::with_options`.
+///
+/// Nothing is allocated: the output of `other` is compared against that of
+/// `self` as it's being formatted, stopping at the first difference, with the
+/// output of `self` kept in a fixed-size buffer (of 4096 bytes) on the stack.
+/// Most symbols print shorter than that, and are therefore formatted once.
+///
+/// **Longer outputs have to be compared one block of 4096 bytes at a time**,
+/// formatting `self` once more (up to the end of the block) for each block,
+/// so the cost grows quadratically with the length of the output past that.
+/// Comparing symbols crafted to print close to the size limit (of about a
+/// megabyte) can then take much longer than comparing their outputs would.
+///
+/// ```
+/// use rustc_demangle::demangle;
+///
+/// assert_eq!(demangle("_ZN3foo3barE"), demangle("_RNvC3foo3bar"));
+/// assert_eq!(demangle("_ZN3foo3barE"), demangle("_ZN3foo3barE.llvm.A5310EB9"));
+/// assert_ne!(demangle("_RNvCs1234_3foo3bar"), demangle("_RNvC3foo3bar"));
+/// ```
+impl<'a> PartialEq for Demangle<'a> {
+    fn eq(&self, other: &Demangle<'a>) -> bool {
+        output_eq(self, other)
+    }
+}
+
+fn output_eq(a: &Demangle, b: &Demangle) -> bool {
+    let mut matcher = OutputMatcher::new(a);
+    let _ = write!(matcher, "{}", b);
+    matcher.finish()
+}
+
+impl<'a> Eq for Demangle<'a> {}
+
+/// Hashes the output of `{}`, consistently with `PartialEq` (and without
+/// allocating), so that e.g. a `HashSet<Demangle>` deduplicates symbols by
+/// their demangling.
+impl<'a> Hash for Demangle<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_demangled(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    macro_rules! t {
+        ($a:expr, $b:expr) => {
+            assert!(ok($a, $b))
+        };
+    }
+
+    macro_rules! t_err {
+        ($a:expr) => {
+            assert!(ok_err($a))
+        };
+    }
+
+    macro_rules! t_nohash {
+        ($a:expr, $b:expr) => {{
+            assert_eq!(format!("{:#}", super::demangle($a)), $b);
+        }};
+    }
+
+    fn ok(sym: &str, expected: &str) -> bool {
+        match super::try_demangle(sym) {
+            Ok(s) => {
+                if s.to_string() == expected {
+                    true
+                } else {
+                    println!("\n{}\n!=\n{}\n", s, expected);
+                    false
+                }
+            }
+            Err(_) => {
+                println!("error demangling");
+                false
+            }
+        }
+    }
+
+    fn ok_err(sym: &str) -> bool {
+        match super::try_demangle(sym) {
+            Ok(_) => {
+                println!("succeeded in demangling");
+                false
+            }
+            Err(_) => super::demangle(sym).to_string() == sym,
+        }
+    }
+
+    #[test]
+    fn demangle() {
+        t_err!("test");
+        t!("_ZN4testE", "test");
+        t_err!("_ZN4test");
+        t!("_ZN4test1a2bcE", "test::a::bc");
+    }
+
+    #[test]
+    fn demangle_dollars() {
+        t!("_ZN4$RP$E", ")");
+        t!("_ZN8$RF$testE", "&test");
+        t!("_ZN8$BP$test4foobE", "*test::foob");
+        t!("_ZN9$u20$test4foobE", " test::foob");
+        t!("_ZN35Bar$LT$$u5b$u32$u3b$$u20$4$u5d$$GT$E", "Bar<[u32; 4]>");
+    }
+
+    #[test]
+    fn demangle_many_dollars() {
+        t!("_ZN13test$u20$test4foobE", "test test::foob");
+        t!("_ZN12test$BP$test4foobE", "test*test::foob");
+    }
+
+    #[test]
+    fn demangle_osx() {
+        t!(
+            "__ZN5alloc9allocator6Layout9for_value17h02a996811f781011E",
+            "alloc::allocator::Layout::for_value::h02a996811f781011"
+        );
+        t!("__ZN38_$LT$core..option..Option$LT$T$GT$$GT$6unwrap18_MSG_FILE_LINE_COL17haf7cb8d5824ee659E", "<core::option::Option<T>>::unwrap::_MSG_FILE_LINE_COL::haf7cb8d5824ee659");
+        t!("__ZN4core5slice89_$LT$impl$u20$core..iter..traits..IntoIterator$u20$for$u20$$RF$$u27$a$u20$$u5b$T$u5d$$GT$9into_iter17h450e234d27262170E", "core::slice::<impl core::iter::traits::IntoIterator for &'a [T]>::into_iter::h450e234d27262170");
+    }
+
+    #[test]
+    fn demangle_windows() {
+        t!("ZN4testE", "test");
+        t!("ZN13test$u20$test4foobE", "test test::foob");
+        t!("ZN12test$RF$test4foobE", "test&test::foob");
+    }
+
+    #[test]
+    fn demangle_elements_beginning_with_underscore() {
+        t!("_ZN13_$LT$test$GT$E", "<test>");
+        t!("_ZN28_$u7b$$u7b$closure$u7d$$u7d$E", "{{closure}}");
+        t!("_ZN15__STATIC_FMTSTRE", "__STATIC_FMTSTR");
+    }
+
+    #[test]
+    fn demangle_trait_impls() {
+        t!(
+            "_ZN71_$LT$Test$u20$$u2b$$u20$$u27$static$u20$as$u20$foo..Bar$LT$Test$GT$$GT$3barE",
+            "<Test + 'static as foo::Bar<Test>>::bar"
+        );
+    }
+
+    #[test]
+    fn demangle_without_hash() {
+        let s = "_ZN3foo17h05af221e174051e9E";
+        t!(s, "foo::h05af221e174051e9");
+        t_nohash!(s, "foo");
+    }
+
+    #[test]
+    fn demangle_without_hash_edgecases() {
+        // One element, no hash.
+        t_nohash!("_ZN3fooE", "foo");
+        // Two elements, no hash.
+        t_nohash!("_ZN3foo3barE", "foo::bar");
+        // Longer-than-normal hash.
+        t_nohash!("_ZN3foo20h05af221e174051e9abcE", "foo");
+        // Shorter-than-normal hash.
+        t_nohash!("_ZN3foo5h05afE", "foo");
+        // Valid hash, but not at the end.
+        t_nohash!("_ZN17h05af221e174051e93fooE", "h05af221e174051e9::foo");
+        // Not a valid hash, missing the 'h'.
+        t_nohash!("_ZN3foo16ffaf221e174051e9E", "foo::ffaf221e174051e9");
+        // Not a valid hash, has a non-hex-digit.
+        t_nohash!("_ZN3foo17hg5af221e174051e9E", "foo::hg5af221e174051e9");
+    }
+
+    #[test]
+    fn demangle_thinlto() {
+        // One element, no hash.
+        t!("_ZN3fooE.llvm.9D1C9369", "foo");
+        t!("_ZN3fooE.llvm.9D1C9369@@16", "foo");
+        t_nohash!(
+            "_ZN9backtrace3foo17hbb467fcdaea5d79bE.llvm.A5310EB9",
+            "backtrace::foo"
+        );
+    }
+
+    #[test]
+    fn demangle_llvm_ir_branch_labels() {
+        t!("_ZN4core5slice77_$LT$impl$u20$core..ops..index..IndexMut$LT$I$GT$$u20$for$u20$$u5b$T$u5d$$GT$9index_mut17haf9727c2edfbc47bE.exit.i.i", "core::slice::<impl core::ops::index::IndexMut<I> for [T]>::index_mut::haf9727c2edfbc47b.exit.i.i");
+        t_nohash!("_ZN4core5slice77_$LT$impl$u20$core..ops..index..IndexMut$LT$I$GT$$u20$for$u20$$u5b$T$u5d$$GT$9index_mut17haf9727c2edfbc47bE.exit.i.i", "core::slice::<impl core::ops::index::IndexMut<I> for [T]>::index_mut.exit.i.i");
+    }
+
+    #[test]
+    fn demangle_elf_symbol_versions() {
+        t!("_ZN3fooE@GLIBC_2.2.5", "foo@GLIBC_2.2.5");
+        t!("_ZN3fooE@@VERS_1", "foo@@VERS_1");
+        t!("_ZN3fooE.cold@@VERS_1.0", "foo.cold@@VERS_1.0");
+        t!("_RNvC3foo3bar@@VERS_1", "foo::bar@@VERS_1");
+        t_nohash!(
+            "_ZN3foo3bar17h05af221e174051e9E@GLIBC_2.2.5",
+            "foo::bar@GLIBC_2.2.5"
+        );
+        t_err!("_ZN3fooE@");
+        t_err!("_ZN3fooE@@@VERS_1");
+        t_err!("_ZN3fooE@VERS@1");
+        t_err!("_ZN3fooE@VERS 1");
+    }
+
+    #[test]
+    fn demangle_ignores_suffix_that_doesnt_look_like_a_symbol() {
+        t_err!("_ZN3fooE.llvm moocow");
+    }
+
+    #[test]
+    fn suffixes_option() {
+        use super::{DemangleOptions, SuffixPolicy};
+
+        let t = |sym, policy| {
+            let options = DemangleOptions::new().suffixes(policy);
+            format!("{:#}", super::demangle(sym).with_options(options))
+        };
+        for &(sym, kept, stripped) in &[
+            ("_ZN3fooE.0.0", "foo.0.0", "foo"),
+            ("_ZN3fooE.llvm.1234abcd", "foo.llvm.1234abcd", "foo"),
+            ("_ZN3fooE.exit.i.i", "foo.exit.i.i", "foo.exit.i.i"),
+            ("_ZN3fooE.exit.0", "foo.exit.0", "foo.exit"),
+            ("_ZN3fooE.7.llvm.1234abcd", "foo.7.llvm.1234abcd", "foo"),
+            ("_ZN3fooE.0a", "foo.0a", "foo.0a"),
+            ("_ZN3fooE.cold", "foo.cold", "foo"),
+            ("_ZN3fooE.cold.1", "foo.cold.1", "foo"),
+            ("_ZN3fooE.constprop.0", "foo.constprop.0", "foo"),
+            ("_ZN3fooE.isra.0.cold", "foo.isra.0.cold", "foo"),
+            ("_ZN3fooE.part.0.lto_priv.0", "foo.part.0.lto_priv.0", "foo"),
+            ("_ZN3fooE.coldx", "foo.coldx", "foo.coldx"),
+            ("_ZN3fooE.exit.cold", "foo.exit.cold", "foo.exit"),
+            ("_RNvC3foo3bar.0.0", "foo::bar.0.0", "foo::bar"),
+            (
+                "_RNvC3foo3bar.constprop.1",
+                "foo::bar.constprop.1",
+                "foo::bar",
+            ),
+            ("_ZN3fooE@@VERS_1", "foo@@VERS_1", "foo@@VERS_1"),
+            (
+                "_ZN3fooE.isra.0@GLIBC_2.2.5",
+                "foo.isra.0@GLIBC_2.2.5",
+                "foo@GLIBC_2.2.5",
+            ),
+        ] {
+            assert_eq!(t(sym, SuffixPolicy::KeepAll), kept);
+            assert_eq!(t(sym, SuffixPolicy::StripKnown), stripped);
+            let unsuffixed = &kept[..kept.find(&['.', '@'][..]).unwrap()];
+            assert_eq!(t(sym, SuffixPolicy::StripAll), unsuffixed);
+        }
+    }
+
+    #[test]
+    fn max_output_bytes() {
+        use super::DemangleOptions;
+
+        let t = |sym, max| {
+            let options = DemangleOptions::new().max_output_bytes(max);
+            super::demangle(sym).with_options(options).to_string()
+        };
+        assert_eq!(t("_ZN3foo3barE", 8), "foo::bar");
+        assert_eq!(t("_ZN3foo3barE", 7), "foo::{output truncated}");
+        assert_eq!(t("_RNvC3foo3bar", 8), "foo::bar");
+        assert_eq!(t("_RNvC3foo3bar", 7), "foo::{output truncated}");
+        assert_eq!(t("_RNvC3foo3bar", 0), "{output truncated}");
+        assert_eq!(t("_RNvC3foo3bar.exit", 8), "foo::bar.exit");
+        assert_eq!(t("__imp__RNvC3foo3bar", 5), "__imp_foo::{output truncated}");
+        assert_eq!(t("la la la", 0), "la la la");
+
+        let sym = super::demangle("_RNvC3foo3bar");
+        let options = DemangleOptions::new().max_output_bytes(7);
+        let mut out = String::new();
+        let checked = sym.with_options(options).write_checked(&mut out, false);
+        assert_eq!(checked, Err(super::CheckedFmtError::Invalid));
+    }
+
+    #[test]
+    fn path_elision() {
+        let t = |sym| {
+            let sym = super::demangle(sym);
+            (
+                format!("{:#}", sym.display().crate_and_leaf()),
+                format!("{:#}", sym.display().last_segments(1)),
+                format!("{:#}", sym.display().last_segments(2)),
+            )
+        };
+        let s = |a: &str, b: &str, c: &str| (a.to_string(), b.to_string(), c.to_string());
+        assert_eq!(
+            t("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E"),
+            s("alloc::…::push", "…::push", "…::Vec<T>::push")
+        );
+        assert_eq!(t("_ZN3foo3barE"), s("foo::bar", "…::bar", "foo::bar"));
+        assert_eq!(
+            t("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E"),
+            s(
+                "<alloc::vec::Vec<T> as core::ops::Drop>::drop",
+                "…::drop",
+                "<alloc::vec::Vec<T> as core::ops::Drop>::drop"
+            )
+        );
+        assert_eq!(
+            t("_RINvNtNtC3foo3bar3baz4quuxINtNtC5alloc3vec3VecNtNtC5alloc6string6StringEE"),
+            s(
+                "foo::…::quux::<alloc::…::Vec<alloc::…::String>>",
+                "…::quux::<…::Vec<…::String>>",
+                "…::baz::quux::<…::vec::Vec<…::string::String>>"
+            )
+        );
+        assert_eq!(
+            t("_RNvXs_NtCs1234_3foo3barINtB4_3BazmEINtNtNtC4core3ops5index5IndexjE5index"),
+            s(
+                "<foo::…::Baz<u32> as core::…::Index<usize>>::index",
+                "…::index",
+                "<…::bar::Baz<u32> as …::index::Index<usize>>::index"
+            )
+        );
+        // Generic arguments of elided segments are elided with them.
+        assert_eq!(
+            t("_RNvMNtC3foo3barINtB2_3BazmE3new"),
+            s(
+                "<foo::…::Baz<u32>>::new",
+                "…::new",
+                "<…::bar::Baz<u32>>::new"
+            )
+        );
+        assert_eq!(
+            t("_RNvNvINtC3foo3BarmE3baz4quux"),
+            s("foo::…::quux", "…::quux", "…::baz::quux")
+        );
+
+        // Hashes and disambiguators aren't segments, and are still printed.
+        let sym = super::demangle("_ZN3foo3bar3baz17h05af221e174051e9E");
+        assert_eq!(
+            sym.display().last_segments(2).to_string(),
+            "…::bar::baz::h05af221e174051e9"
+        );
+        let sym = super::demangle("_RNvNtCs1234_3foo3bar3baz");
+        assert_eq!(
+            sym.display().crate_and_leaf().to_string(),
+            "foo[3c1c0]::…::baz"
+        );
+        assert_eq!(sym.display().last_segments(0).to_string(), "…::baz");
+    }
+
+    #[test]
+    fn runtime_symbols() {
+        let options = super::DemangleOptions::new().runtime_symbols(true);
+        let t = |sym| format!("{:#}", super::demangle(sym).with_options(options));
+        assert_eq!(t("__rust_probestack"), "stack probe (__rust_probestack)");
+        assert_eq!(t("___rust_dealloc"), "alloc shim (___rust_dealloc)");
+        assert_eq!(t("__rg_oom"), "alloc error handler (__rg_oom)");
+        assert_eq!(
+            t("rust_eh_personality.llvm.1234"),
+            "unwinding personality (rust_eh_personality)"
+        );
+        assert_eq!(t("malloc"), "malloc");
+        assert_eq!(t("_ZN3foo3barE"), "foo::bar");
+
+        // Off by default.
+        assert_eq!(super::demangle("__rust_alloc").to_string(), "__rust_alloc");
+    }
+
+    #[test]
+    fn linker_prefixes() {
+        use super::DemangleOptions;
+
+        let t = |sym, demangle_prefixes| {
+            let options = DemangleOptions::new().linker_prefixes(demangle_prefixes);
+            let sym = super::demangle_with_options(sym, options);
+            (format!("{}", sym), format!("{:#}", sym))
+        };
+        let same = |sym: &str| (sym.to_string(), sym.to_string());
+        for &(sym, full, alternate) in &[
+            ("__imp__ZN3foo3barE", "__imp_foo::bar", "__imp_foo::bar"),
+            ("__imp_RNvC3foo3bar", "__imp_foo::bar", "__imp_foo::bar"),
+            (
+                "__imp__RNvCs1234_3foo3bar",
+                "__imp_foo[3c1c0]::bar",
+                "__imp_foo::bar",
+            ),
+            (".L_ZN3foo3barE.exit", ".Lfoo::bar.exit", ".Lfoo::bar.exit"),
+            (
+                ".L_ZN3foo3bar17h05af221e174051e9E",
+                ".Lfoo::bar::h05af221e174051e9",
+                ".Lfoo::bar",
+            ),
+        ] {
+            assert_eq!(t(sym, true), (full.to_string(), alternate.to_string()));
+            assert_eq!(t(sym, false), same(sym));
+            assert!(super::try_demangle(sym).is_ok());
+            assert!(super::is_mangled_rust_symbol(sym));
+        }
+        for &sym in &[
+            "__imp_",
+            "__imp_foo",
+            ".L",
+            ".LBB0_1",
+            ".L.str",
+            "_imp__ZN3fooE",
+        ] {
+            assert_eq!(t(sym, true), same(sym));
+            assert!(super::try_demangle(sym).is_err());
+        }
+        assert_eq!(super::demangle("__imp__ZN3fooE").as_str(), "__imp__ZN3fooE");
+
+        // Errors are found after the prefix, but their offsets include it.
+        let t = |sym| {
+            let err = super::try_demangle(sym).unwrap_err();
+            (err.kind(), err.scheme(), err.offset())
+        };
+        use super::ManglingScheme::{Legacy, V0};
+        use super::TryDemangleErrorKind::*;
+        assert_eq!(t("__imp__ZN3fooXE"), (Invalid, Some(Legacy), 13));
+        assert_eq!(t("_ZN3fooXE"), (Invalid, Some(Legacy), 7));
+        assert_eq!(t(".L_RNvC3foo"), (Invalid, Some(V0), 11));
+        assert_eq!(t("__imp__ZN3fooE!"), (InvalidSuffix, Some(Legacy), 14));
+        assert_eq!(t("__imp_foo"), (BadPrefix, None, 0));
+    }
+
+    #[test]
+    fn is_mangled_rust_symbol() {
+        for sym in &[
+            "_ZN4testE",
+            "ZN4testE",
+            "__ZN4testE",
+            "_ZN59_$LT$Test$u20$$u2b$$u20$$u27$static$u20$as$u20$foo..Bar$GT$3barE",
+            "_ZN3fooE.llvm.9D1C9369",
+            "_ZN3fooE.exit.i.i",
+            "_RNvC3foo3bar",
+            "RNvC3foo3bar",
+            "__RNvC3foo3bar",
+            "_RNvC3foo3bar.llvm.1234",
+            "_RNqCs4fqI2P2rA04_11utf8_identsu30____7hkackfecea1cbdathfdh9hlq6y",
+        ] {
+            assert_eq!((sym, super::try_demangle(sym).is_ok()), (sym, true));
+            assert_eq!((sym, super::is_mangled_rust_symbol(sym)), (sym, true));
+        }
+        for sym in &[
+            "",
+            "la la la",
+            "_ZN",
+            "_ZN4test",
+            "_ZN4testE$",
+            "_ZN4testE.ñ",
+            "_R",
+            "_Rfoo",
+            "_RNvC3foo3bar$",
+            "_ZNvC3foo3bar",
+            "README",
+            "RUST_BACKTRACE",
+            "RGBA",
+            "RNA",
+            "RBAC",
+            "RCA",
+            "RYES",
+            "_RC03foo",
+            "_RC9foo",
+        ] {
+            assert_eq!((sym, super::try_demangle(sym).is_ok()), (sym, false));
+            assert_eq!((sym, super::is_mangled_rust_symbol(sym)), (sym, false));
+        }
+
+        // Malformed v0 symbols aren't caught.
+        assert!(super::try_demangle("_RNvC3foo").is_err());
+        assert!(super::is_mangled_rust_symbol("_RNvC3foo"));
+    }
+
+    #[test]
+    fn split_symbol_plus_offset() {
+        use super::split_symbol_plus_offset as split;
+
+        assert_eq!(split("foo+0x12"), ("foo", Some(0x12)));
+        assert_eq!(split("foo+0XaB"), ("foo", Some(0xab)));
+        assert_eq!(split("foo+18"), ("foo", Some(18)));
+        assert_eq!(split("+0"), ("", Some(0)));
+        assert_eq!(split("foo+0x12+3"), ("foo+0x12", Some(3)));
+        assert_eq!(split("foo"), ("foo", None));
+        assert_eq!(split("foo+"), ("foo+", None));
+        assert_eq!(split("foo+0x"), ("foo+0x", None));
+        assert_eq!(split("foo+-1"), ("foo+-1", None));
+        assert_eq!(split("foo+0x12/0x40"), ("foo+0x12/0x40", None));
+        assert_eq!(split("foo+0x1g"), ("foo+0x1g", None));
+        assert_eq!(
+            split("foo+0x10000000000000000"),
+            ("foo+0x10000000000000000", None)
+        );
+    }
+
+    #[test]
+    fn demangle_into() {
+        let t = |s: &str, len: usize| {
+            let mut buf = [b'*'; 128];
+            let r = super::demangle_into(s, &mut buf[..len])
+                .map(|out| out.to_string())
+                .map_err(|err| err.written());
+            // Nothing is written past `len`.
+            assert!(buf[len..].iter().all(|&b| b == b'*'));
+            (
+                r,
+                String::from_utf8(buf[..len].to_vec()).unwrap_or_default(),
+            )
+        };
+        assert_eq!(t("_ZN3foo3barE", 8).0, Ok("foo::bar".to_string()));
+        assert_eq!(t("_ZN3foo3barE", 7).0, Err(7));
+        assert_eq!(t("_ZN3foo3barE", 0).0, Err(0));
+        assert_eq!(t("_ZN3fooE.exit", 8).0, Ok("foo.exit".to_string()));
+        assert_eq!(t("_ZN3fooE.exit", 5).0, Err(5));
+        assert_eq!(t("la la la", 8).0, Ok("la la la".to_string()));
+        assert_eq!(t("la la la", 4).0, Err(4));
+
+        // Truncation happens at `char` boundaries.
+        let sym = "_RNqCs4fqI2P2rA04_11utf8_identsu30____7hkackfecea1cbdathfdh9hlq6y";
+        assert_eq!(
+            t(sym, 128).0,
+            Ok("utf8_idents[317d481089b8c8fe]::საჭმელად_გემრიელი_სადილი".to_string())
+        );
+        let (r, out) = t(sym, 44);
+        assert_eq!(r, Err(43));
+        assert_eq!(&out[..43], "utf8_idents[317d481089b8c8fe]::საჭმ");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn demangle_cow() {
+        use std::borrow::Cow;
+
+        let t = |s| match super::demangle_cow(s) {
+            Cow::Borrowed(b) => {
+                assert_eq!(b.as_ptr(), s.as_ptr());
+                Err(b)
+            }
+            Cow::Owned(o) => Ok(o),
+        };
+        assert_eq!(t("_ZN3foo3barE"), Ok("foo::bar".to_string()));
+        assert_eq!(t("_ZN3fooE.0"), Ok("foo.0".to_string()));
+        assert_eq!(t("_RNvCs1234_3foo3bar"), Ok("foo[3c1c0]::bar".to_string()));
+        assert_eq!(t("main"), Err("main"));
+        assert_eq!(t("_ZN3fooEv"), Err("_ZN3fooEv"));
+        assert_eq!(t(""), Err(""));
+    }
+
+    #[test]
+    fn find_mangled() {
+        let t = |text| {
+            super::find_mangled(text)
+                .map(|(range, sym)| (range, format!("{:#}", sym)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(t(""), []);
+        assert_eq!(t("_ZN _R _ZN3foo _RNvC3foo"), []);
+        assert_eq!(t("_ZN3fooE"), [(0..8, "foo".to_string())]);
+        assert_eq!(
+            t("mov _ZN3foo3bar17h05af221e174051e9E@PLT, _RNvC3foo3baz;"),
+            [
+                (4..35, "foo::bar".to_string()),
+                (41..54, "foo::baz".to_string())
+            ]
+        );
+        assert_eq!(
+            t("αβ(_ZN3fooE.llvm.A5310EB9,_ZN3barE)"),
+            [(5..27, "foo".to_string()), (28..36, "bar".to_string())]
+        );
+        // Candidates start at the first `_ZN` or `_R`, even if that means
+        // missing a symbol right after it.
+        assert_eq!(t("_R_ZN3fooE"), []);
+        assert_eq!(t("x_ZN3fooE"), [(1..9, "foo".to_string())]);
+
+        let mut found = super::find_mangled("_ZN3fooE _ZN3barE");
+        assert!(found.next().is_some());
+        assert!(found.next().is_some());
+        assert!(found.next().is_none());
+        assert!(found.next().is_none());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn demangle_line() {
+        use std::borrow::Cow;
+
+        let t = |line| match super::demangle_line(line) {
+            Cow::Borrowed(line) => (line.to_string(), false),
+            Cow::Owned(line) => (line, true),
+        };
+        let unchanged = |line: &str| (line.to_string(), false);
+        assert_eq!(t(""), unchanged(""));
+        assert_eq!(t("la la la"), unchanged("la la la"));
+        assert_eq!(t("_ZN _R _ZN3foo"), unchanged("_ZN _R _ZN3foo"));
+        assert_eq!(t("_ZN3fooE"), ("foo".to_string(), true));
+        assert_eq!(
+            t("at _ZN3foo3bar17h05af221e174051e9E+0x10, _RNvC3foo3baz."),
+            (
+                "at foo::bar::h05af221e174051e9+0x10, foo::baz.".to_string(),
+                true
+            )
+        );
+        assert_eq!(
+            t("[_ZN3fooE.llvm.A5310EB9][_RNvC3foo3bar]αβ"),
+            ("[foo][foo::bar]αβ".to_string(), true)
+        );
+        assert_eq!(t("x_ZN3fooE_ZN3barE"), unchanged("x_ZN3fooE_ZN3barE"));
+    }
+
+    #[test]
+    fn demangle_bytes() {
+        let t = |s: &[u8]| super::demangle_bytes(s).map(|sym| sym.to_string());
+        assert_eq!(t(b"_ZN3foo3barE"), Ok("foo::bar".to_string()));
+        assert_eq!(t(b"_RNvC3foo3bar\0\0\0"), Ok("foo::bar".to_string()));
+        assert_eq!(t(b"_ZN3foo3barE\0\xff"), Ok("foo::bar".to_string()));
+        assert_eq!(t(b"la la la"), Ok("la la la".to_string()));
+        assert_eq!(t(b"\0_ZN3foo3barE"), Ok("".to_string()));
+        assert_eq!(t(b""), Ok("".to_string()));
+
+        let err = super::demangle_bytes(b"_ZN3fo\xffE").unwrap_err();
+        assert_eq!(err.valid_up_to(), 6);
+        assert!(super::demangle_bytes(b"_RNvC3foo3bar\xc3").is_err());
+    }
+
+    #[test]
+    fn demangle_all() {
+        let syms = ["_ZN3foo3barE", "", "_RNvC7mycrate4main", "la la la"];
+        let all = super::demangle_all(syms.iter().cloned());
+        assert_eq!(all.len(), syms.len());
+        for (sym, d) in syms.iter().zip(all) {
+            assert_eq!(d, super::demangle(sym));
+        }
+
+        #[cfg(feature = "alloc")]
+        assert_eq!(
+            super::demangle_all_to_strings(syms.iter().cloned()),
+            ["foo::bar", "", "mycrate::main", "la la la"]
+        );
+    }
+
+    #[test]
+    fn display_len() {
+        let t = |sym| {
+            let d = super::demangle(sym);
+            assert_eq!(d.display_len(false), d.to_string().len());
+            assert_eq!(d.display_len(true), format!("{:#}", d).len());
+        };
+        t("_ZN3foo3barE");
+        t("_ZN3foo3bar17h05af221e174051e9E.llvm.A5310EB9");
+        t("_RNvCs1234_7mycrate4main");
+        t("_RINvC3foo3barNvB_3bazE");
+        t("_RNvC7mycrateu10main__h9dh");
+        t("la la la");
+        t("");
+    }
+
+    #[test]
+    fn demangled_eq() {
+        let t = |a, b| {
+            let eq = super::demangled_eq(a, b);
+            assert_eq!(eq, super::demangled_eq(b, a));
+            assert_eq!(
+                (a, b, eq),
+                (
+                    a,
+                    b,
+                    super::demangle(a).to_string() == super::demangle(b).to_string()
+                )
+            );
+            eq
+        };
+        assert!(t("_ZN3foo3barE", "_RNvC3foo3bar"));
+        assert!(t("_ZN3foo3barE", "foo::bar"));
+        assert!(!t("_ZN3foo3barE", "_ZN3foo3bazE"));
+        assert!(!t("_ZN3foo3barE", "_ZN3foo3barE.exit"));
+        assert!(!t("_RNvCs1234_3foo3bar", "_RNvCs4321_3foo3bar"));
+
+        // Long enough to be compared in multiple blocks.
+        let long = |last: &str| format!("_ZN{}{}E", "3foo".repeat(100), last);
+        let (bar, baz) = (long("3bar"), long("3baz"));
+        assert!(t(&bar, &bar));
+        assert!(!t(&bar, &baz));
+    }
+
+    #[test]
+    fn demangle_with_options() {
+        use super::DemangleOptions;
+
+        let t = |s, options| super::demangle_with_options(s, options).to_string();
+        let sym = "_RNvCs1234_3foo3bar";
+        let options = DemangleOptions::new().crate_disambiguators(false);
+        assert_eq!(t(sym, options), "foo::bar");
+        let options = DemangleOptions::new()
+            .hash(false)
+            .crate_disambiguators(true);
+        assert_eq!(t(sym, options), "foo[3c1c0]::bar");
+        assert_eq!(t("_ZN3foo17h05af221e174051e9E", options), "foo");
+
+        // The recursion limit applies to both validation and printing.
+        let sym = "_RNvNvC3foo3bar3baz";
+        let options = DemangleOptions::new().recursion_limit(3);
+        assert_eq!(t(sym, options), "foo::bar::baz");
+        let options = DemangleOptions::new().recursion_limit(2);
+        assert_eq!(t(sym, options), sym);
+        let sym = "_RINvC3foo3barNvNvC3baz1a1bBb_E";
+        let options = DemangleOptions::new().recursion_limit(6);
+        assert!(super::demangle_with_options(sym, options).recursion_limit_reached());
+        // The limit can also be raised past the default.
+        let sym = format!("_R{}C3foo{}", "Nv".repeat(600), "3bar".repeat(600));
+        assert_eq!(t(&sym, DemangleOptions::new()), sym);
+        let options = DemangleOptions::new().recursion_limit(1000);
+        assert_eq!(t(&sym, options), format!("foo{}", "::bar".repeat(600)));
+    }
+
+    #[test]
+    fn crate_name() {
+        for &(sym, name) in &[
+            ("_ZN3foo3barE", Some("foo")),
+            ("_ZN3foo17h05af221e174051e9E", Some("foo")),
+            (
+                "_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E",
+                Some("alloc"),
+            ),
+            ("_ZN30_$LT$u32$u20$as$u20$foo..Foo$GT$3fooE", None),
+            ("_ZN21_$LT$$RF$T$u20$as$u20$foo..Foo$GT$3fooE", None),
+            ("_ZN3foo3barE.llvm.A5310EB9", Some("foo")),
+            ("_RNvC3foo3bar", Some("foo")),
+            ("_RNvCs1234_3foo3bar", Some("foo")),
+            ("_RNCNvC3foo3bar0", Some("foo")),
+            ("_RINvC3foo3barNtC3std6StringE", Some("foo")),
+            ("_RNvMC3fooNtC3bar3Baz3new", Some("foo")),
+            ("_RNvYNtC3foo3BarNtC3baz5Trait3new", Some("baz")),
+            ("_RNvYpNtC3baz5Trait3new", Some("baz")),
+            ("_RNvYNtC3foo3BarNtB4_5Trait3new", Some("foo")),
+            ("_RNvNvB2_3bar3baz", None),
+            ("_RNvCu3fo_9a3bar", None),
+            ("_RNvC3foo3bar.llvm.A5310EB9", Some("foo")),
+            ("main", None),
+        ] {
+            assert_eq!((sym, super::demangle(sym).crate_name()), (sym, name));
+        }
+    }
+
+    #[test]
+    fn crate_disambiguator() {
+        for &(sym, dis) in &[
+            ("_RNvCs1234_3foo3bar", Some(0x3c1c0)),
+            ("_RNvCs_3foo3bar", Some(1)),
+            ("_RNvC3foo3bar", None),
+            ("_RNvMs0_Cs1234_3fooNtC3bar3Baz3new", Some(0x3c1c0)),
+            (
+                "_RNvYNtCs1234_3foo3BarNtCs4321_3baz5Trait3new",
+                Some(0xeb96b),
+            ),
+            (
+                "_RNqCs4fqI2P2rA04_11utf8_identsu30____7hkackfecea1cbdathfdh9hlq6y",
+                Some(0x317d481089b8c8fe),
+            ),
+            ("_ZN3foo17h05af221e174051e9E", None),
+            ("main", None),
+        ] {
+            assert_eq!(
+                (sym, super::demangle(sym).crate_disambiguator()),
+                (sym, dis)
+            );
+        }
+    }
+
+    #[cfg(feature = "ansi")]
+    #[test]
+    fn ansi_colored() {
+        let t = |s| format!("{:#}", super::demangle(s).ansi_colored());
+        assert_eq!(
+            t("_RINvC3foo3barmE"),
+            "\x1b[36mfoo\x1b[0m::\x1b[33mbar\x1b[0m\x1b[2m::<\x1b[0m\
+             \x1b[34mu32\x1b[0m\x1b[2m>\x1b[0m"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barB3_E"),
+            "\x1b[36mfoo\x1b[0m::\x1b[33mbar\x1b[0m\x1b[2m::<\x1b[0m\
+             \x1b[1;31m{invalid syntax}\x1b[0m\x1b[2m>\x1b[0m"
+        );
+        // Only v0 symbols have tokens to color.
+        assert_eq!(t("_ZN3foo3barE"), "foo::bar");
+        assert_eq!(t("la la la"), "la la la");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn pretty() {
+        let t = |s, width| format!("{:#}", super::demangle(s).pretty(width));
+
+        // `Map<Filter<Iter<u8>, {closure#0}>, {closure#1}>`.
+        let sym = "_RINvNtNtC3std3ptr4drop13drop_in_placeINtNtNtNtC4core4iter8adapters3map3Map\
+                   INtNtNtNtC4core4iter8adapters6filter6FilterINtNtNtC4core5slice4iter4IterhE\
+                   NCNvC7mycrate4main0ENCNvC7mycrate4mains_0EE";
+        assert_eq!(
+            t(sym, 60),
+            "std::ptr::drop::drop_in_place::<
+    core::iter::adapters::map::Map<
+        core::iter::adapters::filter::Filter<
+            core::slice::iter::Iter<u8>,
+            mycrate::main::{closure#0},
+        >,
+        mycrate::main::{closure#1},
+    >,
+>"
+        );
+        assert_eq!(t(sym, 200), format!("{:#}", super::demangle(sym)));
+
+        // Commas in e.g. tuples and binders aren't line breaks.
+        assert_eq!(
+            t("_RINvC3foo3barTmINtB2_3BazhtEEE", 0),
+            "foo::bar::<
+    (u32, foo::Baz<
+        u8,
+        u16,
+    >),
+>"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barFG0_RL1_mRL0_mEuE", 20),
+            "foo::bar::<
+    for<'a, 'b> fn(&'a u32, &'b u32),
+>"
+        );
+        assert_eq!(
+            t(
+                "_RINvCs1234_3foo3barINtB2_3BazDINtB2_5TraitmEp6OutputmEL_EE",
+                30
+            ),
+            "foo::bar::<
+    foo::Baz<
+        dyn foo::Trait<
+            u32,
+            Output = u32,
+        >,
+    >,
+>"
+        );
+
+        // Only v0 symbols have generic arguments to break up.
+        assert_eq!(
+            t("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E", 0),
+            "alloc::vec::Vec<T>::push"
+        );
+    }
+
+    #[test]
+    fn write_tokens() {
+        use super::TokenKind::*;
+
+        // Adjacent pieces of the same kind are merged, for readability.
+        let tokens = |s, alternate| {
+            let mut tokens: Vec<(super::TokenKind, String)> = vec![];
+            super::demangle(s)
+                .write_tokens(alternate, |kind, s| match tokens.last_mut() {
+                    Some(&mut (last, ref mut text)) if last == kind => text.push_str(s),
+                    _ => tokens.push((kind, s.to_string())),
+                })
+                .unwrap();
+            tokens
+        };
+        let t = |tokens: &[(super::TokenKind, &str)]| {
+            tokens
+                .iter()
+                .map(|&(kind, s)| (kind, s.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tokens("_RNvCs1234_3foo3bar", false),
+            t(&[(Crate, "foo[3c1c0]"), (Other, "::"), (PathSegment, "bar")])
+        );
+        assert_eq!(
+            tokens("_RNvCs1234_3foo3bar", true),
+            t(&[(Crate, "foo"), (Other, "::"), (PathSegment, "bar")])
+        );
+        assert_eq!(
+            tokens("_RINvC3foo3barKj7b_KRe616263_Kb1_E", true),
+            t(&[
+                (Crate, "foo"),
+                (Other, "::"),
+                (PathSegment, "bar"),
+                (GenericOpen, "::<"),
+                (Const, "123"),
+                (Other, ", "),
+                (Const, "\"abc\""),
+                (Other, ", "),
+                (Const, "true"),
+                (GenericClose, ">"),
+            ])
+        );
+        assert_eq!(
+            tokens("_RINvC3foo3barFG_RL0_mEuE", true),
+            t(&[
+                (Crate, "foo"),
+                (Other, "::"),
+                (PathSegment, "bar"),
+                (GenericOpen, "::<"),
+                (Keyword, "for"),
+                (Other, "<"),
+                (Lifetime, "'a"),
+                (Other, "> "),
+                (Keyword, "fn"),
+                (Other, "(&"),
+                (Lifetime, "'a"),
+                (Other, " "),
+                (PrimitiveType, "u32"),
+                (Other, ")"),
+                (GenericClose, ">"),
+            ])
+        );
+        assert_eq!(
+            tokens("_RINvC3foo3barDNtC3std3FmtEL_E", true),
+            t(&[
+                (Crate, "foo"),
+                (Other, "::"),
+                (PathSegment, "bar"),
+                (GenericOpen, "::<"),
+                (Keyword, "dyn"),
+                (Other, " "),
+                (Crate, "std"),
+                (Other, "::"),
+                (PathSegment, "Fmt"),
+                (GenericClose, ">"),
+            ])
+        );
+        assert_eq!(
+            tokens("_RNvMNtC3foo3barNtB2_3Baz3qux", true),
+            t(&[
+                (Other, "<"),
+                (Crate, "foo"),
+                (Other, "::"),
+                (PathSegment, "bar"),
+                (Other, "::"),
+                (PathSegment, "Baz"),
+                (Other, ">::"),
+                (PathSegment, "qux"),
+            ])
+        );
+        assert_eq!(
+            tokens("_RINvC3foo3barB3_E", true),
+            t(&[
+                (Crate, "foo"),
+                (Other, "::"),
+                (PathSegment, "bar"),
+                (GenericOpen, "::<"),
+                (Error, "{invalid syntax}"),
+                (GenericClose, ">"),
+            ])
+        );
+        // Only v0 symbols are split into tokens.
+        assert_eq!(
+            tokens("_ZN3foo3bar17h05af221e174051e9E", true),
+            t(&[(Other, "foo::bar")])
+        );
+    }
+
+    #[test]
+    fn function_name() {
+        let t = |s| {
+            super::demangle(s)
+                .function_name()
+                .map(|name| (name.to_string(), format!("{:#}", name)))
+        };
+        let same = |s: &str| Some((s.to_string(), s.to_string()));
+        assert_eq!(t("_ZN3foo3barE"), same("bar"));
+        assert_eq!(
+            t("_ZN3foo3bar17h05af221e174051e9E"),
+            Some(("h05af221e174051e9".to_string(), "bar".to_string()))
+        );
+        assert_eq!(t("_ZN3foo3barE.llvm.A5310EB9"), same("bar"));
+        assert_eq!(t("_ZN3foo3barE.exit"), same("bar"));
+        assert_eq!(t("_ZN3foo12Vec$LT$T$GT$E"), same("Vec<T>"));
+        assert_eq!(
+            t("_ZN3foo28_$u7b$$u7b$closure$u7d$$u7d$E"),
+            same("{{closure}}")
+        );
+        assert_eq!(
+            t("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E"),
+            Some(("h1bc3a6b0a0a2e5f5".to_string(), "drop".to_string()))
+        );
+        assert_eq!(t("_RNvC3foo3bar"), same("bar"));
+        assert_eq!(t("_RNvCs1234_3foo3bar"), same("bar"));
+        assert_eq!(
+            t("_RCs1234_3foo"),
+            Some(("foo[3c1c0]".to_string(), "foo".to_string()))
+        );
+        assert_eq!(t("_RINvC3foo3barNtC3std6StringE"), same("bar"));
+        assert_eq!(t("_RNCNvC3foo3bar0"), same("{closure#0}"));
+        assert_eq!(t("_RNSNvC3foo3bar6vtable"), same("{shim:vtable#0}"));
+        assert_eq!(t("_RNvMC3fooNtB2_3Bar3new"), same("new"));
+        assert_eq!(t("_RMC3fooNtB0_3Bar"), same("<foo::Bar>"));
+        assert_eq!(
+            t("_RXC3fooINtB0_3BarmENtB0_5Trait"),
+            same("<foo::Bar<u32> as foo::Trait>")
+        );
+        assert_eq!(t("_RNvNvB2_3bar3baz"), same("baz"));
+        assert_eq!(t("_RNvNvC3foo3bar3baz"), same("baz"));
+        assert_eq!(t("_RINvNtC3foo3bar3bazB4_E"), same("baz"));
+        assert_eq!(t("main"), None);
+
+        // The recursion limit also applies to the skipped parts.
+        let sym = format!("_R{}C3foo{}", "Nv".repeat(10), "3bar".repeat(10));
+        let options = super::DemangleOptions::new().recursion_limit(5);
+        let name = super::demangle(&sym).with_options(options);
+        assert_eq!(
+            name.function_name().unwrap().to_string(),
+            "{recursion limit reached}"
+        );
+    }
+
+    #[test]
+    fn path_segments() {
+        let t = |s| {
+            let sym = super::demangle(s);
+            let segments = sym.path_segments();
+            assert_eq!(segments.len(), segments.clone().count());
+            segments.map(|s| s.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(t("_ZN3foo3barE"), ["foo", "bar"]);
+        assert_eq!(t("_ZN3foo17h05af221e174051e9E"), ["foo"]);
+        assert_eq!(t("_ZN3foo3barE.llvm.A5310EB9"), ["foo", "bar"]);
+        assert_eq!(
+            t("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E"),
+            ["<alloc::vec::Vec<T> as core::ops::Drop>", "drop"]
+        );
+        assert_eq!(t("_RCs1234_3foo"), ["foo"]);
+        assert_eq!(t("_RNCNvCs1234_3foo3bar0"), ["foo", "bar", "{closure#0}"]);
+        assert_eq!(
+            t("_RNSNvC3foo3bar6vtable"),
+            ["foo", "bar", "{shim:vtable#0}"]
+        );
+        assert_eq!(t("_RNvMC3fooINtB2_3BarmE3new"), ["<foo::Bar<u32>>", "new"]);
+        assert_eq!(
+            t("_RINvC3foo3barFmENtC3std3FooE"),
+            ["foo", "bar::<fn(u32) -> std::Foo>"]
+        );
+        assert_eq!(
+            t("_RNvINtC3foo3BarNtNtC3std6string6StringE3baz"),
+            ["foo", "Bar::<std::string::String>", "baz"]
+        );
+        assert!(t("main").is_empty());
+        assert_eq!(t("_ZN3fooE.llvm.A5310EB9"), ["foo"]);
+    }
+
+    #[test]
+    fn unambiguous() {
+        let options = super::DemangleOptions::new().unambiguous(true);
+        let t = |s| format!("{:#}", super::demangle(s).with_options(options));
+        for &(sym, expected) in &[
+            ("_RNvNtCs1234_3foo3bar3baz", "foo[3c1c0]::bar::baz"),
+            ("_RNvNtCs1234_3foo3bars_3baz", "foo[3c1c0]::bar::baz[1]"),
+            ("_RNvMC3fooNtB2_3Bar3new", "<foo::Bar>::new"),
+            ("_RNvMs0_C3fooNtB5_3Bar3new", "<foo::Bar>[2]::new"),
+            (
+                "_RNvXs1_C3fooNtB5_3BarNtB5_5Trait3new",
+                "<foo::Bar as foo::Trait>[3]::new",
+            ),
+            ("_RNvNtC3foo3bar0", "foo::bar"),
+            ("_RNvNtC3foo3bars_0", "foo::bar::[1]"),
+            ("_RNCNvC3foo3bar0", "foo::bar::{closure#0}"),
+            ("_RINvC3foo3barKj7b_E", "foo::bar::<123usize>"),
+            ("_ZN3foo17h05af221e174051e9E", "foo::h05af221e174051e9"),
+        ] {
+            assert_eq!((sym, t(sym)), (sym, expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn path_disambiguators_option() {
+        let options = super::DemangleOptions::new().path_disambiguators(true);
+        let t = |s| format!("{:#}", super::demangle(s).with_options(options));
+        for &(sym, expected) in &[
+            ("_RNvNtCs1234_3foo3bars_3baz", "foo::bar::baz[1]"),
+            ("_RNvNtC3foo3bars_0", "foo::bar::[1]"),
+            ("_RNCNvC3foo3bar0", "foo::bar::{closure#0}"),
+            ("_RNvMs0_C3fooNtB5_3Bar3new", "<foo::Bar>[2]::new"),
+            // Only disambiguators are added.
+            ("_RINvC3foo3barKj7b_E", "foo::bar::<123>"),
+            ("_ZN3foo17h05af221e174051e9E", "foo"),
+        ] {
+            assert_eq!((sym, t(sym)), (sym, expected.to_string()));
+        }
+
+        // `unambiguous(false)` turns them off again.
+        let options = options.unambiguous(false);
+        let sym = super::demangle("_RNvNtC3foo3bars_3baz").with_options(options);
+        assert_eq!(sym.to_string(), "foo::bar::baz");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn demangle_utf16() {
+        let utf16 = |s: &str| s.encode_utf16().collect::<Vec<u16>>();
+        let t = |sym: &[u16]| {
+            let mut buf = "leftovers".to_string();
+            let demangled = super::demangle_utf16(sym, &mut buf).to_string();
+            demangled
+        };
+        assert_eq!(t(&utf16("_RNvC3foo3bar")), "foo::bar");
+        assert_eq!(t(&utf16("_ZN3foo3barE\0_ZN3bazE")), "foo::bar");
+        assert_eq!(t(&utf16("_RNvC3foo3bar.llvm.A5310EB9")), "foo::bar");
+        assert_eq!(t(&[]), "");
+        assert_eq!(t(&[0]), "");
+        // Unpaired surrogates.
+        let mut sym = utf16("_ZN3fooE");
+        sym.insert(4, 0xd800);
+        assert_eq!(t(&sym), "_ZN3\u{fffd}fooE");
+        sym.push(0xdc00);
+        assert_eq!(t(&sym), "_ZN3\u{fffd}fooE\u{fffd}");
+    }
+
+    #[test]
+    fn dont_panic() {
+        super::demangle("_ZN2222222222222222222222EE").to_string();
+        super::demangle("_ZN5*70527e27.ll34csaғE").to_string();
+        super::demangle("_ZN5*70527a54.ll34_$b.1E").to_string();
+        super::demangle(
+            "\
+             _ZN5~saäb4e\n\
+             2734cOsbE\n\
+             5usage20h)3\0\0\0\0\0\0\07e2734cOsbE\
+             ",
+        )
+        .to_string();
+    }
+
+    #[test]
+    fn invalid_no_chop() {
+        t_err!("_ZNfooE");
+    }
+
+    #[test]
+    fn handle_assoc_types() {
+        t!("_ZN151_$LT$alloc..boxed..Box$LT$alloc..boxed..FnBox$LT$A$C$$u20$Output$u3d$R$GT$$u20$$u2b$$u20$$u27$a$GT$$u20$as$u20$core..ops..function..FnOnce$LT$A$GT$$GT$9call_once17h69e8f44b3723e1caE", "<alloc::boxed::Box<alloc::boxed::FnBox<A, Output=R> + 'a> as core::ops::function::FnOnce<A>>::call_once::h69e8f44b3723e1ca");
+    }
+
+    #[test]
+    fn handle_bang() {
+        t!(
+            "_ZN88_$LT$core..result..Result$LT$$u21$$C$$u20$E$GT$$u20$as$u20$std..process..Termination$GT$6report17hfc41d0da4a40b3e8E",
+            "<core::result::Result<!, E> as std::process::Termination>::report::hfc41d0da4a40b3e8"
+        );
+    }
+
+    #[test]
+    fn limit_recursion() {
+        assert_contains!(
+            super::demangle("_RNvB_1a").to_string(),
+            "{recursion limit reached}"
+        );
+        assert_contains!(
+            super::demangle("_RMC0RB2_").to_string(),
+            "{recursion limit reached}"
+        );
+        // Unless backrefs which are part of their own targets are caught early.
+        let options = super::DemangleOptions::new().cyclic_backrefs(true);
+        let sym = super::demangle("_RNvB_1a").with_options(options);
+        assert_eq!(sym.to_string(), "{cyclic backref}::?::a");
+        let options = super::DemangleOptions::new().recursion_limit(6);
+        let sym = super::demangle_with_options("_RINvC3foo3barNvNvC3baz1a1bBb_E", options);
+        assert!(sym.to_string().contains("{recursion limit reached}"));
+    }
+
+    #[test]
+    fn limit_output() {
+        assert_ends_with!(
+            super::demangle("RYFG_FGyyEvRYFF_EvRYFFEvERLB_B_B_ERLRjB_B_B_").to_string(),
+            "{size limit reached}"
+        );
+        // NOTE(eddyb) somewhat reduced version of the above, effectively
+        // `<for<...> fn()>` with a larger number of lifetimes in `...`.
+        assert_ends_with!(
+            super::demangle("_RMC0FGZZZ_Eu").to_string(),
+            "{size limit reached}"
+        );
+    }
+
+    #[test]
+    fn demangled_starts_ends_with() {
+        let legacy = super::demangle("_ZN3foo3bar17h05af221e174051e9E");
+        assert!(legacy.demangled_starts_with(""));
+        assert!(legacy.demangled_starts_with("foo::"));
+        assert!(legacy.demangled_starts_with("foo::bar"));
+        assert!(!legacy.demangled_starts_with("foo::bar::"));
+        assert!(!legacy.demangled_starts_with("bar"));
+        assert!(legacy.demangled_ends_with(""));
+        assert!(legacy.demangled_ends_with("::bar"));
+        assert!(legacy.demangled_ends_with("foo::bar"));
+        assert!(!legacy.demangled_ends_with("h05af221e174051e9"));
+        assert!(!legacy.demangled_ends_with("::foo::bar"));
+
+        let v0 = super::demangle("_RNvNtCs1234_5tokio7runtime5spawn");
+        assert!(v0.demangled_starts_with("tokio::runtime::"));
+        assert!(v0.demangled_ends_with("runtime::spawn"));
+        assert!(!v0.demangled_ends_with("sync::spawn"));
+
+        let unmangled = super::demangle("foo");
+        assert!(unmangled.demangled_starts_with("fo"));
+        assert!(unmangled.demangled_ends_with("oo"));
+        assert!(!unmangled.demangled_ends_with("afoo"));
+    }
+
+    #[test]
+    fn const_type_suffixes_option() {
+        let sym = "_RINvCs1234_3foo3barKj7b_Khff_E";
+        let show = super::DemangleOptions::new().const_type_suffixes(true);
+        let hide = super::DemangleOptions::new().const_type_suffixes(false);
+        assert_eq!(
+            format!("{}", super::demangle(sym)),
+            "foo[3c1c0]::bar::<123usize, 255u8>"
+        );
+        assert_eq!(
+            format!("{}", super::demangle(sym).with_options(hide)),
+            "foo[3c1c0]::bar::<123, 255>"
+        );
+        assert_eq!(
+            format!("{:#}", super::demangle(sym).with_options(show)),
+            "foo::bar::<123usize, 255u8>"
+        );
+        assert_eq!(
+            format!("{:#}", super::demangle(sym).with_options(hide)),
+            "foo::bar::<123, 255>"
+        );
+    }
+
+    #[test]
+    fn const_values_option() {
+        let hide = super::DemangleOptions::new().const_values(false);
+        let t = |sym: &str| format!("{}", super::demangle(sym).with_options(hide));
+        assert_eq!(
+            t("_RMC0INtC8arrayvec8ArrayVechKj7b_E"),
+            "<arrayvec::ArrayVec<u8, _>>"
+        );
+        assert_eq!(
+            t("_RMC0INtC8arrayvec8ArrayVechKpE"),
+            "<arrayvec::ArrayVec<u8, _>>"
+        );
+        // Array lengths and aggregates.
+        assert_eq!(t("_RINvC3foo1fAhj3_E"), "foo::f::<[u8; _]>");
+        assert_eq!(t("_RINvC3foo1fKAj1_j2_EE"), "foo::f::<_>");
+    }
+
+    #[test]
+    fn error_offsets_option() {
+        let options = super::DemangleOptions::new().error_offsets(true);
+        let t = |sym: &str| format!("{}", super::demangle(sym).with_options(options));
+        assert_eq!(t("_RNvC3foo3bar"), "foo::bar");
+        assert_eq!(
+            t("_RINvC3foo3barB3_E"),
+            "foo::bar::<{invalid syntax at byte 7}>"
+        );
+        // Prefixes other than `_R` are counted, and so are linker prefixes,
+        // like in `TryDemangleError::offset`.
+        assert_eq!(
+            t("__RINvC3foo3barB3_E"),
+            "foo::bar::<{invalid syntax at byte 8}>"
+        );
+        assert_eq!(
+            t(".L_RINvC3foo3barB3_E"),
+            ".Lfoo::bar::<{invalid syntax at byte 9}>"
+        );
+        let err = super::try_demangle_strict(".L_RINvC3foo3barB3_E").unwrap_err();
+        assert_eq!(err.offset(), 9);
+        // The targets of backrefs are checked separately.
+        assert_eq!(
+            t("_RINvC3foo3barB3_B3_E"),
+            "foo::bar::<{invalid syntax at byte 7}, {invalid syntax at byte 7}>"
+        );
+
+        // Going past the recursion limit only while printing.
+        let options = options.recursion_limit(4);
+        let sym = "_RINvC3foo3barINvB4_3bazNvB4_3quxEE";
+        assert_eq!(
+            format!("{}", super::demangle(sym).with_options(options)),
+            "foo::bar::<{recursion limit reached at byte 17}::?<>>"
+        );
+    }
+
+    #[test]
+    fn const_int_format_options() {
+        let t = |sym: &str, options: super::DemangleOptions| {
+            format!("{}", super::demangle(sym).with_options(options))
+        };
+        let hex = super::DemangleOptions::new().const_hex(true);
+        let grouped = super::DemangleOptions::new().const_digit_separators(true);
+        let both = hex.const_digit_separators(true);
+
+        let sym = "_RINvC3foo3barKj7b_Kj3e8_Kin1e240_E";
+        assert_eq!(
+            t(sym, hex),
+            "foo::bar::<0x7busize, 0x3e8usize, -0x1e240isize>"
+        );
+        assert_eq!(
+            t(sym, grouped),
+            "foo::bar::<123usize, 1_000usize, -123_456isize>"
+        );
+        assert_eq!(
+            t(sym, both),
+            "foo::bar::<0x7busize, 0x3e8usize, -0x1_e240isize>"
+        );
+
+        // Zero, and the largest values.
+        assert_eq!(t("_RIC0Kj0_E", hex), "::<0x0usize>");
+        assert_eq!(t("_RIC0Kj0_E", grouped), "::<0usize>");
+        assert_eq!(
+            t("_RIC0Koffffffffffffffffffffffffffffffff_E", grouped),
+            "::<340_282_366_920_938_463_463_374_607_431_768_211_455u128>"
+        );
+        assert_eq!(
+            t("_RIC0Koffffffffffffffffffffffffffffffff_E", both),
+            "::<0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffffu128>"
+        );
+
+        // Values not fitting in `u128`, which are always printed in hex.
+        let sym = "_RIC0Ko0100000000000000000000000000000000_E";
+        assert_eq!(t(sym, hex), "::<0x100000000000000000000000000000000u128>");
+        assert_eq!(
+            t(sym, grouped),
+            "::<0x01_0000_0000_0000_0000_0000_0000_0000_0000u128>"
+        );
+
+        // Array lengths are also integer constants.
+        assert_eq!(
+            t("_RINvC3foo1fAhj2710_E", both),
+            "foo::f::<[u8; 0x2710usize]>"
+        );
+        assert_eq!(
+            t("_RINvC3foo1fAhj2710_E", grouped),
+            "foo::f::<[u8; 10_000usize]>"
+        );
+    }
+
+    #[test]
+    fn identity_hash() {
+        use std::fmt::Write;
+
+        // Reference values of 64-bit FNV-1a.
+        assert_eq!(super::demangle("").identity_hash(false), 0xcbf29ce484222325);
+        assert_eq!(
+            super::demangle("a").identity_hash(false),
+            0xaf63dc4c8601ec8c
+        );
+
+        let hash = |sym| super::demangle(sym).identity_hash(true);
+        assert_eq!(hash("_ZN3foo3barE"), hash("_RNvC3foo3bar"));
+        assert_eq!(
+            hash("_ZN3foo3barE"),
+            hash("_ZN3foo3bar17h05af221e174051e9E")
+        );
+        assert_ne!(hash("_ZN3foo3barE"), hash("_ZN3foo3bazE"));
+        assert_ne!(hash("_ZN3foo3barE"), hash("_ZN3foo3barE.cold"));
+
+        // Hashes the output, however it's split between writes.
+        let sym = super::demangle("_RINvC3foo3barNtNtC5alloc6string6StringKj4_E");
+        let mut fnv = super::Fnv1a(super::FNV_OFFSET_BASIS);
+        fnv.write_str(&sym.to_string()).unwrap();
+        assert_eq!(sym.identity_hash(false), fnv.0);
+    }
+
+    #[test]
+    fn instantiating_crate_option() {
+        let options = super::DemangleOptions::new().instantiating_crate(true);
+        let t = |sym: &str| {
+            let sym = super::demangle(sym).with_options(options);
+            (format!("{}", sym), format!("{:#}", sym))
+        };
+        let s = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(
+            t("_RNvC3foo3barCs1234_7mycrate"),
+            s("foo::bar (in mycrate[3c1c0])", "foo::bar (in mycrate)")
+        );
+        assert_eq!(
+            t("_RINvC3foo3barNtCs1234_7mycrate3BazEBd_.llvm.1234"),
+            s(
+                "foo::bar::<mycrate[3c1c0]::Baz> (in mycrate[3c1c0])",
+                "foo::bar::<mycrate::Baz> (in mycrate)"
+            )
+        );
+        assert_eq!(t("_RNvC3foo3bar"), s("foo::bar", "foo::bar"));
+
+        // Trailing data isn't mistaken for an instantiating crate.
+        let t = |s: &str| {
+            super::demangle_prefix(s).map(|(sym, _)| format!("{:#}", sym.with_options(options)))
+        };
+        assert_eq!(t("_RNvC3foo3barNope"), Some("foo::bar".to_string()));
+        assert_eq!(
+            t("_RNvC3foo3barC3std|"),
+            Some("foo::bar (in std)".to_string())
+        );
+    }
+
+    #[test]
+    fn impl_paths_option() {
+        let options = super::DemangleOptions::new().impl_paths(true);
+        let t = |sym: &str| format!("{:#}", super::demangle(sym).with_options(options));
+        for &(sym, expected) in &[
+            (
+                "_RNvXNtC3foo3bazNtC3foo3BarNtC3foo5Trait3new",
+                "<foo::Bar as foo::Trait>[impl in foo::baz]::new",
+            ),
+            (
+                "_RNvXs1_C3fooNtB5_3BarNtB5_5Trait3new",
+                "<foo::Bar as foo::Trait>[3][impl in foo]::new",
+            ),
+            (
+                "_RNvXs_NtCs1234_3foo3barINtB4_3BazmEINtNtNtC4core3ops5index5IndexjE5index",
+                "<foo::bar::Baz<u32> as core::ops::index::Index<usize>>[1][impl in foo::bar]::index",
+            ),
+            // Nested `impl`s (here, in a generic argument) get their own paths.
+            (
+                "_RINvC3foo3barNvMNtB2_3bazNtB2_3Qux3newE",
+                "foo::bar::<<foo::Qux>[impl in foo::baz]::new>",
+            ),
+            // `<T as Trait>` paths have no `impl`.
+            (
+                "_RNvYNtC3foo3BarNtB4_5Trait3new",
+                "<foo::Bar as foo::Trait>::new",
+            ),
+        ] {
+            assert_eq!((sym, t(sym)), (sym, expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn generic_placeholders_option() {
+        let options = super::DemangleOptions::new().generic_placeholders(true);
+        let t = |sym: &str| format!("{}", super::demangle(sym).with_options(options));
+        assert_eq!(
+            t("_RINvC3foo3barNtNtC5alloc6string6StringKj4_E"),
+            "foo::bar::<T0, C0>"
+        );
+        assert_eq!(
+            t("_RNvXs_NtCs1234_3foo3barINtB4_3BazmEINtNtNtC4core3ops5index5IndexjE5index"),
+            "<foo[3c1c0]::bar::Baz<T0> as core::ops::index::Index<T1>>::index"
+        );
+        // Lifetimes are kept, and so are the types they appear in.
+        assert_eq!(t("_RINvC3foo3barL_hE"), "foo::bar::<'_, T0>");
+        assert_eq!(t("_RNvMC3fooRL_INtB2_3BarhE3baz"), "<&foo::Bar<T0>>::baz");
+        // Associated type bindings count as type arguments.
+        let sym = "_RNvXC3fooDINtB2_5TraitmEp6OutputmEL_NtB2_3Foo3baz";
+        assert_eq!(t(sym), "<dyn foo::Trait<T0, Output = T1> as foo::Foo>::baz");
+
+        // Hiding generic arguments takes precedence.
+        let sym = super::demangle("_RINvC3foo3barhE").with_options(options.generics(false));
+        assert_eq!(sym.to_string(), "foo::bar");
+        let sym = sym.with_options(options.unambiguous(true));
+        assert_eq!(sym.to_string(), "foo::bar::<u8>");
+    }
+
+    #[test]
+    fn lifetime_names_option() {
+        use super::{DemangleOptions, LifetimeNames};
+
+        // 27 lifetimes, enough to run out of letters.
+        let sym = "_RINvC3foo3barFGp_RL0_hEuE";
+        assert!(format!("{:#}", super::demangle(sym)).ends_with("'y, 'z, '_26> fn(&'_26 u8)>"));
+        let options = DemangleOptions::new()
+            .lifetime_names(LifetimeNames::Custom(|i, f| write!(f, "l{}", i)));
+        assert_eq!(
+            format!("{:#}", super::demangle(sym).with_options(options)),
+            format!(
+                "foo::bar::<for<{}> fn(&'l26 u8)>",
+                (0..27)
+                    .map(|i| format!("'l{}", i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        );
+        let options = DemangleOptions::new().lifetime_names(LifetimeNames::Numeric);
+        assert!(format!("{:#}", super::demangle(sym).with_options(options))
+            .ends_with("'26, '27> fn(&'27 u8)>"));
+    }
+
+    #[test]
+    fn lifetimes_option() {
+        let t = |sym: &str| {
+            let options = super::DemangleOptions::new().lifetimes(false);
+            format!("{:#}", super::demangle(sym).with_options(options))
+        };
+        assert_eq!(t("_RINvC3foo3barFG_RL0_hEuE"), "foo::bar::<fn(&u8)>");
+        assert_eq!(
+            t("_RINvC3foo3barFG_DNtC3foo5TraitEL0_EuE"),
+            "foo::bar::<fn(dyn foo::Trait)>"
+        );
+        assert_eq!(t("_RINvC3foo3barL_hE"), "foo::bar::<u8>");
+        assert_eq!(t("_RINvC3foo3barL_E"), "foo::bar");
+        assert_eq!(
+            t("_RINvC3foo3barDG0_INtC3foo5TraitL0_EEL_E"),
+            "foo::bar::<dyn foo::Trait>"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barDG_INtC3foo5TraitL0_Ep4ItemRL0_hEL_E"),
+            "foo::bar::<dyn foo::Trait<Item = &u8>>"
+        );
+        // Unsafe binders are types of their own, unlike `for<...>` ones.
+        assert_eq!(t("_RINvC3foo3barWG_RL0_hE"), "foo::bar::<unsafe<> &u8>");
+    }
+
+    #[test]
+    fn binders_option() {
+        use super::{Binders, DemangleOptions};
+
+        let t = |sym: &str, binders| {
+            let options = DemangleOptions::new().binders(binders);
+            format!("{:#}", super::demangle(sym).with_options(options))
+        };
+        let sym = "_RINvC3foo3barFG_FG_RL1_hEuEuE";
+        assert_eq!(
+            t(sym, Binders::Collapsed),
+            "foo::bar::<for<…> fn(for<…> fn(&'a u8))>"
+        );
+        assert_eq!(
+            t(sym, Binders::HideUnused),
+            "foo::bar::<for<'a> fn(fn(&'a u8))>"
+        );
+        // Lifetimes keep their names, even with the binders before them hidden.
+        assert_eq!(
+            t("_RINvC3foo3barFG_FG_RL0_hEuEuE", Binders::HideUnused),
+            "foo::bar::<fn(for<'b> fn(&'b u8))>"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barDG_NtC3foo5TraitEL_E", Binders::HideUnused),
+            "foo::bar::<dyn foo::Trait>"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barWG_hE", Binders::HideUnused),
+            "foo::bar::<unsafe<> u8>"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barWG_hE", Binders::Collapsed),
+            "foo::bar::<unsafe<…> u8>"
+        );
+    }
+
+    #[test]
+    fn display_builder() {
+        let sym = super::demangle("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E");
+        assert_eq!(
+            sym.display().to_string(),
+            "<alloc::vec::Vec<T> as core::ops::Drop>::drop::h1bc3a6b0a0a2e5f5"
+        );
+        assert_eq!(
+            sym.display().no_hash().strip_generics().to_string(),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+        assert_eq!(
+            format!("{:#}", sym.display().strip_generics()),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+
+        let sym = super::demangle("_RINvCs1234_3foo3barINtB2_3BazDINtB2_5TraitmEp6OutputmEL_EE");
+        assert_eq!(
+            sym.display().to_string(),
+            "foo[3c1c0]::bar::<foo[3c1c0]::Baz<dyn foo[3c1c0]::Trait<u32, Output = u32>>>"
+        );
+        assert_eq!(
+            sym.display().no_hash().strip_generics().to_string(),
+            "foo::bar"
+        );
+        assert_eq!(
+            format!("{:#}", sym.display().max_len(22)),
+            "foo::bar::<foo::Baz<d…"
+        );
+        assert_eq!(
+            sym.display().no_hash().max_len(62).to_string(),
+            "foo::bar::<foo::Baz<dyn foo::Trait<u32, Output = u32>>>"
+        );
+        assert_eq!(sym.display().max_len(1).to_string(), "…");
+        assert_eq!(sym.display().max_len(0).to_string(), "");
+
+        // Precision works like `max_len`, and the smaller of the two wins.
+        assert_eq!(format!("{:#.22}", sym), "foo::bar::<foo::Baz<d…");
+        assert_eq!(format!("{:#.8}", sym.display().max_len(22)), "foo::ba…");
+        assert_eq!(format!("{:#.22}", sym.display().max_len(8)), "foo::ba…");
+        assert_eq!(format!("{:.0}", sym), "");
+        assert_eq!(format!("{:.100}", super::demangle("la la la")), "la la la");
+        assert_eq!(format!("{:.6}", super::demangle("la la la")), "la la…");
+        assert_eq!(
+            format!("{:#.7}", sym.display().identifier_safe()),
+            "foo$$b$u2026$"
+        );
+
+        // Padding applies to the final (truncated, escaped) output.
+        let sym = super::demangle("_ZN3foo3barE");
+        assert_eq!(format!("[{:10}]", sym), "[foo::bar  ]");
+        assert_eq!(format!("[{:>10}]", sym), "[  foo::bar]");
+        assert_eq!(format!("[{:-^11}]", sym), "[-foo::bar--]");
+        assert_eq!(format!("[{:4}]", sym), "[foo::bar]");
+        assert_eq!(format!("[{:>6.4}]", sym), "[  foo…]");
+        assert_eq!(
+            format!("[{:>12}]", sym.display().identifier_safe()),
+            "[    foo$$bar]"
+        );
+        assert_eq!(format!("[{:>10}]", super::demangle("la")), "[        la]");
+
+        // Generic arguments in `dyn` trait paths can be stripped on their own.
+        let sym = super::demangle("_RINvC3foo3barDINtC3foo5TraitmEp6OutputmEL_E");
+        let options = super::DemangleOptions::new().generics(false);
+        assert_eq!(format!("{:#}", sym.with_options(options)), "foo::bar");
     }
 
     #[test]
-    fn demangle() {
-        t_err!("test");
-        t!("_ZN4testE", "test");
-        t_err!("_ZN4test");
-        t!("_ZN4test1a2bcE", "test::a::bc");
+    fn max_width() {
+        let sym = super::demangle(
+            "_RINvNtNtC3std3ptr4drop13drop_in_placeINtNtNtC5alloc3vec4into8IntoIterhEEB6_",
+        );
+        let t = |n| format!("{:#}", sym.display().max_width(n));
+        assert_eq!(
+            t(100),
+            "std::ptr::drop::drop_in_place::<alloc::vec::into::IntoIter<u8>>"
+        );
+        assert_eq!(t(60), "std::…::drop_in_place::<alloc::…::IntoIter<u8>>");
+        assert_eq!(t(30), "std::…::drop_in_place::<…>");
+        assert_eq!(t(25), "std::…::drop_in_place");
+        assert_eq!(t(20), "std::…::drop_in_pla…");
+        assert_eq!(t(3), "st…");
+        assert_eq!(t(0), "");
+
+        // Never cut between the two `:` of a `::`.
+        assert_eq!(t(10), "std::…::d…");
+        assert_eq!(t(9), "std::…::…");
+        assert_eq!(t(8), "std::……");
+
+        let sym = super::demangle("_RINvCs1234_3foo3barINtB2_3BazDINtB2_5TraitmEp6OutputmEL_EE");
+        assert_eq!(
+            sym.display().max_width(60).to_string(),
+            "foo[3c1c0]::bar::<…>"
+        );
+        assert_eq!(format!("{:#}", sym.display().max_width(10)), "foo::bar");
+
+        // Legacy symbols with generic arguments in `<...>` paths.
+        let sym = super::demangle("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E");
+        assert_eq!(
+            sym.display().max_width(50).to_string(),
+            "<alloc::vec::Vec as core::ops::Drop>::drop::h1bc3…"
+        );
+        assert_eq!(
+            format!("{:#}", sym.display().max_width(40)),
+            "<alloc::vec::Vec as core::ops::Drop>::d…"
+        );
+
+        // `max_len` (or the precision) still applies on top.
+        let sym = super::demangle("_RNvNtNtC3std3ptr4drop13drop_in_place");
+        assert_eq!(
+            format!("{:#}", sym.display().max_width(25).max_len(12)),
+            "std::…::dro…"
+        );
+        assert_eq!(
+            format!("{:#.12}", sym.display().max_width(25)),
+            "std::…::dro…"
+        );
     }
 
     #[test]
-    fn demangle_dollars() {
-        t!("_ZN4$RP$E", ")");
-        t!("_ZN8$RF$testE", "&test");
-        t!("_ZN8$BP$test4foobE", "*test::foob");
-        t!("_ZN9$u20$test4foobE", " test::foob");
-        t!("_ZN35Bar$LT$$u5b$u32$u3b$$u20$4$u5d$$GT$E", "Bar<[u32; 4]>");
+    fn without_hash() {
+        let sym = super::demangle("_ZN3foo3bar17h05af221e174051e9E.cold");
+        assert_eq!(sym.without_hash().to_string(), "foo::bar.cold");
+        assert_eq!(format!("{:#}", sym.without_hash()), "foo::bar.cold");
+
+        // Only the hash is affected, v0 crate disambiguators and const type
+        // suffixes are still printed without the alternate flag.
+        let sym = super::demangle("_RINvCs1234_3foo3barKj1_E");
+        assert_eq!(sym.without_hash().to_string(), "foo[3c1c0]::bar::<1usize>");
+        assert_eq!(format!("{:#}", sym.without_hash()), "foo::bar::<1>");
+
+        // It composes with options, and with the rest of `DisplayBuilder`.
+        let options = super::DemangleOptions::new().crate_disambiguators(false);
+        let sym = sym.with_options(options);
+        assert_eq!(sym.without_hash().to_string(), "foo::bar::<1usize>");
+        let options = super::DemangleOptions::new().hash(true);
+        let sym = sym.with_options(options);
+        assert_eq!(format!("{:#}", sym.without_hash()), "foo[3c1c0]::bar::<1>");
+        assert_eq!(
+            format!("{:.12}", sym.without_hash().strip_generics()),
+            "foo[3c1c0]:…"
+        );
     }
 
     #[test]
-    fn demangle_many_dollars() {
-        t!("_ZN13test$u20$test4foobE", "test test::foob");
-        t!("_ZN12test$BP$test4foobE", "test*test::foob");
+    fn without_generics() {
+        let t = |s| format!("{:#}", super::demangle(s).without_generics());
+        assert_eq!(t("_RINvC3foo3barmE"), "foo::bar");
+        assert_eq!(t("_RNvMC3fooINtB2_3BarmE3new"), "<foo::Bar>::new");
+        assert_eq!(
+            t("_RNvXC3fooINtB2_3BarmEINtB2_5TraitmE3baz"),
+            "<foo::Bar as foo::Trait>::baz"
+        );
+        assert_eq!(t("_RINvC3foo3barRINtB2_3BarmEE"), "foo::bar");
+        assert_eq!(
+            t("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E"),
+            "alloc::vec::Vec::push"
+        );
+        assert_eq!(
+            t("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E"),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+        assert_eq!(t("_ZN3fooE.exit"), "foo.exit");
+        assert_eq!(t("la<la>la"), "la<la>la");
     }
 
     #[test]
-    fn demangle_osx() {
-        t!(
-            "__ZN5alloc9allocator6Layout9for_value17h02a996811f781011E",
-            "alloc::allocator::Layout::for_value::h02a996811f781011"
+    fn ascii_escaped() {
+        let t = |s| format!("{:#}", super::demangle(s).display().ascii_escaped());
+        assert_eq!(t("_ZN3foo3barE"), "foo::bar");
+        assert_eq!(
+            t("_RNvC7mycrateu10main__h9dh"),
+            "mycrate::main_\\u{3b1}\\u{3b2}"
+        );
+        assert_eq!(t("la la ☃"), "la la \\u{2603}");
+
+        let sym = super::demangle("_RNvC7mycrateu10main__h9dh");
+        assert_eq!(
+            sym.display().max_len(9).ascii_escaped().to_string(),
+            "mycrate:\\u{2026}"
+        );
+        // Already ASCII-only.
+        assert_eq!(
+            sym.display().identifier_safe().ascii_escaped().to_string(),
+            sym.display().identifier_safe().to_string()
         );
-        t!("__ZN38_$LT$core..option..Option$LT$T$GT$$GT$6unwrap18_MSG_FILE_LINE_COL17haf7cb8d5824ee659E", "<core::option::Option<T>>::unwrap::_MSG_FILE_LINE_COL::haf7cb8d5824ee659");
-        t!("__ZN4core5slice89_$LT$impl$u20$core..iter..traits..IntoIterator$u20$for$u20$$RF$$u27$a$u20$$u5b$T$u5d$$GT$9into_iter17h450e234d27262170E", "core::slice::<impl core::iter::traits::IntoIterator for &'a [T]>::into_iter::h450e234d27262170");
     }
 
     #[test]
-    fn demangle_windows() {
-        t!("ZN4testE", "test");
-        t!("ZN13test$u20$test4foobE", "test test::foob");
-        t!("ZN12test$RF$test4foobE", "test&test::foob");
+    fn html_escaped() {
+        let t = |s| format!("{:#}", super::demangle(s).display().html_escaped());
+        assert_eq!(t("_ZN3foo3barE"), "foo::bar");
+        assert_eq!(
+            t("_ZN5alloc3vec12Vec$LT$T$GT$4push17h05af221e174051e9E"),
+            "alloc::vec::Vec&lt;T&gt;::push"
+        );
+        assert_eq!(t("_RNvC7mycrateu10main__h9dh"), "mycrate::main_αβ");
+        assert_eq!(
+            t("_RINvC3foo3barKRe22273c26_E"),
+            "foo::bar::&lt;&quot;\\&quot;&#39;&lt;&amp;&quot;&gt;"
+        );
+        assert_eq!(t("a<b>"), "a&lt;b&gt;");
+
+        // Truncation and padding count the characters of the unescaped output.
+        let sym = super::demangle("_RINvC3foo3barRhE");
+        assert_eq!(
+            format!("{:#}", sym.display().max_len(12).html_escaped()),
+            "foo::bar::&lt;…"
+        );
+        assert_eq!(
+            format!("{:#>17}", sym.display().html_escaped()),
+            "##foo::bar::&lt;&amp;u8&gt;"
+        );
     }
 
     #[test]
-    fn demangle_elements_beginning_with_underscore() {
-        t!("_ZN13_$LT$test$GT$E", "<test>");
-        t!("_ZN28_$u7b$$u7b$closure$u7d$$u7d$E", "{{closure}}");
-        t!("_ZN15__STATIC_FMTSTRE", "__STATIC_FMTSTR");
+    fn identifier_safe() {
+        let t = |s| format!("{:#}", super::demangle(s).display().identifier_safe());
+        assert_eq!(
+            t("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h1bc3a6b0a0a2e5f5E"),
+            "$LT$alloc$$vec$$Vec$LT$T$GT$$u20$as$u20$core$$ops$$Drop$GT$$$drop"
+        );
+        assert_eq!(
+            t("_RNvNSC3foo6vtable0"),
+            "foo$$$u7b$shim$u3a$vtable$u23$0$u7d$"
+        );
+        assert_eq!(
+            t("_RINvC3foo3barTmFEuEE"),
+            "foo$$bar$$$LT$$LP$u32$C$$u20$fn$LP$$RP$$RP$$GT$"
+        );
+        assert_eq!(t("_ZN3foo3barE.llvm.A5310EB9"), "foo$$bar");
+        assert_eq!(t("_ZN3fooE.exit"), "foo.exit");
+        assert_eq!(t("la$la:la"), "la$u24$la$u3a$la");
+        assert_eq!(t("trailing:"), "trailing$u3a$");
+        assert_eq!(t("café"), "caf$ue9$");
+
+        // Truncation happens before escaping.
+        let sym = super::demangle("_ZN3foo3barE");
+        assert_eq!(
+            sym.display().max_len(7).identifier_safe().to_string(),
+            "foo$$b$u2026$"
+        );
     }
 
     #[test]
-    fn demangle_trait_impls() {
-        t!(
-            "_ZN71_$LT$Test$u20$$u2b$$u20$$u27$static$u20$as$u20$foo..Bar$LT$Test$GT$$GT$3barE",
-            "<Test + 'static as foo::Bar<Test>>::bar"
+    fn write_checked() {
+        use super::CheckedFmtError;
+
+        let check = |sym: &str, alternate| {
+            let mut out = String::new();
+            super::demangle(sym)
+                .write_checked(&mut out, alternate)
+                .map(|()| out)
+        };
+        assert_eq!(
+            check("_ZN3foo17h05af221e174051e9E", false),
+            Ok("foo::h05af221e174051e9".to_string())
+        );
+        assert_eq!(
+            check("_ZN3foo17h05af221e174051e9E", true),
+            Ok("foo".to_string())
+        );
+        assert_eq!(check("la la la", false), Ok("la la la".to_string()));
+        assert_eq!(check("_RNvB0_3bar", false), Err(CheckedFmtError::Invalid));
+        assert_eq!(check("_RNvB_3bar", true), Err(CheckedFmtError::Invalid));
+        assert_eq!(
+            check("_RINvC3foo3barNvB_3bazE", false),
+            Err(CheckedFmtError::Invalid)
+        );
+
+        // Errors from the output itself are passed through.
+        let mut out = super::PrefixMatcher {
+            remaining: "bar",
+            mismatch: false,
+        };
+        assert_eq!(
+            super::demangle("_RNvC3foo3bar").write_checked(&mut out, false),
+            Err(CheckedFmtError::Fmt(core::fmt::Error))
         );
     }
 
     #[test]
-    fn demangle_without_hash() {
-        let s = "_ZN3foo17h05af221e174051e9E";
-        t!(s, "foo::h05af221e174051e9");
-        t_nohash!(s, "foo");
+    fn validate_v0() {
+        use super::TryDemangleErrorKind::*;
+        use super::V0Production::*;
+
+        let stats = |s| {
+            super::validate_v0(s).map(|stats| {
+                (
+                    stats.max_depth(),
+                    stats.backrefs(),
+                    stats.punycode_identifiers(),
+                    stats.const_args(),
+                    stats.productions(),
+                )
+            })
+        };
+        let error = |s| {
+            super::validate_v0(s)
+                .map_err(|err| (err.kind(), err.offset(), err.production()))
+                .unwrap_err()
+        };
+
+        assert_eq!(stats("_RNvC3foo3bar"), Ok((2, 0, 0, 0, 4)));
+        assert_eq!(stats("_RNvC3foo3bar.llvm.1234"), Ok((2, 0, 0, 0, 4)));
+        assert_eq!(stats("_RNvCs1234_7mycrateu8gr_6ka8l"), Ok((2, 0, 1, 0, 4)));
+        // Backrefs are counted, but their targets aren't parsed again.
+        assert_eq!(stats("_RINvC3foo3barB2_Kj3_E"), Ok((3, 1, 0, 1, 8)));
+        // The instantiating crate is part of the symbol.
+        assert_eq!(stats("_RNvC3foo3barC3baz"), Ok((2, 0, 0, 0, 6)));
+
+        assert_eq!(error("_ZN3foo3barE"), (BadPrefix, 0, None));
+        assert_eq!(error("_RNvC3foo3bar$"), (InvalidSuffix, 13, None));
+        assert_eq!(error("_RNvC3foo3ba"), (Invalid, 13, Some(Identifier)));
     }
 
     #[test]
-    fn demangle_without_hash_edgecases() {
-        // One element, no hash.
-        t_nohash!("_ZN3fooE", "foo");
-        // Two elements, no hash.
-        t_nohash!("_ZN3foo3barE", "foo::bar");
-        // Longer-than-normal hash.
-        t_nohash!("_ZN3foo20h05af221e174051e9abcE", "foo");
-        // Shorter-than-normal hash.
-        t_nohash!("_ZN3foo5h05afE", "foo");
-        // Valid hash, but not at the end.
-        t_nohash!("_ZN17h05af221e174051e93fooE", "h05af221e174051e9::foo");
-        // Not a valid hash, missing the 'h'.
-        t_nohash!("_ZN3foo16ffaf221e174051e9E", "foo::ffaf221e174051e9");
-        // Not a valid hash, has a non-hex-digit.
-        t_nohash!("_ZN3foo17hg5af221e174051e9E", "foo::hg5af221e174051e9");
+    fn try_demangle_strict() {
+        use super::TryDemangleErrorKind::*;
+        use super::V0Production::*;
+
+        let error = |s| {
+            super::try_demangle_strict(s)
+                .map_err(|err| (err.kind(), err.offset(), err.production()))
+                .err()
+        };
+
+        assert_eq!(error("_ZN3foo3barE"), None);
+        assert_eq!(error("_RNvC3foo3bar"), None);
+        assert_eq!(error("_RNvC3foo3barB_"), None);
+        assert_eq!(error("la la la"), Some((BadPrefix, 0, None)));
+
+        // Only found by following backrefs, unlike with `try_demangle`.
+        assert!(super::try_demangle("_RINvC3foo3barB3_E").is_ok());
+        assert_eq!(error("_RINvC3foo3barB3_E"), Some((Invalid, 7, Some(Path))));
+        assert_eq!(error("_RNvC3foo3barB2_"), Some((Invalid, 6, Some(Path))));
+        assert_eq!(
+            error("_RINvC3foo3barNvB_3bazE"),
+            Some((CyclicBackref, 18, Some(Backref)))
+        );
     }
 
     #[test]
-    fn demangle_thinlto() {
-        // One element, no hash.
-        t!("_ZN3fooE.llvm.9D1C9369", "foo");
-        t!("_ZN3fooE.llvm.9D1C9369@@16", "foo");
-        t_nohash!(
-            "_ZN9backtrace3foo17hbb467fcdaea5d79bE.llvm.A5310EB9",
-            "backtrace::foo"
+    fn demangle_checked() {
+        use super::CheckedFmtError;
+        use super::ManglingScheme::{Legacy, V0};
+        use super::TryDemangleErrorKind::*;
+        use super::V0Production::{Backref, Identifier, Path};
+
+        let check = |sym: &str, alternate| {
+            let mut out = String::new();
+            super::demangle_checked(sym, &mut out, alternate).map(|()| {
+                assert!(!out.is_empty());
+                out
+            })
+        };
+        let error = |sym: &str| match check(sym, false) {
+            Err(CheckedFmtError::Parse(err)) => {
+                Some((err.kind(), err.scheme(), err.offset(), err.production()))
+            }
+            _ => None,
+        };
+        assert_eq!(
+            check("_ZN3foo17h05af221e174051e9E", true),
+            Ok("foo".to_string())
+        );
+        assert_eq!(
+            check(".L_RNvC3foo3bar.llvm.1234", false),
+            Ok(".Lfoo::bar".to_string())
+        );
+
+        // Errors found while parsing.
+        assert_eq!(error("la la la"), Some((BadPrefix, None, 0, None)));
+        assert_eq!(error("_ZN3foo"), Some((Invalid, Some(Legacy), 7, None)));
+        assert_eq!(
+            error("_RNvC3foo"),
+            Some((Invalid, Some(V0), 9, Some(Identifier)))
+        );
+
+        // Errors only found while printing.
+        assert_eq!(
+            error("_RINvC3foo3barB3_E"),
+            Some((Invalid, Some(V0), 7, Some(Path)))
+        );
+        assert_eq!(
+            error(".L_RINvC3foo3barB3_E"),
+            Some((Invalid, Some(V0), 9, Some(Path)))
+        );
+        // The backref refers to the path it's part of, which is found when
+        // reaching it again, through its target.
+        assert_eq!(
+            error("_RINvC3foo3barNvB_3bazE"),
+            Some((CyclicBackref, Some(V0), 18, Some(Backref)))
         );
     }
 
     #[test]
-    fn demangle_llvm_ir_branch_labels() {
-        t!("_ZN4core5slice77_$LT$impl$u20$core..ops..index..IndexMut$LT$I$GT$$u20$for$u20$$u5b$T$u5d$$GT$9index_mut17haf9727c2edfbc47bE.exit.i.i", "core::slice::<impl core::ops::index::IndexMut<I> for [T]>::index_mut::haf9727c2edfbc47b.exit.i.i");
-        t_nohash!("_ZN4core5slice77_$LT$impl$u20$core..ops..index..IndexMut$LT$I$GT$$u20$for$u20$$u5b$T$u5d$$GT$9index_mut17haf9727c2edfbc47bE.exit.i.i", "core::slice::<impl core::ops::index::IndexMut<I> for [T]>::index_mut.exit.i.i");
+    fn mangling_scheme() {
+        use super::ManglingScheme;
+
+        let t = |s| super::demangle(s).mangling_scheme();
+        assert_eq!(t("_ZN3fooE"), ManglingScheme::Legacy);
+        assert_eq!(t("__ZN3fooE.llvm.1234"), ManglingScheme::Legacy);
+        assert_eq!(t("ZN3fooE"), ManglingScheme::Legacy);
+        assert_eq!(t("_RNvC3foo3bar"), ManglingScheme::V0);
+        assert_eq!(t("__RNvC3foo3bar.0"), ManglingScheme::V0);
+        assert_eq!(t("RNvC3foo3bar"), ManglingScheme::V0);
+        // Rejected for their trailing data.
+        assert_eq!(t("_ZN3fooE$"), ManglingScheme::NotMangled);
+        assert_eq!(t("_RNvC3foo3bar$"), ManglingScheme::NotMangled);
+        assert_eq!(t("_ZN3fooEv"), ManglingScheme::NotMangled);
+        assert_eq!(t("la la la"), ManglingScheme::NotMangled);
     }
 
     #[test]
-    fn demangle_ignores_suffix_that_doesnt_look_like_a_symbol() {
-        t_err!("_ZN3fooE.llvm moocow");
+    fn demangle_prefix() {
+        let t = |s: &str| super::demangle_prefix(s).map(|(sym, len)| (sym.to_string(), len));
+        assert_eq!(t("_ZN3fooE"), Some(("foo".to_string(), 8)));
+        assert_eq!(t("_ZN3fooE.llvm.1234"), Some(("foo".to_string(), 8)));
+        assert_eq!(t("_ZN3fooEαβγ"), Some(("foo".to_string(), 8)));
+        assert_eq!(t("_RNvC3foo3bar"), Some(("foo::bar".to_string(), 13)));
+        assert_eq!(t("_RNvC3foo3barαβγ"), Some(("foo::bar".to_string(), 13)));
+        // Trailing data that looks like the start of an instantiating crate.
+        assert_eq!(t("_RNvC3foo3barNope"), Some(("foo::bar".to_string(), 13)));
+        assert_eq!(t("_RNvC3foo3barC3std|"), Some(("foo::bar".to_string(), 18)));
+        assert_eq!(t("_ZN3foo"), None);
+        assert_eq!(t("_ZN3fαoE"), None);
+        assert_eq!(t("foo"), None);
+        assert_eq!(t(""), None);
+
+        // The same inputs aren't symbols as a whole.
+        assert!(super::try_demangle("_ZN3fooEαβγ").is_err());
+        assert!(super::try_demangle("_RNvC3foo3barNope").is_err());
     }
 
     #[test]
-    fn dont_panic() {
-        super::demangle("_ZN2222222222222222222222EE").to_string();
-        super::demangle("_ZN5*70527e27.ll34csaғE").to_string();
-        super::demangle("_ZN5*70527a54.ll34_$b.1E").to_string();
-        super::demangle(
-            "\
-             _ZN5~saäb4e\n\
-             2734cOsbE\n\
-             5usage20h)3\0\0\0\0\0\0\07e2734cOsbE\
-             ",
-        )
-        .to_string();
+    fn eq_and_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |sym: &super::Demangle| {
+            let mut state = DefaultHasher::new();
+            sym.hash(&mut state);
+            let mut other_state = DefaultHasher::new();
+            sym.hash_demangled(&mut other_state);
+            assert_eq!(state.finish(), other_state.finish());
+            state.finish()
+        };
+        let long_path = |last| {
+            let mut sym = "_ZN".to_string();
+            for _ in 0..100 {
+                sym.push_str("3foo");
+            }
+            sym.push_str(last);
+            sym.push('E');
+            sym
+        };
+        let (a, b) = (long_path("3bar"), long_path("3baz"));
+        let v0 = format!("_R{}C3foo{}3bar", "Nv".repeat(100), "3foo".repeat(99));
+
+        // Legacy symbols are printed one `char` at a time, and v0 ones one
+        // identifier at a time, yet both hash the same.
+        let equal = [
+            ("_ZN3foo3barE", "_RNvC3foo3bar"),
+            ("_ZN3foo3barE", "_ZN3foo3barE.llvm.A5310EB9"),
+            ("_ZN3foo3barE.0", "_RNvC3foo3bar.0"),
+            ("foo::bar", "_RNvC3foo3bar"),
+            (&a, &a),
+            (&a, &v0),
+        ];
+        for &(x, y) in &equal {
+            let (x, y) = (super::demangle(x), super::demangle(y));
+            assert_eq!(x, y);
+            assert_eq!(hash(&x), hash(&y));
+        }
+
+        let different = [
+            ("_ZN3foo3barE", "_ZN3foo3bazE"),
+            ("_ZN3foo3barE", "_ZN3foo3barE.0"),
+            ("_ZN3foo3barE.0", "_ZN3foo3barE"),
+            ("_ZN3foo3barE", "_ZN3foo17h05af221e174051e9E"),
+            ("_RNvCs1234_3foo3bar", "_RNvC3foo3bar"),
+            (&a, &b),
+        ];
+        for &(x, y) in &different {
+            let (x, y) = (super::demangle(x), super::demangle(y));
+            assert_ne!(x, y);
+            assert_ne!(hash(&x), hash(&y));
+        }
+
+        // Options are taken into account.
+        let hide = super::DemangleOptions::new().hash(false);
+        let x = super::demangle("_RNvCs1234_3foo3bar").with_options(hide);
+        let y = super::demangle("_RNvC3foo3bar");
+        assert_eq!(x, y);
+        assert_eq!(hash(&x), hash(&y));
     }
 
+    // This takes a while, as the output (of about a megabyte) gets formatted
+    // once for every 4096 bytes of it.
     #[test]
-    fn invalid_no_chop() {
-        t_err!("_ZNfooE");
+    fn eq_long_output() {
+        let sym = "_RYFG_FGyyEvRYFF_EvRYFFEvERLB_B_B_ERLRjB_B_B_";
+        assert!(super::demangle(sym).to_string().len() > 900_000);
+        assert_eq!(super::demangle(sym), super::demangle(sym));
+        assert!(super::demangled_eq(sym, sym));
+        assert!(!super::demangled_eq(sym, "_RNvC3foo3bar"));
     }
 
     #[test]
-    fn handle_assoc_types() {
-        t!("_ZN151_$LT$alloc..boxed..Box$LT$alloc..boxed..FnBox$LT$A$C$$u20$Output$u3d$R$GT$$u20$$u2b$$u20$$u27$a$GT$$u20$as$u20$core..ops..function..FnOnce$LT$A$GT$$GT$9call_once17h69e8f44b3723e1caE", "<alloc::boxed::Box<alloc::boxed::FnBox<A, Output=R> + 'a> as core::ops::function::FnOnce<A>>::call_once::h69e8f44b3723e1ca");
+    fn eq_block_boundaries() {
+        let sym = |name: &str| format!("_ZN{}{}E", name.len(), name);
+        let x = |n| "x".repeat(n);
+        for &n in &[4095, 4096, 4097, 8192] {
+            assert!(super::demangled_eq(&sym(&x(n)), &sym(&x(n))));
+            assert!(!super::demangled_eq(&sym(&x(n)), &sym(&x(n + 1))));
+            assert!(!super::demangled_eq(&sym(&x(n + 1)), &sym(&x(n))));
+            let y = format!("{}y", x(n - 1));
+            assert!(!super::demangled_eq(&sym(&x(n)), &sym(&y)));
+        }
     }
 
     #[test]
-    fn handle_bang() {
-        t!(
-            "_ZN88_$LT$core..result..Result$LT$$u21$$C$$u20$E$GT$$u20$as$u20$std..process..Termination$GT$6report17hfc41d0da4a40b3e8E",
-            "<core::result::Result<!, E> as std::process::Termination>::report::hfc41d0da4a40b3e8"
+    fn try_demangle_error() {
+        use super::ManglingScheme::{Legacy, V0};
+        use super::TryDemangleErrorKind::*;
+
+        let t = |s| {
+            let err = super::try_demangle(s).unwrap_err();
+            (err.kind(), err.scheme(), err.offset())
+        };
+        assert_eq!(t(""), (BadPrefix, None, 0));
+        assert_eq!(t("foo"), (BadPrefix, None, 0));
+        assert_eq!(t("_R"), (BadPrefix, None, 0));
+        assert_eq!(t("_ZN"), (Invalid, Some(Legacy), 3));
+        assert_eq!(t("__ZN3foo"), (Invalid, Some(Legacy), 8));
+        assert_eq!(t("ZN3foo3barX"), (Invalid, Some(Legacy), 10));
+        assert_eq!(t("_ZN3fαoE"), (Invalid, Some(Legacy), 5));
+        assert_eq!(
+            t("_ZN99999999999999999999fooE"),
+            (Invalid, Some(Legacy), 22)
+        );
+        assert_eq!(t("_ZN3fooEαβγ"), (InvalidSuffix, Some(Legacy), 8));
+        assert_eq!(t("_ZN3fooE.llvm.α"), (InvalidSuffix, Some(Legacy), 8));
+        assert_eq!(t("_Rx"), (Invalid, Some(V0), 2));
+        assert_eq!(t("__RNvC3foo"), (Invalid, Some(V0), 10));
+        assert_eq!(t("_RNvC3foo3bar!"), (InvalidSuffix, Some(V0), 13));
+        assert_eq!(t("_RNvC3foo3barNope"), (InvalidSuffix, Some(V0), 13));
+        // `Q` is `&mut`, so `E` is the first unexpected byte.
+        assert_eq!(t("_RINvC3foo3barQE"), (Invalid, Some(V0), 16));
+        assert_eq!(
+            t(&format!("_RNv{}C3foo3bar", "Nv".repeat(500))),
+            (RecursionLimit, Some(V0), 1002)
         );
     }
 
     #[test]
-    fn limit_recursion() {
-        assert_contains!(
-            super::demangle("_RNvB_1a").to_string(),
-            "{recursion limit reached}"
-        );
-        assert_contains!(
-            super::demangle("_RMC0RB2_").to_string(),
-            "{recursion limit reached}"
+    fn try_demangle_error_production() {
+        use super::V0Production::*;
+
+        let t = |s| {
+            let err = super::try_demangle(s).unwrap_err();
+            (err.offset(), err.production())
+        };
+        assert_eq!(t("foo"), (0, None));
+        assert_eq!(t("_ZN3foo"), (7, None));
+        assert_eq!(t("_RNvC3foo3bar!"), (13, None));
+        assert_eq!(t("_Rx"), (2, Some(Path)));
+        assert_eq!(t("_RNvC3foo"), (9, Some(Identifier)));
+        assert_eq!(t("_RNvC3foo3ba"), (13, Some(Identifier)));
+        assert_eq!(t("_RN0C3foo3bar"), (4, Some(Path)));
+        assert_eq!(t("_RINvC3foo3barRL_x"), (18, Some(Type)));
+        assert_eq!(t("_RINvC3foo3barKjx_E"), (17, Some(Const)));
+        assert_eq!(t("_RINvC3foo3barKAjx_EE"), (18, Some(Const)));
+        assert_eq!(t("_RINvC3foo3barINtBz_3BazEE"), (20, Some(Backref)));
+        // Types and consts can themselves contain paths.
+        assert_eq!(t("_RINvC3foo3barQE"), (16, Some(Path)));
+        assert_eq!(t("_RINvC3foo3barAhE"), (17, Some(Const)));
+        assert_eq!(
+            t(&format!("_RNv{}C3foo3bar", "Nv".repeat(500))),
+            (1002, Some(Path))
         );
     }
 
     #[test]
-    fn limit_output() {
-        assert_ends_with!(
-            super::demangle("RYFG_FGyyEvRYFF_EvRYFFEvERLB_B_B_ERLRjB_B_B_").to_string(),
-            "{size limit reached}"
+    fn try_demangle_error_display() {
+        let t = |s| super::try_demangle(s).unwrap_err().to_string();
+        assert_eq!(t("foo"), "not a Rust symbol (unrecognized prefix)");
+        assert_eq!(t("_ZN3foo!3barE"), "invalid legacy symbol (at offset 7)");
+        assert_eq!(t("_RNvC3foo"), "invalid v0 symbol (at offset 9)");
+        assert_eq!(
+            t("_ZN3fooEv"),
+            "invalid suffix after legacy symbol (at offset 8)"
         );
-        // NOTE(eddyb) somewhat reduced version of the above, effectively
-        // `<for<...> fn()>` with a larger number of lifetimes in `...`.
-        assert_ends_with!(
-            super::demangle("_RMC0FGZZZ_Eu").to_string(),
-            "{size limit reached}"
+        assert_eq!(
+            t(&format!("_RNv{}C3foo3bar", "Nv".repeat(500))),
+            "recursion limit reached while parsing v0 symbol (at offset 1002)"
         );
+
+        let err: &dyn core::error::Error = &super::try_demangle("foo").unwrap_err();
+        assert!(err.source().is_none());
     }
 
-    #[cfg(feature = "std")]
-    fn demangle_str(input: &str) -> String {
-        let mut output = Vec::new();
-        super::demangle_line(input, &mut output, false);
-        String::from_utf8(output).unwrap()
+    #[test]
+    fn mangled_len() {
+        let t = |s: &str| super::demangle(s).mangled_len();
+        assert_eq!(t("_ZN3fooE"), 8);
+        assert_eq!(t("__ZN3fooE.exit.i.i"), 9);
+        assert_eq!(t("_ZN3fooE.llvm.A5310EB9"), 8);
+        assert_eq!(t("_ZN3fooE.llvm.A5310EB9.exit"), 8);
+        assert_eq!(t("_RNvC3foo3bar"), 13);
+        assert_eq!(t("_RNvC3foo3barC3std.i"), 18);
+        assert_eq!(t("_RNvC3foo3bar!"), 0);
+        assert_eq!(t("foo"), 0);
+        assert_eq!(t(""), 0);
+
+        let (sym, len) = super::demangle_prefix("_ZN3fooE.exit.i.i").unwrap();
+        assert_eq!((sym.mangled_len(), len), (8, 8));
     }
 
     #[test]
-    #[cfg(feature = "std")]
-    fn find_multiple() {
+    fn suffix() {
+        let t = |s| super::demangle(s).suffix();
+        assert_eq!(t("_ZN3fooE"), "");
+        assert_eq!(t("__ZN3fooE.exit.i.i"), ".exit.i.i");
+        assert_eq!(t("_ZN3fooE.llvm.A5310EB9"), ".llvm.A5310EB9");
+        assert_eq!(t("_ZN3fooE.exit.llvm.A5310EB9"), ".exit.llvm.A5310EB9");
+        assert_eq!(t("_ZN3fooE.llvm.A5310EB9.exit"), ".llvm.A5310EB9.exit");
+        assert_eq!(t("_RNvC3foo3bar.0.0"), ".0.0");
+        assert_eq!(t("_RNvC3foo3barC3std.i"), ".i");
+        assert_eq!(t("_RNvC3foo3bar!"), "");
+        assert_eq!(t("foo.0"), "");
+
+        let sym = "_ZN3fooE.0.0";
+        let demangled = super::demangle(sym);
         assert_eq!(
-            demangle_str("_ZN3fooE.llvm moocow _ZN3fooE.llvm"),
-            "foo.llvm moocow foo.llvm"
+            (&sym[..demangled.mangled_len()], demangled.suffix()),
+            ("_ZN3fooE", ".0.0")
         );
+
+        let (sym, _) = super::demangle_prefix("_ZN3fooE.exit.i.i").unwrap();
+        assert_eq!(sym.suffix(), "");
     }
 
     #[test]
-    #[cfg(feature = "std")]
-    fn interleaved_new_legacy() {
-        assert_eq!(
-            demangle_str("_ZN3fooE.llvm moocow _RNvMNtNtNtNtCs8a2262Dv4r_3mio3sys4unix8selector5epollNtB2_8Selector6select _ZN3fooE.llvm"),
-            "foo.llvm moocow <mio::sys::unix::selector::epoll::Selector>::select foo.llvm"
-        );
+    fn as_mangled_str() {
+        for &sym in &[
+            "_ZN3fooE",
+            "_ZN3fooE.llvm.A5310EB9",
+            "_RNvC3foo3bar.llvm.A5310EB9.exit",
+            "_RNvC3foo3bar!",
+            "foo.llvm.A5310EB9",
+            "",
+        ] {
+            let demangled = super::demangle(sym);
+            assert_eq!(demangled.as_mangled_str(), sym);
+            let options = super::DemangleOptions::new().hash(false);
+            assert_eq!(demangled.with_options(options).as_mangled_str(), sym);
+        }
+
+        let (sym, _) = super::demangle_prefix("_ZN3fooE.exit.i.i").unwrap();
+        assert_eq!(sym.as_mangled_str(), "_ZN3fooE");
     }
 }
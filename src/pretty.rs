@@ -0,0 +1,187 @@
+//! Printing long symbols over multiple lines, breaking up their generic
+//! arguments, e.g. for iterator adapter chains.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use super::{Demangle, TokenKind};
+
+/// Wrapper for printing a symbol over multiple lines, see `Demangle::pretty`.
+#[derive(Copy, Clone, Debug)]
+pub struct Pretty<'a, 'b> {
+    sym: &'b Demangle<'a>,
+    width: usize,
+}
+
+impl<'a, 'b> Pretty<'a, 'b> {
+    pub(crate) fn new(sym: &'b Demangle<'a>, width: usize) -> Pretty<'a, 'b> {
+        Pretty { sym, width }
+    }
+}
+
+/// How much each level of broken up generic arguments is indented by.
+const INDENT: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MarkKind {
+    /// Right after the start of a list of generic arguments (e.g. `<`).
+    Open,
+    /// At the `, ` between two generic arguments.
+    Sep,
+    /// At the end of a list of generic arguments (i.e. `>`).
+    Close,
+}
+
+/// A position in the (single line) output where it can be broken up.
+#[derive(Copy, Clone)]
+struct Mark {
+    kind: MarkKind,
+    /// Offset in bytes, for slicing the output.
+    byte: usize,
+    /// Offset in `char`s, for measuring the output.
+    char: usize,
+}
+
+/// The single line output, and the positions it can be broken up at.
+struct Collector {
+    out: String,
+    chars: usize,
+    marks: Vec<Mark>,
+    /// Whether each open bracket is for generic arguments (or e.g. a tuple,
+    /// in which case any `, ` inside it can't be broken up).
+    open: Vec<bool>,
+}
+
+impl Collector {
+    fn mark(&mut self, kind: MarkKind) {
+        self.marks.push(Mark {
+            kind,
+            byte: self.out.len(),
+            char: self.chars,
+        });
+    }
+
+    fn push(&mut self, kind: TokenKind, s: &str) {
+        match kind {
+            TokenKind::GenericClose => {
+                self.open.pop();
+                self.mark(MarkKind::Close);
+            }
+            TokenKind::Other if s == ", " && self.open.last() == Some(&true) => {
+                self.mark(MarkKind::Sep);
+            }
+            TokenKind::Other => {
+                let mut prev = None;
+                for c in s.chars() {
+                    match c {
+                        '(' | '[' | '{' | '<' => self.open.push(false),
+                        // Not part of a `->`.
+                        '>' if prev == Some('-') => {}
+                        ')' | ']' | '}' | '>' if self.open.last() == Some(&false) => {
+                            self.open.pop();
+                        }
+                        _ => {}
+                    }
+                    prev = Some(c);
+                }
+            }
+            _ => {}
+        }
+
+        self.out.push_str(s);
+        self.chars += s.chars().count();
+
+        if kind == TokenKind::GenericOpen {
+            self.open.push(true);
+            self.mark(MarkKind::Open);
+        }
+    }
+}
+
+/// Returns the width of the generic arguments opened by the mark at `i`,
+/// if printed on a single line, up to the end of the list and whatever
+/// follows it, until the next place the output could be broken up at.
+fn flat_width(marks: &[Mark], i: usize, total_chars: usize) -> usize {
+    let mut depth = 0;
+    let mut end = total_chars;
+    for (j, mark) in marks.iter().enumerate().skip(i + 1) {
+        match mark.kind {
+            MarkKind::Open => depth += 1,
+            MarkKind::Sep => {}
+            MarkKind::Close if depth > 0 => depth -= 1,
+            MarkKind::Close => {
+                end = marks.get(j + 1).map_or(total_chars, |next| next.char);
+                break;
+            }
+        }
+    }
+    end - marks[i].char
+}
+
+fn newline(f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    f.write_char('\n')?;
+    for _ in 0..indent * INDENT {
+        f.write_char(' ')?;
+    }
+    Ok(())
+}
+
+impl<'a, 'b> fmt::Display for Pretty<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut collector = Collector {
+            out: String::new(),
+            chars: 0,
+            marks: Vec::new(),
+            open: Vec::new(),
+        };
+        self.sym
+            .write_tokens(f.alternate(), |kind, s| collector.push(kind, s))?;
+        let Collector {
+            out, chars, marks, ..
+        } = collector;
+
+        // Whether each list of generic arguments being printed is broken up.
+        let mut broken: Vec<bool> = Vec::new();
+        let mut indent = 0;
+        let mut column = 0;
+        let mut printed = (0, 0);
+        for (i, mark) in marks.iter().enumerate() {
+            f.write_str(&out[printed.0..mark.byte])?;
+            column += mark.char - printed.1;
+            printed = (mark.byte, mark.char);
+
+            match mark.kind {
+                MarkKind::Open => {
+                    // Everything inside a list that fits on the line fits too.
+                    let outer_fits = broken.last() == Some(&false);
+                    let fits = outer_fits || column + flat_width(&marks, i, chars) <= self.width;
+                    broken.push(!fits);
+                    if !fits {
+                        indent += 1;
+                        newline(f, indent)?;
+                        column = indent * INDENT;
+                    }
+                }
+                MarkKind::Sep => {
+                    if broken.last() == Some(&true) {
+                        f.write_char(',')?;
+                        newline(f, indent)?;
+                        column = indent * INDENT;
+                        // Skip the `, `.
+                        printed = (mark.byte + 2, mark.char + 2);
+                    }
+                }
+                MarkKind::Close => {
+                    if broken.pop() == Some(true) {
+                        f.write_char(',')?;
+                        indent -= 1;
+                        newline(f, indent)?;
+                        column = indent * INDENT;
+                    }
+                }
+            }
+        }
+        f.write_str(&out[printed.0..])
+    }
+}
@@ -0,0 +1,189 @@
+//! Decoding of Punycode (RFC 3492), which v0 symbols use for identifiers
+//! with non-ASCII characters (see `Ident` in the `v0` module).
+
+use core::{char, fmt};
+
+/// Error returned from `decode_punycode`, for malformed Punycode, or when
+/// decoding was stopped by the closure receiving the characters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PunycodeError;
+
+impl fmt::Display for PunycodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid punycode")
+    }
+}
+
+impl core::error::Error for PunycodeError {}
+
+/// Decodes the Punycode encoding of an identifier, made of its basic (ASCII)
+/// characters, `ascii`, and of the `deltas` (lowercase `a-z0-9`) encoding
+/// the rest of its characters, e.g. from `punycode{ascii-deltas}` as printed
+/// for undecodable identifiers (see `PunycodeFallback`), or from the
+/// `u<len>ascii_deltas` identifiers of v0 symbols.
+///
+/// The decoded characters aren't produced in order: for each of them,
+/// `insert(i, c)` is called, to insert `c` at position `i` (in characters)
+/// of the identifier decoded so far (starting with the characters of
+/// `ascii`, at positions `0`, `1`, etc.), which never allocates, unlike
+/// collecting them would. `insert` can stop decoding by returning `Err`.
+///
+/// ```
+/// use rustc_demangle::decode_punycode;
+///
+/// let mut chars = vec![];
+/// decode_punycode("gre", "6ka8l", |i, c| {
+///     chars.insert(i, c);
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert_eq!(chars.into_iter().collect::<String>(), "grüße");
+///
+/// assert!(decode_punycode("", "9!", |_, _| Ok(())).is_err());
+/// ```
+pub fn decode_punycode<F>(ascii: &str, deltas: &str, mut insert: F) -> Result<(), PunycodeError>
+where
+    F: FnMut(usize, char) -> Result<(), PunycodeError>,
+{
+    if !ascii.is_ascii() {
+        return Err(PunycodeError);
+    }
+
+    let mut punycode_bytes = deltas.bytes().peekable();
+
+    let mut len = 0;
+
+    // Populate initial output from ASCII fragment.
+    for c in ascii.chars() {
+        insert(len, c)?;
+        len += 1;
+    }
+
+    if punycode_bytes.peek().is_none() {
+        return Ok(());
+    }
+
+    // Punycode parameters and initial state.
+    let base = 36;
+    let t_min = 1;
+    let t_max = 26;
+    let skew = 38;
+    let mut damp = 700;
+    let mut bias = 72;
+    let mut i: usize = 0;
+    let mut n: usize = 0x80;
+
+    loop {
+        // Read one delta value.
+        let mut delta: usize = 0;
+        let mut w = 1;
+        let mut k: usize = 0;
+        loop {
+            use core::cmp::{max, min};
+
+            k += base;
+            let t = min(max(k.saturating_sub(bias), t_min), t_max);
+
+            let d = match punycode_bytes.next() {
+                Some(d @ b'a'..=b'z') => d - b'a',
+                Some(d @ b'0'..=b'9') => 26 + (d - b'0'),
+                _ => return Err(PunycodeError),
+            };
+            let d = d as usize;
+            delta = delta
+                .checked_add(d.checked_mul(w).ok_or(PunycodeError)?)
+                .ok_or(PunycodeError)?;
+            if d < t {
+                break;
+            }
+            w = w.checked_mul(base - t).ok_or(PunycodeError)?;
+        }
+
+        // Compute the new insert position and character.
+        len += 1;
+        i = i.checked_add(delta).ok_or(PunycodeError)?;
+        n = n.checked_add(i / len).ok_or(PunycodeError)?;
+        i %= len;
+
+        let n_u32 = n as u32;
+        let c = if n_u32 as usize == n {
+            char::from_u32(n_u32).ok_or(PunycodeError)?
+        } else {
+            return Err(PunycodeError);
+        };
+
+        // Insert the new character and increment the insert position.
+        insert(i, c)?;
+        i += 1;
+
+        // If there are no more deltas, decoding is complete.
+        if punycode_bytes.peek().is_none() {
+            return Ok(());
+        }
+
+        // Perform bias adaptation.
+        delta /= damp;
+        damp = 2;
+
+        delta += delta / len;
+        let mut k = 0;
+        while delta > ((base - t_min) * t_max) / 2 {
+            delta /= base - t_min;
+            k += base;
+        }
+        bias = k + ((base - t_min + 1) * delta) / (delta + skew);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_punycode, PunycodeError};
+    use std::prelude::v1::*;
+
+    fn decode(ascii: &str, deltas: &str) -> Result<String, PunycodeError> {
+        let mut chars = vec![];
+        decode_punycode(ascii, deltas, |i, c| {
+            chars.insert(i, c);
+            Ok(())
+        })?;
+        Ok(chars.into_iter().collect())
+    }
+
+    #[test]
+    fn decode_valid() {
+        assert_eq!(decode("", ""), Ok(String::new()));
+        assert_eq!(decode("foo", ""), Ok("foo".to_string()));
+        assert_eq!(decode("", "tda"), Ok("ü".to_string()));
+        assert_eq!(decode("gre", "ioa"), Ok("grüe".to_string()));
+        assert_eq!(decode("gre", "6ka8l"), Ok("grüße".to_string()));
+        // From RFC 3492, section 7.1 (A and L).
+        assert_eq!(
+            decode("", "egbpdaj6bu4bxfgehfvwxn"),
+            Ok("ليهمابتكلموشعربي؟".to_string())
+        );
+        assert_eq!(
+            decode("3B", "ww4c5e180e575a65lsy2b"),
+            Ok("3年B組金八先生".to_string())
+        );
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode("", "!"), Err(PunycodeError));
+        assert_eq!(decode("", "A"), Err(PunycodeError));
+        assert_eq!(decode("ü", "a"), Err(PunycodeError));
+        assert_eq!(decode("", "9999999999999999"), Err(PunycodeError));
+
+        // Stopping from the closure.
+        let mut inserted = 0;
+        let r = decode_punycode("abc", "", |_, _| {
+            inserted += 1;
+            if inserted == 2 {
+                Err(PunycodeError)
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!((r, inserted), (Err(PunycodeError), 2));
+    }
+}
@@ -0,0 +1,83 @@
+//! Descriptions of the unmangled symbols defined by the Rust runtime, i.e.
+//! the standard library, the allocator shim generated by rustc, and the
+//! panic runtimes, which show up in backtraces but have no Rust path.
+
+/// The known runtime symbols, and their descriptions, sorted by symbol.
+const RUNTIME_SYMBOLS: &[(&str, &str)] = &[
+    ("__rdl_alloc", "default allocator"),
+    ("__rdl_alloc_zeroed", "default allocator"),
+    ("__rdl_dealloc", "default allocator"),
+    ("__rdl_oom", "default alloc error handler"),
+    ("__rdl_realloc", "default allocator"),
+    ("__rg_alloc", "global allocator"),
+    ("__rg_alloc_zeroed", "global allocator"),
+    ("__rg_dealloc", "global allocator"),
+    ("__rg_oom", "alloc error handler"),
+    ("__rg_realloc", "global allocator"),
+    ("__rust_alloc", "alloc shim"),
+    ("__rust_alloc_error_handler", "alloc error handler shim"),
+    ("__rust_alloc_zeroed", "alloc shim"),
+    ("__rust_dealloc", "alloc shim"),
+    ("__rust_drop_panic", "panic in drop"),
+    ("__rust_foreign_exception", "foreign exception"),
+    ("__rust_panic_cleanup", "panic runtime"),
+    ("__rust_probestack", "stack probe"),
+    ("__rust_realloc", "alloc shim"),
+    ("__rust_start_panic", "panic runtime"),
+    ("rust_begin_unwind", "panic handler"),
+    ("rust_eh_personality", "unwinding personality"),
+    ("rust_oom", "alloc error handler"),
+    ("rust_panic", "panic runtime"),
+];
+
+/// Returns a short description of `sym`, if it's one of the unmangled
+/// symbols defined by the Rust runtime (e.g. `alloc shim` for
+/// `__rust_alloc`, or `panic handler` for `rust_begin_unwind`), optionally
+/// with the extra leading `_` added by Mach-O, see
+/// `DemangleOptions::runtime_symbols`.
+///
+/// ```
+/// use rustc_demangle::runtime_symbol_description;
+///
+/// assert_eq!(runtime_symbol_description("__rust_alloc"), Some("alloc shim"));
+/// assert_eq!(runtime_symbol_description("___rust_probestack"), Some("stack probe"));
+/// assert_eq!(runtime_symbol_description("malloc"), None);
+/// ```
+pub fn runtime_symbol_description(sym: &str) -> Option<&'static str> {
+    let lookup = |sym: &str| {
+        RUNTIME_SYMBOLS
+            .binary_search_by_key(&sym, |&(sym, _)| sym)
+            .ok()
+            .map(|i| RUNTIME_SYMBOLS[i].1)
+    };
+    lookup(sym).or_else(|| match sym.as_bytes().first() {
+        Some(b'_') => lookup(&sym[1..]),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{runtime_symbol_description, RUNTIME_SYMBOLS};
+
+    #[test]
+    fn sorted() {
+        for pair in RUNTIME_SYMBOLS.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{:?} out of order", pair[1].0);
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        for &(sym, description) in RUNTIME_SYMBOLS {
+            assert_eq!(runtime_symbol_description(sym), Some(description));
+        }
+        assert_eq!(
+            runtime_symbol_description("_rust_panic"),
+            Some("panic runtime")
+        );
+        assert_eq!(runtime_symbol_description("rust_panic2"), None);
+        assert_eq!(runtime_symbol_description("__rust"), None);
+        assert_eq!(runtime_symbol_description(""), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Collation keys for sorting demangled symbols in a human-sensible order.
+
+use core::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use super::Demangle;
+
+/// A collation key for a demangled symbol, see `Demangle::sort_key`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey {
+    /// Path segments (without generic arguments), each ranked before its
+    /// name so that e.g. closures come after all the named items.
+    path: Vec<(SegmentRank, String)>,
+    /// Generic arguments of each path segment (empty if none).
+    generics: Vec<String>,
+    /// The full output (including hashes and disambiguators), as the final
+    /// tie-breaker.
+    full: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SegmentRank {
+    /// Named items, e.g. `foo` or `Vec`.
+    Named,
+    /// Qualified paths, e.g. `<Foo as Bar>`.
+    Qualified,
+    /// Compiler-generated items, e.g. `{closure#0}` or `{shim:vtable#0}`.
+    Generated,
+}
+
+impl SortKey {
+    pub(crate) fn new(sym: &Demangle) -> SortKey {
+        let mut short = String::new();
+        let mut full = String::new();
+        let _ = write!(short, "{:#}", sym);
+        let _ = write!(full, "{}", sym);
+
+        let mut path = Vec::new();
+        let mut generics: Vec<String> = Vec::new();
+        for segment in split_top_level(&short, "::") {
+            // The generic arguments of v0 paths are printed as `::<...>`,
+            // so they're split off from the segment they belong to (while
+            // qualified paths only ever start a path).
+            if segment.starts_with('<') {
+                if let Some(args) = generics.last_mut() {
+                    if args.is_empty() {
+                        *args = String::from(segment);
+                        continue;
+                    }
+                }
+            }
+            let rank = match segment.as_bytes().first() {
+                Some(b'<') => SegmentRank::Qualified,
+                Some(b'{') => SegmentRank::Generated,
+                _ => SegmentRank::Named,
+            };
+            let (name, args) = match rank {
+                SegmentRank::Named => match segment.find('<') {
+                    Some(i) => segment.split_at(i),
+                    None => (segment, ""),
+                },
+                _ => (segment, ""),
+            };
+            path.push((rank, String::from(name)));
+            generics.push(String::from(args));
+        }
+
+        SortKey {
+            path,
+            generics,
+            full,
+        }
+    }
+}
+
+/// Splits `s` at every occurrence of `sep` which isn't nested within any
+/// kind of brackets.
+fn split_top_level<'s>(s: &'s str, sep: &'s str) -> impl Iterator<Item = &'s str> + 's {
+    let mut rest = Some(s);
+    core::iter::from_fn(move || {
+        let s = rest?;
+        let mut depth = 0usize;
+        for (i, c) in s.char_indices() {
+            match c {
+                '<' | '(' | '[' | '{' => depth += 1,
+                // Don't get confused by the `>` in `->`.
+                '>' if s[..i].ends_with('-') => {}
+                '>' | ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ if depth == 0 && s[i..].starts_with(sep) => {
+                    rest = Some(&s[i + sep.len()..]);
+                    return Some(&s[..i]);
+                }
+                _ => {}
+            }
+        }
+        rest = None;
+        Some(s)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use super::split_top_level;
+
+    #[test]
+    fn split() {
+        let split = |s| split_top_level(s, "::").collect::<Vec<_>>();
+        assert_eq!(split("foo"), ["foo"]);
+        assert_eq!(split("foo::bar"), ["foo", "bar"]);
+        assert_eq!(
+            split("<foo::Foo as bar::Bar>::baz::<a::A>::{closure#0}"),
+            ["<foo::Foo as bar::Bar>", "baz", "<a::A>", "{closure#0}"]
+        );
+        assert_eq!(
+            split("foo::<fn(a::A) -> b::B>::bar"),
+            ["foo", "<fn(a::A) -> b::B>", "bar"]
+        );
+    }
+
+    #[test]
+    fn order() {
+        let key = |sym| ::demangle(sym).sort_key();
+
+        // Generic arguments only matter once paths are equal.
+        assert!(key("_RINvC3foo1aNtC3std3VecE") < key("_RNvC3foo1b"));
+        assert!(key("_RINvC3foo1aaE") < key("_RINvC3foo1ahE"));
+        assert!(key("_RINvNvC3foo1a1bNtC3std3VecE") < key("_RNvNvNvC3foo1a1b1c"));
+        assert!(key("_RNvINvC3foo1aNtC3std3VecE1b") < key("_RNvNvC3foo1a1c"));
+        assert!(key("_RNvINvC3foo1ahE1b") < key("_RNvINvC3foo1aaE1c"));
+        assert_eq!(
+            key("_RINvNvC3foo1a1bNtC3std3VecE").generics,
+            ["", "", "<std::Vec>"]
+        );
+        // Qualified paths aren't generic arguments.
+        assert_eq!(key("_RNvMNtC3foo1aNtB2_3Bar3new").generics, ["", ""]);
+        // Crates are grouped together.
+        assert!(key("_RNvC1b1a") > key("_RNvNtC1a1z1z"));
+        // Hashes are only used as a tie-breaker.
+        assert!(key("_ZN3foo17h0000000000000001E") < key("_ZN3foo17h0000000000000002E"));
+        assert!(key("_ZN3foo17h0000000000000002E") < key("_ZN4foo217h0000000000000001E"));
+    }
+}
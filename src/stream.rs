@@ -0,0 +1,841 @@
+//! Demangling of symbols embedded in arbitrary text, e.g. the output of
+//! `nm`, `perf script` or a backtrace.
+//!
+//! Symbols end at the first character which can't be part of one, so any
+//! decorations following them (such as the `+0x1c/0x40` offset and size
+//! from kernel logs, see also `split_symbol_plus_offset`) are kept as-is.
+
+use core::fmt::Write as _;
+use core::mem;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::string::String;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::vec::Vec;
+
+use super::{next_candidate_symbol, try_demangle};
+
+// Maximum number of distinct symbols remembered by a single deduplicating
+// `demangle_stream_with_options` run, to bound its memory usage.
+const DEDUP_CACHE_CAPACITY: usize = 1 << 16;
+
+// Approximate size (in bytes) of the batches of lines handed out to worker
+// threads, when demangling with `StreamOptions::threads`.
+const PIPELINE_BATCH_SIZE: usize = 64 * 1024;
+
+// The first bytes of gzip members and zstd frames. Neither can start valid
+// UTF-8 text, so uncompressed inputs are never mistaken for compressed ones.
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Options for `demangle_stream_with_options`.
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    include_hash: bool,
+    dedup: bool,
+    threads: usize,
+    backtrace: bool,
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    compress_output: Compression,
+}
+
+/// Compression formats for the output of `demangle_stream_with_options`,
+/// see `StreamOptions::compress_output`.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "gzip", feature = "zstd"))))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Uncompressed output (the default).
+    None,
+    /// gzip, with the default compression level.
+    #[cfg(feature = "gzip")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+    Gzip,
+    /// zstd, with the default compression level.
+    #[cfg(feature = "zstd")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "zstd")))]
+    Zstd,
+}
+
+impl StreamOptions {
+    /// Creates the default options: hashes are included, and symbols are
+    /// demangled from scratch every time they're found.
+    pub fn new() -> StreamOptions {
+        StreamOptions {
+            include_hash: true,
+            dedup: false,
+            threads: 1,
+            backtrace: false,
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            compress_output: Compression::None,
+        }
+    }
+
+    /// Whether to include the hashes of symbols in the output, i.e. whether
+    /// they're formatted with `{}` (the default) or `{:#}`.
+    pub fn include_hash(mut self, include_hash: bool) -> StreamOptions {
+        self.include_hash = include_hash;
+        self
+    }
+
+    /// Whether to cache demangled symbols for the duration of the run, and
+    /// reuse them whenever the same mangled symbol is found again.
+    ///
+    /// This is a significant speedup for inputs like sample-based profiles,
+    /// which repeat the same few thousand symbols millions of times.
+    pub fn dedup(mut self, dedup: bool) -> StreamOptions {
+        self.dedup = dedup;
+        self
+    }
+
+    /// The number of worker threads to demangle with (`1` by default).
+    ///
+    /// With more than one thread, the input is processed as a pipeline:
+    /// the calling thread reads batches of lines and hands them out to the
+    /// worker threads, and then writes out their results in the original
+    /// order, so that reading, demangling and writing all overlap.
+    ///
+    /// Each worker thread keeps its own cache when `dedup` is enabled.
+    pub fn threads(mut self, threads: usize) -> StreamOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Whether the input is a backtrace, as printed by `std::backtrace` or
+    /// on panics (`false` by default), e.g.:
+    ///
+    /// ```text
+    ///   12: _ZN3foo3bar17h05af221e174051e9E
+    ///              at src/foo.rs:34:5
+    /// ```
+    ///
+    /// Symbols are then only demangled on the frame lines (keeping their
+    /// numbering and indentation intact), and the `at ...` location lines are
+    /// always copied to the output exactly as they are.
+    pub fn backtrace(mut self, backtrace: bool) -> StreamOptions {
+        self.backtrace = backtrace;
+        self
+    }
+
+    /// How to compress the output (`Compression::None` by default). This is
+    /// independent of whether the input was compressed.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "gzip", feature = "zstd"))))]
+    pub fn compress_output(mut self, compression: Compression) -> StreamOptions {
+        self.compress_output = compression;
+        self
+    }
+}
+
+impl Default for StreamOptions {
+    fn default() -> StreamOptions {
+        StreamOptions::new()
+    }
+}
+
+/// Statistics about a `demangle_stream_with_options` run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    symbols: u64,
+    cache_hits: u64,
+}
+
+impl StreamStats {
+    /// Returns the number of candidate symbols found in the input.
+    pub fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Returns the number of symbols which were found in the deduplication
+    /// cache (always `0` if `StreamOptions::dedup` wasn't enabled).
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits
+    }
+
+    /// Returns the fraction (between `0.0` and `1.0`) of symbols which were
+    /// found in the deduplication cache.
+    pub fn hit_rate(&self) -> f64 {
+        if self.symbols == 0 {
+            0.0
+        } else {
+            self.cache_hits as f64 / self.symbols as f64
+        }
+    }
+}
+
+/// State of a single `demangle_stream_with_options` run.
+struct Demangler<'o> {
+    options: &'o StreamOptions,
+    /// Previously seen candidate symbols, mapped to their demangling,
+    /// or `None` if they turned out not to be Rust symbols.
+    cache: HashMap<String, Option<String>>,
+    stats: StreamStats,
+}
+
+impl<'o> Demangler<'o> {
+    fn new(options: &'o StreamOptions) -> Demangler<'o> {
+        Demangler {
+            options,
+            cache: HashMap::new(),
+            stats: StreamStats::default(),
+        }
+    }
+
+    fn demangle_line(&mut self, line: &str, output: &mut impl io::Write) -> io::Result<()> {
+        if !self.options.backtrace {
+            return self.demangle_symbols(line, output);
+        }
+        // This may be a whole batch of lines, when pipelining.
+        for line in line.split_inclusive('\n') {
+            if line.trim_start().starts_with("at ") {
+                output.write_all(line.as_bytes())?;
+            } else {
+                self.demangle_symbols(line, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn demangle_symbols(&mut self, line: &str, output: &mut impl io::Write) -> io::Result<()> {
+        let mut head = 0;
+        while let Some(candidate) = next_candidate_symbol(line, head) {
+            output.write_all(&line.as_bytes()[head..candidate.start])?;
+            head = candidate.end;
+            self.demangle_symbol(&line[candidate], output)?;
+        }
+        output.write_all(&line.as_bytes()[head..])
+    }
+
+    fn demangle_symbol(&mut self, mangled: &str, output: &mut impl io::Write) -> io::Result<()> {
+        self.stats.symbols += 1;
+
+        if !self.options.dedup {
+            return match try_demangle(mangled) {
+                Ok(demangled) => {
+                    if self.options.include_hash {
+                        write!(output, "{}", demangled)
+                    } else {
+                        write!(output, "{:#}", demangled)
+                    }
+                }
+                Err(_) => output.write_all(mangled.as_bytes()),
+            };
+        }
+
+        if let Some(cached) = self.cache.get(mangled) {
+            self.stats.cache_hits += 1;
+            let cached = cached.as_ref().map_or(mangled, |s| s);
+            return output.write_all(cached.as_bytes());
+        }
+
+        let demangled = try_demangle(mangled).ok().map(|demangled| {
+            let mut s = String::new();
+            if self.options.include_hash {
+                let _ = write!(s, "{}", demangled);
+            } else {
+                let _ = write!(s, "{:#}", demangled);
+            }
+            s
+        });
+        output.write_all(demangled.as_ref().map_or(mangled, |s| s).as_bytes())?;
+        if self.cache.len() < DEDUP_CACHE_CAPACITY {
+            self.cache.insert(mangled.into(), demangled);
+        }
+        Ok(())
+    }
+}
+
+/// Process a stream of data from `input` into the provided `output`, demangling any symbols found
+/// within.
+///
+/// Note that the underlying implementation will perform many relatively small writes to the
+/// output. If the output is expensive to write to (e.g., requires syscalls), consider using
+/// `std::io::BufWriter`.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn demangle_stream<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    include_hash: bool,
+) -> io::Result<()> {
+    let options = StreamOptions::new().include_hash(include_hash);
+    demangle_stream_with_options(input, output, &options).map(|_| ())
+}
+
+/// The same as `demangle_stream`, but configured through `StreamOptions`,
+/// and reporting some statistics about the run once it's finished.
+///
+/// With the `gzip` (or `zstd`) feature enabled, gzip-compressed (or
+/// zstd-compressed) `input` is detected and decompressed on the fly, and
+/// the output can be compressed, see `StreamOptions::compress_output`.
+///
+/// ```
+/// use rustc_demangle::{demangle_stream_with_options, StreamOptions};
+///
+/// let input = "_ZN3foo3barE _ZN3foo3barE la la la _ZN3foo3barE";
+/// let mut output = Vec::new();
+/// let options = StreamOptions::new().dedup(true);
+/// let stats = demangle_stream_with_options(&mut input.as_bytes(), &mut output, &options).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "foo::bar foo::bar la la la foo::bar");
+/// assert_eq!((stats.symbols(), stats.cache_hits()), (3, 2));
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn demangle_stream_with_options<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &StreamOptions,
+) -> io::Result<StreamStats> {
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    {
+        // The start of `input` may not all be available at once (e.g. from
+        // a pipe), so it's moved out of it, to be put back afterwards.
+        let mut magic = [0; 4];
+        let mut len = 0;
+        while len < magic.len() {
+            let buf = match input.fill_buf() {
+                Ok(buf) => buf,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if buf.is_empty() {
+                break;
+            }
+            let n = buf.len().min(magic.len() - len);
+            magic[len..len + n].copy_from_slice(&buf[..n]);
+            input.consume(n);
+            len += n;
+        }
+        let magic = &magic[..len];
+        let mut input = io::Read::chain(magic, input);
+
+        #[cfg(feature = "gzip")]
+        {
+            if magic.starts_with(&GZIP_MAGIC) {
+                let input = flate2::bufread::MultiGzDecoder::new(input);
+                return demangle_to(&mut io::BufReader::new(input), output, options);
+            }
+        }
+        #[cfg(feature = "zstd")]
+        {
+            if magic.starts_with(&ZSTD_MAGIC) {
+                let input = zstd::stream::read::Decoder::with_buffer(input)?;
+                return demangle_to(&mut io::BufReader::new(input), output, options);
+            }
+        }
+        demangle_to(&mut input, output, options)
+    }
+
+    #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+    demangle_to(input, output, options)
+}
+
+/// Demangle the (already decompressed) `input`, compressing the output as
+/// requested by `options`.
+fn demangle_to<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &StreamOptions,
+) -> io::Result<StreamStats> {
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    {
+        match options.compress_output {
+            Compression::None => {}
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let level = flate2::Compression::default();
+                let mut output = flate2::write::GzEncoder::new(output, level);
+                let stats = demangle_uncompressed(input, &mut output, options)?;
+                output.finish()?;
+                return Ok(stats);
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut output = zstd::stream::write::Encoder::new(output, 0)?;
+                let stats = demangle_uncompressed(input, &mut output, options)?;
+                output.finish()?;
+                return Ok(stats);
+            }
+        }
+    }
+
+    demangle_uncompressed(input, output, options)
+}
+
+fn demangle_uncompressed<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &StreamOptions,
+) -> io::Result<StreamStats> {
+    if options.threads > 1 {
+        return demangle_stream_pipelined(input, output, options);
+    }
+
+    let mut demangler = Demangler::new(options);
+    let mut buf = String::new();
+    // We read in lines to reduce the memory usage at any time.
+    //
+    // demangle_line is also more efficient with relatively small buffers as it will copy around
+    // trailing data during demangling. In the future we might directly stream to the output but at
+    // least right now that seems to be less efficient.
+    while input.read_line(&mut buf)? > 0 {
+        demangler.demangle_line(&buf, output)?;
+        buf.clear();
+    }
+    Ok(demangler.stats)
+}
+
+/// Demangles all symbols found in the file at `input`, writing the result
+/// to a newly created (or truncated) file at `output`.
+///
+/// Both files are read and written through buffers, like `demangle_stream`
+/// would. The input isn't memory-mapped, as another process truncating it
+/// while mapped would crash this one (with `SIGBUS`), instead of causing an
+/// error. An error is returned if `input` and `output` are the same file, as
+/// truncating `output` would lose its contents before they could be read.
+///
+/// Like `demangle_stream_with_options`, this transparently decompresses
+/// compressed input files when the `gzip` (or `zstd`) feature is enabled.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn demangle_file(
+    input: &Path,
+    output: &Path,
+    options: &StreamOptions,
+) -> io::Result<StreamStats> {
+    let input_file = File::open(input)?;
+    if let Ok(output_metadata) = fs::metadata(output) {
+        if same_file(input, &input_file.metadata()?, output, &output_metadata)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "input and output are the same file",
+            ));
+        }
+    }
+    let mut input = io::BufReader::new(input_file);
+    let mut output = io::BufWriter::new(File::create(output)?);
+    let stats = demangle_stream_with_options(&mut input, &mut output, options)?;
+    io::Write::flush(&mut output)?;
+    Ok(stats)
+}
+
+/// Returns whether the files at `a` and `b` (with the given metadata) are
+/// the same file, i.e. the same inode on Unix (even if through different
+/// hard links), or the same canonical path elsewhere.
+fn same_file(
+    a: &Path,
+    a_metadata: &fs::Metadata,
+    b: &Path,
+    b_metadata: &fs::Metadata,
+) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let _ = (a, b);
+        Ok(a_metadata.dev() == b_metadata.dev() && a_metadata.ino() == b_metadata.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a_metadata, b_metadata);
+        Ok(fs::canonicalize(a)? == fs::canonicalize(b)?)
+    }
+}
+
+fn demangle_stream_pipelined<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &StreamOptions,
+) -> io::Result<StreamStats> {
+    // Bound the number of batches in flight, so that a slow `output` can't
+    // cause the whole `input` to be buffered in memory.
+    let max_in_flight = 2 * options.threads as u64;
+
+    let (batch_tx, batch_rx) = mpsc::channel::<(u64, String)>();
+    let batch_rx = Mutex::new(batch_rx);
+    let (done_tx, done_rx) = mpsc::channel::<(u64, Vec<u8>)>();
+
+    thread::scope(|scope| {
+        // Moving the `Sender` into the closure ensures that it
+        // gets dropped on early (error) returns, which lets workers exit.
+        let batch_tx = batch_tx;
+        let workers: Vec<_> = (0..options.threads)
+            .map(|_| {
+                let batch_rx = &batch_rx;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || {
+                    let mut demangler = Demangler::new(options);
+                    loop {
+                        let batch = batch_rx.lock().unwrap().recv();
+                        let (seq, text) = match batch {
+                            Ok(batch) => batch,
+                            Err(mpsc::RecvError) => break,
+                        };
+                        let mut out = Vec::with_capacity(text.len());
+                        demangler
+                            .demangle_line(&text, &mut out)
+                            .expect("writing to a `Vec` should never fail");
+                        if done_tx.send((seq, out)).is_err() {
+                            break;
+                        }
+                    }
+                    demangler.stats
+                })
+            })
+            .collect();
+        drop(done_tx);
+
+        let mut buf = String::new();
+        let mut sent = 0;
+        let mut written = 0;
+        let mut finished = BTreeMap::new();
+        loop {
+            let mut eof = false;
+            while buf.len() < PIPELINE_BATCH_SIZE {
+                if input.read_line(&mut buf)? == 0 {
+                    eof = true;
+                    break;
+                }
+            }
+            if !buf.is_empty() {
+                batch_tx
+                    .send((sent, mem::take(&mut buf)))
+                    .expect("demangling worker threads exited early");
+                sent += 1;
+            }
+
+            // Write out all the batches that are ready, waiting for more to
+            // finish if there are too many in flight (or the input ended).
+            loop {
+                while let Ok((seq, out)) = done_rx.try_recv() {
+                    finished.insert(seq, out);
+                }
+                while let Some(out) = finished.remove(&written) {
+                    output.write_all(&out)?;
+                    written += 1;
+                }
+                if written == sent || (!eof && sent - written < max_in_flight) {
+                    break;
+                }
+                let (seq, out) = done_rx
+                    .recv()
+                    .expect("demangling worker threads exited early");
+                finished.insert(seq, out);
+            }
+
+            if eof {
+                break;
+            }
+        }
+        drop(batch_tx);
+
+        let mut stats = StreamStats::default();
+        for worker in workers {
+            let worker_stats = worker.join().unwrap();
+            stats.symbols += worker_stats.symbols;
+            stats.cache_hits += worker_stats.cache_hits;
+        }
+        Ok(stats)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    use super::Compression;
+    use super::{demangle_file, demangle_stream_with_options, Demangler, StreamOptions};
+
+    fn demangle_str(input: &str) -> String {
+        let options = StreamOptions::new().include_hash(false);
+        let mut output = Vec::new();
+        Demangler::new(&options)
+            .demangle_line(input, &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn find_multiple() {
+        assert_eq!(
+            demangle_str("_ZN3fooE.llvm moocow _ZN3fooE.llvm"),
+            "foo.llvm moocow foo.llvm"
+        );
+    }
+
+    #[test]
+    fn interleaved_new_legacy() {
+        assert_eq!(
+            demangle_str("_ZN3fooE.llvm moocow _RNvMNtNtNtNtCs8a2262Dv4r_3mio3sys4unix8selector5epollNtB2_8Selector6select _ZN3fooE.llvm"),
+            "foo.llvm moocow <mio::sys::unix::selector::epoll::Selector>::select foo.llvm"
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(
+            demangle_str("[<ffffffff81000000>] _ZN3foo3barE+0x1c/0x40"),
+            "[<ffffffff81000000>] foo::bar+0x1c/0x40"
+        );
+        assert_eq!(
+            demangle_str("_RNvC3foo3bar+28 (/usr/bin/foo)"),
+            "foo::bar+28 (/usr/bin/foo)"
+        );
+        assert_eq!(
+            demangle_str("_ZN3foo17h05af221e174051e9E.llvm.1234+0x8"),
+            "foo+0x8"
+        );
+    }
+
+    #[test]
+    fn backtrace() {
+        let input = "thread 'main' panicked at src/main.rs:2:5:\n\
+                     stack backtrace:\n   \
+                       0: _ZN3std9panicking11begin_panic17h05af221e174051e9E\n             \
+                                  at /rustc/library/std/src/panicking.rs:593:12\n   \
+                       1: _RNvCs1234_3foo4main\n             \
+                                  at ./_RNvC3foo3bar/src/main.rs:2:5\n  \
+                      12: main\n";
+        let expected = "thread 'main' panicked at src/main.rs:2:5:\n\
+                        stack backtrace:\n   \
+                          0: std::panicking::begin_panic\n             \
+                                     at /rustc/library/std/src/panicking.rs:593:12\n   \
+                          1: foo::main\n             \
+                                     at ./_RNvC3foo3bar/src/main.rs:2:5\n  \
+                         12: main\n";
+        for &threads in &[1, 4] {
+            let options = StreamOptions::new()
+                .include_hash(false)
+                .backtrace(true)
+                .threads(threads);
+            let mut output = Vec::new();
+            demangle_stream_with_options(&mut input.as_bytes(), &mut output, &options).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn dedup() {
+        let input = "_ZN3foo17h05af221e174051e9E _R _ZN3foo17h05af221e174051e9E\n\
+                     _RNvC3foo3bar _R _RNvC3foo3bar _ZN3foo17h05af221e174051e9E\n";
+        for &include_hash in &[true, false] {
+            let mut expected = Vec::new();
+            let options = StreamOptions::new().include_hash(include_hash);
+            let stats =
+                demangle_stream_with_options(&mut input.as_bytes(), &mut expected, &options)
+                    .unwrap();
+            assert_eq!((stats.symbols(), stats.cache_hits()), (7, 0));
+
+            let mut output = Vec::new();
+            let stats = demangle_stream_with_options(
+                &mut input.as_bytes(),
+                &mut output,
+                &options.dedup(true),
+            )
+            .unwrap();
+            assert_eq!(output, expected);
+            assert_eq!((stats.symbols(), stats.cache_hits()), (7, 4));
+        }
+    }
+
+    #[test]
+    fn pipelined() {
+        let mut input = String::new();
+        for i in 0..50_000 {
+            input.push_str(match i % 4 {
+                0 => "_ZN3foo17h05af221e174051e9E la la la\n",
+                1 => "  3: _RNvMNtNtNtNtCs8a2262Dv4r_3mio3sys4unix8selector5epollNtB2_8Selector6select\n",
+                2 => "_RNvC3foo3bar _ZN3foo3barE\n",
+                _ => "no symbols here\n",
+            });
+        }
+
+        for &dedup in &[false, true] {
+            let options = StreamOptions::new().dedup(dedup);
+            let mut expected = Vec::new();
+            let expected_stats =
+                demangle_stream_with_options(&mut input.as_bytes(), &mut expected, &options)
+                    .unwrap();
+
+            for &threads in &[2, 7] {
+                let mut output = Vec::new();
+                let stats = demangle_stream_with_options(
+                    &mut input.as_bytes(),
+                    &mut output,
+                    &options.clone().threads(threads),
+                )
+                .unwrap();
+                assert!(output == expected);
+                assert_eq!(stats.symbols(), expected_stats.symbols());
+                if !dedup {
+                    assert_eq!(stats.cache_hits(), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn file() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("rustc-demangle-{}-in.txt", std::process::id()));
+        let output = dir.join(format!("rustc-demangle-{}-out.txt", std::process::id()));
+        let contents = "_ZN3foo17h05af221e174051e9E la la la\n_RNvC3foo3bar\n";
+
+        for &threads in &[1, 2] {
+            std::fs::write(&input, contents).unwrap();
+            let options = StreamOptions::new().include_hash(false).threads(threads);
+            let stats = demangle_file(&input, &output, &options).unwrap();
+            assert_eq!(stats.symbols(), 2);
+            assert_eq!(
+                std::fs::read_to_string(&output).unwrap(),
+                "foo la la la\nfoo::bar\n"
+            );
+
+            std::fs::write(&input, "").unwrap();
+            demangle_file(&input, &output, &options).unwrap();
+            assert_eq!(std::fs::read_to_string(&output).unwrap(), "");
+        }
+
+        // The input can't also be the output, which would be truncated first.
+        std::fs::write(&input, contents).unwrap();
+        let options = StreamOptions::new();
+        let err = demangle_file(&input, &input, &options).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let same = dir.join(".").join(input.file_name().unwrap());
+        let err = demangle_file(&input, &same, &options).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), contents);
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        // `_ZN3foo17h05af221e174051e9E la la la\n_RNvC3foo3bar\n`, compressed
+        // with each of the three DEFLATE block types (repeated 200 times for
+        // the dynamic Huffman one, to make it worth using).
+        const DYNAMIC: &[u8] = &[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xed, 0xcb, 0xbd, 0x0a,
+            0x40, 0x50, 0x00, 0x80, 0xd1, 0xdd, 0x53, 0x78, 0x04, 0xd7, 0x4f, 0x32, 0xcb, 0x6a,
+            0x30, 0x5a, 0x6e, 0x57, 0x91, 0x41, 0x29, 0x83, 0xe7, 0x97, 0x3c, 0x83, 0xed, 0xd4,
+            0x37, 0x7e, 0x27, 0xce, 0x63, 0xb5, 0x9d, 0x67, 0x68, 0xf7, 0xa2, 0x49, 0x5b, 0x59,
+            0x86, 0x35, 0xb4, 0x75, 0xd1, 0x84, 0xb5, 0x1b, 0xf2, 0x23, 0x7d, 0x65, 0x71, 0x1a,
+            0xef, 0xfe, 0xdd, 0xaa, 0x25, 0x5d, 0x59, 0x44, 0x10, 0x04, 0x41, 0x10, 0x04, 0x41,
+            0x10, 0x04, 0x41, 0x10, 0x04, 0x41, 0x10, 0x04, 0x41, 0x10, 0x04, 0x41, 0x10, 0x04,
+            0x41, 0x10, 0x04, 0x41, 0x10, 0x04, 0x41, 0x10, 0xe4, 0x1f, 0xf2, 0x00, 0x14, 0x78,
+            0x43, 0x26, 0xd8, 0x27, 0x00, 0x00,
+        ];
+        const FIXED: &[u8] = &[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x8b, 0x8f, 0xf2, 0x33,
+            0x4e, 0xcb, 0xcf, 0x37, 0x34, 0xcf, 0x30, 0x30, 0x4d, 0x4c, 0x33, 0x32, 0x32, 0x4c,
+            0x35, 0x34, 0x37, 0x31, 0x30, 0x35, 0x4c, 0xb5, 0x74, 0x55, 0xc8, 0x49, 0x84, 0x20,
+            0xae, 0xf8, 0x20, 0xbf, 0x32, 0x67, 0x90, 0x32, 0xe3, 0xa4, 0xc4, 0x22, 0x2e, 0x00,
+            0x67, 0xaf, 0x27, 0x8d, 0x33, 0x00, 0x00, 0x00,
+        ];
+        const STORED: &[u8] = &[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x33, 0x00, 0xcc,
+            0xff, 0x5f, 0x5a, 0x4e, 0x33, 0x66, 0x6f, 0x6f, 0x31, 0x37, 0x68, 0x30, 0x35, 0x61,
+            0x66, 0x32, 0x32, 0x31, 0x65, 0x31, 0x37, 0x34, 0x30, 0x35, 0x31, 0x65, 0x39, 0x45,
+            0x20, 0x6c, 0x61, 0x20, 0x6c, 0x61, 0x20, 0x6c, 0x61, 0x0a, 0x5f, 0x52, 0x4e, 0x76,
+            0x43, 0x33, 0x66, 0x6f, 0x6f, 0x33, 0x62, 0x61, 0x72, 0x0a, 0x67, 0xaf, 0x27, 0x8d,
+            0x33, 0x00, 0x00, 0x00,
+        ];
+
+        let line = "foo la la la\nfoo::bar\n";
+        let options = StreamOptions::new().include_hash(false);
+        for &(input, repeat) in &[(DYNAMIC, 200), (FIXED, 1), (STORED, 1)] {
+            let mut output = Vec::new();
+            let stats =
+                demangle_stream_with_options(&mut &input[..], &mut output, &options).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), line.repeat(repeat));
+            assert_eq!(stats.symbols(), 2 * repeat as u64);
+        }
+
+        // Concatenated members are decompressed one after the other.
+        let input = [DYNAMIC, FIXED, STORED].concat();
+        let mut output = Vec::new();
+        demangle_stream_with_options(&mut &input[..], &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), line.repeat(202));
+
+        // The magic number is found even if it's split across reads.
+        let mut input = std::io::BufReader::with_capacity(1, FIXED);
+        let mut output = Vec::new();
+        demangle_stream_with_options(&mut input, &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), line);
+
+        // Uncompressed (and very short) inputs are demangled as usual.
+        for &(input, expected) in &[("", ""), ("_", "_"), ("_RNvC3foo3bar\n", "foo::bar\n")] {
+            let mut input = std::io::BufReader::with_capacity(1, input.as_bytes());
+            let mut output = Vec::new();
+            demangle_stream_with_options(&mut input, &mut output, &options).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+
+        // Corrupted and truncated inputs are rejected.
+        let mut input = FIXED.to_vec();
+        input[30] ^= 1;
+        let mut output = Vec::new();
+        assert!(demangle_stream_with_options(&mut &input[..], &mut output, &options).is_err());
+        let input = &DYNAMIC[..DYNAMIC.len() - 1];
+        let mut output = Vec::new();
+        assert!(demangle_stream_with_options(&mut &input[..], &mut output, &options).is_err());
+
+        // The output can be compressed too, whether the input was or not.
+        let input = "_RNvC3foo3bar\n".repeat(100);
+        let gzip_output = StreamOptions::new().compress_output(Compression::Gzip);
+        let mut compressed = Vec::new();
+        demangle_stream_with_options(&mut input.as_bytes(), &mut compressed, &gzip_output).unwrap();
+        assert!(compressed.starts_with(&super::GZIP_MAGIC));
+        let mut output = Vec::new();
+        demangle_stream_with_options(&mut &compressed[..], &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "foo::bar\n".repeat(100));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let input = "_ZN3foo17h05af221e174051e9E la la la\n_RNvC3foo3bar\n".repeat(100);
+        let zstd_output = StreamOptions::new().compress_output(Compression::Zstd);
+        let mut compressed = Vec::new();
+        demangle_stream_with_options(&mut input.as_bytes(), &mut compressed, &zstd_output).unwrap();
+        assert!(compressed.starts_with(&super::ZSTD_MAGIC));
+
+        let line = "foo la la la\nfoo::bar\n";
+        let options = StreamOptions::new().include_hash(false);
+        let mut output = Vec::new();
+        let stats =
+            demangle_stream_with_options(&mut &compressed[..], &mut output, &options).unwrap();
+        // The symbols were already demangled (with their hashes) when compressing.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "foo::h05af221e174051e9 la la la\nfoo::bar\n".repeat(100)
+        );
+        assert_eq!(stats.symbols(), 0);
+
+        let mut compressed = Vec::new();
+        let zstd_output = options.clone().compress_output(Compression::Zstd);
+        demangle_stream_with_options(&mut input.as_bytes(), &mut compressed, &zstd_output).unwrap();
+
+        // Concatenated frames are decompressed one after the other.
+        let input = [&compressed[..], &compressed[..]].concat();
+        let mut output = Vec::new();
+        demangle_stream_with_options(&mut &input[..], &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), line.repeat(200));
+
+        // The magic number is found even if it's split across reads.
+        let mut input = std::io::BufReader::with_capacity(1, &compressed[..]);
+        let mut output = Vec::new();
+        demangle_stream_with_options(&mut input, &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), line.repeat(100));
+
+        // Truncated inputs are rejected.
+        let input = &compressed[..compressed.len() - 1];
+        let mut output = Vec::new();
+        assert!(demangle_stream_with_options(&mut &input[..], &mut output, &options).is_err());
+    }
+}
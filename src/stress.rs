@@ -0,0 +1,267 @@
+//! Generators for known-pathological (v0) symbols, for benchmarking and
+//! hardening symbolication pipelines against the same inputs this crate
+//! is tested against.
+//!
+//! All the generated symbols are syntactically valid, but (given large
+//! enough parameters) either get rejected for hitting one of the limits of
+//! this crate, print e.g. `{recursion limit reached}` for the same reason,
+//! or produce unusually large output from a small input.
+
+use std::string::String;
+
+/// A symbol whose path is nested `depth` levels deep, i.e.
+/// `foo::bar::...::bar`.
+///
+/// Above the recursion limit (a few hundred levels), this is rejected by
+/// `try_demangle` (see `TryDemangleError::recursion_limit_reached`).
+pub fn nested_paths(depth: usize) -> String {
+    let mut sym = String::from("_R");
+    for _ in 0..depth {
+        sym.push_str("Nv");
+    }
+    sym.push_str("C3foo");
+    for _ in 0..depth {
+        sym.push_str("3bar");
+    }
+    sym
+}
+
+/// A symbol with a chain of `depth` reference types, hidden from the initial
+/// validation by `demangle` (which doesn't follow backrefs) inside a crate
+/// name, and reached through a backref at the end of the chain, which refers
+/// back to its start.
+///
+/// Printing it always hits the recursion limit, which has to also account
+/// for the long chain itself, instead of only the backrefs followed (or the
+/// stack would overflow, for large enough `depth`). With the cyclic backref
+/// reported as such instead (see `DemangleOptions::cyclic_backrefs`), that
+/// only happens if `depth` itself is above the recursion limit.
+pub fn deep_backref_recursion(depth: usize) -> String {
+    let mut sym = format!("_RIC{}", depth);
+    let backref_start = sym.len() - 2;
+    for _ in 0..depth {
+        sym.push('R');
+    }
+    // Refer to the start of the chain, i.e. just after the identifier length.
+    push_backref(&mut sym, backref_start);
+    sym.push('E');
+    sym
+}
+
+/// A symbol with `n + 1` generic arguments, each a pair of two copies of
+/// the previous argument (starting from `u8`), through backrefs.
+///
+/// The size of the symbol is linear in `n`, but the size of its demangling
+/// is exponential, so for large enough `n` (around 16), printing it hits
+/// the size limit.
+pub fn exponential_backrefs(n: usize) -> String {
+    let mut sym = String::from("_RINvC1a1f");
+    let mut prev = sym.len() - 2;
+    sym.push('h');
+    for _ in 0..n {
+        let start = sym.len() - 2;
+        sym.push('T');
+        push_backref(&mut sym, prev);
+        push_backref(&mut sym, prev);
+        sym.push('E');
+        prev = start;
+    }
+    sym.push('E');
+    sym
+}
+
+/// A symbol whose name is `len` non-ASCII characters long, and therefore
+/// punycode-encoded.
+///
+/// Names longer than can be decoded (a few thousand characters, as this
+/// module requires `std`, and therefore allows allocating) are printed in
+/// their encoded form, as `punycode{...}`.
+pub fn long_punycode(len: usize) -> String {
+    let name: String = "αβγδε".chars().cycle().take(len).collect();
+    let encoded = punycode_encode(&name);
+    format!("_RNvC3foou{}_{}", encoded.len(), encoded)
+}
+
+/// Appends a backref to the given position (relative to the `_R` prefix).
+fn push_backref(sym: &mut String, pos: usize) {
+    sym.push('B');
+    if pos > 0 {
+        push_base62(sym, pos - 1);
+    }
+    sym.push('_');
+}
+
+fn push_base62(sym: &mut String, mut x: usize) {
+    const DIGITS: &[u8; 62] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let start = sym.len();
+    loop {
+        sym.insert(start, DIGITS[x % 62] as char);
+        x /= 62;
+        if x == 0 {
+            break;
+        }
+    }
+}
+
+/// Punycode-encodes `s` (as in RFC 3492, but with `_` as the delimiter
+/// after the ASCII characters, like in v0 symbols).
+fn punycode_encode(s: &str) -> String {
+    // Punycode parameters, see also `v0::Ident::punycode_decode`.
+    let base = 36;
+    let t_min = 1;
+    let t_max = 26;
+    let skew = 38;
+    let digit = |d: u32| {
+        (if d < 26 {
+            b'a' + d as u8
+        } else {
+            b'0' + (d - 26) as u8
+        }) as char
+    };
+    let adapt = |mut delta: u32, points: u32, first: bool| {
+        delta /= if first { 700 } else { 2 };
+        delta += delta / points;
+        let mut k = 0;
+        while delta > ((base - t_min) * t_max) / 2 {
+            delta /= base - t_min;
+            k += base;
+        }
+        k + ((base - t_min + 1) * delta) / (delta + skew)
+    };
+
+    let mut out: String = s.chars().filter(|c| c.is_ascii()).collect();
+    let basic = out.len() as u32;
+    if basic > 0 {
+        out.push('_');
+    }
+
+    let mut n = 0x80;
+    let mut delta = 0;
+    let mut bias = 72;
+    let mut handled = basic;
+    let total = s.chars().count() as u32;
+    while handled < total {
+        let m = s
+            .chars()
+            .map(|c| c as u32)
+            .filter(|&c| c >= n)
+            .min()
+            .unwrap();
+        delta += (m - n) * (handled + 1);
+        n = m;
+        for c in s.chars().map(|c| c as u32) {
+            if c < n {
+                delta += 1;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = base;
+                loop {
+                    let t = if k <= bias {
+                        t_min
+                    } else if k >= bias + t_max {
+                        t_max
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    out.push(digit(t + (q - t) % (base - t)));
+                    q = (q - t) / (base - t);
+                    k += base;
+                }
+                out.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    #[test]
+    fn nested_paths() {
+        let sym = super::nested_paths(2);
+        assert_eq!(::demangle(&sym).to_string(), "foo::bar::bar");
+        let err = ::try_demangle(&super::nested_paths(1000)).unwrap_err();
+        assert!(err.recursion_limit_reached());
+    }
+
+    #[test]
+    fn deep_backref_recursion() {
+        let sym = super::deep_backref_recursion(3);
+        let demangled = ::demangle(&sym).to_string();
+        assert!(demangled.starts_with("RRR::<&&&&"));
+        assert!(demangled.ends_with("&{recursion limit reached}>"));
+        assert!(!::demangle(&sym).recursion_limit_reached());
+        let options = ::DemangleOptions::new().cyclic_backrefs(true);
+        let demangled = ::demangle(&sym).with_options(options).to_string();
+        assert_eq!(demangled, "RRR::<&&&{cyclic backref}>");
+        let sym = super::deep_backref_recursion(100_000);
+        let demangled = ::demangle(&sym).with_options(options);
+        assert!(demangled.recursion_limit_reached());
+        assert!(demangled
+            .to_string()
+            .ends_with("&{recursion limit reached}>"));
+    }
+
+    #[test]
+    fn exponential_backrefs() {
+        let sym = super::exponential_backrefs(2);
+        assert_eq!(
+            ::demangle(&sym).to_string(),
+            "a::f::<u8, (u8, u8), ((u8, u8), (u8, u8))>"
+        );
+        let sym = super::exponential_backrefs(32);
+        assert!(sym.len() < 1000);
+        assert!(::demangle(&sym)
+            .to_string()
+            .ends_with("{size limit reached}"));
+        let options = ::DemangleOptions::new().max_output_bytes(1000);
+        let demangled = ::demangle(&sym).with_options(options).to_string();
+        assert!(demangled.len() < 1100);
+        assert!(demangled.ends_with("{output truncated}"));
+    }
+
+    #[test]
+    fn long_punycode() {
+        assert_eq!(
+            ::demangle(&super::long_punycode(7)).to_string(),
+            "foo::αβγδεαβ"
+        );
+        let name: String = "αβγδε".chars().cycle().take(100).collect();
+        assert_eq!(
+            ::demangle(&super::long_punycode(100)).to_string(),
+            format!("foo::{}", name)
+        );
+        let name: String = "αβγδε".chars().cycle().take(1000).collect();
+        assert_eq!(
+            ::demangle(&super::long_punycode(1000)).to_string(),
+            format!("foo::{}", name)
+        );
+        assert!(::demangle(&super::long_punycode(5000))
+            .to_string()
+            .starts_with("foo::punycode{"));
+    }
+
+    #[test]
+    fn punycode_encode() {
+        // Examples from RFC 3492 (with `_` instead of `-`).
+        assert_eq!(
+            super::punycode_encode("他们为什么不说中文"),
+            "ihqwcrb4cv8a8dqg056pqjye"
+        );
+        assert_eq!(
+            super::punycode_encode("3年B組金八先生"),
+            "3B_ww4c5e180e575a65lsy2b"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Splitting the output of demangling into tokens, e.g. for syntax highlighting.
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use super::Demangle;
+
+/// The kind of a token in the output of demangling a symbol, see
+/// `Demangle::write_tokens`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// The name of a crate, with its disambiguator if printed, e.g. `std` or
+    /// `foo[3c1c0]`.
+    Crate,
+    /// A path segment other than a crate, e.g. `Vec`, `push` or `{closure#0}`.
+    PathSegment,
+    /// The start of a list of generic arguments, i.e. `<` or `::<`.
+    GenericOpen,
+    /// The end of a list of generic arguments, i.e. `>`.
+    GenericClose,
+    /// A lifetime, e.g. `'a` or `'_`.
+    Lifetime,
+    /// A constant value, e.g. `123usize`, `true` or `"foo"`.
+    Const,
+    /// A keyword, e.g. `dyn`, `fn`, `mut` or `as`.
+    Keyword,
+    /// A primitive type, e.g. `u8` or `str`.
+    PrimitiveType,
+    /// The message for an error found while printing, e.g. `{invalid syntax}`
+    /// (or the `?` printed in place of everything after it).
+    Error,
+    /// Anything else, e.g. punctuation, or the whole of a symbol which isn't
+    /// a v0 symbol.
+    Other,
+}
+
+/// Adapter passing everything written to it to `emit`, along with the kind
+/// of the token being printed, as kept up to date in `kind` by the printer.
+struct TokenWriter<'a, F> {
+    kind: &'a Cell<TokenKind>,
+    emit: F,
+}
+
+impl<'a, F: FnMut(TokenKind, &str)> Write for TokenWriter<'a, F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !s.is_empty() {
+            (self.emit)(self.kind.get(), s);
+        }
+        Ok(())
+    }
+}
+
+/// Helper for printing a symbol while keeping track of the kind of the token
+/// being printed in `kind`.
+pub(crate) struct Tokens<'a, 'b> {
+    sym: &'b Demangle<'a>,
+    kind: &'b Cell<TokenKind>,
+}
+
+impl<'a, 'b> Tokens<'a, 'b> {
+    pub(crate) fn new(sym: &'b Demangle<'a>, kind: &'b Cell<TokenKind>) -> Tokens<'a, 'b> {
+        Tokens { sym, kind }
+    }
+}
+
+impl<'a, 'b> fmt::Display for Tokens<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.sym
+            .fmt_with_tokens(f, &self.sym.options, Some(self.kind))
+    }
+}
+
+pub(crate) fn write_tokens<F>(sym: &Demangle, alternate: bool, emit: F) -> fmt::Result
+where
+    F: FnMut(TokenKind, &str),
+{
+    let kind = Cell::new(TokenKind::Other);
+    let mut writer = TokenWriter { kind: &kind, emit };
+    let tokens = Tokens::new(sym, &kind);
+    if alternate {
+        write!(writer, "{:#}", tokens)
+    } else {
+        write!(writer, "{}", tokens)
+    }
+}
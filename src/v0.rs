@@ -1,5 +1,12 @@
+use core::cell::Cell;
 use core::convert::TryFrom;
-use core::{char, fmt, iter, mem, str};
+use core::{char, fmt, iter, mem, ops, str};
+
+use punycode::{decode_punycode, PunycodeError};
+use {
+    Binders, ConstEscapes, DemangleOptions, LifetimeNames, PathElision, PunycodeFallback,
+    TokenKind, V0Production, V0Stats,
+};
 
 #[allow(unused_macros)]
 macro_rules! write {
@@ -12,29 +19,69 @@ macro_rules! write {
 }
 
 // Maximum recursion depth when parsing symbols before we just bail out saying
-// "this symbol is invalid"
-const MAX_DEPTH: u32 = 500;
+// "this symbol is invalid" (also the default for
+// `DemangleOptions::recursion_limit`).
+pub const MAX_DEPTH: u32 = 500;
 
 /// Representation of a demangled symbol name.
 pub struct Demangle<'a> {
     inner: &'a str,
+    /// The length of the prefix (e.g. `_R`) before `inner`.
+    prefix_len: usize,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ParseError {
     /// Symbol doesn't match the expected `v0` grammar.
     Invalid,
 
     /// Parsing the symbol crossed the recursion limit (see `MAX_DEPTH`).
     RecursedTooDeep,
+
+    /// A backref was found in its own target (possibly through other
+    /// backrefs), which would otherwise recurse until the recursion limit.
+    CyclicBackref,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::Invalid => "invalid v0 symbol syntax",
+            ParseError::RecursedTooDeep => "recursion limit reached while parsing v0 symbol",
+            ParseError::CyclicBackref => "cyclic backref in v0 symbol",
+        })
+    }
 }
 
+impl core::error::Error for ParseError {}
+
 /// De-mangles a Rust symbol into a more readable version
 ///
 /// This function will take a **mangled** symbol and return a value. When printed,
 /// the de-mangled version will be written. If the symbol does not look like
 /// a mangled symbol, the original value will be written instead.
 pub fn demangle(s: &str) -> Result<(Demangle, &str), ParseError> {
+    demangle_with_error_offset(s, &DemangleOptions::new()).map_err(|(err, _, _)| err)
+}
+
+/// The same as `demangle`, except errors also include the offset (in `s`) at
+/// which they were found, which is `0` if `s` doesn't start with `_R` (or one
+/// of the other accepted prefixes), and the production being parsed at the
+/// time, and the recursion limit (`MAX_DEPTH` by default) and the handling of
+/// unknown tags are taken from `options`.
+pub fn demangle_with_error_offset<'a>(
+    s: &'a str,
+    options: &DemangleOptions,
+) -> Result<(Demangle<'a>, &'a str), (ParseError, usize, V0Production)> {
+    demangle_with_stats(s, options).map(|(d, rest, _)| (d, rest))
+}
+
+/// The same as `demangle_with_error_offset`, but also returns statistics about
+/// the structure of the symbol, as found while validating it (see `V0Stats`).
+pub fn demangle_with_stats<'a>(
+    s: &'a str,
+    options: &DemangleOptions,
+) -> Result<(Demangle<'a>, &'a str, V0Stats), (ParseError, usize, V0Production)> {
     // First validate the symbol. If it doesn't look like anything we're
     // expecting, we just print it literally. Note that we must handle non-Rust
     // symbols because we could have any function in the backtrace.
@@ -49,59 +96,295 @@ pub fn demangle(s: &str) -> Result<(Demangle, &str), ParseError> {
         // On OSX, symbols are prefixed with an extra _
         inner = &s[3..];
     } else {
-        return Err(ParseError::Invalid);
+        return Err((ParseError::Invalid, 0, V0Production::Path));
     }
+    let prefix_len = s.len() - inner.len();
 
     // Paths always start with uppercase characters.
     match inner.as_bytes()[0] {
         b'A'..=b'Z' => {}
-        _ => return Err(ParseError::Invalid),
+        _ => return Err((ParseError::Invalid, prefix_len, V0Production::Path)),
     }
 
-    // only work with ascii text
-    if inner.bytes().any(|c| c & 0x80 != 0) {
-        return Err(ParseError::Invalid);
-    }
+    // only work with ascii text (anything after it can only be trailing data)
+    let inner = match inner.bytes().position(|c| c & 0x80 != 0) {
+        Some(i) => &inner[..i],
+        None => inner,
+    };
+
+    let max_depth = options.recursion_limit;
+    let mut dummy_options = DemangleOptions::new();
+    dummy_options.unknown_tags = options.unknown_tags;
 
     // Verify that the symbol is indeed a valid path.
     let try_parse_path = |parser| {
         let mut dummy_printer = Printer {
             parser: Ok(parser),
             out: None,
+            options: dummy_options,
             bound_lifetime_depth: 0,
+            bound_lifetime_uses: None,
+            errored_at: 0,
+            prefix_len,
+            type_placeholders: 0,
+            const_placeholders: 0,
+            token_kind: None,
+            production: V0Production::Path,
+            errored_in: V0Production::Path,
         };
         dummy_printer
             .print_path(false)
             .expect("`fmt::Error`s should be impossible without a `fmt::Formatter`");
-        dummy_printer.parser
+        let errored_at = dummy_printer.prefix_len + dummy_printer.errored_at;
+        let errored_in = dummy_printer.errored_in;
+        dummy_printer
+            .parser
+            .map_err(|err| (err, errored_at, errored_in))
     };
     let mut parser = Parser {
         sym: inner,
         next: 0,
         depth: 0,
+        max_depth,
+        backref_limit: Some(inner.len()),
+        stats: V0Stats::default(),
     };
     parser = try_parse_path(parser)?;
 
     // Instantiating crate (paths always start with uppercase characters).
     if let Some(&(b'A'..=b'Z')) = parser.sym.as_bytes().get(parser.next) {
-        parser = try_parse_path(parser)?;
+        // If it doesn't parse, leave it as trailing data instead.
+        let after_path = parser.next;
+        let stats = parser.stats;
+        parser = try_parse_path(parser).unwrap_or(Parser {
+            sym: inner,
+            next: after_path,
+            depth: 0,
+            max_depth,
+            backref_limit: Some(inner.len()),
+            stats,
+        });
     }
 
-    Ok((Demangle { inner }, &parser.sym[parser.next..]))
+    // Leave out any trailing data, which `fmt` must not try to parse as the
+    // instantiating crate.
+    let inner = &inner[..parser.next];
+
+    Ok((
+        Demangle { inner, prefix_len },
+        &s[prefix_len + parser.next..],
+        parser.stats,
+    ))
 }
 
-impl<'s> fmt::Display for Demangle<'s> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl<'s> Demangle<'s> {
+    /// Account for `len` more bytes (e.g. a linker prefix) before the symbol
+    /// in the offsets of errors, so they're relative to the whole input.
+    pub fn with_outer_prefix(self, len: usize) -> Demangle<'s> {
+        Demangle {
+            prefix_len: len + self.prefix_len,
+            ..self
+        }
+    }
+
+    /// Print the symbol to `f`, keeping track of the kind of the token being
+    /// printed in `tokens`, if any (see `Demangle::write_tokens` in the crate
+    /// root).
+    pub fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+        options: &DemangleOptions,
+        tokens: Option<&Cell<TokenKind>>,
+    ) -> fmt::Result {
+        let mut printer = Printer {
+            parser: Ok(Parser {
+                sym: self.inner,
+                next: 0,
+                depth: 0,
+                max_depth: options.recursion_limit,
+                backref_limit: if options.cyclic_backrefs {
+                    Some(self.inner.len())
+                } else {
+                    None
+                },
+                stats: V0Stats::default(),
+            }),
+            out: Some(f),
+            options: *options,
+            bound_lifetime_depth: 0,
+            bound_lifetime_uses: None,
+            errored_at: 0,
+            prefix_len: self.prefix_len,
+            type_placeholders: 0,
+            const_placeholders: 0,
+            token_kind: tokens,
+            production: V0Production::Path,
+            errored_in: V0Production::Path,
+        };
+        printer.print_path(true)?;
+        if options.instantiating_crate {
+            printer.print_instantiating_crate()?;
+        }
+        Ok(())
+    }
+
+    /// Like `fmt`, but only prints the last segment of the path, see
+    /// `Demangle::function_name` in the crate root.
+    pub fn fmt_last_segment(
+        &self,
+        f: &mut fmt::Formatter,
+        options: &DemangleOptions,
+    ) -> fmt::Result {
         let mut printer = Printer {
             parser: Ok(Parser {
                 sym: self.inner,
                 next: 0,
                 depth: 0,
+                max_depth: options.recursion_limit,
+                backref_limit: if options.cyclic_backrefs {
+                    Some(self.inner.len())
+                } else {
+                    None
+                },
+                stats: V0Stats::default(),
             }),
             out: Some(f),
+            options: *options,
+            bound_lifetime_depth: 0,
+            bound_lifetime_uses: None,
+            errored_at: 0,
+            prefix_len: self.prefix_len,
+            type_placeholders: 0,
+            const_placeholders: 0,
+            token_kind: None,
+            production: V0Production::Path,
+            errored_in: V0Production::Path,
+        };
+        printer.print_last_path_segment()
+    }
+
+    /// Returns the name of the crate the symbol is defined in (see
+    /// `Demangle::crate_name` in the crate root), unless it's punycode.
+    pub fn crate_name(&self) -> Option<&'s str> {
+        let (_, name) = self.crate_root()?;
+        if name.punycode.is_empty() {
+            Some(name.ascii)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the disambiguator of the crate the symbol is defined in, as
+    /// printed (in hex) by e.g. `foo[3c1c0]::bar`, or `None` if it has none.
+    pub fn crate_disambiguator(&self) -> Option<u64> {
+        let (dis, _) = self.crate_root()?;
+        if dis != 0 {
+            Some(dis)
+        } else {
+            None
+        }
+    }
+
+    fn crate_root(&self) -> Option<(u64, Ident<'s>)> {
+        let mut printer = Printer {
+            parser: Ok(Parser {
+                sym: self.inner,
+                next: 0,
+                depth: 0,
+                max_depth: MAX_DEPTH,
+                backref_limit: Some(self.inner.len()),
+                stats: V0Stats::default(),
+            }),
+            out: None,
+            options: DemangleOptions::new(),
             bound_lifetime_depth: 0,
+            bound_lifetime_uses: None,
+            errored_at: 0,
+            prefix_len: self.prefix_len,
+            type_placeholders: 0,
+            const_placeholders: 0,
+            token_kind: None,
+            production: V0Production::Path,
+            errored_in: V0Production::Path,
+        };
+        printer.crate_root()
+    }
+
+    /// Returns the first error encountered while printing, if any, with the
+    /// offset and production it was found at (see `demangle_with_error_offset`).
+    ///
+    /// Unlike `demangle`, this follows backrefs, so it can find e.g. symbols
+    /// which only go past the recursion limit (of `options`) through backrefs.
+    /// The instantiating crate is also checked, even if `options` hides it.
+    pub fn first_error(
+        &self,
+        options: &DemangleOptions,
+    ) -> Option<(ParseError, usize, V0Production)> {
+        struct FirstError<'a, 's> {
+            sym: &'a Demangle<'s>,
+            max_depth: u32,
+            unknown_tags: bool,
+            error: Cell<Option<(ParseError, usize, V0Production)>>,
+        }
+
+        impl<'a, 's> fmt::Display for FirstError<'a, 's> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let mut options = DemangleOptions::new();
+                options.checked = true;
+                options.unknown_tags = self.unknown_tags;
+                let mut printer = Printer {
+                    parser: Ok(Parser {
+                        sym: self.sym.inner,
+                        next: 0,
+                        depth: 0,
+                        max_depth: self.max_depth,
+                        backref_limit: Some(self.sym.inner.len()),
+                        stats: V0Stats::default(),
+                    }),
+                    out: Some(f),
+                    options,
+                    bound_lifetime_depth: 0,
+                    bound_lifetime_uses: None,
+                    errored_at: 0,
+                    prefix_len: self.sym.prefix_len,
+                    type_placeholders: 0,
+                    const_placeholders: 0,
+                    token_kind: None,
+                    production: V0Production::Path,
+                    errored_in: V0Production::Path,
+                };
+                let mut r = printer.print_path(true);
+                if r.is_ok() {
+                    r = printer.print_instantiating_crate();
+                }
+                let errored_at = printer.prefix_len + printer.errored_at;
+                let errored_in = printer.errored_in;
+                self.error.set(
+                    printer
+                        .parser
+                        .err()
+                        .map(|err| (err, errored_at, errored_in)),
+                );
+                r
+            }
+        }
+
+        struct Sink;
+
+        impl fmt::Write for Sink {
+            fn write_str(&mut self, _: &str) -> fmt::Result {
+                Ok(())
+            }
+        }
+
+        let first_error = FirstError {
+            sym: self,
+            max_depth: options.recursion_limit,
+            unknown_tags: options.unknown_tags,
+            error: Cell::new(None),
         };
-        printer.print_path(true)
+        let _ = fmt::write(&mut Sink, format_args!("{}", first_error));
+        first_error.error.get()
     }
 }
 
@@ -114,6 +397,11 @@ struct Ident<'s> {
 
 const SMALL_PUNYCODE_LEN: usize = 128;
 
+/// The most characters `Ident::try_large_punycode_decode` decodes, which
+/// keeps the (quadratic) cost of inserting them bounded.
+#[cfg(feature = "alloc")]
+const LARGE_PUNYCODE_LEN: usize = 4096;
+
 impl<'s> Ident<'s> {
     /// Attempt to decode punycode on the stack (allocation-free),
     /// and pass the char slice to the closure, if successful.
@@ -123,7 +411,7 @@ impl<'s> Ident<'s> {
         let mut out_len = 0;
         let r = self.punycode_decode(|i, c| {
             // Check there's space left for another character.
-            out.get(out_len).ok_or(())?;
+            out.get(out_len).ok_or(PunycodeError)?;
 
             // Move the characters after the insert position.
             let mut j = out_len;
@@ -146,122 +434,37 @@ impl<'s> Ident<'s> {
         }
     }
 
-    /// Decode punycode as insertion positions and characters
-    /// and pass them to the closure, which can return `Err(())`
-    /// to stop the decoding process.
-    fn punycode_decode<F: FnMut(usize, char) -> Result<(), ()>>(
-        &self,
-        mut insert: F,
-    ) -> Result<(), ()> {
-        let mut punycode_bytes = self.punycode.bytes().peekable();
-        if punycode_bytes.peek().is_none() {
-            return Err(());
-        }
-
-        let mut len = 0;
-
-        // Populate initial output from ASCII fragment.
-        for c in self.ascii.chars() {
-            insert(len, c)?;
-            len += 1;
-        }
-
-        // Punycode parameters and initial state.
-        let base = 36;
-        let t_min = 1;
-        let t_max = 26;
-        let skew = 38;
-        let mut damp = 700;
-        let mut bias = 72;
-        let mut i: usize = 0;
-        let mut n: usize = 0x80;
-
-        loop {
-            // Read one delta value.
-            let mut delta: usize = 0;
-            let mut w = 1;
-            let mut k: usize = 0;
-            loop {
-                use core::cmp::{max, min};
-
-                k += base;
-                let t = min(max(k.saturating_sub(bias), t_min), t_max);
-
-                let d = match punycode_bytes.next() {
-                    Some(d @ b'a'..=b'z') => d - b'a',
-                    Some(d @ b'0'..=b'9') => 26 + (d - b'0'),
-                    _ => return Err(()),
-                };
-                let d = d as usize;
-                delta = delta.checked_add(d.checked_mul(w).ok_or(())?).ok_or(())?;
-                if d < t {
-                    break;
-                }
-                w = w.checked_mul(base - t).ok_or(())?;
-            }
-
-            // Compute the new insert position and character.
-            len += 1;
-            i = i.checked_add(delta).ok_or(())?;
-            n = n.checked_add(i / len).ok_or(())?;
-            i %= len;
-
-            let n_u32 = n as u32;
-            let c = if n_u32 as usize == n {
-                char::from_u32(n_u32).ok_or(())?
-            } else {
-                return Err(());
-            };
-
-            // Insert the new character and increment the insert position.
-            insert(i, c)?;
-            i += 1;
-
-            // If there are no more deltas, decoding is complete.
-            if punycode_bytes.peek().is_none() {
-                return Ok(());
-            }
-
-            // Perform bias adaptation.
-            delta /= damp;
-            damp = 2;
-
-            delta += delta / len;
-            let mut k = 0;
-            while delta > ((base - t_min) * t_max) / 2 {
-                delta /= base - t_min;
-                k += base;
+    /// Like `try_small_punycode_decode`, but decoding into a heap buffer,
+    /// for identifiers longer than `SMALL_PUNYCODE_LEN` characters.
+    /// This supports up to `LARGE_PUNYCODE_LEN` characters.
+    #[cfg(feature = "alloc")]
+    fn try_large_punycode_decode<F: FnOnce(&[char]) -> R, R>(&self, f: F) -> Option<R> {
+        let mut out = ::alloc::vec::Vec::new();
+        let r = self.punycode_decode(|i, c| {
+            if out.len() == LARGE_PUNYCODE_LEN {
+                return Err(PunycodeError);
             }
-            bias = k + ((base - t_min + 1) * delta) / (delta + skew);
+            out.insert(i, c);
+            Ok(())
+        });
+        if r.is_ok() {
+            Some(f(&out))
+        } else {
+            None
         }
     }
-}
-
-impl<'s> fmt::Display for Ident<'s> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.try_small_punycode_decode(|chars| {
-            for &c in chars {
-                c.fmt(f)?;
-            }
-            Ok(())
-        })
-        .unwrap_or_else(|| {
-            if !self.punycode.is_empty() {
-                f.write_str("punycode{")?;
-
-                // Reconstruct a standard Punycode encoding,
-                // by using `-` as the separator.
-                if !self.ascii.is_empty() {
-                    f.write_str(self.ascii)?;
-                    f.write_str("-")?;
-                }
-                f.write_str(self.punycode)?;
 
-                f.write_str("}")
-            } else {
-                f.write_str(self.ascii)
-            }
-        })
+    /// Decode punycode as insertion positions and characters
+    /// and pass them to the closure, which can return `Err`
+    /// to stop the decoding process (see `decode_punycode`).
+    fn punycode_decode<F: FnMut(usize, char) -> Result<(), PunycodeError>>(
+        &self,
+        insert: F,
+    ) -> Result<(), PunycodeError> {
+        if self.punycode.is_empty() {
+            return Err(PunycodeError);
+        }
+        decode_punycode(self.ascii, self.punycode, insert)
     }
 }
 
@@ -273,17 +476,22 @@ struct HexNibbles<'s> {
 impl<'s> HexNibbles<'s> {
     /// Decode an integer value (with the "most significant nibble" first),
     /// returning `None` if it can't fit in an `u64`.
-    // FIXME(eddyb) should this "just" use `u128` instead?
     fn try_parse_uint(&self) -> Option<u64> {
+        self.try_parse_u128().and_then(|v| u64::try_from(v).ok())
+    }
+
+    /// Like `try_parse_uint`, but for values up to 128 bits (e.g. the
+    /// `u128`/`i128` constants of const generics).
+    fn try_parse_u128(&self) -> Option<u128> {
         let nibbles = self.nibbles.trim_start_matches("0");
 
-        if nibbles.len() > 16 {
+        if nibbles.len() > 32 {
             return None;
         }
 
         let mut v = 0;
         for nibble in nibbles.chars() {
-            v = (v << 4) | (nibble.to_digit(16).unwrap() as u64);
+            v = (v << 4) | (nibble.to_digit(16).unwrap() as u128);
         }
         Some(v)
     }
@@ -367,6 +575,34 @@ impl<'s> HexNibbles<'s> {
     }
 }
 
+/// The name of a special (i.e. uppercase) namespace, for those rustc uses,
+/// i.e. `C` for closures (including coroutines) and `S` for shims.
+fn special_namespace_name(ns: char) -> Option<&'static str> {
+    match ns {
+        'C' => Some("closure"),
+        'S' => Some("shim"),
+        _ => None,
+    }
+}
+
+/// The description of a shim (in the `S` namespace) of the kind rustc names
+/// `name`, if known, see `DemangleOptions::shim_kinds`.
+fn shim_kind(name: &Ident) -> Option<&'static str> {
+    if !name.punycode.is_empty() {
+        return None;
+    }
+    Some(match name.ascii {
+        "vtable" => "vtable-shim",
+        "reify" => "reify-shim",
+        "reify_fnptr" => "fn-ptr-shim",
+        "reify_vtable" => "reify-vtable-shim",
+        "tls" => "tls-shim",
+        "by_move" => "by-move-shim",
+        "by_ref" => "by-ref-shim",
+        _ => return None,
+    })
+}
+
 fn basic_type(tag: u8) -> Option<&'static str> {
     Some(match tag {
         b'b' => "bool",
@@ -385,6 +621,9 @@ fn basic_type(tag: u8) -> Option<&'static str> {
         b'y' => "u64",
         b'o' => "u128",
         b'j' => "usize",
+        // `f16` and `f128` have no tags of their own: rustc mangles them as
+        // paths to crates named `f16` and `f128` instead (i.e. `C3f16` and
+        // `C4f128`), which print as the types' names without special-casing.
         b'f' => "f32",
         b'd' => "f64",
         b'z' => "!",
@@ -399,12 +638,26 @@ struct Parser<'s> {
     sym: &'s str,
     next: usize,
     depth: u32,
+    max_depth: u32,
+
+    /// The position of the innermost backref whose target is being parsed
+    /// (or the length of `sym`, outside of backrefs), which nothing in that
+    /// target can reach, as backrefs only refer to what precedes them, so a
+    /// backref found there is part of its own target (see `backref`), or
+    /// `None` to follow such backrefs until the recursion limit is reached
+    /// instead (see `DemangleOptions::cyclic_backrefs`).
+    backref_limit: Option<usize>,
+
+    /// Statistics about what was parsed so far (see `V0Stats`), which are
+    /// only meaningful when not following backrefs (i.e. while validating).
+    stats: V0Stats,
 }
 
 impl<'s> Parser<'s> {
     fn push_depth(&mut self) -> Result<(), ParseError> {
         self.depth += 1;
-        if self.depth > MAX_DEPTH {
+        self.stats.max_depth = self.stats.max_depth.max(self.depth);
+        if self.depth > self.max_depth {
             Err(ParseError::RecursedTooDeep)
         } else {
             Ok(())
@@ -508,13 +761,23 @@ impl<'s> Parser<'s> {
     fn backref(&mut self) -> Result<Parser<'s>, ParseError> {
         let s_start = self.next - 1;
         let i = self.integer_62()?;
+        self.stats.backrefs += 1;
+        self.stats.productions += 1;
         if i >= s_start as u64 {
             return Err(ParseError::Invalid);
         }
+        if let Some(limit) = self.backref_limit {
+            if s_start >= limit {
+                return Err(ParseError::CyclicBackref);
+            }
+        }
         let mut new_parser = Parser {
             sym: self.sym,
             next: i as usize,
             depth: self.depth,
+            max_depth: self.max_depth,
+            backref_limit: self.backref_limit.map(|_| s_start),
+            stats: self.stats,
         };
         new_parser.push_depth()?;
         Ok(new_parser)
@@ -540,8 +803,10 @@ impl<'s> Parser<'s> {
         }
 
         let ident = &self.sym[start..self.next];
+        self.stats.productions += 1;
 
         if is_punycode {
+            self.stats.punycode_identifiers += 1;
             let ident = match ident.bytes().rposition(|b| b == b'_') {
                 Some(i) => Ident {
                     ascii: &ident[..i],
@@ -575,31 +840,80 @@ struct Printer<'a, 'b: 'a, 's> {
     /// The output formatter to demangle to, or `None` while skipping printing.
     out: Option<&'a mut fmt::Formatter<'b>>,
 
+    /// Options controlling the output (some of which default to depending on
+    /// whether alternate formatting, i.e. `{:#}`, was requested of `out`).
+    options: DemangleOptions,
+
     /// Cumulative number of lifetimes bound by `for<...>` binders ('G'),
     /// anywhere "around" the current entity (e.g. type) being demangled.
     /// This value is not tracked while skipping printing, as it'd be unused.
     ///
     /// See also the documentation on the `Printer::in_binder` method.
     bound_lifetime_depth: u32,
+
+    /// While looking for uses of the lifetimes bound by a binder (see
+    /// `Printer::uses_bound_lifetimes`), its lifetimes (by depth, like in
+    /// `print_lifetime_from_index`), and whether any of them were used yet.
+    bound_lifetime_uses: Option<(ops::Range<u64>, bool)>,
+
+    /// The position (see `Parser::next`) at which the parser errored, if it
+    /// did (see `Printer::set_error`).
+    errored_at: usize,
+
+    /// The length of the prefix (e.g. `_R`) before `Parser::sym`, to turn
+    /// `errored_at` into an offset in the symbol.
+    prefix_len: usize,
+
+    /// The innermost production being parsed, i.e. path, type or const (see
+    /// `Printer::in_production`), and the one the parser errored in, if it
+    /// did (which can also be an identifier or backref, see `parse!`).
+    production: V0Production,
+    errored_in: V0Production,
+
+    /// Number of type and const generic arguments printed so far as
+    /// placeholders (see `DemangleOptions::generic_placeholders`), i.e. the
+    /// index of the next placeholder of each kind.
+    type_placeholders: usize,
+    const_placeholders: usize,
+
+    /// The kind of the token being printed, if tokens are being emitted (see
+    /// `Printer::in_token`).
+    token_kind: Option<&'a Cell<TokenKind>>,
+}
+
+/// The segments of a path which aren't printed (see `PathElision`), counted
+/// backwards from its last segment, i.e. `start..end` with `0` for the last
+/// segment, `1` for the one before it, etc.
+#[derive(Copy, Clone)]
+struct ElidedSegments {
+    start: usize,
+    end: usize,
+}
+
+impl ElidedSegments {
+    fn contains(self, from_leaf: usize) -> bool {
+        self.start <= from_leaf && from_leaf < self.end
+    }
 }
 
 impl ParseError {
-    /// Snippet to print when the error is initially encountered.
+    /// Snippet to print (in braces) when the error is initially encountered.
     fn message(&self) -> &str {
         match self {
-            ParseError::Invalid => "{invalid syntax}",
-            ParseError::RecursedTooDeep => "{recursion limit reached}",
+            ParseError::Invalid => "invalid syntax",
+            ParseError::RecursedTooDeep => "recursion limit reached",
+            ParseError::CyclicBackref => "cyclic backref",
         }
     }
 }
 
 /// Mark the parser as errored (with `ParseError::Invalid`), print the
-/// appropriate message (see `ParseError::message`) and return early.
+/// appropriate message (see `Printer::print_error`) and return early.
 macro_rules! invalid {
     ($printer:ident) => {{
         let err = ParseError::Invalid;
-        $printer.print(err.message())?;
-        $printer.parser = Err(err);
+        $printer.set_error(err);
+        $printer.print_error(err)?;
         return Ok(());
     }};
 }
@@ -609,7 +923,7 @@ macro_rules! invalid {
 ///
 /// If the parser errored, before or now, this returns early,
 /// from the current function, after printing either:
-/// * for a new error, the appropriate message (see `ParseError::message`)
+/// * for a new error, the appropriate message (see `Printer::print_error`)
 /// * for an earlier error, only `?` -  this allows callers to keep printing
 ///   the approximate syntax of the path/type/const, despite having errors,
 ///   e.g. `Vec<[(A, ?); ?]>` instead of `Vec<[(A, ?`
@@ -619,16 +933,29 @@ macro_rules! parse {
             Ok(ref mut parser) => match parser.$method($($($arg),*)*) {
                 Ok(x) => x,
                 Err(err) => {
-                    $printer.print(err.message())?;
-                    $printer.parser = Err(err);
+                    if let Some(production) = parser_method_production(stringify!($method)) {
+                        $printer.production = production;
+                    }
+                    $printer.set_error(err);
+                    $printer.print_error(err)?;
                     return Ok(());
                 }
             }
-            Err(_) => return $printer.print("?"),
+            Err(_) => return $printer.print_token(TokenKind::Error, "?"),
         }
     };
 }
 
+/// The production parsed by the given `Parser` method (as named in `parse!`),
+/// if it's one on its own, instead of a part of the production being printed.
+fn parser_method_production(method: &str) -> Option<V0Production> {
+    match method {
+        "ident" => Some(V0Production::Identifier),
+        "backref" => Some(V0Production::Backref),
+        _ => None,
+    }
+}
+
 impl<'a, 'b, 's> Printer<'a, 'b, 's> {
     /// Eat the given character from the parser,
     /// returning `false` if the parser errored.
@@ -636,6 +963,35 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         self.parser.as_mut().map(|p| p.eat(b)) == Ok(true)
     }
 
+    /// Mark the parser as errored, remembering its position (and the
+    /// production being parsed) at the time.
+    fn set_error(&mut self, err: ParseError) {
+        if let Ok(ref parser) = self.parser {
+            self.errored_at = parser.next;
+            self.errored_in = self.production;
+        }
+        self.parser = Err(err);
+    }
+
+    /// Parse (and print) `production` using the given closure, keeping track of
+    /// it as the innermost production, for errors (see `set_error`).
+    fn in_production<F>(&mut self, production: V0Production, f: F) -> fmt::Result
+    where
+        F: FnOnce(&mut Self) -> fmt::Result,
+    {
+        let outer = mem::replace(&mut self.production, production);
+        let r = f(self);
+        self.production = outer;
+        r
+    }
+
+    /// Count one more path, type or const as parsed, for `V0Stats`.
+    fn count_production(&mut self) {
+        if let Ok(ref mut parser) = self.parser {
+            parser.stats.productions += 1;
+        }
+    }
+
     /// Skip printing (i.e. `self.out` will be `None`) for the duration of the
     /// given closure. This should not change parsing behavior, only disable the
     /// output, but there may be optimizations (such as not traversing backrefs).
@@ -648,6 +1004,53 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         self.out = orig_out;
     }
 
+    /// Find the crate root (i.e. `C`) of a path, going through generic
+    /// arguments, `impl`s (whose own paths aren't otherwise printed) and
+    /// backrefs, and returning its disambiguator and name, or `None` if the
+    /// path is invalid.
+    fn crate_root(&mut self) -> Option<(u64, Ident<'s>)> {
+        let parser = self.parser.as_mut().ok()?;
+        parser.push_depth().ok()?;
+        match parser.next().ok()? {
+            b'C' => {
+                let dis = parser.disambiguator().ok()?;
+                Some((dis, parser.ident().ok()?))
+            }
+            b'N' => {
+                parser.namespace().ok()?;
+                self.crate_root()
+            }
+            b'M' | b'X' => {
+                parser.disambiguator().ok()?;
+                self.crate_root()
+            }
+            b'Y' => {
+                // Use the trait's crate, as the type may not have one.
+                self.skipping_printing(|this| this.print_type());
+                self.crate_root()
+            }
+            b'I' => self.crate_root(),
+            b'B' => {
+                let backref = parser.backref().ok()?;
+                self.parser = Ok(backref);
+                self.crate_root()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether generic arguments (including the associated type bindings of
+    /// trait objects) are to be parsed without printing them.
+    fn hides_generics(&self) -> bool {
+        !self.options.generics && self.out.is_some() && self.parser.is_ok()
+    }
+
+    /// Whether lifetimes (and the binders introducing them) are to be parsed
+    /// without printing them (see `DemangleOptions::lifetimes`).
+    fn hides_lifetimes(&self) -> bool {
+        !self.options.lifetimes && self.out.is_some()
+    }
+
     /// Print the target of a backref, using the given closure.
     /// When printing is being skipped, the backref will only be parsed,
     /// ignoring the backref's target completely.
@@ -663,7 +1066,11 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
 
         let orig_parser = mem::replace(&mut self.parser, Ok(backref_parser));
         let r = f(self);
-        self.parser = orig_parser;
+        // Printing will stop on `fmt::Error`, so any error from inside the
+        // backref can be kept around instead (see `Demangle::first_error`).
+        if r.is_ok() {
+            self.parser = orig_parser;
+        }
         r
     }
 
@@ -673,6 +1080,24 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         }
     }
 
+    /// Output the message for a newly encountered parse error (see
+    /// `ParseError::message`), with the offset it was found at (see
+    /// `DemangleOptions::error_offsets`), or fail with `fmt::Error` instead,
+    /// if the output must not contain errors (see `DemangleOptions::checked`).
+    fn print_error(&mut self, err: ParseError) -> fmt::Result {
+        if self.options.checked && self.out.is_some() {
+            return Err(fmt::Error);
+        }
+        if self.options.error_offsets {
+            let offset = self.prefix_len + self.errored_at;
+            return self.print_token(
+                TokenKind::Error,
+                format_args!("{{{} at byte {}}}", err.message(), offset),
+            );
+        }
+        self.print_token(TokenKind::Error, format_args!("{{{}}}", err.message()))
+    }
+
     /// Output the given value to `self.out` (using `fmt::Display` formatting),
     /// if printing isn't being skipped.
     fn print(&mut self, x: impl fmt::Display) -> fmt::Result {
@@ -682,9 +1107,97 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         Ok(())
     }
 
-    /// Output the given `char`s (escaped using `char::escape_debug`), with the
-    /// whole sequence wrapped in quotes, for either a `char` or `&str` literal,
-    /// if printing isn't being skipped.
+    /// Print using the given closure, marking its output as a token of the
+    /// given `kind` (unless overridden by nested tokens), if tokens are being
+    /// emitted (see `Demangle::write_tokens` in the crate root).
+    fn in_token<F>(&mut self, kind: TokenKind, f: F) -> fmt::Result
+    where
+        F: FnOnce(&mut Self) -> fmt::Result,
+    {
+        let token_kind = match self.token_kind {
+            Some(token_kind) => token_kind,
+            None => return f(self),
+        };
+        let outer = token_kind.replace(kind);
+        let r = f(self);
+        token_kind.set(outer);
+        r
+    }
+
+    /// Output the given value as a token of the given `kind`, see `in_token`.
+    fn print_token(&mut self, kind: TokenKind, x: impl fmt::Display) -> fmt::Result {
+        self.in_token(kind, |this| this.print(x))
+    }
+
+    /// Output the given identifier (if printing isn't being skipped), with its
+    /// punycode decoded, or, if that's not possible, in the form chosen by
+    /// `DemangleOptions::undecodable_punycode` (or fail with `fmt::Error` in
+    /// the same cases as `print_error`, for `PunycodeFallback::Error`).
+    fn print_ident(&mut self, ident: Ident) -> fmt::Result {
+        let out = match &mut self.out {
+            Some(out) => out,
+            None => return Ok(()),
+        };
+
+        fn print_chars(out: &mut fmt::Formatter, chars: &[char]) -> fmt::Result {
+            for &c in chars {
+                fmt::Display::fmt(&c, out)?;
+            }
+            Ok(())
+        }
+
+        let decoded = ident.try_small_punycode_decode(|chars| print_chars(out, chars));
+        #[cfg(feature = "alloc")]
+        let decoded = match decoded {
+            Some(r) => Some(r),
+            None => ident.try_large_punycode_decode(|chars| print_chars(out, chars)),
+        };
+        if let Some(r) = decoded {
+            return r;
+        }
+        if ident.punycode.is_empty() {
+            return out.write_str(ident.ascii);
+        }
+
+        // Reconstruct a standard Punycode encoding, by using `-` as the
+        // separator (unless printing the identifier as it was in the symbol).
+        let separator = match self.options.undecodable_punycode {
+            PunycodeFallback::Raw => "_",
+            _ => "-",
+        };
+        let separator = if ident.ascii.is_empty() {
+            ""
+        } else {
+            separator
+        };
+        match self.options.undecodable_punycode {
+            PunycodeFallback::Error if self.options.checked => Err(fmt::Error),
+            PunycodeFallback::Placeholder | PunycodeFallback::Error => {
+                out.write_str("punycode{")?;
+                out.write_str(ident.ascii)?;
+                out.write_str(separator)?;
+                out.write_str(ident.punycode)?;
+                out.write_str("}")
+            }
+            PunycodeFallback::Raw => {
+                out.write_str(ident.ascii)?;
+                out.write_str(separator)?;
+                out.write_str(ident.punycode)
+            }
+            PunycodeFallback::Escaped => {
+                let chars = ident.ascii.chars().chain(separator.chars());
+                for c in chars.chain(ident.punycode.chars()) {
+                    out.write_fmt(format_args!("\\u{{{:x}}}", c as u32))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Output the given `char`s (escaped according to
+    /// `DemangleOptions::const_escapes`), with the whole sequence wrapped in
+    /// quotes, for either a `char` or `&str` literal, if printing isn't being
+    /// skipped.
     fn print_quoted_escaped_chars(
         &mut self,
         quote: char,
@@ -702,8 +1215,18 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                     continue;
                 }
 
-                for escaped in c.escape_debug() {
-                    out.write_char(escaped)?;
+                match self.options.const_escapes {
+                    ConstEscapes::Always => {
+                        for escaped in c.escape_default() {
+                            out.write_char(escaped)?;
+                        }
+                    }
+                    ConstEscapes::NonPrintable => {
+                        for escaped in c.escape_debug() {
+                            out.write_char(escaped)?;
+                        }
+                    }
+                    ConstEscapes::Never => out.write_char(c)?,
                 }
             }
             out.write_char(quote)?;
@@ -715,29 +1238,44 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
     /// An index of `0` always refers to `'_`, but starting with `1`,
     /// indices refer to late-bound lifetimes introduced by a binder.
     fn print_lifetime_from_index(&mut self, lt: u64) -> fmt::Result {
-        // Bound lifetimes aren't tracked when skipping printing.
+        // Bound lifetimes aren't tracked when skipping printing, unless
+        // looking for their uses.
         if self.out.is_none() {
+            if let Some((ref lifetimes, ref mut used)) = self.bound_lifetime_uses {
+                let depth = (self.bound_lifetime_depth as u64).checked_sub(lt);
+                if lt != 0 && depth.map(|depth| lifetimes.contains(&depth)) == Some(true) {
+                    *used = true;
+                }
+            }
             return Ok(());
         }
 
-        self.print("'")?;
-        if lt == 0 {
-            return self.print("_");
-        }
-        match (self.bound_lifetime_depth as u64).checked_sub(lt) {
-            Some(depth) => {
-                // Try to print lifetimes alphabetically first.
-                if depth < 26 {
-                    let c = (b'a' + depth as u8) as char;
-                    self.print(c)
-                } else {
-                    // Use `'_123` after running out of letters.
-                    self.print("_")?;
-                    self.print(depth)
-                }
+        self.in_token(TokenKind::Lifetime, |this| {
+            this.print("'")?;
+            if lt == 0 {
+                return this.print("_");
             }
-            None => invalid!(self),
-        }
+            match (this.bound_lifetime_depth as u64).checked_sub(lt) {
+                Some(depth) => match this.options.lifetime_names {
+                    // Try to print lifetimes alphabetically first.
+                    LifetimeNames::Alphabetic if depth < 26 => {
+                        let c = (b'a' + depth as u8) as char;
+                        this.print(c)
+                    }
+                    LifetimeNames::Alphabetic => {
+                        // Use `'_123` after running out of letters.
+                        this.print("_")?;
+                        this.print(depth)
+                    }
+                    LifetimeNames::Numeric => this.print(depth + 1),
+                    LifetimeNames::Custom(name) => match this.out {
+                        Some(ref mut out) => name(depth, out),
+                        None => Ok(()),
+                    },
+                },
+                None => invalid!(this),
+            }
+        })
     }
 
     /// Optionally enter a binder ('G') for late-bound lifetimes,
@@ -745,23 +1283,52 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
     /// and make those lifetimes visible to it (via depth level).
     fn in_binder<F>(&mut self, f: F) -> fmt::Result
     where
-        F: FnOnce(&mut Self) -> fmt::Result,
+        F: Fn(&mut Self) -> fmt::Result,
+    {
+        self.in_binder_introduced_by("for", false, f)
+    }
+
+    /// Like `in_binder`, but printing `keyword<...> ` instead of `for<...> `,
+    /// even without any lifetimes if `always` is set (e.g. for `unsafe<> T`,
+    /// which is a different type from `T`).
+    fn in_binder_introduced_by<F>(&mut self, keyword: &str, always: bool, f: F) -> fmt::Result
+    where
+        F: Fn(&mut Self) -> fmt::Result,
     {
         let bound_lifetimes = parse!(self, opt_integer_62(b'G'));
 
-        // Don't track bound lifetimes when skipping printing.
-        if self.out.is_none() {
+        // Don't track bound lifetimes when skipping printing, unless looking
+        // for their uses.
+        if self.out.is_none() && self.bound_lifetime_uses.is_none() {
             return f(self);
         }
 
-        if bound_lifetimes > 0 {
-            self.print("for<")?;
-            for i in 0..bound_lifetimes {
-                if i > 0 {
-                    self.print(", ")?;
+        let first_depth = self.bound_lifetime_depth;
+        self.bound_lifetime_depth += bound_lifetimes as u32;
+
+        // Binders only introducing lifetimes vanish along with them.
+        let mut hides_lifetimes = self.hides_lifetimes();
+        if !hides_lifetimes
+            && bound_lifetimes > 0
+            && self.options.binders == Binders::HideUnused
+            && self.out.is_some()
+        {
+            hides_lifetimes = !self.uses_bound_lifetimes(first_depth, &f);
+        }
+        if (bound_lifetimes > 0 && !hides_lifetimes) || always {
+            self.print_token(TokenKind::Keyword, keyword)?;
+            self.print("<")?;
+            if hides_lifetimes {
+                // Only `always` binders get here, e.g. `unsafe<> T`.
+            } else if self.options.binders == Binders::Collapsed && bound_lifetimes > 0 {
+                self.print("…")?;
+            } else {
+                for i in 0..bound_lifetimes {
+                    if i > 0 {
+                        self.print(", ")?;
+                    }
+                    self.print_lifetime_from_index(bound_lifetimes - i)?;
                 }
-                self.bound_lifetime_depth += 1;
-                self.print_lifetime_from_index(1)?;
             }
             self.print("> ")?;
         }
@@ -774,10 +1341,55 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         r
     }
 
-    /// Print list elements using the given closure and separator,
-    /// until the end of the list ('E') is found, or the parser errors.
-    /// Returns the number of elements printed.
-    fn print_sep_list<F>(&mut self, f: F, sep: &str) -> Result<usize, fmt::Error>
+    /// Whether any of the lifetimes of the binder being entered, i.e. those at
+    /// depths from `first_depth` up to `bound_lifetime_depth`, are used in the
+    /// rest of it, which is parsed by `f` without printing it, then reparsed.
+    fn uses_bound_lifetimes<F>(&mut self, first_depth: u32, f: &F) -> bool
+    where
+        F: Fn(&mut Self) -> fmt::Result,
+    {
+        let parser = match self.parser {
+            Ok(ref parser) => Parser { ..*parser },
+            Err(_) => return true,
+        };
+        let lifetimes = first_depth as u64..self.bound_lifetime_depth as u64;
+        let outer = self.bound_lifetime_uses.replace((lifetimes, false));
+        self.skipping_printing(f);
+        let uses = mem::replace(&mut self.bound_lifetime_uses, outer);
+        self.parser = Ok(parser);
+        uses.map(|(_, used)| used) != Some(false)
+    }
+
+    /// Print the generic arguments of a path, up to the end of the list ('E'),
+    /// starting with `open` (e.g. `<`), and returning whether it was printed,
+    /// which, if lifetimes are hidden, only happens if any argument isn't one.
+    fn print_generic_args(&mut self, open: &str) -> Result<bool, fmt::Error> {
+        let mut opened = !self.hides_lifetimes();
+        if opened {
+            self.print_token(TokenKind::GenericOpen, open)?;
+        }
+        let mut i = 0;
+        while self.parser.is_ok() && !self.eat(b'E') {
+            let hidden =
+                self.hides_lifetimes() && self.parser.as_ref().map(|p| p.peek()) == Ok(Some(b'L'));
+            if !hidden {
+                if i > 0 {
+                    self.print(", ")?;
+                } else if !opened {
+                    self.print_token(TokenKind::GenericOpen, open)?;
+                    opened = true;
+                }
+                i += 1;
+            }
+            self.print_generic_arg()?;
+        }
+        Ok(opened)
+    }
+
+    /// Print list elements using the given closure and separator,
+    /// until the end of the list ('E') is found, or the parser errors.
+    /// Returns the number of elements printed.
+    fn print_sep_list<F>(&mut self, f: F, sep: &str) -> Result<usize, fmt::Error>
     where
         F: Fn(&mut Self) -> fmt::Result,
     {
@@ -792,8 +1404,198 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         Ok(i)
     }
 
+    fn print_disambiguator(&mut self, dis: u64) -> fmt::Result {
+        if let Some(out) = &mut self.out {
+            // Not `fmt::LowerHex`, which would add `0x` for `{:#}`.
+            out.write_fmt(format_args!("[{:x}]", dis))?;
+        }
+        Ok(())
+    }
+
+    /// Whether `print_path_segment` prints nothing at all for a segment (of
+    /// an implementation-specific namespace), and so doesn't need a `::`.
+    fn is_empty_path_segment(&self, ns: Option<char>, dis: u64, name: &Ident) -> bool {
+        ns.is_none()
+            && name.ascii.is_empty()
+            && name.punycode.is_empty()
+            && !(self.options.path_disambiguators && dis != 0)
+    }
+
+    /// Print the last segment of a nested path (`N`), i.e. just its name.
+    fn print_path_segment(&mut self, ns: Option<char>, dis: u64, name: Ident) -> fmt::Result {
+        let shim_kind = match ns {
+            Some('S') if self.options.shim_kinds && !self.options.legacy_closures => {
+                shim_kind(&name)
+            }
+            _ => None,
+        };
+        if let Some(shim_kind) = shim_kind {
+            self.print("{")?;
+            self.print(shim_kind)?;
+            if dis != 0 {
+                self.print("#")?;
+                self.print(dis)?;
+            }
+            return self.print("}");
+        }
+
+        match ns {
+            // Legacy symbols only had e.g. `{{closure}}` and `{{vtable.shim}}`.
+            Some(ns) if self.options.legacy_closures => {
+                self.print("{{")?;
+                if ns == 'S' && (!name.ascii.is_empty() || !name.punycode.is_empty()) {
+                    self.print_ident(name)?;
+                    self.print(".")?;
+                }
+                self.print_special_namespace(ns)?;
+                self.print("}}")
+            }
+
+            // Special namespaces, like closures and shims.
+            Some(ns) => {
+                self.print("{")?;
+                self.print_special_namespace(ns)?;
+                if !name.ascii.is_empty() || !name.punycode.is_empty() {
+                    self.print(":")?;
+                    self.print_ident(name)?;
+                }
+                self.print("#")?;
+                self.print(dis)?;
+                self.print("}")
+            }
+
+            // Implementation-specific/unspecified namespaces.
+            None => {
+                self.print_ident(name)?;
+                if self.options.path_disambiguators && dis != 0 {
+                    self.print_disambiguator(dis)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Print the name of a special namespace (see `special_namespace_name`),
+    /// or, for those unknown to this crate, its letter, e.g. `X`, marked as
+    /// such (i.e. `unknown-namespace-X`) if `unknown_namespaces` is set.
+    fn print_special_namespace(&mut self, ns: char) -> fmt::Result {
+        match special_namespace_name(ns) {
+            Some(name) => self.print(name),
+            None => {
+                if self.options.unknown_namespaces {
+                    self.print("unknown-namespace-")?;
+                }
+                self.print(ns)
+            }
+        }
+    }
+
+    /// Print only the last segment of a path, without any generic arguments,
+    /// see `Demangle::function_name` in the crate root.
+    fn print_last_path_segment(&mut self) -> fmt::Result {
+        let tag = match self.parser {
+            Ok(ref parser) => parser.peek(),
+            Err(_) => None,
+        };
+        match tag {
+            Some(b'N') => {
+                parse!(self, push_depth);
+                parse!(self, next);
+                let ns = parse!(self, namespace);
+                self.skipping_printing(|this| this.print_path(false));
+                if let Err(err) = self.parser {
+                    return self.print_error(err);
+                }
+                let dis = parse!(self, disambiguator);
+                let name = parse!(self, ident);
+                self.print_path_segment(ns, dis, name)?;
+                self.pop_depth();
+            }
+            Some(b'I') => {
+                parse!(self, push_depth);
+                parse!(self, next);
+                self.print_last_path_segment()?;
+                if self.parser.is_ok() {
+                    self.skipping_printing(|this| {
+                        this.print_sep_list(Self::print_generic_arg, ", ")
+                            .map(|_| ())
+                    });
+                    if let Err(err) = self.parser {
+                        return self.print_error(err);
+                    }
+                }
+                self.pop_depth();
+            }
+            Some(b'B') => {
+                parse!(self, push_depth);
+                parse!(self, next);
+                self.print_backref(Self::print_last_path_segment)?;
+                self.pop_depth();
+            }
+            // Crate roots and qualified paths (e.g. `<Foo as Trait>`) are
+            // their own last segments.
+            _ => self.print_path(false)?,
+        }
+        Ok(())
+    }
+
     fn print_path(&mut self, in_value: bool) -> fmt::Result {
+        let elided = self.elided_path_segments();
+        self.in_production(V0Production::Path, |this| {
+            this.print_path_elided(in_value, 0, elided)
+        })
+    }
+
+    /// Find the segments of the path about to be printed which are elided,
+    /// according to `DemangleOptions::path_elision`, by counting the nested
+    /// paths (`N`) wrapping its root, without printing anything.
+    fn elided_path_segments(&self) -> ElidedSegments {
+        let none = ElidedSegments { start: 0, end: 0 };
+        let mut parser = match self.parser {
+            Ok(ref parser) if self.out.is_some() => Parser { ..*parser },
+            _ => return none,
+        };
+        if self.options.path_elision == PathElision::Full {
+            return none;
+        }
+
+        let mut segments = 1;
+        loop {
+            match parser.next() {
+                Ok(b'N') => {
+                    if parser.namespace().is_err() {
+                        return none;
+                    }
+                    segments += 1;
+                }
+                Ok(b'I') => {}
+                Ok(b'B') => match parser.backref() {
+                    Ok(backref) => parser = backref,
+                    Err(_) => return none,
+                },
+                Ok(_) => break,
+                Err(_) => return none,
+            }
+        }
+
+        // Segments are counted from the first one, but printed from the last.
+        let elided = self.options.path_elision.elided(segments);
+        ElidedSegments {
+            start: segments - elided.end,
+            end: segments - elided.start,
+        }
+    }
+
+    /// Print a path, which is the prefix of the path whose segments are
+    /// `elided`, ending `from_leaf` segments before its last one.
+    fn print_path_elided(
+        &mut self,
+        in_value: bool,
+        from_leaf: usize,
+        elided: ElidedSegments,
+    ) -> fmt::Result {
         parse!(self, push_depth);
+        self.count_production();
 
         let tag = parse!(self, next);
         match tag {
@@ -801,19 +1603,28 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 let dis = parse!(self, disambiguator);
                 let name = parse!(self, ident);
 
-                self.print(name)?;
-                if let Some(out) = &mut self.out {
-                    if !out.alternate() && dis != 0 {
-                        out.write_str("[")?;
-                        fmt::LowerHex::fmt(&dis, out)?;
-                        out.write_str("]")?;
-                    }
+                if elided.contains(from_leaf) {
+                    self.print("…")?;
+                } else {
+                    self.in_token(TokenKind::Crate, |this| {
+                        this.print_ident(name)?;
+                        let shows_dis = match this.out {
+                            Some(ref out) => {
+                                this.options.shows_crate_disambiguators(out.alternate())
+                            }
+                            None => false,
+                        };
+                        if shows_dis && dis != 0 {
+                            this.print_disambiguator(dis)?;
+                        }
+                        Ok(())
+                    })?;
                 }
             }
             b'N' => {
                 let ns = parse!(self, namespace);
 
-                self.print_path(in_value)?;
+                self.print_path_elided(in_value, from_leaf + 1, elided)?;
 
                 // HACK(eddyb) if the parser is already marked as having errored,
                 // `parse!` below will print a `?` without its preceding `::`
@@ -827,83 +1638,190 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 let dis = parse!(self, disambiguator);
                 let name = parse!(self, ident);
 
-                match ns {
-                    // Special namespaces, like closures and shims.
-                    Some(ns) => {
-                        self.print("::{")?;
-                        match ns {
-                            'C' => self.print("closure")?,
-                            'S' => self.print("shim")?,
-                            _ => self.print(ns)?,
-                        }
-                        if !name.ascii.is_empty() || !name.punycode.is_empty() {
-                            self.print(":")?;
-                            self.print(name)?;
-                        }
-                        self.print("#")?;
-                        self.print(dis)?;
-                        self.print("}")?;
+                if elided.contains(from_leaf) {
+                    // Only the first of the elided segments prints anything.
+                    if !elided.contains(from_leaf + 1) {
+                        self.print("::…")?;
                     }
-
-                    // Implementation-specific/unspecified namespaces.
-                    None => {
-                        if !name.ascii.is_empty() || !name.punycode.is_empty() {
-                            self.print("::")?;
-                            self.print(name)?;
-                        }
+                } else {
+                    if !self.is_empty_path_segment(ns, dis, &name) {
+                        self.print("::")?;
                     }
+                    self.in_token(TokenKind::PathSegment, |this| {
+                        this.print_path_segment(ns, dis, name)
+                    })?;
                 }
             }
             b'M' | b'X' | b'Y' => {
-                if tag != b'Y' {
-                    // Ignore the `impl`'s own path.
-                    parse!(self, disambiguator);
-                    self.skipping_printing(|this| this.print_path(false));
-                }
-
-                self.print("<")?;
-                self.print_type()?;
-                if tag != b'M' {
-                    self.print(" as ")?;
-                    self.print_path(false)?;
+                if elided.contains(from_leaf) && self.out.is_some() {
+                    self.print("…")?;
+                    self.skipping_printing(|this| this.print_qualified_path(tag));
+                } else {
+                    self.print_qualified_path(tag)?;
                 }
-                self.print(">")?;
             }
             b'I' => {
-                self.print_path(in_value)?;
-                if in_value {
-                    self.print("::")?;
+                self.print_path_elided(in_value, from_leaf, elided)?;
+                if self.hides_generics() || (elided.contains(from_leaf) && self.out.is_some()) {
+                    self.skipping_printing(|this| {
+                        this.print_sep_list(Self::print_generic_arg, ", ")
+                            .map(|_| ())
+                    });
+                } else if self.options.collapsed_generics && self.out.is_some() {
+                    let open = if in_value { "::<" } else { "<" };
+                    self.print_token(TokenKind::GenericOpen, open)?;
+                    self.print("…")?;
+                    self.print_token(TokenKind::GenericClose, ">")?;
+                    self.skipping_printing(|this| {
+                        this.print_sep_list(Self::print_generic_arg, ", ")
+                            .map(|_| ())
+                    });
+                } else {
+                    let open = if in_value { "::<" } else { "<" };
+                    if self.print_generic_args(open)? {
+                        self.print_token(TokenKind::GenericClose, ">")?;
+                    }
                 }
-                self.print("<")?;
-                self.print_sep_list(Self::print_generic_arg, ", ")?;
-                self.print(">")?;
             }
             b'B' => {
-                self.print_backref(|this| this.print_path(in_value))?;
+                self.print_backref(|this| this.print_path_elided(in_value, from_leaf, elided))?;
             }
-            _ => invalid!(self),
+            _ => self.print_unknown_tag(tag)?,
         }
 
         self.pop_depth();
         Ok(())
     }
 
+    /// Print `{unknown 'Z'}` for a path (or type) or const starting with the
+    /// unknown `tag`, if `DemangleOptions::unknown_tags` allows it, skipping
+    /// the rest of it, which is assumed to be a list of generic arguments
+    /// ending with `E` (like most productions which could be added to v0),
+    /// or, otherwise, mark the parser as errored (like `invalid!`).
+    fn print_unknown_tag(&mut self, tag: u8) -> fmt::Result {
+        // `E` can't start a new production, as it ends lists.
+        if !self.options.unknown_tags || !tag.is_ascii_alphabetic() || tag == b'E' {
+            invalid!(self);
+        }
+
+        self.print(format_args!("{{unknown '{}'}}", tag as char))?;
+        self.skipping_printing(|this| {
+            this.print_sep_list(Self::print_generic_arg, ", ")
+                .map(|_| ())
+        });
+        // Any error was found (and its message dropped) while skipping.
+        if let Err(err) = self.parser {
+            self.print_error(err)?;
+        }
+        Ok(())
+    }
+
+    /// Print the rest of a path starting with `tag`, one of `M`, `X` or `Y`
+    /// (i.e. `<T>`, `<T as Trait>`, or the path of an `impl` printed as one).
+    fn print_qualified_path(&mut self, tag: u8) -> fmt::Result {
+        let mut impl_dis = 0;
+        let mut impl_path = None;
+        if tag != b'Y' {
+            // Ignore the `impl`'s own path, unless it's printed at the end.
+            impl_dis = parse!(self, disambiguator);
+            if self.options.impl_paths && self.out.is_some() {
+                if let Ok(ref parser) = self.parser {
+                    impl_path = Some(Parser { ..*parser });
+                }
+            }
+            self.skipping_printing(|this| this.print_path(false));
+        }
+
+        self.print("<")?;
+        self.print_type()?;
+        if tag != b'M' {
+            self.print(" ")?;
+            self.print_token(TokenKind::Keyword, "as")?;
+            self.print(" ")?;
+            self.print_path(false)?;
+        }
+        self.print(">")?;
+        if (self.options.path_disambiguators || self.options.impl_paths) && impl_dis != 0 {
+            self.print_disambiguator(impl_dis)?;
+        }
+        if let Some(impl_path) = impl_path {
+            self.print("[impl in ")?;
+            let orig_parser = mem::replace(&mut self.parser, Ok(impl_path));
+            let r = self.print_path(false);
+            // Like in `print_backref`, keep any error on `fmt::Error`.
+            if r.is_ok() {
+                self.parser = orig_parser;
+            }
+            r?;
+            self.print("]")?;
+        }
+        Ok(())
+    }
+
+    /// Print the optional path of the crate which instantiated the symbol
+    /// (following its own path), as ` (in crate)`.
+    fn print_instantiating_crate(&mut self) -> fmt::Result {
+        let tag = match self.parser {
+            Ok(ref parser) => parser.peek(),
+            Err(_) => None,
+        };
+        if let Some(b'A'..=b'Z') = tag {
+            self.print(" (in ")?;
+            self.print_path(false)?;
+            self.print(")")?;
+        }
+        Ok(())
+    }
+
     fn print_generic_arg(&mut self) -> fmt::Result {
         if self.eat(b'L') {
             let lt = parse!(self, integer_62);
+            if self.hides_lifetimes() {
+                return Ok(());
+            }
             self.print_lifetime_from_index(lt)
         } else if self.eat(b'K') {
+            if let Ok(ref mut parser) = self.parser {
+                parser.stats.const_args += 1;
+            }
+            if self.prints_placeholders() {
+                self.skipping_printing(|this| this.print_const(false));
+                self.const_placeholders += 1;
+                return self.print(format_args!("C{}", self.const_placeholders - 1));
+            }
             self.print_const(false)
         } else {
-            self.print_type()
+            self.print_type_arg()
+        }
+    }
+
+    /// Print a type in generic argument position (including the associated
+    /// type bindings of trait objects), or a placeholder in its stead.
+    fn print_type_arg(&mut self) -> fmt::Result {
+        if self.prints_placeholders() {
+            self.skipping_printing(Self::print_type);
+            self.type_placeholders += 1;
+            return self.print(format_args!("T{}", self.type_placeholders - 1));
         }
+        self.print_type()
+    }
+
+    /// Whether type and const generic arguments are to be parsed without
+    /// printing them, and replaced with placeholders (`T0`, `C0`, etc.).
+    fn prints_placeholders(&self) -> bool {
+        self.options.generic_placeholders && self.out.is_some() && self.parser.is_ok()
     }
 
     fn print_type(&mut self) -> fmt::Result {
+        self.in_production(V0Production::Type, Self::print_type_inner)
+    }
+
+    fn print_type_inner(&mut self) -> fmt::Result {
+        self.count_production();
         let tag = parse!(self, next);
 
         if let Some(ty) = basic_type(tag) {
-            return self.print(ty);
+            return self.print_token(TokenKind::PrimitiveType, ty);
         }
 
         parse!(self, push_depth);
@@ -913,13 +1831,14 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 self.print("&")?;
                 if self.eat(b'L') {
                     let lt = parse!(self, integer_62);
-                    if lt != 0 {
+                    if lt != 0 && !self.hides_lifetimes() {
                         self.print_lifetime_from_index(lt)?;
                         self.print(" ")?;
                     }
                 }
                 if tag != b'R' {
-                    self.print("mut ")?;
+                    self.print_token(TokenKind::Keyword, "mut")?;
+                    self.print(" ")?;
                 }
                 self.print_type()?;
             }
@@ -927,10 +1846,11 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
             b'P' | b'O' => {
                 self.print("*")?;
                 if tag != b'P' {
-                    self.print("mut ")?;
+                    self.print_token(TokenKind::Keyword, "mut")?;
                 } else {
-                    self.print("const ")?;
+                    self.print_token(TokenKind::Keyword, "const")?;
                 }
+                self.print(" ")?;
                 self.print_type()?;
             }
 
@@ -968,11 +1888,13 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 };
 
                 if is_unsafe {
-                    this.print("unsafe ")?;
+                    this.print_token(TokenKind::Keyword, "unsafe")?;
+                    this.print(" ")?;
                 }
 
                 if let Some(abi) = abi {
-                    this.print("extern \"")?;
+                    this.print_token(TokenKind::Keyword, "extern")?;
+                    this.print(" \"")?;
 
                     // If the ABI had any `-`, they were replaced with `_`,
                     // so the parts between `_` have to be re-joined with `-`.
@@ -986,11 +1908,12 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                     this.print("\" ")?;
                 }
 
-                this.print("fn(")?;
+                this.print_token(TokenKind::Keyword, "fn")?;
+                this.print("(")?;
                 this.print_sep_list(Self::print_type, ", ")?;
                 this.print(")")?;
 
-                if this.eat(b'u') {
+                if !this.options.explicit_unit_returns && this.eat(b'u') {
                     // Skip printing the return type if it's 'u', i.e. `()`.
                 } else {
                     this.print(" -> ")?;
@@ -1000,7 +1923,13 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 Ok(())
             })?,
             b'D' => {
-                self.print("dyn ")?;
+                // `D*` is rustc's (unstable) encoding of `dyn*` trait objects.
+                if self.eat(b'*') {
+                    self.print_token(TokenKind::Keyword, "dyn*")?;
+                } else {
+                    self.print_token(TokenKind::Keyword, "dyn")?;
+                }
+                self.print(" ")?;
                 self.in_binder(|this| {
                     this.print_sep_list(Self::print_dyn_trait, " + ")?;
                     Ok(())
@@ -1010,11 +1939,13 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                     invalid!(self);
                 }
                 let lt = parse!(self, integer_62);
-                if lt != 0 {
+                if lt != 0 && !self.hides_lifetimes() {
                     self.print(" + ")?;
                     self.print_lifetime_from_index(lt)?;
                 }
             }
+            // Unsafe binder types, e.g. `unsafe<'a> &'a T`.
+            b'W' => self.in_binder_introduced_by("unsafe", true, Self::print_type)?,
             b'B' => {
                 self.print_backref(Self::print_type)?;
             }
@@ -1046,9 +1977,14 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
             Ok(open)
         } else if self.eat(b'I') {
             self.print_path(false)?;
-            self.print("<")?;
-            self.print_sep_list(Self::print_generic_arg, ", ")?;
-            Ok(true)
+            if self.hides_generics() {
+                self.skipping_printing(|this| {
+                    this.print_sep_list(Self::print_generic_arg, ", ")
+                        .map(|_| ())
+                });
+                return Ok(false);
+            }
+            self.print_generic_args("<")
         } else {
             self.print_path(false)?;
             Ok(false)
@@ -1059,27 +1995,46 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         let mut open = self.print_path_maybe_open_generics()?;
 
         while self.eat(b'p') {
+            if self.hides_generics() {
+                self.skipping_printing(|this| {
+                    parse!(this, ident);
+                    this.print_type()
+                });
+                continue;
+            }
             if !open {
-                self.print("<")?;
+                self.print_token(TokenKind::GenericOpen, "<")?;
                 open = true;
             } else {
                 self.print(", ")?;
             }
 
             let name = parse!(self, ident);
-            self.print(name)?;
+            self.in_token(TokenKind::PathSegment, |this| this.print_ident(name))?;
             self.print(" = ")?;
-            self.print_type()?;
+            self.print_type_arg()?;
         }
 
         if open {
-            self.print(">")?;
+            self.print_token(TokenKind::GenericClose, ">")?;
         }
 
         Ok(())
     }
 
     fn print_const(&mut self, in_value: bool) -> fmt::Result {
+        self.in_production(V0Production::Const, |this| this.print_const_inner(in_value))
+    }
+
+    fn print_const_inner(&mut self, in_value: bool) -> fmt::Result {
+        if !self.options.const_values && self.out.is_some() && self.parser.is_ok() {
+            // Parsing while skipping printing can't fail, as that's exactly
+            // what was already done when validating the whole symbol.
+            self.skipping_printing(|this| this.print_const(in_value));
+            return self.print_token(TokenKind::Const, "_");
+        }
+        self.count_production();
+
         let tag = parse!(self, next);
 
         parse!(self, push_depth);
@@ -1102,20 +2057,22 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         };
 
         match tag {
-            b'p' => self.print("_")?,
+            b'p' => self.print_token(TokenKind::Const, "_")?,
 
             // Primitive leaves with hex-encoded values (see `basic_type`).
-            b'h' | b't' | b'm' | b'y' | b'o' | b'j' => self.print_const_uint(tag)?,
-            b'a' | b's' | b'l' | b'x' | b'n' | b'i' => {
-                if self.eat(b'n') {
-                    self.print("-")?;
+            b'h' | b't' | b'm' | b'y' | b'o' | b'j' => {
+                self.in_token(TokenKind::Const, |this| this.print_const_uint(tag))?
+            }
+            b'a' | b's' | b'l' | b'x' | b'n' | b'i' => self.in_token(TokenKind::Const, |this| {
+                if this.eat(b'n') {
+                    this.print("-")?;
                 }
 
-                self.print_const_uint(tag)?;
-            }
+                this.print_const_uint(tag)
+            })?,
             b'b' => match parse!(self, hex_nibbles).try_parse_uint() {
-                Some(0) => self.print("false")?,
-                Some(1) => self.print("true")?,
+                Some(0) => self.print_token(TokenKind::Const, "false")?,
+                Some(1) => self.print_token(TokenKind::Const, "true")?,
                 _ => invalid!(self),
             },
             b'c' => {
@@ -1124,7 +2081,9 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                     .and_then(|v| u32::try_from(v).ok())
                     .and_then(char::from_u32);
                 match valid_char {
-                    Some(c) => self.print_quoted_escaped_chars('\'', iter::once(c))?,
+                    Some(c) => self.in_token(TokenKind::Const, |this| {
+                        this.print_quoted_escaped_chars('\'', iter::once(c))
+                    })?,
                     None => invalid!(self),
                 }
             }
@@ -1135,19 +2094,20 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                 open_brace_if_outside_expr(self)?;
                 self.print("*")?;
 
-                self.print_const_str_literal()?;
+                self.in_token(TokenKind::Const, Self::print_const_str_literal)?;
             }
 
             b'R' | b'Q' => {
                 // NOTE(eddyb) this prints `"..."` instead of `&*"..."`, which
                 // is what `Re..._` would imply (see comment for `str` above).
                 if tag == b'R' && self.eat(b'e') {
-                    self.print_const_str_literal()?;
+                    self.in_token(TokenKind::Const, Self::print_const_str_literal)?;
                 } else {
                     open_brace_if_outside_expr(self)?;
                     self.print("&")?;
                     if tag != b'R' {
-                        self.print("mut ")?;
+                        self.print_token(TokenKind::Keyword, "mut")?;
+                        self.print(" ")?;
                     }
                     self.print_const(true)?;
                 }
@@ -1183,7 +2143,9 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
                             |this| {
                                 parse!(this, disambiguator);
                                 let name = parse!(this, ident);
-                                this.print(name)?;
+                                this.in_token(TokenKind::PathSegment, |this| {
+                                    this.print_ident(name)
+                                })?;
                                 this.print(": ")?;
                                 this.print_const(true)
                             },
@@ -1197,7 +2159,7 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
             b'B' => {
                 self.print_backref(|this| this.print_const(in_value))?;
             }
-            _ => invalid!(self),
+            _ => self.print_unknown_tag(tag)?,
         }
 
         if opened_brace {
@@ -1211,18 +2173,40 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
     fn print_const_uint(&mut self, ty_tag: u8) -> fmt::Result {
         let hex = parse!(self, hex_nibbles);
 
-        match hex.try_parse_uint() {
-            Some(v) => self.print(v)?,
+        match hex.try_parse_u128() {
+            Some(mut v) if !self.options.const_hex => {
+                // Enough for the 39 decimal digits of `u128::MAX`.
+                let mut digits = [0; 39];
+                let mut start = digits.len();
+                loop {
+                    start -= 1;
+                    digits[start] = b'0' + (v % 10) as u8;
+                    v /= 10;
+                    if v == 0 {
+                        break;
+                    }
+                }
+                self.print_const_digits(str::from_utf8(&digits[start..]).unwrap(), 3)?;
+            }
 
-            // Print anything that doesn't fit in `u64` verbatim.
-            None => {
+            // Print anything that doesn't fit in `u128` verbatim.
+            None if !self.options.const_hex => {
                 self.print("0x")?;
-                self.print(hex.nibbles)?;
+                self.print_const_digits(hex.nibbles, 4)?;
+            }
+
+            _ => {
+                let nibbles = match hex.nibbles.trim_start_matches('0') {
+                    "" => "0",
+                    nibbles => nibbles,
+                };
+                self.print("0x")?;
+                self.print_const_digits(nibbles, 4)?;
             }
         }
 
         if let Some(out) = &mut self.out {
-            if !out.alternate() {
+            if self.options.shows_const_type_suffixes(out.alternate()) {
                 let ty = basic_type(ty_tag).unwrap();
                 self.print(ty)?;
             }
@@ -1231,11 +2215,86 @@ impl<'a, 'b, 's> Printer<'a, 'b, 's> {
         Ok(())
     }
 
+    /// Print the digits of an integer constant, separated with `_` into
+    /// groups of `group_len` digits (counting from the last digit), if
+    /// `DemangleOptions::const_digit_separators` is set.
+    fn print_const_digits(&mut self, digits: &str, group_len: usize) -> fmt::Result {
+        if !self.options.const_digit_separators {
+            return self.print(digits);
+        }
+
+        let (first, mut rest) = match digits.len() % group_len {
+            0 => digits.split_at(group_len.min(digits.len())),
+            first_len => digits.split_at(first_len),
+        };
+        self.print(first)?;
+        while !rest.is_empty() {
+            let (group, next) = rest.split_at(group_len);
+            self.print("_")?;
+            self.print(group)?;
+            rest = next;
+        }
+        Ok(())
+    }
+
     fn print_const_str_literal(&mut self) -> fmt::Result {
-        match parse!(self, hex_nibbles).try_parse_str_chars() {
-            Some(chars) => self.print_quoted_escaped_chars('"', chars),
+        let hex = parse!(self, hex_nibbles);
+        let chars = match hex.try_parse_str_chars() {
+            Some(chars) => chars,
             None => invalid!(self),
+        };
+
+        if self.options.raw_str_consts {
+            if let Some(hashes) = self.raw_str_hashes(hex.try_parse_str_chars().unwrap()) {
+                self.print("r")?;
+                for _ in 0..hashes {
+                    self.print("#")?;
+                }
+                self.print("\"")?;
+                for c in chars {
+                    self.print(c)?;
+                }
+                self.print("\"")?;
+                for _ in 0..hashes {
+                    self.print("#")?;
+                }
+                return Ok(());
+            }
+        }
+
+        self.print_quoted_escaped_chars('"', chars)
+    }
+
+    /// Returns how many `#`s are needed around `chars` to print them as a raw
+    /// string literal, or `None` if any of them would have to be escaped
+    /// (according to `DemangleOptions::const_escapes`), as raw strings can't
+    /// contain escapes.
+    fn raw_str_hashes(&self, chars: impl Iterator<Item = char>) -> Option<usize> {
+        let mut hashes = 0;
+        // How many `#`s would be needed for the string to not end at the
+        // current position, if it's part of a `"` followed by `#`s.
+        let mut after_quote = None;
+        for c in chars {
+            let escaped = match c {
+                '"' | '\'' | '\\' => false,
+                _ => match self.options.const_escapes {
+                    ConstEscapes::Always => c.escape_default().len() > 1,
+                    ConstEscapes::NonPrintable => c.escape_debug().len() > 1,
+                    ConstEscapes::Never => false,
+                },
+            };
+            if escaped {
+                return None;
+            }
+
+            after_quote = match (c, after_quote) {
+                ('"', _) => Some(1),
+                ('#', Some(n)) => Some(n + 1),
+                _ => None,
+            };
+            hashes = hashes.max(after_quote.unwrap_or(0));
         }
+        Some(hashes)
     }
 }
 
@@ -1295,6 +2354,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn demangle_long_punycode() {
+        // 4095 and 4096 ASCII characters, followed by `ü`.
+        let ascii = "a".repeat(4095);
+        let sym = format!("_RNvC3foou{}{}_t713b", ascii.len() + 6, ascii);
+        if cfg!(feature = "alloc") {
+            t_nohash!(&sym, format!("foo::{}ü", ascii));
+        } else {
+            t_nohash!(&sym, format!("foo::punycode{{{}-t713b}}", ascii));
+        }
+        let ascii = "a".repeat(4096);
+        let sym = format!("_RNvC3foou{}{}_ec23b", ascii.len() + 6, ascii);
+        t_nohash!(&sym, format!("foo::punycode{{{}-ec23b}}", ascii));
+    }
+
+    #[test]
+    fn demangle_undecodable_punycode() {
+        use {DemangleOptions, PunycodeFallback};
+
+        let with_fallback = |sym, fallback| {
+            let options = DemangleOptions::new().undecodable_punycode(fallback);
+            format!("{:#}", ::demangle(sym).with_options(options))
+        };
+        // Malformed punycode, with and without an ASCII part.
+        let sym = "_RNvC3foou5foo_9";
+        assert_eq!(
+            with_fallback(sym, PunycodeFallback::Placeholder),
+            "foo::punycode{foo-9}"
+        );
+        assert_eq!(with_fallback(sym, PunycodeFallback::Raw), "foo::foo_9");
+        assert_eq!(
+            with_fallback(sym, PunycodeFallback::Error),
+            "foo::punycode{foo-9}"
+        );
+        let sym = "_RNvC3foou1z";
+        assert_eq!(
+            with_fallback(sym, PunycodeFallback::Placeholder),
+            "foo::punycode{z}"
+        );
+        assert_eq!(with_fallback(sym, PunycodeFallback::Raw), "foo::z");
+        assert_eq!(
+            with_fallback(sym, PunycodeFallback::Escaped),
+            "foo::\\u{7a}"
+        );
+
+        let check = |sym, fallback| {
+            let options = DemangleOptions::new().undecodable_punycode(fallback);
+            let mut out = String::new();
+            ::demangle(sym)
+                .with_options(options)
+                .write_checked(&mut out, true)
+                .map(|()| out)
+        };
+        assert_eq!(
+            check("_RNvC3foou5foo_9", PunycodeFallback::Error),
+            Err(::CheckedFmtError::Invalid)
+        );
+        assert_eq!(
+            check("_RNvC3foou5foo_9", PunycodeFallback::Raw),
+            Ok("foo::foo_9".to_string())
+        );
+        // Identifiers without punycode, or with decodable punycode, are fine.
+        assert_eq!(
+            check("_RNvC3foou8gdel_5qa", PunycodeFallback::Error),
+            Ok("foo::gödel".to_string())
+        );
+    }
+
     #[test]
     fn demangle_closure() {
         t_nohash!(
@@ -1307,6 +2434,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn demangle_special_namespaces() {
+        t_nohash!("_RNSNvC3foo3bar6vtable", "foo::bar::{shim:vtable#0}");
+        t_nohash!("_RNANvC3foo3bar0", "foo::bar::{A#0}");
+        t_nohash!("_RNZNvC3foo3bars_3baz", "foo::bar::{Z:baz#1}");
+
+        let t = |sym: &str| {
+            let options = ::DemangleOptions::new().unknown_namespaces(true);
+            format!("{:#}", ::demangle(sym).with_options(options))
+        };
+        assert_eq!(t("_RNSNvC3foo3bar6vtable"), "foo::bar::{shim:vtable#0}");
+        assert_eq!(t("_RNANvC3foo3bar0"), "foo::bar::{unknown-namespace-A#0}");
+        assert_eq!(
+            t("_RNZNvC3foo3bars_3baz"),
+            "foo::bar::{unknown-namespace-Z:baz#1}"
+        );
+    }
+
+    #[test]
+    fn demangle_legacy_closures() {
+        let t = |sym: &str| {
+            let options = ::DemangleOptions::new().legacy_closures(true);
+            format!("{:#}", ::demangle(sym).with_options(options))
+        };
+        assert_eq!(
+            t("_RNCNCNgCs6DXkGYLi8lr_2cc5spawn00B5_"),
+            "cc::spawn::{{closure}}::{{closure}}"
+        );
+        assert_eq!(t("_RNCNvC3foo3bar4name"), "foo::bar::{{closure}}");
+        assert_eq!(t("_RNSNvC3foo3bar6vtable"), "foo::bar::{{vtable.shim}}");
+        assert_eq!(t("_RNSNvC3foo3bar0"), "foo::bar::{{shim}}");
+        assert_eq!(t("_RNANvC3foo3bar0"), "foo::bar::{{A}}");
+    }
+
+    #[test]
+    fn demangle_shim_kinds() {
+        let t = |sym: &str| {
+            let options = ::DemangleOptions::new().shim_kinds(true);
+            format!("{:#}", ::demangle(sym).with_options(options))
+        };
+        assert_eq!(t("_RNSNvC3foo3bar6vtable"), "foo::bar::{vtable-shim}");
+        assert_eq!(t("_RNSNvC3foo3bar5reify"), "foo::bar::{reify-shim}");
+        assert_eq!(t("_RNSNvC3foo3bar11reify_fnptr"), "foo::bar::{fn-ptr-shim}");
+        assert_eq!(t("_RNSNvC3foo3bars_6vtable"), "foo::bar::{vtable-shim#1}");
+        // Unknown kinds are printed as usual.
+        assert_eq!(t("_RNSNvC3foo3bar3new"), "foo::bar::{shim:new#0}");
+        assert_eq!(t("_RNSNvC3foo3bar0"), "foo::bar::{shim#0}");
+    }
+
     #[test]
     fn demangle_dyn_trait() {
         t_nohash!(
@@ -1315,6 +2491,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn demangle_f16_f128() {
+        t_nohash!("_RINvC3foo3barC3f16E", "foo::bar::<f16>");
+        t_nohash!("_RINvC3foo3barC4f128E", "foo::bar::<f128>");
+        t_nohash_type!("INtC3foo3BarRSC3f16E", "foo::Bar<&[f16]>");
+        t_nohash_type!("INtC3foo3BarAC4f128j4_E", "foo::Bar<[f128; 4]>");
+        t!(
+            "_RINvC3foo3barFC3f16EC4f128E",
+            "foo::bar::<fn(f16) -> f128>"
+        );
+    }
+
+    #[test]
+    fn demangle_dyn_star() {
+        t_nohash!(
+            "_RINvC3foo3barD*NtC3foo5TraitEL_E",
+            "foo::bar::<dyn* foo::Trait>"
+        );
+        t_nohash!(
+            "_RINvC3foo3barRD*INtC3foo5TraitmEp6OutputmEL_E",
+            "foo::bar::<&dyn* foo::Trait<u32, Output = u32>>"
+        );
+    }
+
+    #[test]
+    fn demangle_unsafe_binder() {
+        t_nohash!("_RINvC3foo3barWG_RL0_hE", "foo::bar::<unsafe<'a> &'a u8>");
+        t_nohash!(
+            "_RINvC3foo3barWG0_TRL1_hRL0_hEE",
+            "foo::bar::<unsafe<'a, 'b> (&'a u8, &'b u8)>"
+        );
+        t_nohash!("_RINvC3foo3barWhE", "foo::bar::<unsafe<> u8>");
+        // Lifetimes from outer binders are still visible.
+        t_nohash!(
+            "_RINvC3foo3barFG_WG_RL1_RL0_hEuE",
+            "foo::bar::<for<'a> fn(unsafe<'b> &'a &'b u8)>"
+        );
+    }
+
     #[test]
     fn demangle_const_generics_preview() {
         // NOTE(eddyb) this was hand-written, before rustc had working
@@ -1330,7 +2545,23 @@ mod tests {
     fn demangle_min_const_generics() {
         t_const!("p", "_");
         t_const_suffixed!("hb_", "11", "u8");
-        t_const_suffixed!("off00ff00ff00ff00ff_", "0xff00ff00ff00ff00ff", "u128");
+        t_const_suffixed!("off00ff00ff00ff00ff_", "4703991516010230251775", "u128");
+        t_const_suffixed!(
+            "offffffffffffffffffffffffffffffff_",
+            "340282366920938463463374607431768211455",
+            "u128"
+        );
+        t_const_suffixed!(
+            "nn80000000000000000000000000000000_",
+            "-170141183460469231731687303715884105728",
+            "i128"
+        );
+        // Anything wider than `u128` can't be a valid constant anyway.
+        t_const_suffixed!(
+            "o1ffffffffffffffffffffffffffffffff_",
+            "0x1ffffffffffffffffffffffffffffffff",
+            "u128"
+        );
         t_const_suffixed!("s98_", "152", "i16");
         t_const_suffixed!("anb_", "-11", "i8");
         t_const!("b0_", "false");
@@ -1359,6 +2590,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn demangle_const_escapes() {
+        use {ConstEscapes, DemangleOptions};
+
+        let t = |sym: &str, escapes| {
+            let options = DemangleOptions::new().const_escapes(escapes);
+            let sym = format!("_RIC0K{}E", sym);
+            format!("{:#}", ::demangle(&sym).with_options(options))
+        };
+        let cases = [
+            ("c76_", "'v'", "'v'", "'v'"),
+            ("c27_", r"'\''", r"'\''", "'''"),
+            ("c22_", r#"'"'"#, r#"'"'"#, r#"'"'"#),
+            ("c5c_", r"'\\'", r"'\\'", r"'\'"),
+            ("ca_", r"'\n'", r"'\n'", "'\n'"),
+            ("c2202_", "'∂'", r"'\u{2202}'", "'∂'"),
+            // A combining character, and a private use one.
+            ("c301_", r"'\u{301}'", r"'\u{301}'", "'\u{301}'"),
+            ("ce000_", r"'\u{e000}'", r"'\u{e000}'", "'\u{e000}'"),
+            ("Re27090a_", r#""'\t\n""#, r#""'\t\n""#, "\"'\t\n\""),
+            ("Ree28882c3bc_", "\"∂ü\"", r#""\u{2202}\u{fc}""#, "\"∂ü\""),
+        ];
+        for &(sym, non_printable, always, never) in &cases {
+            let expected = |value| format!("::<{}>", value);
+            assert_eq!(
+                (sym, t(sym, ConstEscapes::NonPrintable)),
+                (sym, expected(non_printable))
+            );
+            assert_eq!((sym, t(sym, ConstEscapes::Always)), (sym, expected(always)));
+            assert_eq!((sym, t(sym, ConstEscapes::Never)), (sym, expected(never)));
+        }
+    }
+
+    #[test]
+    fn demangle_unknown_tags() {
+        use {demangle_with_options, DemangleOptions};
+
+        let t = |sym: &str| {
+            let options = DemangleOptions::new().unknown_tags(true);
+            format!("{:#}", demangle_with_options(sym, options))
+        };
+        assert_eq!(t("_RINvC3foo3barZhtEmE"), "foo::bar::<{unknown 'Z'}, u32>");
+        assert_eq!(t("_RINvC3foo3barKzhEmE"), "foo::bar::<{unknown 'z'}, u32>");
+        assert_eq!(t("_RINvC3foo3barZZhEEmE"), "foo::bar::<{unknown 'Z'}, u32>");
+        assert_eq!(t("_RNvYZEC3foo3bar"), "<{unknown 'Z'} as foo>::bar");
+        assert_eq!(t("_RINvC3foo3barKVZEUE"), "foo::bar::<{{unknown 'Z'}}>");
+
+        // What follows unknown tags still has to parse (up to an `E`).
+        assert_eq!(t("_RINvC3foo3barZhtmE"), "_RINvC3foo3barZhtmE");
+        assert_eq!(t("_RINvC3foo3bar_E"), "_RINvC3foo3bar_E");
+        assert_eq!(t("_RZ"), "_RZ");
+
+        // Without the option, unknown tags are only printed as errors if
+        // found while printing (e.g. through backrefs).
+        assert_eq!(
+            ::demangle("_RINvC3foo3barZE").to_string(),
+            "_RINvC3foo3barZE"
+        );
+        let sym = demangle_with_options(
+            "_RINvC3foo3barZEBb_E",
+            DemangleOptions::new().unknown_tags(true),
+        );
+        assert_eq!(
+            format!("{:#}", sym),
+            "foo::bar::<{unknown 'Z'}, {unknown 'Z'}>"
+        );
+        let sym = sym.with_options(DemangleOptions::new());
+        assert_eq!(format!("{:#}", sym), "foo::bar::<{invalid syntax}>");
+    }
+
+    #[test]
+    fn demangle_const_raw_str() {
+        use {ConstEscapes, DemangleOptions};
+
+        let t = |sym: &str, options: DemangleOptions| {
+            let options = options.raw_str_consts(true);
+            let sym = format!("_RIC0K{}E", sym);
+            format!("{:#}", ::demangle(&sym).with_options(options))
+        };
+        let default = DemangleOptions::new();
+        assert_eq!(t("Re_", default), r#"::<r"">"#);
+        assert_eq!(t("Re616263_", default), r#"::<r"abc">"#);
+        assert_eq!(t("e616263_", default), r#"::<{*r"abc"}>"#);
+        assert_eq!(t("Re5c27_", default), r#"::<r"\'">"#);
+        assert_eq!(t("Re22_", default), r##"::<r#"""#>"##);
+        assert_eq!(t("Re2223_", default), r###"::<r##""#"##>"###);
+        assert_eq!(t("Re23222322_", default), r###"::<r##"#"#""##>"###);
+        assert_eq!(t("Ree28882c3bc_", default), r#"::<r"∂ü">"#);
+
+        // Strings with characters which need escaping aren't raw.
+        assert_eq!(t("Re5c0a_", default), r#"::<"\\\n">"#);
+        let always = default.const_escapes(ConstEscapes::Always);
+        assert_eq!(t("Re5c_", always), r#"::<r"\">"#);
+        assert_eq!(t("Ree28882_", always), r#"::<"\u{2202}">"#);
+        let never = default.const_escapes(ConstEscapes::Never);
+        assert_eq!(t("Re0a_", never), "::<r\"\n\">");
+
+        // Char constants are unaffected.
+        assert_eq!(t("c5c_", default), r"::<'\\'>");
+    }
+
     // NOTE(eddyb) this uses the same strings as `demangle_const_str` and should
     // be kept in sync with it - while a macro could be used to generate both
     // `str` and `&str` tests, from a single list of strings, this seems clearer.
@@ -1427,6 +2759,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn demangle_const_nested_aggregates() {
+        t_const!("ATh1_b1_ETh2_b0_EE", "{[(1, true), (2, false)]}");
+        t_const!(
+            "RVNtC3foo3BarS1xVNtC3foo3BazTh1_EE",
+            "{&foo::Bar { x: foo::Baz(1) }}"
+        );
+        t_const!("QTAEc61_E", "{&mut ([], 'a')}");
+        t_const!(
+            "TVNvINtNtC4core6option6OptionRShE4NoneUE",
+            "{(core::option::Option::<&[u8]>::None,)}"
+        );
+        // Unit-like structs and tuple structs, and backrefs to constants.
+        t_const!("VNtC3foo3BarTE", "{foo::Bar()}");
+        t_const!("VNtC3foo3BarU", "{foo::Bar}");
+        t_const!("Ah1_B4_E", "{[1, 1]}");
+    }
+
     #[test]
     fn demangle_exponential_explosion() {
         // NOTE(eddyb) because of the prefix added by `t_nohash_type!` is
@@ -1459,6 +2809,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rustc_symbol_names() {
+        // NOTE these mirror rustc's `tests/ui/symbol-names/*.v0.stderr`,
+        // which print `demangling(...)` and `demangling-alt(...)` using this
+        // crate, so any changes here have to be coordinated with rustc.
+        t!(
+            "_RNvCs4fqI2P2rA04_5basic4main",
+            "basic[317d481089b8c8fe]::main"
+        );
+        t_nohash!("_RNvCs4fqI2P2rA04_5basic4main", "basic::main");
+        t!(
+            "_RNvMNtCs4fqI2P2rA04_11issue_609253fooINtB2_3FooNtNtB4_4llvm3FooE3foo",
+            "<issue_60925[317d481089b8c8fe]::foo::Foo<issue_60925[317d481089b8c8fe]::llvm::Foo>>::foo"
+        );
+        t_nohash!(
+            "_RNvMNtCs4fqI2P2rA04_11issue_609253fooINtB2_3FooNtNtB4_4llvm3FooE3foo",
+            "<issue_60925::foo::Foo<issue_60925::llvm::Foo>>::foo"
+        );
+        t!(
+            "_RNvXCs4fqI2P2rA04_1cINtB2_4CharKc2202_ENtB2_3Foo3foo",
+            "<c[317d481089b8c8fe]::Char<'∂'> as c[317d481089b8c8fe]::Foo>::foo"
+        );
+        t_nohash!(
+            "_RNvXCs4fqI2P2rA04_1cINtB2_4CharKc2202_ENtB2_3Foo3foo",
+            "<c::Char<'∂'> as c::Foo>::foo"
+        );
+    }
+
     #[test]
     fn demangling_limits() {
         // Stress tests found via fuzzing.
@@ -1471,16 +2849,66 @@ mod tests {
                 super::demangle(sym).map(|_| ()),
                 Err(super::ParseError::RecursedTooDeep)
             );
+            assert!(::try_demangle(sym).err().unwrap().recursion_limit_reached());
         }
 
-        assert_contains!(
-            ::demangle(
-                "RIC20tRYIMYNRYFG05_EB5_B_B6_RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRR\
-        RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRB_E",
-            )
-            .to_string(),
-            "{recursion limit reached}"
+        let sym = ::demangle(
+            "RIC20tRYIMYNRYFG05_EB5_B_B6_RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRR\
+        RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRB_E",
+        );
+        assert_contains!(sym.to_string(), "{recursion limit reached}");
+        assert!(!sym.recursion_limit_reached());
+        let sym = sym.with_options(::DemangleOptions::new().cyclic_backrefs(true));
+        assert!(sym.to_string().contains("{cyclic backref}"));
+    }
+
+    #[test]
+    fn cyclic_backrefs() {
+        let t = |sym: &str| {
+            let options = ::DemangleOptions::new().cyclic_backrefs(true);
+            format!("{:#}", ::demangle(sym).with_options(options))
+        };
+        // Only found when printing, as backrefs aren't followed before that.
+        assert_eq!(t("_RNvNvB1_1x1y"), "{cyclic backref}::?::x::y");
+        assert_eq!(
+            t("_RINvC3foo3barNvB_3bazE"),
+            "foo::bar::<foo::bar<{cyclic backref}::?>::baz>"
+        );
+        // Backrefs to (non-overlapping) earlier backrefs are fine.
+        assert_eq!(
+            t("_RINvC3foo3barNvB2_1xNvBb_1yE"),
+            "foo::bar::<foo::x, foo::x::y>"
+        );
+        // By default, they're followed until the recursion limit is reached.
+        let demangled = format!("{:#}", ::demangle("_RNvNvB1_1x1y"));
+        assert!(demangled.starts_with("{recursion limit reached}::?::x::x::x::"));
+    }
+
+    #[test]
+    fn first_error() {
+        let first_error = |sym| {
+            super::demangle(sym)
+                .unwrap()
+                .0
+                .first_error(&::DemangleOptions::new())
+        };
+        assert_eq!(first_error("_RNvC3foo3bar"), None);
+        assert_eq!(
+            first_error("_RINvC3foo3barB3_E"),
+            Some((super::ParseError::Invalid, 7, ::V0Production::Path))
+        );
+        assert_eq!(
+            first_error("_RINvC3foo3barNvB_3bazE"),
+            Some((
+                super::ParseError::CyclicBackref,
+                18,
+                ::V0Production::Backref
+            ))
         );
+        assert!(!::try_demangle("_RNvC3foo")
+            .err()
+            .unwrap()
+            .recursion_limit_reached());
     }
 
     #[test]
@@ -1532,5 +2960,6 @@ mod tests {
         sym.push('E');
 
         assert_contains!(::demangle(&sym).to_string(), "{recursion limit reached}");
+        assert!(::demangle(&sym).recursion_limit_reached());
     }
 }